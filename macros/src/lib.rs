@@ -0,0 +1,261 @@
+//! Macros which run the CHIP-8 assembler at compile time, so programs embedded in Rust can't
+//! drift from their source.
+//!
+//! ```
+//! use chip8_assembler_macros::chip8_asm;
+//!
+//! const ROM: [u8; 6] = chip8_asm! {
+//!     start:
+//!         LD V0, 5
+//!         ADD V0, 1
+//!         JP start
+//! };
+//! assert_eq!(ROM, [0x60, 0x05, 0x70, 0x01, 0x12, 0x00]);
+//! ```
+//!
+//! Since the source is tokenized by Rust before the assembler sees it, comments inside
+//! `chip8_asm!` must be written as `//` comments rather than `;` comments.
+//!
+//! Source which doesn't assemble fails to compile, with the error on the offending line:
+//!
+//! ```compile_fail
+//! # use chip8_assembler_macros::chip8_asm;
+//! let rom = chip8_asm! {
+//!     JP nowhere
+//! };
+//! ```
+//!
+//! ```compile_fail
+//! # use chip8_assembler_macros::include_chip8;
+//! let rom = include_chip8!("tests/missing.asm");
+//! ```
+
+extern crate proc_macro;
+
+use chip8_assembler::{assemble_path, assemble_source, Assembly, Diagnostic, Diagnostics, Options};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The name given to the source of a `chip8_asm!` invocation in diagnostics. Includes are
+/// resolved relative to the crate's manifest directory.
+const INLINE_SOURCE_NAME: &str = "chip8_asm!";
+
+/// Assembles the CHIP-8 source inside the macro, expanding to a `[u8; N]` array of the
+/// program's bytes. Assembly errors are reported as compile errors on the offending line.
+#[proc_macro]
+pub fn chip8_asm(input: TokenStream) -> TokenStream {
+    let mut source = SourceText::default();
+    source.push_stream(input);
+
+    let path = manifest_dir().join(INLINE_SOURCE_NAME);
+    match assemble_source(&path, &source.text, &Options::default()) {
+        Ok(assembly) => byte_array(&assembly),
+        Err(diagnostics) => compile_errors(&diagnostics, |diagnostic| {
+            // Errors inside the macro point at their line, and errors in included files at the
+            // whole invocation.
+            let span = if diagnostic.file == path && diagnostic.line > 0 {
+                source
+                    .line_spans
+                    .get(diagnostic.line - 1)
+                    .copied()
+                    .flatten()
+            } else {
+                None
+            };
+            match span {
+                Some(span) => (span, diagnostic.message()),
+                None => (Span::call_site(), diagnostic.to_string()),
+            }
+        }),
+    }
+}
+
+/// Assembles the file at the given path, relative to the crate's manifest directory, expanding
+/// to a `[u8; N]` array of the program's bytes.
+#[proc_macro]
+pub fn include_chip8(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let (literal, span) = match &tokens[..] {
+        [TokenTree::Literal(literal)] => (literal.to_string(), literal.span()),
+        _ => {
+            return compile_error(
+                "include_chip8! takes a single string literal",
+                Span::call_site(),
+            )
+        }
+    };
+    let name = match unquote(&literal) {
+        Some(name) => name,
+        None => return compile_error("include_chip8! takes a single string literal", span),
+    };
+
+    let path = manifest_dir().join(name);
+    match assemble_path(&path, &Options::default()) {
+        Ok(assembly) => byte_array(&assembly),
+        Err(diagnostics) => {
+            compile_errors(&diagnostics, |diagnostic| (span, diagnostic.to_string()))
+        }
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+/// Strips the quotes from a plain string literal, refusing anything with escapes.
+fn unquote(literal: &str) -> Option<&str> {
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    if inner.contains('\\') {
+        None
+    } else {
+        Some(inner)
+    }
+}
+
+/// Source text rebuilt from tokens, keeping them on the lines they were written on so that
+/// diagnostics can be mapped back to a span.
+#[derive(Default)]
+struct SourceText {
+    text: String,
+    /// The span of the first token on each line of `text`.
+    line_spans: Vec<Option<Span>>,
+    first_line: Option<usize>,
+    /// Line and column just past the last token written.
+    end: (usize, usize),
+}
+
+impl SourceText {
+    fn push_stream(&mut self, stream: TokenStream) {
+        for token in stream {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.push_stream(group.stream());
+                    self.push(close, group.span_close());
+                }
+                token => self.push(&token.to_string(), token.span()),
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        let (line, column) = (span.line(), span.column());
+        let first_line = *self.first_line.get_or_insert(line);
+
+        let relative = line.saturating_sub(first_line);
+        if self.line_spans.len() <= relative {
+            // Pad with newlines so each token lands on the same line it was written on.
+            while self.line_spans.len() <= relative {
+                if !self.line_spans.is_empty() {
+                    self.text.push('\n');
+                }
+                self.line_spans.push(None);
+            }
+            self.line_spans[relative] = Some(span);
+        } else if (line, column) != self.end {
+            self.text.push(' ');
+        }
+
+        self.text.push_str(text);
+        let end = span.end();
+        self.end = (end.line(), end.column());
+    }
+}
+
+fn byte_array(assembly: &Assembly) -> TokenStream {
+    let mut elements = TokenStream::new();
+    for (idx, byte) in assembly.bytes.iter().enumerate() {
+        if idx > 0 {
+            elements.extend(Some(TokenTree::Punct(Punct::new(',', Spacing::Alone))));
+        }
+        elements.extend(Some(TokenTree::Literal(Literal::u8_suffixed(*byte))));
+    }
+    let array = TokenTree::Group(Group::new(Delimiter::Bracket, elements));
+
+    // Referencing every file read with `include_bytes!` makes cargo rebuild when they change.
+    let mut block = TokenStream::new();
+    for file in assembly.files.iter().filter(|f| f.is_file()) {
+        block.extend(track_file(file));
+    }
+    block.extend(Some(array));
+    TokenStream::from(TokenTree::Group(Group::new(Delimiter::Brace, block)))
+}
+
+/// `const _: &[u8] = include_bytes!("path");`
+fn track_file(path: &Path) -> TokenStream {
+    let span = Span::call_site();
+    let path = Literal::string(&path.to_string_lossy());
+    let include = vec![
+        TokenTree::Ident(Ident::new("include_bytes", span)),
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenStream::from(TokenTree::Literal(path)),
+        )),
+    ];
+    let slice = TokenTree::Group(Group::new(
+        Delimiter::Bracket,
+        TokenStream::from(TokenTree::Ident(Ident::new("u8", span))),
+    ));
+
+    let mut item = TokenStream::new();
+    item.extend(vec![
+        TokenTree::Ident(Ident::new("const", span)),
+        TokenTree::Ident(Ident::new("_", span)),
+        TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+        TokenTree::Punct(Punct::new('&', Spacing::Alone)),
+        slice,
+        TokenTree::Punct(Punct::new('=', Spacing::Alone)),
+    ]);
+    item.extend(include);
+    item.extend(Some(TokenTree::Punct(Punct::new(';', Spacing::Alone))));
+    item
+}
+
+/// Expands to a block raising one compile error per assembler error, so they're all reported
+/// at once.
+fn compile_errors<F>(diagnostics: &Diagnostics, locate: F) -> TokenStream
+where
+    F: Fn(&Diagnostic) -> (Span, String),
+{
+    let mut block = TokenStream::new();
+    for diagnostic in diagnostics.errors() {
+        let (span, msg) = locate(diagnostic);
+        block.extend(compile_error(&msg, span));
+        block.extend(Some(TokenTree::Punct(Punct::new(';', Spacing::Alone))));
+    }
+    block.extend(Some(TokenTree::Group(Group::new(
+        Delimiter::Bracket,
+        TokenStream::new(),
+    ))));
+    TokenStream::from(TokenTree::Group(Group::new(Delimiter::Brace, block)))
+}
+
+fn compile_error(msg: &str, span: Span) -> TokenStream {
+    let mut literal = Literal::string(msg);
+    literal.set_span(span);
+    let mut args = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(literal)),
+    );
+    args.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ]
+    .into_iter()
+    .collect()
+}
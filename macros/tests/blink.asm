@@ -0,0 +1,7 @@
+; Included by tests/macros.rs.
+start:
+    LD I, dot
+    DRW V0, V0, 1
+    JP start
+dot:
+    DB 0x80
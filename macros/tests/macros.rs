@@ -0,0 +1,34 @@
+//! Programs assembled at compile time, checked against their bytes.
+
+use chip8_assembler_macros::{chip8_asm, include_chip8};
+
+#[test]
+fn assembles_inline_source() {
+    const ROM: [u8; 8] = chip8_asm! {
+        // Labels may be used before they're defined.
+        start:
+            LD I, dot
+            DRW V0, V1, 1
+            JP start
+        dot:
+            DB 0x80, 0x40
+    };
+    assert_eq!(ROM, [0xA2, 0x06, 0xD0, 0x11, 0x12, 0x00, 0x80, 0x40]);
+}
+
+#[test]
+fn keeps_statements_on_their_own_lines() {
+    let rom = chip8_asm! {
+        CLS
+        LD [I], V3
+        LD V4, [I]
+        RET
+    };
+    assert_eq!(rom, [0x00, 0xE0, 0xF3, 0x55, 0xF4, 0x65, 0x00, 0xEE]);
+}
+
+#[test]
+fn includes_a_file_relative_to_the_manifest() {
+    const ROM: [u8; 7] = include_chip8!("tests/blink.asm");
+    assert_eq!(ROM, [0xA2, 0x06, 0xD0, 0x01, 0x12, 0x00, 0x80]);
+}
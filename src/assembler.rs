@@ -0,0 +1,730 @@
+use crate::blocks;
+use crate::cache::parse_file;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::linker::link;
+use crate::object::{
+    is_uninitialized, LineKind, LineMapping, ObjectFile, RelocTarget, Relocation, Section, Symbol,
+    SymbolValue, DEFAULT_SECTION,
+};
+use crate::parser::{
+    bounded, parse_data, parse_statement, statement_size, Line, Operand, Resolver, Value,
+};
+use crate::procs::Procs;
+use crate::pseudo;
+use crate::target::Target;
+use crate::testing;
+use crate::{Assembly, Options, ParseErr};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Assembles `text`, which was read from `path`, into a program laid out as the options
+/// describe. If `text` is `None` it's read using the options' file provider.
+pub(crate) fn assemble(
+    path: &Path,
+    text: Option<&str>,
+    opts: &Options,
+) -> Result<Assembly, Diagnostics> {
+    let object = compile(path, text, opts)?;
+    link(&[object], &opts.layout)
+}
+
+/// Assembles `text`, which was read from `path`, into a relocatable object.
+pub(crate) fn compile(
+    path: &Path,
+    text: Option<&str>,
+    opts: &Options,
+) -> Result<ObjectFile, Diagnostics> {
+    let mut source = Source::default();
+    source.load(path, text, opts, &mut Vec::new());
+    let lines = std::mem::take(&mut source.lines);
+    let lines = testing::lower(lines, opts.tests, |file, number, err| {
+        source.error_at(file, number, err)
+    });
+    let lines = blocks::lower(lines, |file, number, err| {
+        source.error_at(file, number, err)
+    });
+
+    let (sizes, first) = lay_out(path, &source.files, &lines, opts);
+    for (file, number, err) in first.errors {
+        source.error_at(file, number, err);
+    }
+    // Offsets past the end of a section can't be laid out at all.
+    if first.too_large > 0 {
+        return Err(source.diagnostics);
+    }
+    let FirstPass {
+        symbols,
+        sections,
+        procs,
+        imports,
+        exports,
+        ..
+    } = first;
+
+    if let Some((idx, name)) = procs.unclosed() {
+        let err = ParseErr::UnbalancedBlock(format!("`PROC {}` is never ended with `ENDP`", name));
+        source.error(&lines[idx], err);
+    }
+
+    let mut exported = HashSet::new();
+    for (idx, name) in exports {
+        let line = &lines[idx];
+        match symbols.get(&name) {
+            Some(Value::Reloc(RelocTarget::Symbol(_))) => {
+                source.error(line, ParseErr::InvalidOperand(name))
+            }
+            Some(_) => {
+                exported.insert(name);
+            }
+            None => source.error(line, ParseErr::UndefinedSymbol(name)),
+        }
+    }
+
+    let names = sections.names;
+    let mut bytes: Vec<Vec<u8>> = vec![Vec::new(); names.len()];
+    let mut section = 0;
+    let mut relocations = Vec::new();
+    let mut line_map = Vec::new();
+    for (line, &size) in lines.iter().zip(&sizes) {
+        let statement = match &line.line.statement {
+            Some(statement) => statement,
+            None => continue,
+        };
+        let offset = bytes[section].len() as u16;
+        let here = RelocTarget::Section { section, offset };
+        let mut resolver = Resolver::new(&symbols, here);
+        let assembled = match (statement.mnemonic.as_str(), &statement.operands[..]) {
+            ("SECTION", [Operand::Value(name)]) => {
+                section = names.iter().position(|n| n == name).unwrap_or(section);
+                continue;
+            }
+            ("DEFINE", _) | ("SECTION", _) | ("IMPORT", _) | ("EXPORT", _) => continue,
+            ("PROC", _) | ("ENDP", _) => continue,
+            ("DS", _) => Ok(vec![0; usize::from(size)]),
+            (mnemonic, _) if is_uninitialized(&names[section]) => Err(ParseErr::Uninitialized(
+                String::from(mnemonic),
+                names[section].clone(),
+            )),
+            ("DB", _) | ("DW", _) => parse_data(statement, &mut resolver),
+            _ => {
+                // Sections are always placed at even addresses, so this is only odd if the
+                // final address will be.
+                if !offset.is_multiple_of(2) {
+                    source.warning(
+                        line,
+                        format!(
+                            "instruction at odd offset {:03X} in section `{}`",
+                            offset, names[section]
+                        ),
+                    );
+                }
+                parse_statement(statement, opts.target, &mut resolver).map(|instructions| {
+                    instructions
+                        .into_iter()
+                        .flat_map(|instr| instr.to_bytes().to_vec())
+                        .collect()
+                })
+            }
+        };
+        // Keep the size the first pass expected even on error, so later offsets still match.
+        let assembled = match assembled {
+            Ok(assembled) => {
+                relocations.extend(resolver.fixups.into_iter().map(|fixup| Relocation {
+                    section,
+                    offset: offset + fixup.offset,
+                    kind: fixup.kind,
+                    target: fixup.target,
+                }));
+                assembled
+            }
+            Err(err) => {
+                source.error(line, err);
+                vec![0; usize::from(size)]
+            }
+        };
+
+        let kind = match statement.mnemonic.as_str() {
+            _ if line.kind != LineKind::Code => line.kind.clone(),
+            "DB" | "DW" | "DS" => LineKind::Data,
+            _ if pseudo::expansion_len(statement, opts.target).is_some() => LineKind::Expanded,
+            _ => LineKind::Code,
+        };
+        line_map.push(LineMapping {
+            section,
+            offset,
+            len: assembled.len() as u16,
+            file: line.file,
+            line: line.number,
+            kind,
+        });
+        bytes[section].extend(assembled);
+    }
+
+    for diagnostic in procs.check(&lines, &source.files, &symbols, opts.target) {
+        source.diagnostics.push(diagnostic);
+    }
+    if source.diagnostics.has_errors() {
+        return Err(source.diagnostics);
+    }
+
+    let mut symbols: Vec<Symbol> = symbols
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with(blocks::HIDDEN_PREFIX))
+        .filter_map(|(name, value)| {
+            let value = match value {
+                Value::Number(number) => SymbolValue::Absolute(number as u16),
+                Value::Reloc(RelocTarget::Section { section, offset }) => {
+                    SymbolValue::Relative { section, offset }
+                }
+                // Imports belong to whichever object defines them.
+                Value::Reloc(RelocTarget::Symbol(_)) => return None,
+            };
+            Some(Symbol {
+                exported: exported.contains(&name),
+                name,
+                value,
+            })
+        })
+        .collect();
+    symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(ObjectFile {
+        name: path.to_path_buf(),
+        sections: names
+            .into_iter()
+            .zip(bytes)
+            .map(|(name, bytes)| {
+                // Only the size of uninitialized sections is kept.
+                if is_uninitialized(&name) {
+                    Section {
+                        name,
+                        reserved: bytes.len() as u16,
+                        bytes: Vec::new(),
+                    }
+                } else {
+                    Section {
+                        name,
+                        bytes,
+                        reserved: 0,
+                    }
+                }
+            })
+            .collect(),
+        symbols,
+        imports,
+        relocations,
+        files: source.files,
+        lines: line_map,
+        warnings: source.diagnostics.0,
+    })
+}
+
+/// The first pass over `lines`, which lays out each line and defines the symbols, returning the
+/// size of each line along with what it found. With a cache, it picks up from where the last
+/// assembly of `path` was at the start of the first file which changed, and stops once it's
+/// back in step with it.
+fn lay_out(
+    path: &Path,
+    files: &[PathBuf],
+    lines: &[SourceLine],
+    opts: &Options,
+) -> (Vec<u16>, FirstPass) {
+    let cache = match &opts.cache {
+        Some(cache) => cache,
+        None => {
+            let mut pass = FirstPass::new(opts);
+            let sizes = lines
+                .iter()
+                .enumerate()
+                .map(|(idx, line)| pass.line(idx, line, opts))
+                .collect();
+            return (sizes, pass);
+        }
+    };
+    let previous = cache.laid_out(path).filter(|previous| {
+        previous.defines == opts.defines
+            && previous.target == opts.target
+            && previous.files == files
+    });
+    let previous = previous.as_deref();
+
+    // The lines before `same_to` are the same as last time, as are those from `same_from` on.
+    let (same_to, same_from) = match previous {
+        Some(previous) => {
+            let same = |(a, b): &(&SourceLine, &SourceLine)| a == b;
+            let same_to = lines.iter().zip(&previous.lines).take_while(same).count();
+            let same_from = if lines.len() == previous.lines.len() {
+                let rev = lines.iter().rev().zip(previous.lines.iter().rev());
+                lines.len() - rev.take_while(same).count()
+            } else {
+                usize::MAX
+            };
+            (same_to, same_from)
+        }
+        None => (0, usize::MAX),
+    };
+
+    let resume = previous.and_then(|previous| {
+        let checkpoint = previous
+            .checkpoints
+            .iter()
+            .rposition(|(line, _)| *line <= same_to)?;
+        Some((previous, checkpoint))
+    });
+    let (start, mut pass, mut sizes, mut checkpoints) = match resume {
+        Some((previous, checkpoint)) => {
+            let (start, pass) = &previous.checkpoints[checkpoint];
+            (
+                *start,
+                pass.clone(),
+                previous.sizes[..*start].to_vec(),
+                previous.checkpoints[..checkpoint].to_vec(),
+            )
+        }
+        None => (0, FirstPass::new(opts), Vec::new(), Vec::new()),
+    };
+
+    let mut end = lines.len();
+    for (idx, line) in lines.iter().enumerate().skip(start) {
+        if idx > 0 && line.file == lines[idx - 1].file {
+            sizes.push(pass.line(idx, line, opts));
+            continue;
+        }
+        // The rest of the lines are laid out as last time if they're the same, and start out
+        // the same.
+        if let Some(previous) = previous.filter(|_| idx >= same_from) {
+            let checkpoint = previous
+                .checkpoints
+                .binary_search_by_key(&idx, |(line, _)| *line);
+            if let Ok(checkpoint) = checkpoint {
+                let earlier = &previous.checkpoints[checkpoint].1;
+                if pass.in_step(earlier) {
+                    checkpoints.extend(
+                        previous.checkpoints[checkpoint..]
+                            .iter()
+                            .map(|(line, later)| (*line, pass.spliced(earlier, later))),
+                    );
+                    sizes.extend_from_slice(&previous.sizes[idx..]);
+                    pass = pass.spliced(earlier, &previous.pass);
+                    end = idx;
+                    break;
+                }
+            }
+        }
+        checkpoints.push((idx, pass.clone()));
+        sizes.push(pass.line(idx, line, opts));
+    }
+
+    let laid_out = LaidOut {
+        defines: opts.defines.clone(),
+        target: opts.target,
+        files: files.to_vec(),
+        lines: lines.to_vec(),
+        checkpoints,
+        sizes: sizes.clone(),
+        pass: pass.clone(),
+    };
+    cache.keep_laid_out(path, laid_out, end - start);
+    (sizes, pass)
+}
+
+/// The first pass over an object's lines, kept by the cache so the next assembly of the same
+/// file can reuse what hasn't changed.
+#[derive(Debug)]
+pub(crate) struct LaidOut {
+    defines: HashMap<String, u16>,
+    target: Target,
+    files: Vec<PathBuf>,
+    lines: Vec<SourceLine>,
+    /// Where the first pass was at the start of each file's lines, by line index.
+    checkpoints: Vec<(usize, FirstPass)>,
+    sizes: Vec<u16>,
+    pass: FirstPass,
+}
+
+/// What the first pass has found so far.
+#[derive(Debug, Clone)]
+struct FirstPass {
+    symbols: HashMap<String, Value>,
+    sections: Sections,
+    procs: Procs,
+    imports: Vec<String>,
+    /// The index of each `EXPORT` line, and a name it exports.
+    exports: Vec<(usize, String)>,
+    /// How many lines didn't fit in their section.
+    too_large: usize,
+    /// The file, line number and problem of each error.
+    errors: Vec<(usize, usize, ParseErr)>,
+}
+
+impl FirstPass {
+    fn new(opts: &Options) -> Self {
+        FirstPass {
+            symbols: opts
+                .defines
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::Number(i64::from(*value))))
+                .collect(),
+            sections: Sections::default(),
+            procs: Procs::default(),
+            imports: Vec::new(),
+            exports: Vec::new(),
+            too_large: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Lays out the line at `idx`, which follows those laid out so far, returning its size.
+    /// The size is kept so the second pass lays lines out the same even on error.
+    fn line(&mut self, idx: usize, line: &SourceLine, opts: &Options) -> u16 {
+        let mut size = 0;
+        if let Some(label) = &line.line.label {
+            let here = Value::Reloc(self.sections.here());
+            self.define(line, label, here);
+        }
+        if let Some(statement) = &line.line.statement {
+            match (statement.mnemonic.as_str(), &statement.operands[..]) {
+                // Definitions from the command line override those in the source.
+                ("DEFINE", [Operand::Value(name), _]) if opts.defines.contains_key(name) => {}
+                ("DEFINE", [Operand::Value(name), Operand::Value(value)]) => {
+                    let value = Resolver::new(&self.symbols, self.sections.here())
+                        .value(value)
+                        .and_then(|value| match value {
+                            Value::Number(number) => bounded(number, u16::MAX)
+                                .map(|number| Value::Number(i64::from(number))),
+                            reloc => Ok(reloc),
+                        });
+                    match value {
+                        Ok(value) => self.define(line, name, value),
+                        Err(err) => self.error(line, err),
+                    }
+                }
+                ("DEFINE", operands) => self.error(
+                    line,
+                    ParseErr::IncorrectArgumentCount {
+                        required: 2,
+                        found: operands.len() as u8,
+                        msg: String::from("DEFINE"),
+                    },
+                ),
+                ("SECTION", [Operand::Value(name)]) => self.sections.switch(name),
+                ("SECTION", [op]) => self.error(line, ParseErr::InvalidOperand(op.to_string())),
+                ("SECTION", operands) => self.error(
+                    line,
+                    ParseErr::IncorrectArgumentCount {
+                        required: 1,
+                        found: operands.len() as u8,
+                        msg: String::from("SECTION"),
+                    },
+                ),
+                ("PROC", [Operand::Value(name)]) => {
+                    let here = self.sections.here();
+                    self.define(line, name, Value::Reloc(here));
+                    if let Err(err) =
+                        self.procs
+                            .open(name, self.sections.current, self.sections.offset(), idx)
+                    {
+                        self.error(line, err);
+                    }
+                }
+                ("ENDP", []) => {
+                    if let Err(err) =
+                        self.procs
+                            .close(self.sections.current, self.sections.offset(), idx)
+                    {
+                        self.error(line, err);
+                    }
+                }
+                ("PROC", [op]) => self.error(line, ParseErr::InvalidOperand(op.to_string())),
+                (mnemonic @ "PROC", operands) | (mnemonic @ "ENDP", operands) => self.error(
+                    line,
+                    ParseErr::IncorrectArgumentCount {
+                        required: if mnemonic == "PROC" { 1 } else { 0 },
+                        found: operands.len() as u8,
+                        msg: String::from(mnemonic),
+                    },
+                ),
+                (mnemonic @ "IMPORT", operands) | (mnemonic @ "EXPORT", operands) => {
+                    if operands.is_empty() {
+                        self.error(
+                            line,
+                            ParseErr::IncorrectArgumentCount {
+                                required: 1,
+                                found: 0,
+                                msg: String::from(mnemonic),
+                            },
+                        );
+                    }
+                    for operand in operands {
+                        match operand {
+                            Operand::Value(name) if mnemonic == "IMPORT" => {
+                                let import = Value::Reloc(RelocTarget::Symbol(name.clone()));
+                                self.define(line, name, import);
+                                self.imports.push(name.clone());
+                            }
+                            Operand::Value(name) => self.exports.push((idx, name.clone())),
+                            op => self.error(line, ParseErr::InvalidOperand(op.to_string())),
+                        }
+                    }
+                }
+                ("DS", [Operand::Value(count)]) => {
+                    match Resolver::new(&self.symbols, self.sections.here()).count(count) {
+                        Ok(count) => size = count,
+                        Err(err) => self.error(line, err),
+                    }
+                }
+                ("DS", [op]) => self.error(line, ParseErr::InvalidOperand(op.to_string())),
+                ("DS", operands) => self.error(
+                    line,
+                    ParseErr::IncorrectArgumentCount {
+                        required: 1,
+                        found: operands.len() as u8,
+                        msg: String::from("DS"),
+                    },
+                ),
+                _ => size = statement_size(statement, opts.target),
+            }
+            if let Err(err) = self.sections.advance(size) {
+                self.error(line, err);
+                self.too_large += 1;
+            }
+        }
+        size
+    }
+
+    /// Whether the lines after this and `other` are laid out the same, if they're the same
+    /// lines.
+    fn in_step(&self, other: &FirstPass) -> bool {
+        self.symbols == other.symbols
+            && self.sections == other.sections
+            && self.procs.same_open(&other.procs)
+    }
+
+    /// What `later` found, having gone on from `earlier`, as it would be had it gone on from
+    /// `self` instead. `self` must be in step with `earlier`.
+    fn spliced(&self, earlier: &FirstPass, later: &FirstPass) -> FirstPass {
+        FirstPass {
+            symbols: later.symbols.clone(),
+            sections: later.sections.clone(),
+            procs: self.procs.spliced(&earlier.procs, &later.procs),
+            imports: spliced(&self.imports, &earlier.imports, &later.imports),
+            exports: spliced(&self.exports, &earlier.exports, &later.exports),
+            too_large: self.too_large + later.too_large - earlier.too_large,
+            errors: spliced(&self.errors, &earlier.errors, &later.errors),
+        }
+    }
+
+    fn define(&mut self, line: &SourceLine, name: &str, value: Value) {
+        if self.symbols.insert(String::from(name), value).is_some() {
+            self.error(line, ParseErr::DuplicateSymbol(String::from(name)));
+        }
+    }
+
+    fn error(&mut self, line: &SourceLine, err: ParseErr) {
+        self.errors.push((line.file, line.number, err));
+    }
+}
+
+/// What's in `later` after `earlier`, following what's in `new` instead.
+pub(crate) fn spliced<T: Clone>(new: &[T], earlier: &[T], later: &[T]) -> Vec<T> {
+    new.iter().chain(&later[earlier.len()..]).cloned().collect()
+}
+
+/// The sections of an object, and how much has been assembled into each so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sections {
+    names: Vec<String>,
+    sizes: Vec<u16>,
+    current: usize,
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Sections {
+            names: vec![String::from(DEFAULT_SECTION)],
+            sizes: vec![0],
+            current: 0,
+        }
+    }
+}
+
+impl Sections {
+    fn switch(&mut self, name: &str) {
+        self.current = match self.names.iter().position(|n| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.names.push(String::from(name));
+                self.sizes.push(0);
+                self.names.len() - 1
+            }
+        };
+    }
+
+    fn here(&self) -> RelocTarget {
+        RelocTarget::Section {
+            section: self.current,
+            offset: self.offset(),
+        }
+    }
+
+    /// How much has been assembled into the current section.
+    fn offset(&self) -> u16 {
+        self.sizes[self.current]
+    }
+
+    /// Moves past `size` bytes, failing if the section would grow past the end of memory.
+    fn advance(&mut self, size: u16) -> Result<(), ParseErr> {
+        let offset = self.offset();
+        let end = offset.checked_add(size).ok_or(ParseErr::ValueOutOfRange {
+            value: i64::from(offset) + i64::from(size),
+            max: u16::MAX,
+        })?;
+        self.sizes[self.current] = end;
+        Ok(())
+    }
+}
+
+/// One line of source, tagged with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceLine {
+    pub(crate) file: usize,
+    pub(crate) number: usize,
+    pub(crate) line: Line,
+    /// Set when lowering replaces what was written.
+    pub(crate) kind: LineKind,
+}
+
+/// The flattened lines of a file and everything it includes.
+#[derive(Debug, Default)]
+struct Source {
+    files: Vec<PathBuf>,
+    lines: Vec<SourceLine>,
+    diagnostics: Diagnostics,
+}
+
+impl Source {
+    fn load(
+        &mut self,
+        path: &Path,
+        text: Option<&str>,
+        opts: &Options,
+        including: &mut Vec<PathBuf>,
+    ) {
+        let read;
+        let text = match text {
+            Some(text) => text,
+            None => {
+                let contents = opts.files.read(path).and_then(|bytes| {
+                    String::from_utf8(bytes)
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+                });
+                match contents {
+                    Ok(contents) => {
+                        read = contents;
+                        &read
+                    }
+                    Err(err) => {
+                        self.diagnostics
+                            .push(Diagnostic::io(path.to_path_buf(), &err));
+                        return;
+                    }
+                }
+            }
+        };
+        let file = self.files.len();
+        self.files.push(path.to_path_buf());
+        including.push(opts.files.canonicalize(path));
+
+        let parsed = match &opts.cache {
+            Some(cache) => cache.parse(path, text),
+            None => parse_file(text),
+        };
+        for (idx, parsed) in parsed.iter().enumerate() {
+            let line = match parsed {
+                Ok(parsed) => SourceLine {
+                    file,
+                    number: idx + 1,
+                    line: parsed.clone(),
+                    kind: LineKind::Code,
+                },
+                Err(err) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        path.to_path_buf(),
+                        idx + 1,
+                        err.clone(),
+                    ));
+                    continue;
+                }
+            };
+
+            let include = match &line.line.statement {
+                Some(statement) if statement.mnemonic == "INCLUDE" => match &statement.operands[..]
+                {
+                    [Operand::Str(name)] => Ok(name.clone()),
+                    [op] => Err(ParseErr::InvalidOperand(op.to_string())),
+                    ops => Err(ParseErr::IncorrectArgumentCount {
+                        required: 1,
+                        found: ops.len() as u8,
+                        msg: String::from("INCLUDE"),
+                    }),
+                },
+                _ => {
+                    self.lines.push(line);
+                    continue;
+                }
+            };
+
+            // The label on an include line still marks the address it appears at.
+            if line.line.label.is_some() {
+                self.lines.push(SourceLine {
+                    line: Line {
+                        label: line.line.label.clone(),
+                        statement: None,
+                    },
+                    kind: LineKind::Code,
+                    ..line
+                });
+            }
+
+            let name = match include {
+                Ok(name) => name,
+                Err(err) => {
+                    self.error(&line, err);
+                    continue;
+                }
+            };
+            match resolve_include(path, &name, opts) {
+                Some(resolved) if including.contains(&opts.files.canonicalize(&resolved)) => {
+                    self.error(&line, ParseErr::RecursiveInclude(name))
+                }
+                Some(resolved) => self.load(&resolved, None, opts, including),
+                None => self.error(&line, ParseErr::IncludeNotFound(name)),
+            }
+        }
+
+        including.pop();
+    }
+
+    fn error(&mut self, line: &SourceLine, err: ParseErr) {
+        self.error_at(line.file, line.number, err);
+    }
+
+    fn error_at(&mut self, file: usize, number: usize, err: ParseErr) {
+        let file = self.files[file].clone();
+        self.diagnostics.push(Diagnostic::error(file, number, err));
+    }
+
+    fn warning(&mut self, line: &SourceLine, msg: String) {
+        let file = self.files[line.file].clone();
+        self.diagnostics
+            .push(Diagnostic::warning(file, line.number, msg));
+    }
+}
+
+/// Finds an included file next to the file including it, or failing that in the include paths.
+fn resolve_include(from: &Path, name: &str, opts: &Options) -> Option<PathBuf> {
+    let relative = from.parent().unwrap_or_else(|| Path::new("")).join(name);
+    std::iter::once(relative)
+        .chain(opts.include_paths.iter().map(|dir| dir.join(name)))
+        .find(|candidate| opts.files.is_file(candidate))
+}
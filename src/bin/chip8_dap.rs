@@ -0,0 +1,13 @@
+//! The CHIP-8 debug adapter, speaking the Debug Adapter Protocol over stdio.
+
+use std::io;
+use std::process;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = chip8_assembler::dap::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,13 @@
+//! The CHIP-8 assembly language server, speaking the Language Server Protocol over stdio.
+
+use std::io;
+use std::process;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = chip8_assembler::lsp::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
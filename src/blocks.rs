@@ -0,0 +1,306 @@
+//! Structured control flow, lowered into branch pseudo-instructions and hidden labels before
+//! the program is assembled:
+//!
+//! ```text
+//! .if V0 == 5         ; JNE V0, 5, else
+//!     ...
+//! .else               ; JP end, then else:
+//!     ...
+//! .endif              ; end:
+//!
+//! .loop               ; start:
+//!     ...
+//!     .break          ; JP end
+//!     ...
+//!     .continue       ; JP next
+//!     ...
+//! .while V1 != 0      ; next:, then JNE V1, 0, start, then end:
+//!
+//! .loop               ; start:
+//!     ...
+//! .again              ; next:, then JP start, then end:
+//! ```
+//!
+//! A loop is closed either by `.while`, which goes around again if its condition holds, or by
+//! `.again`, which always does and so is only left by `.break`.
+//!
+//! Conditions compare a register with a register or byte using `==`, `!=`, `<`, `<=`, `>` or
+//! `>=`. Blocks can be nested. The labels generated start with `.`, so they can't clash with
+//! labels in the source, and are left out of the symbol table.
+
+use crate::assembler::SourceLine;
+use crate::object::LineKind;
+use crate::parser::{Comparison, Line, Operand, ParseErr, Statement};
+
+/// Prefix of the labels generated for blocks, which can't start a label in source.
+pub(crate) const HIDDEN_PREFIX: char = '.';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    If,
+    Loop,
+}
+
+impl Kind {
+    /// The directive which opens the block.
+    fn opener(self) -> &'static str {
+        match self {
+            Kind::If => ".if",
+            Kind::Loop => ".loop",
+        }
+    }
+}
+
+/// A block which has been opened but not yet closed.
+#[derive(Debug)]
+struct Block {
+    kind: Kind,
+    id: usize,
+    /// Where the block was opened, for diagnostics.
+    file: usize,
+    number: usize,
+    has_else: bool,
+}
+
+impl Block {
+    fn label(&self, part: &str) -> String {
+        format!("{}{}_{}", self.kind.opener(), self.id, part)
+    }
+}
+
+/// Replaces every block directive with the branches and labels it stands for, reporting any
+/// blocks which aren't balanced.
+pub(crate) fn lower<F>(lines: Vec<SourceLine>, mut error: F) -> Vec<SourceLine>
+where
+    F: FnMut(usize, usize, ParseErr),
+{
+    let mut lowered = Vec::with_capacity(lines.len());
+    let mut open: Vec<Block> = Vec::new();
+    let mut next_id = 0;
+
+    for line in lines {
+        let statement = match &line.line.statement {
+            Some(statement) if statement.mnemonic.starts_with('.') => statement.clone(),
+            _ => {
+                lowered.push(line);
+                continue;
+            }
+        };
+        let mut out = Output {
+            lines: &mut lowered,
+            file: line.file,
+            number: line.number,
+        };
+        if let Some(label) = &line.line.label {
+            out.label(label.clone());
+        }
+
+        let result = match statement.mnemonic.as_str() {
+            ".IF" => condition(&statement).map(|(lhs, comparison, rhs)| {
+                let block = open_block(&mut open, &mut next_id, Kind::If, &line);
+                let branch = comparison.negate().branch();
+                out.statement(branch, vec![lhs, rhs, Operand::Value(block.label("else"))]);
+            }),
+            ".LOOP" => no_operands(&statement).map(|()| {
+                let block = open_block(&mut open, &mut next_id, Kind::Loop, &line);
+                out.label(block.label("start"));
+            }),
+            ".ELSE" => no_operands(&statement)
+                .and_then(|()| innermost(&open, Kind::If, ".else"))
+                .and_then(|block| {
+                    if block.has_else {
+                        return Err(ParseErr::UnbalancedBlock(format!(
+                            "the `.if` on line {} already has an `.else`",
+                            block.number
+                        )));
+                    }
+                    out.statement("JP", vec![Operand::Value(block.label("end"))]);
+                    out.label(block.label("else"));
+                    Ok(())
+                })
+                .map(|()| open.last_mut().unwrap().has_else = true),
+            ".ENDIF" => no_operands(&statement)
+                .and_then(|()| innermost(&open, Kind::If, ".endif"))
+                .map(|block| block.label(if block.has_else { "end" } else { "else" }))
+                .map(|label| {
+                    out.label(label);
+                    open.pop();
+                }),
+            ".WHILE" => condition(&statement).and_then(|(lhs, comparison, rhs)| {
+                let block = innermost(&open, Kind::Loop, ".while")?;
+                let (next, start, end) = (
+                    block.label("next"),
+                    block.label("start"),
+                    block.label("end"),
+                );
+                out.label(next);
+                out.statement(comparison.branch(), vec![lhs, rhs, Operand::Value(start)]);
+                out.label(end);
+                open.pop();
+                Ok(())
+            }),
+            ".AGAIN" => no_operands(&statement)
+                .and_then(|()| innermost(&open, Kind::Loop, ".again"))
+                .map(|block| {
+                    (
+                        block.label("next"),
+                        block.label("start"),
+                        block.label("end"),
+                    )
+                })
+                .map(|(next, start, end)| {
+                    out.label(next);
+                    out.statement("JP", vec![Operand::Value(start)]);
+                    out.label(end);
+                    open.pop();
+                }),
+            directive @ ".BREAK" | directive @ ".CONTINUE" => no_operands(&statement)
+                .and_then(|()| {
+                    open.iter()
+                        .rev()
+                        .find(|b| b.kind == Kind::Loop)
+                        .ok_or_else(|| {
+                            ParseErr::UnbalancedBlock(format!(
+                                "`{}` outside of a `.loop`",
+                                directive.to_ascii_lowercase()
+                            ))
+                        })
+                })
+                .map(|block| {
+                    let part = if directive == ".BREAK" { "end" } else { "next" };
+                    out.statement("JP", vec![Operand::Value(block.label(part))]);
+                }),
+            // Unknown directives are reported as invalid instructions when assembled.
+            _ => {
+                out.push(Line {
+                    label: None,
+                    statement: Some(statement.clone()),
+                });
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            error(line.file, line.number, err);
+        }
+    }
+
+    for block in open {
+        let (closer, parts): (_, &[_]) = match block.kind {
+            Kind::If => (".endif", &["else", "end"]),
+            Kind::Loop => (".while` or `.again", &["next", "end"]),
+        };
+        let msg = format!(
+            "`{}` is never closed with `{}`",
+            block.kind.opener(),
+            closer
+        );
+        error(block.file, block.number, ParseErr::UnbalancedBlock(msg));
+        // Define the labels the block would have, so branches to them aren't reported too.
+        let mut out = Output {
+            lines: &mut lowered,
+            file: block.file,
+            number: block.number,
+        };
+        for part in parts {
+            out.label(block.label(part));
+        }
+    }
+    lowered
+}
+
+/// Lines generated for a single directive, all attributed to the line it was on.
+struct Output<'a> {
+    lines: &'a mut Vec<SourceLine>,
+    file: usize,
+    number: usize,
+}
+
+impl<'a> Output<'a> {
+    fn label(&mut self, label: String) {
+        self.push(Line {
+            label: Some(label),
+            statement: None,
+        });
+    }
+
+    fn statement(&mut self, mnemonic: &str, operands: Vec<Operand>) {
+        self.push(Line {
+            label: None,
+            statement: Some(Statement {
+                mnemonic: String::from(mnemonic),
+                operands,
+            }),
+        });
+    }
+
+    fn push(&mut self, line: Line) {
+        self.lines.push(SourceLine {
+            file: self.file,
+            number: self.number,
+            line,
+            kind: LineKind::Expanded,
+        });
+    }
+}
+
+fn open_block<'a>(
+    open: &'a mut Vec<Block>,
+    next_id: &mut usize,
+    kind: Kind,
+    line: &SourceLine,
+) -> &'a Block {
+    open.push(Block {
+        kind,
+        id: *next_id,
+        file: line.file,
+        number: line.number,
+        has_else: false,
+    });
+    *next_id += 1;
+    open.last().unwrap()
+}
+
+/// The innermost open block, which must be of the kind `directive` belongs to.
+fn innermost<'a>(open: &'a [Block], kind: Kind, directive: &str) -> Result<&'a Block, ParseErr> {
+    match open.last() {
+        Some(block) if block.kind == kind => Ok(block),
+        Some(block) => Err(ParseErr::UnbalancedBlock(format!(
+            "`{}` doesn't match the `{}` on line {}",
+            directive,
+            block.kind.opener(),
+            block.number
+        ))),
+        None => Err(ParseErr::UnbalancedBlock(format!(
+            "`{}` without `{}`",
+            directive,
+            kind.opener()
+        ))),
+    }
+}
+
+fn condition(statement: &Statement) -> Result<(Operand, Comparison, Operand), ParseErr> {
+    match &statement.operands[..] {
+        [lhs @ Operand::Register(_), Operand::Compare(comparison), rhs @ Operand::Register(_)]
+        | [lhs @ Operand::Register(_), Operand::Compare(comparison), rhs @ Operand::Value(_)] => {
+            Ok((lhs.clone(), *comparison, rhs.clone()))
+        }
+        operands if operands.len() != 3 => Err(ParseErr::IncorrectArgumentCount {
+            required: 3,
+            found: operands.len() as u8,
+            msg: statement.mnemonic.to_ascii_lowercase(),
+        }),
+        _ => Err(ParseErr::UnimplementedInstruction(statement.to_string())),
+    }
+}
+
+fn no_operands(statement: &Statement) -> Result<(), ParseErr> {
+    if statement.operands.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseErr::IncorrectArgumentCount {
+            required: 0,
+            found: statement.operands.len() as u8,
+            msg: statement.mnemonic.to_ascii_lowercase(),
+        })
+    }
+}
@@ -0,0 +1,402 @@
+//! Generating programs from Rust rather than from source text.
+//!
+//! ```
+//! use chip8_assembler::builder::{ProgramBuilder, I};
+//! use chip8_assembler::{V0, V1};
+//!
+//! let mut b = ProgramBuilder::new();
+//! b.ld(V0, 5).ld(I, "sprite");
+//! b.label("loop").drw(V0, V1, 1).jp("loop");
+//! b.label("sprite").db(&[0x80]);
+//! let assembly = b.build().unwrap();
+//! assert_eq!(assembly.bytes, [0x60, 0x05, 0xA2, 0x08, 0xD0, 0x11, 0x12, 0x04, 0x80]);
+//! ```
+
+use crate::instruction::{Addr, Instruction, Vx, V0};
+use crate::machine::MEMORY_SIZE;
+use crate::{Assembly, ParseErr, PROGRAM_START};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The `I` register, as in `LD I, addr` and `ADD I, Vx`.
+#[derive(Debug, Clone, Copy)]
+pub struct I;
+
+/// The memory `I` points at, as in `LD [I], Vx`.
+#[derive(Debug, Clone, Copy)]
+pub struct AtI;
+
+/// The delay timer.
+#[derive(Debug, Clone, Copy)]
+pub struct DT;
+
+/// The sound timer.
+#[derive(Debug, Clone, Copy)]
+pub struct ST;
+
+/// A key press, as in `LD Vx, K`.
+#[derive(Debug, Clone, Copy)]
+pub struct K;
+
+/// A font sprite, as in `LD F, Vx`.
+#[derive(Debug, Clone, Copy)]
+pub struct F;
+
+/// BCD digits, as in `LD B, Vx`.
+#[derive(Debug, Clone, Copy)]
+pub struct B;
+
+/// Where an instruction refers to: either a label, which may be defined later, or a fixed
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Label(String),
+    Address(u16),
+}
+
+impl From<&str> for Location {
+    fn from(label: &str) -> Self {
+        Location::Label(String::from(label))
+    }
+}
+
+impl From<String> for Location {
+    fn from(label: String) -> Self {
+        Location::Label(label)
+    }
+}
+
+impl From<u16> for Location {
+    fn from(addr: u16) -> Self {
+        Location::Address(addr)
+    }
+}
+
+impl From<Addr> for Location {
+    fn from(addr: Addr) -> Self {
+        Location::Address(addr.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Fragment {
+    Bytes(Vec<u8>),
+    /// An instruction whose address operand isn't known until every label is.
+    Fixup(fn(Addr) -> Instruction, Location),
+    /// A `DW` of a label.
+    Word(Location),
+}
+
+/// Builds a program one instruction at a time. Labels may be referred to before they're
+/// defined, and are resolved by [`ProgramBuilder::build`], which produces the same bytes the
+/// text assembler would for the equivalent source.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramBuilder {
+    fragments: Vec<Fragment>,
+    labels: HashMap<String, u16>,
+    address: u16,
+    errors: Vec<ParseErr>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        ProgramBuilder {
+            address: PROGRAM_START,
+            ..ProgramBuilder::default()
+        }
+    }
+
+    /// The address the next instruction will be placed at.
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Marks the current address with a label.
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        if self
+            .labels
+            .insert(String::from(name), self.address)
+            .is_some()
+        {
+            self.errors
+                .push(ParseErr::DuplicateSymbol(String::from(name)));
+        }
+        self
+    }
+
+    pub fn instruction(&mut self, instr: Instruction) -> &mut Self {
+        let assembled = instr.to_bytes();
+        self.push(Fragment::Bytes(assembled.to_vec()), 2)
+    }
+
+    pub fn db(&mut self, bytes: &[u8]) -> &mut Self {
+        self.push(Fragment::Bytes(bytes.to_vec()), byte_len(bytes))
+    }
+
+    pub fn dw(&mut self, words: &[u16]) -> &mut Self {
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let len = byte_len(&bytes);
+        self.push(Fragment::Bytes(bytes), len)
+    }
+
+    /// Emits the address of a label as a big-endian word.
+    pub fn dw_label<L: Into<Location>>(&mut self, location: L) -> &mut Self {
+        self.push(Fragment::Word(location.into()), 2)
+    }
+
+    pub fn cls(&mut self) -> &mut Self {
+        self.instruction(Instruction::Cls)
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.instruction(Instruction::Ret)
+    }
+
+    pub fn sys<L: Into<Location>>(&mut self, location: L) -> &mut Self {
+        self.push(Fragment::Fixup(Instruction::Sys, location.into()), 2)
+    }
+
+    pub fn jp<L: Into<Location>>(&mut self, location: L) -> &mut Self {
+        self.push(Fragment::Fixup(Instruction::Jmp, location.into()), 2)
+    }
+
+    /// `JP V0, addr`.
+    pub fn jp_v0<L: Into<Location>>(&mut self, location: L) -> &mut Self {
+        self.push(Fragment::Fixup(Instruction::JmpV0, location.into()), 2)
+    }
+
+    pub fn call<L: Into<Location>>(&mut self, location: L) -> &mut Self {
+        self.push(Fragment::Fixup(Instruction::Call, location.into()), 2)
+    }
+
+    /// `SE Vx, byte` or `SE Vx, Vy`.
+    pub fn se<S: SkipOperand>(&mut self, vx: Vx, operand: S) -> &mut Self {
+        self.instruction(operand.skip_eq(vx))
+    }
+
+    /// `SNE Vx, byte` or `SNE Vx, Vy`.
+    pub fn sne<S: SkipOperand>(&mut self, vx: Vx, operand: S) -> &mut Self {
+        self.instruction(operand.skip_not_eq(vx))
+    }
+
+    /// Every form of `LD`, such as `b.ld(V0, 5)`, `b.ld(V0, V1)`, `b.ld(I, "sprite")`, or
+    /// `b.ld(AtI, V3)`.
+    pub fn ld<D, S>(&mut self, dest: D, source: S) -> &mut Self
+    where
+        (D, S): LdOperands,
+    {
+        (dest, source).emit(self);
+        self
+    }
+
+    /// `ADD Vx, byte`, `ADD Vx, Vy`, or `ADD I, Vx`.
+    pub fn add<D, S>(&mut self, dest: D, source: S) -> &mut Self
+    where
+        (D, S): AddOperands,
+    {
+        self.instruction((dest, source).instruction())
+    }
+
+    pub fn or(&mut self, vx: Vx, vy: Vx) -> &mut Self {
+        self.instruction(Instruction::Or(vx, vy))
+    }
+
+    pub fn and(&mut self, vx: Vx, vy: Vx) -> &mut Self {
+        self.instruction(Instruction::And(vx, vy))
+    }
+
+    pub fn xor(&mut self, vx: Vx, vy: Vx) -> &mut Self {
+        self.instruction(Instruction::XOr(vx, vy))
+    }
+
+    pub fn sub(&mut self, vx: Vx, vy: Vx) -> &mut Self {
+        self.instruction(Instruction::SubVx(vx, vy))
+    }
+
+    pub fn subn(&mut self, vx: Vx, vy: Vx) -> &mut Self {
+        self.instruction(Instruction::SubN(vx, vy))
+    }
+
+    /// `SHR Vx`, which like the text form leaves `Vy` as `V0`, encoding `8x06`.
+    pub fn shr(&mut self, vx: Vx) -> &mut Self {
+        self.instruction(Instruction::ShiftRight(vx, V0))
+    }
+
+    /// `SHL Vx`, which like the text form leaves `Vy` as `V0`, encoding `8x0E`.
+    pub fn shl(&mut self, vx: Vx) -> &mut Self {
+        self.instruction(Instruction::ShiftLeft(vx, V0))
+    }
+
+    pub fn rnd(&mut self, vx: Vx, mask: u8) -> &mut Self {
+        self.instruction(Instruction::Rand(vx, mask))
+    }
+
+    /// Draws a sprite `height` rows tall, which must be no more than 15.
+    pub fn drw(&mut self, vx: Vx, vy: Vx, height: u8) -> &mut Self {
+        if height > 0x0F {
+            self.errors.push(ParseErr::ValueOutOfRange {
+                value: i64::from(height),
+                max: 0x0F,
+            });
+        }
+        self.instruction(Instruction::Draw(vx, vy, height & 0x0F))
+    }
+
+    pub fn skp(&mut self, vx: Vx) -> &mut Self {
+        self.instruction(Instruction::SkipKeyPressed(vx))
+    }
+
+    pub fn sknp(&mut self, vx: Vx) -> &mut Self {
+        self.instruction(Instruction::SkipKeyNotPressed(vx))
+    }
+
+    /// Resolves every label and produces the program.
+    pub fn build(&self) -> Result<Assembly, ParseErr> {
+        if let Some(err) = self.errors.first() {
+            return Err(err.clone());
+        }
+
+        let mut bytes = Vec::new();
+        for fragment in &self.fragments {
+            match fragment {
+                Fragment::Bytes(fixed) => bytes.extend(fixed),
+                Fragment::Fixup(make, location) => {
+                    let addr = self.resolve(location)?;
+                    if addr > 0x0FFF {
+                        return Err(ParseErr::ValueOutOfRange {
+                            value: i64::from(addr),
+                            max: 0x0FFF,
+                        });
+                    }
+                    bytes.extend(&make(Addr(addr)).to_bytes());
+                }
+                Fragment::Word(location) => bytes.extend(&self.resolve(location)?.to_be_bytes()),
+            }
+        }
+
+        Ok(Assembly {
+            base: PROGRAM_START,
+            bytes,
+            symbols: self.labels.clone().into_iter().collect(),
+            labels: self.labels.keys().cloned().collect(),
+            ..Assembly::default()
+        })
+    }
+
+    fn resolve(&self, location: &Location) -> Result<u16, ParseErr> {
+        match location {
+            Location::Label(label) => self
+                .labels
+                .get(label)
+                .copied()
+                .ok_or_else(|| ParseErr::UndefinedSymbol(label.clone())),
+            Location::Address(addr) => Ok(*addr),
+        }
+    }
+
+    /// Adds what's been built, unless it would run past the end of memory.
+    fn push(&mut self, fragment: Fragment, len: u16) -> &mut Self {
+        let end = usize::from(self.address) + usize::from(len);
+        if end > MEMORY_SIZE {
+            self.errors.push(ParseErr::ValueOutOfRange {
+                value: end as i64,
+                max: MEMORY_SIZE as u16,
+            });
+        } else {
+            self.fragments.push(fragment);
+            self.address = end as u16;
+        }
+        self
+    }
+}
+
+/// The length of some bytes, saturating so that too many don't fit after any address.
+fn byte_len(bytes: &[u8]) -> u16 {
+    u16::try_from(bytes.len()).unwrap_or(u16::MAX)
+}
+
+/// The second operand of `SE` and `SNE`: a byte or a register.
+pub trait SkipOperand {
+    fn skip_eq(self, vx: Vx) -> Instruction;
+    fn skip_not_eq(self, vx: Vx) -> Instruction;
+}
+
+impl SkipOperand for u8 {
+    fn skip_eq(self, vx: Vx) -> Instruction {
+        Instruction::SkipEq(vx, self)
+    }
+
+    fn skip_not_eq(self, vx: Vx) -> Instruction {
+        Instruction::SkipNotEq(vx, self)
+    }
+}
+
+impl SkipOperand for Vx {
+    fn skip_eq(self, vx: Vx) -> Instruction {
+        Instruction::SkipEqVx(vx, self)
+    }
+
+    fn skip_not_eq(self, vx: Vx) -> Instruction {
+        Instruction::SkipNotEqVx(vx, self)
+    }
+}
+
+/// The operand pairs accepted by [`ProgramBuilder::ld`].
+pub trait LdOperands {
+    fn emit(self, builder: &mut ProgramBuilder);
+}
+
+macro_rules! ld_operands {
+    ($(($dest:ty, $source:ty) => |$d:ident, $s:ident| $instr:expr;)*) => {
+        $(
+            impl LdOperands for ($dest, $source) {
+                fn emit(self, builder: &mut ProgramBuilder) {
+                    let ($d, $s) = self;
+                    builder.instruction($instr);
+                }
+            }
+        )*
+    };
+}
+
+ld_operands! {
+    (Vx, u8) => |vx, constant| Instruction::Load(vx, constant);
+    (Vx, Vx) => |vx, vy| Instruction::LoadVx(vx, vy);
+    (Vx, DT) => |vx, _dt| Instruction::LoadDelay(vx);
+    (Vx, K) => |vx, _k| Instruction::LoadKey(vx);
+    (Vx, AtI) => |vx, _i| Instruction::LoadRegisters(vx);
+    (DT, Vx) => |_dt, vx| Instruction::SetDelay(vx);
+    (ST, Vx) => |_st, vx| Instruction::SetSound(vx);
+    (F, Vx) => |_f, vx| Instruction::LoadFont(vx);
+    (B, Vx) => |_b, vx| Instruction::LoadBcd(vx);
+    (AtI, Vx) => |_i, vx| Instruction::StoreRegisters(vx);
+}
+
+impl<L: Into<Location>> LdOperands for (I, L) {
+    fn emit(self, builder: &mut ProgramBuilder) {
+        builder.push(Fragment::Fixup(Instruction::LoadI, self.1.into()), 2);
+    }
+}
+
+/// The operand pairs accepted by [`ProgramBuilder::add`].
+pub trait AddOperands {
+    fn instruction(self) -> Instruction;
+}
+
+impl AddOperands for (Vx, u8) {
+    fn instruction(self) -> Instruction {
+        Instruction::Add(self.0, self.1)
+    }
+}
+
+impl AddOperands for (Vx, Vx) {
+    fn instruction(self) -> Instruction {
+        Instruction::AddVx(self.0, self.1)
+    }
+}
+
+impl AddOperands for (I, Vx) {
+    fn instruction(self) -> Instruction {
+        Instruction::AddI(self.1)
+    }
+}
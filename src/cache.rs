@@ -0,0 +1,121 @@
+use crate::assembler::LaidOut;
+use crate::parser::{parse_line, Line};
+use crate::ParseErr;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Each line of a file, parsed.
+pub(crate) type ParsedFile = Arc<Vec<Result<Line, ParseErr>>>;
+
+/// Keeps what earlier assemblies worked out from each file, so reassembling after a change only
+/// redoes what the change affects:
+///
+/// - Files are parsed once for each text they hold, keyed by its hash, so the same text read
+///   from two paths is only parsed once.
+/// - The first pass, which lays out each line and defines the symbols, picks up at the start of
+///   the first file which changed. It stops again at the start of a later file once it's back
+///   in step, with the same symbols and section sizes as last time and the same lines from
+///   there on, as the rest is then laid out as it was before.
+///
+/// The second pass, which resolves operands and fixups, always runs over the whole program, as
+/// a line anywhere can use a symbol which moved. The result is the same as assembling without
+/// a cache.
+///
+/// Set it as [`crate::Options::cache`] and reuse the options for each assembly. Only the latest
+/// contents of each path, and the latest layout of each main file, are kept.
+#[derive(Debug, Default)]
+pub struct AssemblyCache {
+    parsed: Mutex<Parsed>,
+    laid_out: Mutex<HashMap<PathBuf, Arc<LaidOut>>>,
+    reused: AtomicUsize,
+    lines_laid_out: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Parsed {
+    /// The hash of what was last read from each path.
+    paths: HashMap<PathBuf, u64>,
+    texts: HashMap<u64, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Kept to tell texts with the same hash apart.
+    text: String,
+    parsed: ParsedFile,
+}
+
+impl AssemblyCache {
+    pub fn new() -> Self {
+        AssemblyCache::default()
+    }
+
+    /// How many times a file has been found already parsed.
+    pub fn reused(&self) -> usize {
+        self.reused.load(Ordering::Relaxed)
+    }
+
+    /// How many lines the first pass has laid out, over every assembly.
+    pub fn lines_laid_out(&self) -> usize {
+        self.lines_laid_out.load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) {
+        *self.parsed.lock().unwrap() = Parsed::default();
+        self.laid_out.lock().unwrap().clear();
+    }
+
+    /// Parses `text`, read from `path`, unless the same text has been parsed before.
+    pub(crate) fn parse(&self, path: &Path, text: &str) -> ParsedFile {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut parsed = self.parsed.lock().unwrap();
+        let Parsed { paths, texts } = &mut *parsed;
+        if let Some(old) = paths.insert(path.to_path_buf(), hash) {
+            if old != hash && !paths.values().any(|&h| h == old) {
+                texts.remove(&old);
+            }
+        }
+        match texts.get(&hash) {
+            Some(entry) if entry.text == text => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                entry.parsed.clone()
+            }
+            _ => {
+                let file = parse_file(text);
+                texts.insert(
+                    hash,
+                    Entry {
+                        text: String::from(text),
+                        parsed: file.clone(),
+                    },
+                );
+                file
+            }
+        }
+    }
+
+    /// The first pass of the last assembly of `path`.
+    pub(crate) fn laid_out(&self, path: &Path) -> Option<Arc<LaidOut>> {
+        self.laid_out.lock().unwrap().get(path).cloned()
+    }
+
+    /// Keeps the first pass of an assembly of `path`, which laid out `lines` of it afresh.
+    pub(crate) fn keep_laid_out(&self, path: &Path, laid_out: LaidOut, lines: usize) {
+        self.lines_laid_out.fetch_add(lines, Ordering::Relaxed);
+        self.laid_out
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::new(laid_out));
+    }
+}
+
+pub(crate) fn parse_file(text: &str) -> ParsedFile {
+    Arc::new(text.lines().map(parse_line).collect())
+}
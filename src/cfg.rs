@@ -0,0 +1,225 @@
+//! The instructions of an assembled program, found from the lines they were assembled from, and
+//! how control flows between them.
+
+use crate::files::FileProvider;
+use crate::instruction::{Instruction, Vx, VF};
+use crate::target::Target;
+use crate::{Assembly, LineKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An instruction, and the index of the [`crate::SourceMapping`] it was assembled from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decoded {
+    pub(crate) instr: Instruction,
+    pub(crate) mapping: usize,
+}
+
+/// What happens after an instruction runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    Next,
+    /// Either the next instruction or the one after.
+    Skip,
+    Jump(u16),
+    /// To the subroutine, and then the next instruction once it returns.
+    Call(u16),
+    Return,
+    /// Somewhere which depends on a register.
+    Indirect,
+}
+
+impl Flow {
+    pub(crate) fn of(instr: Instruction) -> Self {
+        match instr {
+            Instruction::Jmp(addr) => Flow::Jump(addr.value()),
+            Instruction::Call(addr) => Flow::Call(addr.value()),
+            Instruction::Ret => Flow::Return,
+            Instruction::JmpV0(_) => Flow::Indirect,
+            Instruction::SkipEq(..)
+            | Instruction::SkipNotEq(..)
+            | Instruction::SkipEqVx(..)
+            | Instruction::SkipNotEqVx(..)
+            | Instruction::SkipKeyPressed(_)
+            | Instruction::SkipKeyNotPressed(_) => Flow::Skip,
+            _ => Flow::Next,
+        }
+    }
+
+    /// Whether the instruction after this one can only be reached some other way.
+    pub(crate) fn ends_block(self) -> bool {
+        matches!(self, Flow::Jump(_) | Flow::Return | Flow::Indirect)
+    }
+}
+
+/// The code of an assembled program.
+#[derive(Debug, Default)]
+pub(crate) struct Code {
+    /// Every instruction, by address.
+    pub(crate) instructions: BTreeMap<u16, Decoded>,
+    /// The addresses of every byte of data.
+    pub(crate) data: BTreeSet<u16>,
+}
+
+impl Code {
+    /// Decodes the lines of the program which aren't data.
+    pub(crate) fn new(assembly: &Assembly) -> Self {
+        let mut code = Code::default();
+        for (idx, mapping) in assembly.source_map.iter().enumerate() {
+            let is_data = mapping.kind == LineKind::Data;
+            let start = usize::from(mapping.address.wrapping_sub(assembly.base));
+            let end = (start + usize::from(mapping.len)).min(assembly.bytes.len());
+            let bytes = assembly.bytes.get(start..end).unwrap_or_default();
+            if is_data || !bytes.len().is_multiple_of(2) {
+                code.data
+                    .extend((0..mapping.len).map(|offset| mapping.address + offset));
+                continue;
+            }
+            for (offset, word) in bytes.chunks(2).enumerate() {
+                if let Some(instr) = Instruction::decode(u16::from_be_bytes([word[0], word[1]])) {
+                    let address = mapping.address + offset as u16 * 2;
+                    code.instructions.insert(
+                        address,
+                        Decoded {
+                            instr,
+                            mapping: idx,
+                        },
+                    );
+                }
+            }
+        }
+        code
+    }
+
+    /// Every address control can move to other than the next instruction.
+    pub(crate) fn targets(&self) -> BTreeSet<u16> {
+        let mut targets = BTreeSet::new();
+        for (&address, decoded) in &self.instructions {
+            match Flow::of(decoded.instr) {
+                Flow::Jump(target) | Flow::Call(target) => {
+                    targets.insert(target);
+                }
+                Flow::Skip => {
+                    targets.insert(address + 4);
+                }
+                Flow::Next | Flow::Return | Flow::Indirect => {}
+            }
+        }
+        targets
+    }
+}
+
+/// Reads the text of each of a program's files, leaving any which can't be read empty.
+pub(crate) fn read_sources(assembly: &Assembly, files: &dyn FileProvider) -> Vec<String> {
+    assembly
+        .files
+        .iter()
+        .map(|path| {
+            files
+                .read(path)
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// A set of registers, one bit each.
+pub(crate) type Registers = u16;
+
+pub(crate) fn bit(vx: Vx) -> Registers {
+    1 << vx.index()
+}
+
+/// `V0` through `Vx`.
+pub(crate) fn up_to(vx: Vx) -> Registers {
+    ((1u32 << (vx.index() + 1)) - 1) as Registers
+}
+
+/// The registers an instruction reads.
+pub(crate) fn reads(instr: Instruction, target: Target) -> Registers {
+    use Instruction::*;
+
+    match instr {
+        SkipEq(vx, _) | SkipNotEq(vx, _) | Add(vx, _) => bit(vx),
+        SkipEqVx(vx, vy) | SkipNotEqVx(vx, vy) => bit(vx) | bit(vy),
+        Or(vx, vy) | And(vx, vy) | XOr(vx, vy) | AddVx(vx, vy) | SubVx(vx, vy) | SubN(vx, vy) => {
+            bit(vx) | bit(vy)
+        }
+        LoadVx(_, vy) => bit(vy),
+        ShiftRight(vx, vy) | ShiftLeft(vx, vy) => {
+            bit(if target.shifts_in_place() { vx } else { vy })
+        }
+        JmpV0(addr) if target.jump_uses_vx() => 1 << (addr.value() >> 8),
+        JmpV0(_) => 1,
+        Draw(vx, vy, _) => bit(vx) | bit(vy),
+        SkipKeyPressed(vx)
+        | SkipKeyNotPressed(vx)
+        | SetDelay(vx)
+        | SetSound(vx)
+        | AddI(vx)
+        | LoadFont(vx)
+        | LoadBcd(vx) => bit(vx),
+        StoreRegisters(vx) => up_to(vx),
+        _ => 0,
+    }
+}
+
+/// The registers an instruction writes, including `VF` when it's set as a flag.
+pub(crate) fn writes(instr: Instruction, target: Target) -> Registers {
+    use Instruction::*;
+
+    match instr {
+        Load(vx, _) | Add(vx, _) | LoadVx(vx, _) | Rand(vx, _) | LoadDelay(vx) | LoadKey(vx) => {
+            bit(vx)
+        }
+        Or(vx, _) | And(vx, _) | XOr(vx, _) if target.logic_resets_vf() => bit(vx) | bit(VF),
+        Or(vx, _) | And(vx, _) | XOr(vx, _) => bit(vx),
+        AddVx(vx, _) | SubVx(vx, _) | SubN(vx, _) | ShiftRight(vx, _) | ShiftLeft(vx, _) => {
+            bit(vx) | bit(VF)
+        }
+        Draw(..) => bit(VF),
+        LoadRegisters(vx) => up_to(vx),
+        _ => 0,
+    }
+}
+
+/// Whether an instruction sets `VF` as a flag, overwriting whatever it held.
+pub(crate) fn sets_flag(instr: Instruction, target: Target) -> bool {
+    use Instruction::*;
+
+    match instr {
+        AddVx(..) | SubVx(..) | SubN(..) | ShiftRight(..) | ShiftLeft(..) | Draw(..) => true,
+        Or(..) | And(..) | XOr(..) => target.logic_resets_vf(),
+        _ => false,
+    }
+}
+
+/// The registers live going into each instruction, which may be read before they're next
+/// written. `kills` gives the registers a call to an address is taken to overwrite; what a call
+/// reads isn't known, so isn't counted.
+pub(crate) fn liveness<F>(code: &Code, target: Target, kills: F) -> BTreeMap<u16, Registers>
+where
+    F: Fn(u16) -> Registers,
+{
+    let mut live: BTreeMap<u16, Registers> = code.instructions.keys().map(|&a| (a, 0)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // Going backwards means most values settle in one pass.
+        for (&address, decoded) in code.instructions.iter().rev() {
+            let at = |address: u16| live.get(&address).copied().unwrap_or(0);
+            let out = match Flow::of(decoded.instr) {
+                Flow::Next => at(address + 2),
+                Flow::Skip => at(address + 2) | at(address + 4),
+                Flow::Jump(target) => at(target),
+                Flow::Call(target) => at(address + 2) & !kills(target),
+                Flow::Return | Flow::Indirect => 0,
+            };
+            let new = (out & !writes(decoded.instr, target)) | reads(decoded.instr, target);
+            if at(address) != new {
+                live.insert(address, new);
+                changed = true;
+            }
+        }
+    }
+    live
+}
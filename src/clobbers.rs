@@ -0,0 +1,323 @@
+//! Checks of the registers subroutines say they change, written in comments on their labels or
+//! on the lines just above:
+//!
+//! ```text
+//! ; @clobbers V0, VF
+//! ; @returns V1
+//! draw_score:
+//! ```
+//!
+//! `@clobbers` lists the registers a subroutine may leave with any value, and `@returns` those
+//! it leaves a result in. Every other register is preserved. Alternatively `@preserves V1-V5`
+//! lists the preserved registers, leaving the rest clobbered.
+//!
+//! A subroutine is warned about if it writes a register it preserves, and a call is warned about
+//! if a register the subroutine clobbers is read afterwards without being set again. Registers
+//! a subroutine saves with `LD [I], Vx` and restores with `LD Vx, [I]` count as preserved, even
+//! if it writes them in between.
+//!
+//! Register lists end at the first word which isn't a register, so a note can follow them:
+//!
+//! ```text
+//! ; @preserves V1 - V5 for the caller's loop
+//! ```
+
+use crate::cfg::{bit, liveness, up_to, writes, Code, Flow, Registers};
+use crate::diagnostic::Diagnostic;
+use crate::instruction::{Instruction, Vx};
+use crate::parser::{parse_line, Operand};
+use crate::target::Target;
+use crate::Assembly;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// The registers a subroutine says it changes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Contract {
+    clobbers: Option<Registers>,
+    preserves: Option<Registers>,
+    returns: Registers,
+}
+
+impl Contract {
+    fn is_empty(&self) -> bool {
+        self.clobbers.is_none() && self.preserves.is_none() && self.returns == 0
+    }
+
+    /// The registers left with any value.
+    fn clobbered(&self) -> Registers {
+        match (self.clobbers, self.preserves) {
+            (Some(clobbers), _) => clobbers,
+            (None, Some(preserves)) => !(preserves | self.returns),
+            (None, None) => 0,
+        }
+    }
+
+    /// The registers left as they were.
+    fn preserved(&self) -> Registers {
+        !(self.clobbered() | self.returns)
+    }
+}
+
+/// A subroutine with a contract, and the line its label is on.
+#[derive(Debug)]
+struct Annotated {
+    name: String,
+    contract: Contract,
+    line: usize,
+}
+
+/// What running a subroutine writes, and which instruction first writes each register.
+#[derive(Debug, Clone, Copy, Default)]
+struct Effects {
+    writes: Registers,
+    first: [Option<u16>; 16],
+}
+
+pub(crate) fn check(
+    assembly: &Assembly,
+    code: &Code,
+    sources: &[String],
+    target: Target,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut annotated: BTreeMap<u16, Annotated> = BTreeMap::new();
+    for (file, text) in sources.iter().enumerate() {
+        for (annotation, err) in annotations(text) {
+            if let Some(err) = err {
+                diagnostics.push(Diagnostic::warning(
+                    assembly.files[file].clone(),
+                    annotation.line,
+                    err,
+                ));
+            }
+            if let Some(&address) = assembly.symbols.get(&annotation.name) {
+                annotated.insert(address, annotation);
+            }
+        }
+    }
+
+    let mut memo = HashMap::new();
+    let warn_at = |address: u16, msg: String| {
+        let mapping = &assembly.source_map[code.instructions[&address].mapping];
+        Diagnostic::warning(assembly.files[mapping.file].clone(), mapping.line, msg)
+    };
+
+    for (&address, sub) in &annotated {
+        let written = effects(code, target, address, &mut memo);
+        let broken = written.writes & sub.contract.preserved();
+        for x in (0..16).filter(|&x| broken & (1 << x) != 0) {
+            let vx = Vx(x);
+            if let Some(at) = written.first[usize::from(x)] {
+                let instr = code.instructions[&at].instr;
+                diagnostics.push(warn_at(
+                    at,
+                    format!("`{}` preserves {}, but `{}` writes it", sub.name, vx, instr),
+                ));
+            }
+        }
+    }
+
+    // Calls to subroutines without contracts are taken to overwrite whatever they write.
+    for decoded in code.instructions.values() {
+        if let Flow::Call(callee) = Flow::of(decoded.instr) {
+            effects(code, target, callee, &mut memo);
+        }
+    }
+    let kills = |callee: u16| match annotated.get(&callee) {
+        Some(sub) => !sub.contract.preserved(),
+        None => memo
+            .get(&callee)
+            .copied()
+            .flatten()
+            .map_or(0, |effects| effects.writes),
+    };
+    let live = liveness(code, target, kills);
+    for (&address, decoded) in &code.instructions {
+        let sub = match Flow::of(decoded.instr) {
+            Flow::Call(callee) => match annotated.get(&callee) {
+                Some(sub) => sub,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let after = live.get(&(address + 2)).copied().unwrap_or(0);
+        let relied = after & sub.contract.clobbered();
+        for x in (0..16).filter(|&x| relied & (1 << x) != 0) {
+            diagnostics.push(warn_at(
+                address,
+                format!(
+                    "{} is read after calling `{}`, which clobbers it",
+                    Vx(x),
+                    sub.name
+                ),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Follows a subroutine from `entry` to each of its returns, including what the subroutines it
+/// calls write.
+fn effects(
+    code: &Code,
+    target: Target,
+    entry: u16,
+    memo: &mut HashMap<u16, Option<Effects>>,
+) -> Effects {
+    match memo.get(&entry) {
+        Some(Some(effects)) => return *effects,
+        // Recursive calls add nothing more.
+        Some(None) => return Effects::default(),
+        None => {}
+    }
+    memo.insert(entry, None);
+
+    let mut found = Effects::default();
+    // Registers stored to memory and loaded back again.
+    let mut saved = 0;
+    let mut restored = 0;
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![entry];
+    while let Some(address) = pending.pop() {
+        let decoded = match code.instructions.get(&address) {
+            Some(decoded) if seen.insert(address) => decoded,
+            _ => continue,
+        };
+        let mut written = writes(decoded.instr, target);
+        match decoded.instr {
+            Instruction::StoreRegisters(vx) => saved |= up_to(vx),
+            Instruction::LoadRegisters(vx) => restored |= up_to(vx),
+            _ => {}
+        }
+        match Flow::of(decoded.instr) {
+            Flow::Next => pending.push(address + 2),
+            Flow::Skip => pending.extend(&[address + 2, address + 4]),
+            Flow::Jump(to) => pending.push(to),
+            Flow::Call(callee) => {
+                written |= effects(code, target, callee, memo).writes;
+                pending.push(address + 2);
+            }
+            Flow::Return | Flow::Indirect => {}
+        }
+        found.writes |= written;
+        for x in 0..16 {
+            if written & (1 << x) != 0 {
+                let first = &mut found.first[x];
+                *first = Some(first.map_or(address, |f: u16| f.min(address)));
+            }
+        }
+    }
+    let kept = saved & restored;
+    found.writes &= !kept;
+    for x in (0..16).filter(|&x| kept & (1 << x) != 0) {
+        found.first[x] = None;
+    }
+    memo.insert(entry, Some(found));
+    found
+}
+
+/// Finds the contracts in a file, each with a message if it couldn't be fully read.
+fn annotations(text: &str) -> Vec<(Annotated, Option<String>)> {
+    let mut found = Vec::new();
+    let mut pending = Contract::default();
+    let mut pending_err = None;
+    for (idx, source) in text.lines().enumerate() {
+        let line = match parse_line(source) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        let comment = source.find(';').map(|idx| &source[idx + 1..]);
+        let name = line.label.clone().or_else(|| match &line.statement {
+            Some(statement) if statement.mnemonic == "PROC" => match &statement.operands[..] {
+                [Operand::Value(name)] => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        });
+        let err = comment.and_then(|comment| parse_contract(comment, &mut pending).err());
+        pending_err = pending_err.or(err);
+
+        match name {
+            Some(name) => {
+                if !pending.is_empty() || pending_err.is_some() {
+                    found.push((
+                        Annotated {
+                            name,
+                            contract: pending,
+                            line: idx + 1,
+                        },
+                        pending_err.take(),
+                    ));
+                }
+                pending = Contract::default();
+            }
+            // Contracts above a label carry down to it through comments and blank lines.
+            None if line.statement.is_none() => {}
+            None => {
+                pending = Contract::default();
+                pending_err = None;
+            }
+        }
+    }
+    found
+}
+
+/// Adds any annotations in a comment to a contract.
+fn parse_contract(comment: &str, contract: &mut Contract) -> Result<(), String> {
+    lazy_static! {
+        static ref ANNOTATION: Regex =
+            Regex::new("@(?P<kind>clobbers|preserves|returns)\\b(?P<registers>[^@]*)").unwrap();
+    }
+
+    for captures in ANNOTATION.captures_iter(comment) {
+        let registers = parse_registers(&captures["registers"])?;
+        match &captures["kind"] {
+            "clobbers" => *contract.clobbers.get_or_insert(0) |= registers,
+            "preserves" => *contract.preserves.get_or_insert(0) |= registers,
+            _ => contract.returns |= registers,
+        }
+    }
+    Ok(())
+}
+
+/// Parses registers separated by commas or spaces, such as `V0, V3-V5, VF`, up to the first
+/// word which isn't one.
+fn parse_registers(list: &str) -> Result<Registers, String> {
+    lazy_static! {
+        static ref ITEM: Regex =
+            Regex::new("^[\\s,]*(?P<first>[^\\s,-]+)(?:\\s*-\\s*(?P<last>[^\\s,]*))?").unwrap();
+    }
+
+    let register = |name: &str| {
+        let mut chars = name.chars();
+        match (
+            chars.next(),
+            chars.next().and_then(|c| c.to_digit(16)),
+            chars.next(),
+        ) {
+            (Some('V'), Some(x), None) | (Some('v'), Some(x), None) => Ok(x as u8),
+            _ => Err(format!("`{}` isn't a register", name)),
+        }
+    };
+
+    let mut registers = 0;
+    let mut rest = list;
+    while let Some(captures) = ITEM.captures(rest) {
+        let first = match register(&captures["first"]) {
+            Ok(first) => first,
+            // Only a list which doesn't start with a register is a mistake.
+            Err(err) if registers == 0 => return Err(err),
+            Err(_) => break,
+        };
+        let last = match captures.name("last") {
+            Some(last) => register(last.as_str())?,
+            None => first,
+        };
+        for x in first..=last {
+            registers |= bit(Vx(x));
+        }
+        rest = &rest[captures[0].len()..];
+    }
+    Ok(registers)
+}
@@ -0,0 +1,372 @@
+//! Which instructions a program ran, and which way each of its skips went, turned into
+//! coverage of the lines they were assembled from.
+//!
+//! Set [`Machine::coverage`](crate::machine::Machine::coverage) to record what a machine runs,
+//! then make a [`CoverageReport`] from it and the program's [`Assembly`].
+
+use crate::cfg::{read_sources, Code, Flow};
+use crate::files::FileProvider;
+use crate::instruction::Instruction;
+use crate::machine::MEMORY_SIZE;
+use crate::Assembly;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How many times a skip instruction skipped, or didn't.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// What a machine has run, by address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, Branch>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            hits: vec![0; MEMORY_SIZE],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// How many times the instruction at `address` ran.
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[usize::from(address) % MEMORY_SIZE]
+    }
+
+    /// Which way the skip at `address` went, if it ran.
+    pub fn branch(&self, address: u16) -> Option<Branch> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Adds what another run recorded to this one.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (hits, other) in self.hits.iter_mut().zip(&other.hits) {
+            *hits += other;
+        }
+        for (&address, other) in &other.branches {
+            let branch = self.branches.entry(address).or_default();
+            branch.taken += other.taken;
+            branch.not_taken += other.not_taken;
+        }
+    }
+
+    /// Counts the instruction at `address` as having run once more, as when a program halts
+    /// there, which stops the machine rather than running it.
+    pub fn hit(&mut self, address: u16) {
+        self.hits[usize::from(address) % MEMORY_SIZE] += 1;
+    }
+
+    /// Records that `instr` ran at `pc`, leaving the machine about to run `next`.
+    pub(crate) fn record(&mut self, pc: u16, instr: Instruction, next: u16) {
+        self.hit(pc);
+        if Flow::of(instr) == Flow::Skip {
+            let branch = self.branches.entry(pc).or_default();
+            if next == (pc + 2) & 0x0FFF {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+    }
+}
+
+/// How a [`CoverageReport`] is written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoverageFormat {
+    /// A summary of each file, with the lines which weren't fully covered.
+    #[default]
+    Text,
+    /// The tracefile format read by `genhtml` and most coverage services.
+    Lcov,
+    /// A listing of each file with every line marked.
+    Html,
+}
+
+impl FromStr for CoverageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(CoverageFormat::Text),
+            "lcov" => Ok(CoverageFormat::Lcov),
+            "html" => Ok(CoverageFormat::Html),
+            other => Err(format!("unknown coverage format `{}`", other)),
+        }
+    }
+}
+
+/// Coverage of a line with instructions on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineCoverage {
+    /// Index into [`CoverageReport::files`].
+    pub file: usize,
+    pub line: usize,
+    /// The most times any of the line's instructions ran.
+    pub hits: u64,
+    /// Each skip on the line, in address order.
+    pub branches: Vec<Branch>,
+}
+
+impl LineCoverage {
+    /// Whether the line ran, and each of its skips went both ways.
+    pub fn covered(&self) -> bool {
+        self.hits > 0 && self.branches.iter().all(|b| b.taken > 0 && b.not_taken > 0)
+    }
+}
+
+/// Coverage of each line of a program's source.
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    pub files: Vec<PathBuf>,
+    /// Every line with instructions on it, by file and then line.
+    pub lines: Vec<LineCoverage>,
+    sources: Vec<String>,
+}
+
+impl CoverageReport {
+    /// Maps what was recorded back to the lines of `assembly`, reading its files from `files`.
+    pub fn new(assembly: &Assembly, coverage: &Coverage, files: &dyn FileProvider) -> Self {
+        let sources = read_sources(assembly, files);
+        let code = Code::new(assembly);
+        let mut lines: BTreeMap<(usize, usize), LineCoverage> = BTreeMap::new();
+        for (&address, decoded) in &code.instructions {
+            let mapping = &assembly.source_map[decoded.mapping];
+            let line = lines
+                .entry((mapping.file, mapping.line))
+                .or_insert_with(|| LineCoverage {
+                    file: mapping.file,
+                    line: mapping.line,
+                    hits: 0,
+                    branches: Vec::new(),
+                });
+            line.hits = line.hits.max(coverage.hits(address));
+            if Flow::of(decoded.instr) == Flow::Skip {
+                line.branches
+                    .push(coverage.branch(address).unwrap_or_default());
+            }
+        }
+        CoverageReport {
+            files: assembly.files.clone(),
+            lines: lines.into_values().collect(),
+            sources,
+        }
+    }
+
+    /// Drops the lines `keep` rejects, such as those of tests.
+    pub fn retain<F: FnMut(&LineCoverage) -> bool>(&mut self, keep: F) {
+        self.lines.retain(keep);
+    }
+
+    pub fn render(&self, format: CoverageFormat) -> String {
+        match format {
+            CoverageFormat::Text => self.text(),
+            CoverageFormat::Lcov => self.lcov(),
+            CoverageFormat::Html => self.html(),
+        }
+    }
+
+    /// A line per file with the share of lines and branches covered, followed by the lines
+    /// which never ran and those with a skip which only went one way.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (file, lines) in self.by_file() {
+            let path = self.files[file].display();
+            writeln!(text, "{}: {}", path, summary(lines)).unwrap();
+            let missed: Vec<usize> = lines
+                .iter()
+                .filter(|l| l.hits == 0)
+                .map(|l| l.line)
+                .collect();
+            if !missed.is_empty() {
+                writeln!(text, "  not run: {}", line_ranges(&missed)).unwrap();
+            }
+            for line in lines.iter().filter(|l| l.hits > 0 && !l.covered()) {
+                let never = if line.branches.iter().any(|b| b.taken == 0) {
+                    "skipped"
+                } else {
+                    "fell through"
+                };
+                writeln!(text, "  {}: never {}", line.line, never).unwrap();
+            }
+        }
+        if self.by_file().count() > 1 {
+            writeln!(text, "total: {}", summary(&self.lines)).unwrap();
+        }
+        text
+    }
+
+    /// An LCOV tracefile, with a branch for each way each skip can go: 0 for falling through
+    /// and 1 for skipping.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::from("TN:\n");
+        for (file, lines) in self.by_file() {
+            writeln!(lcov, "SF:{}", self.files[file].display()).unwrap();
+            for line in lines {
+                writeln!(lcov, "DA:{},{}", line.line, line.hits).unwrap();
+            }
+            for line in lines {
+                for (block, branch) in line.branches.iter().enumerate() {
+                    for (idx, count) in [branch.not_taken, branch.taken].iter().enumerate() {
+                        let count = match line.hits {
+                            0 => String::from("-"),
+                            _ => count.to_string(),
+                        };
+                        writeln!(lcov, "BRDA:{},{},{},{}", line.line, block, idx, count).unwrap();
+                    }
+                }
+            }
+            let (branches, branches_hit) = branch_counts(lines);
+            writeln!(lcov, "BRF:{}\nBRH:{}", branches, branches_hit).unwrap();
+            let hit = lines.iter().filter(|l| l.hits > 0).count();
+            writeln!(lcov, "LF:{}\nLH:{}", lines.len(), hit).unwrap();
+            lcov.push_str("end_of_record\n");
+        }
+        lcov
+    }
+
+    /// A page listing each file, with how often each line ran and its lines coloured by
+    /// whether they were covered.
+    pub fn html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Coverage</title>\n\
+             <style>\n\
+             body { font-family: sans-serif; }\n\
+             table { border-collapse: collapse; font-family: monospace; }\n\
+             th { text-align: right; color: #666; font-weight: normal; }\n\
+             td { padding: 0 0.5em; white-space: pre; }\n\
+             td.n, td.h { text-align: right; color: #666; }\n\
+             tr.run { background: #dfd; }\n\
+             tr.partial { background: #ffc; }\n\
+             tr.missed { background: #fdd; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        for (file, lines) in self.by_file() {
+            let path = escape(&self.files[file].display().to_string());
+            writeln!(html, "<h2>{}</h2>\n<p>{}</p>", path, summary(lines)).unwrap();
+            html.push_str(
+                "<table>\n<tr><th>Line</th><th>Runs</th><th>Skipped/fell through</th>\
+                 <th></th></tr>\n",
+            );
+            let mut lines = lines.iter().peekable();
+            for (idx, source) in self.sources[file].lines().enumerate() {
+                let number = idx + 1;
+                let coverage = lines.next_if(|l| l.line == number);
+                let (class, hits) = match coverage {
+                    None => ("", String::new()),
+                    Some(line) if line.hits == 0 => ("missed", String::from("0")),
+                    Some(line) if !line.covered() => ("partial", line.hits.to_string()),
+                    Some(line) => ("run", line.hits.to_string()),
+                };
+                let branches = coverage.map_or_else(String::new, |line| {
+                    line.branches
+                        .iter()
+                        .map(|b| format!(" {}/{}", b.taken, b.not_taken))
+                        .collect()
+                });
+                writeln!(
+                    html,
+                    "<tr class=\"{}\"><td class=\"n\">{}</td><td class=\"h\">{}</td>\
+                     <td class=\"h\">{}</td><td>{}</td></tr>",
+                    class,
+                    number,
+                    hits,
+                    branches.trim_start(),
+                    escape(source)
+                )
+                .unwrap();
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// The lines of each file which has any.
+    fn by_file(&self) -> impl Iterator<Item = (usize, &[LineCoverage])> {
+        self.lines
+            .chunk_by(|a, b| a.file == b.file)
+            .map(|lines| (lines[0].file, lines))
+    }
+}
+
+/// How many branches there are, and how many went their way at least once.
+fn branch_counts(lines: &[LineCoverage]) -> (usize, usize) {
+    let branches = lines.iter().flat_map(|l| &l.branches);
+    let hit = branches
+        .clone()
+        .map(|b| usize::from(b.taken > 0) + usize::from(b.not_taken > 0))
+        .sum();
+    (branches.count() * 2, hit)
+}
+
+fn summary(lines: &[LineCoverage]) -> String {
+    let hit = lines.iter().filter(|l| l.hits > 0).count();
+    let mut summary = format!(
+        "{}/{} lines ({})",
+        hit,
+        lines.len(),
+        percent(hit, lines.len())
+    );
+    let (branches, branches_hit) = branch_counts(lines);
+    if branches > 0 {
+        write!(
+            summary,
+            ", {}/{} branches ({})",
+            branches_hit,
+            branches,
+            percent(branches_hit, branches)
+        )
+        .unwrap();
+    }
+    summary
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    match whole {
+        0 => String::from("-"),
+        _ => format!("{:.1}%", part as f64 * 100.0 / whole as f64),
+    }
+}
+
+/// Writes sorted line numbers with runs collapsed, as in `3, 7-9`.
+fn line_ranges(lines: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
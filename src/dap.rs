@@ -0,0 +1,572 @@
+//! A debug adapter, speaking the Debug Adapter Protocol, which runs programs on the built-in
+//! interpreter. `launch` takes the `program` to assemble, and optionally its `target` and
+//! `stopOnEntry`. Expressions can use registers, symbols, numbers, `+`, `-`, and `[ADDR]` for
+//! the byte at an address.
+
+use crate::debugger::{Debugger, Stop};
+use crate::json::Json;
+use crate::lsp::read_message;
+use crate::machine::MEMORY_SIZE;
+use crate::parser::parse_number;
+use crate::{assemble_path, FsFiles, Options, Target};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// The only thread, as the interpreter runs one program.
+const THREAD_ID: usize = 1;
+
+// References to each scope of variables.
+const REGISTERS_REF: usize = 1;
+const TIMERS_REF: usize = 2;
+const MEMORY_REF: usize = 3;
+
+/// Rows of 16 bytes of memory shown from `I`.
+const MEMORY_ROWS: u16 = 4;
+
+/// Serves requests read from `input`, writing responses and events to `output`, until the
+/// client disconnects or closes `input`.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut adapter = Adapter::default();
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        for reply in adapter.handle(&message) {
+            let text = reply.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+        }
+        output.flush()?;
+        if adapter.done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Adapter {
+    /// Sequence number of the last message sent.
+    seq: usize,
+    debugger: Option<Debugger>,
+    /// Lines sent by the client start at 0 rather than 1.
+    zero_based_lines: bool,
+    stop_on_entry: bool,
+    /// The addresses of the breakpoints set in each source file.
+    breakpoints: HashMap<PathBuf, BTreeSet<u16>>,
+    done: bool,
+}
+
+impl Adapter {
+    /// Handles a request, returning its response followed by any events.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        if message.get("type").as_str() != Some("request") {
+            return Vec::new();
+        }
+        let command = message.get("command").as_str().unwrap_or_default();
+        let arguments = message.get("arguments");
+        let mut events = Vec::new();
+        let result = self.request(command, arguments, &mut events);
+
+        let request_seq = message.get("seq").as_u64().unwrap_or(0) as usize;
+        let mut fields = vec![
+            ("type", Json::from("response")),
+            ("request_seq", Json::from(request_seq)),
+            ("command", Json::from(command)),
+            ("success", Json::from(result.is_ok())),
+        ];
+        match result {
+            Ok(body) => fields.push(("body", body)),
+            Err(message) => fields.push(("message", Json::from(message))),
+        }
+        let mut replies = vec![self.message(fields)];
+        for (event, body) in events {
+            let event = self.message(vec![
+                ("type", Json::from("event")),
+                ("event", Json::from(event)),
+                ("body", body),
+            ]);
+            replies.push(event);
+        }
+        replies
+    }
+
+    fn message(&mut self, mut fields: Vec<(&str, Json)>) -> Json {
+        self.seq += 1;
+        fields.push(("seq", Json::from(self.seq)));
+        Json::object(fields)
+    }
+
+    fn request(
+        &mut self,
+        command: &str,
+        arguments: &Json,
+        events: &mut Vec<(&'static str, Json)>,
+    ) -> Result<Json, String> {
+        match command {
+            "initialize" => {
+                self.zero_based_lines = arguments.get("linesStartAt1").as_bool() == Some(false);
+                Ok(capabilities())
+            }
+            "launch" => {
+                self.launch(arguments, events)?;
+                events.push(("initialized", Json::Object(BTreeMap::new())));
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => {
+                Ok(Json::object(vec![("breakpoints", Json::Array(Vec::new()))]))
+            }
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push(stopped("entry", None));
+                } else {
+                    let stop = self.debugger()?.resume();
+                    events.extend(stop_events(stop));
+                }
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object(vec![(
+                "threads",
+                Json::Array(vec![Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("CHIP-8")),
+                ])]),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Json::object(vec![(
+                "scopes",
+                Json::Array(vec![
+                    scope("Registers", REGISTERS_REF),
+                    scope("Timers", TIMERS_REF),
+                    scope("Memory", MEMORY_REF),
+                ]),
+            )])),
+            "variables" => {
+                let reference = arguments.get("variablesReference").as_u64().unwrap_or(0);
+                self.variables(reference as usize)
+            }
+            "evaluate" => {
+                let expression = arguments.get("expression").as_str().unwrap_or_default();
+                let debugger = self.debugger()?;
+                let value = evaluate(debugger, expression)?;
+                Ok(Json::object(vec![
+                    ("result", Json::from(describe(debugger, value))),
+                    ("variablesReference", Json::from(0usize)),
+                ]))
+            }
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => {
+                let debugger = self.debugger()?;
+                let stop = match command {
+                    "continue" => debugger.resume(),
+                    "next" => debugger.step_over(),
+                    "stepIn" => debugger.step(),
+                    "stepBack" => debugger.step_back(),
+                    "reverseContinue" => debugger.reverse_resume(),
+                    _ => debugger.step_out(),
+                };
+                events.extend(stop_events(stop));
+                Ok(Json::object(vec![(
+                    "allThreadsContinued",
+                    Json::from(true),
+                )]))
+            }
+            // Programs only run while a request is being handled, so they're always paused.
+            "pause" => {
+                events.push(stopped("pause", None));
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            other => Err(format!("unsupported request `{}`", other)),
+        }
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| String::from("no program has been launched"))
+    }
+
+    fn launch(
+        &mut self,
+        arguments: &Json,
+        events: &mut Vec<(&'static str, Json)>,
+    ) -> Result<(), String> {
+        let program = arguments
+            .get("program")
+            .as_str()
+            .ok_or("`launch` needs a `program`")?;
+        let target = match arguments.get("target").as_str() {
+            Some(target) => target.parse::<Target>()?,
+            None => Target::default(),
+        };
+        self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+
+        let opts = Options {
+            target,
+            ..Options::default()
+        };
+        let assembly = match assemble_path(Path::new(program), &opts) {
+            Ok(assembly) => assembly,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics.0 {
+                    events.push(output(&diagnostic.to_string()));
+                }
+                return Err(format!("`{}` failed to assemble", program));
+            }
+        };
+        for warning in &assembly.warnings {
+            events.push(output(&warning.to_string()));
+        }
+        let debugger = Debugger::new(assembly, target, &FsFiles).map_err(|e| e.to_string())?;
+        self.debugger = Some(debugger);
+        Ok(())
+    }
+
+    /// Replaces the breakpoints in a source file, moving each to the next line with code.
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .get("path")
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or("`setBreakpoints` needs a source path")?;
+        let offset = self.line_offset();
+        let debugger = self.debugger()?;
+        let file = file_index(debugger, &path);
+
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments.get("breakpoints").as_array() {
+            let line = breakpoint.get("line").as_u64().unwrap_or(0) as usize + offset;
+            let address = file.and_then(|file| debugger.address_of(file, line));
+            let found = address.and_then(|address| debugger.line_at(address));
+            let mut fields = vec![("verified", Json::from(found.is_some()))];
+            if let (Some(address), Some((_, line))) = (address, found) {
+                addresses.insert(address);
+                fields.push(("line", Json::from(line - offset)));
+                fields.push((
+                    "instructionReference",
+                    Json::from(format!("0x{:03X}", address)),
+                ));
+            } else {
+                fields.push(("message", Json::from("no code on or after this line")));
+            }
+            breakpoints.push(Json::object(fields));
+        }
+
+        let previous = self.breakpoints.insert(path, addresses).unwrap_or_default();
+        let wanted: BTreeSet<u16> = self.breakpoints.values().flatten().copied().collect();
+        let debugger = self.debugger()?;
+        for address in previous.difference(&wanted) {
+            debugger.clear_breakpoint(*address);
+        }
+        for &address in &wanted {
+            debugger.set_breakpoint(address);
+        }
+        Ok(Json::object(vec![(
+            "breakpoints",
+            Json::Array(breakpoints),
+        )]))
+    }
+
+    /// The frame being run, then the `CALL` each return address on the stack came from.
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let offset = self.line_offset();
+        let debugger = self.debugger()?;
+        let machine = &debugger.machine;
+        let mut addresses = vec![machine.pc];
+        addresses.extend(machine.stack.iter().rev().map(|&r| r.wrapping_sub(2)));
+
+        let frames: Vec<Json> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, &address)| {
+                let name = match debugger.assembly.label_at(address) {
+                    Some((label, _)) => String::from(label),
+                    None => format!("{:03X}", address),
+                };
+                let mut fields = vec![
+                    ("id", Json::from(id)),
+                    ("name", Json::from(name)),
+                    (
+                        "instructionPointerReference",
+                        Json::from(format!("0x{:03X}", address)),
+                    ),
+                    ("column", Json::from(1 - offset)),
+                ];
+                // Frames without source are given line 0, as the protocol asks.
+                match debugger.line_at(address) {
+                    Some((file, line)) => {
+                        fields.push(("source", source(&debugger.assembly.files[file])));
+                        fields.push(("line", Json::from(line - offset)));
+                    }
+                    None => fields.push(("line", Json::from(0usize))),
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Ok(Json::object(vec![
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::Array(frames)),
+        ]))
+    }
+
+    fn variables(&mut self, reference: usize) -> Result<Json, String> {
+        let debugger = self.debugger()?;
+        let machine = &debugger.machine;
+        let byte = |value: u8| format!("0x{:02X} ({})", value, value);
+        let variables: Vec<(String, String)> = match reference {
+            REGISTERS_REF => {
+                let mut registers: Vec<(String, String)> = machine
+                    .v
+                    .iter()
+                    .enumerate()
+                    .map(|(x, &value)| (format!("V{:X}", x), byte(value)))
+                    .collect();
+                registers.push((String::from("I"), address(debugger, machine.i)));
+                registers.push((String::from("PC"), address(debugger, machine.pc)));
+                registers.push((String::from("SP"), machine.stack.len().to_string()));
+                registers
+            }
+            TIMERS_REF => vec![
+                (String::from("DT"), byte(machine.delay_timer)),
+                (String::from("ST"), byte(machine.sound_timer)),
+            ],
+            MEMORY_REF => (0..MEMORY_ROWS)
+                .map(|row| {
+                    let start = machine.i.wrapping_add(row * 16);
+                    let bytes: Vec<String> = (0..16)
+                        .map(|offset| {
+                            let at = (usize::from(start) + offset) % MEMORY_SIZE;
+                            format!("{:02X}", machine.memory[at])
+                        })
+                        .collect();
+                    (format!("[I+{}]", row * 16), bytes.join(" "))
+                })
+                .collect(),
+            _ => return Err(format!("no variables with reference {}", reference)),
+        };
+        let variables = variables
+            .into_iter()
+            .map(|(name, value)| {
+                Json::object(vec![
+                    ("name", Json::from(name)),
+                    ("value", Json::from(value)),
+                    ("variablesReference", Json::from(0usize)),
+                ])
+            })
+            .collect();
+        Ok(Json::object(vec![("variables", Json::Array(variables))]))
+    }
+
+    fn line_offset(&self) -> usize {
+        self.zero_based_lines as usize
+    }
+}
+
+/// The events telling the client the program has stopped, or finished.
+fn stop_events(stop: Stop) -> Vec<(&'static str, Json)> {
+    match stop {
+        Stop::Stepped => vec![stopped("step", None)],
+        Stop::HistoryStart => vec![stopped("step", Some(stop.to_string()))],
+        Stop::Breakpoint(_) => vec![stopped("breakpoint", None)],
+        Stop::Watchpoint { .. } => vec![stopped("data breakpoint", Some(stop.to_string()))],
+        Stop::Halted => vec![
+            (
+                "exited",
+                Json::object(vec![("exitCode", Json::from(0usize))]),
+            ),
+            ("terminated", Json::Object(BTreeMap::new())),
+        ],
+        Stop::WaitingForKey | Stop::Limit => vec![stopped("pause", Some(stop.to_string()))],
+        Stop::Error(_) => vec![stopped("exception", Some(stop.to_string()))],
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("supportsConfigurationDoneRequest", Json::from(true)),
+        ("supportsEvaluateForHovers", Json::from(true)),
+        ("supportsTerminateRequest", Json::from(true)),
+        ("supportsStepBack", Json::from(true)),
+    ])
+}
+
+fn stopped(reason: &str, text: Option<String>) -> (&'static str, Json) {
+    (
+        "stopped",
+        Json::object(vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+            ("text", Json::from(text)),
+        ]),
+    )
+}
+
+fn output(text: &str) -> (&'static str, Json) {
+    (
+        "output",
+        Json::object(vec![
+            ("category", Json::from("stderr")),
+            ("output", Json::from(format!("{}\n", text))),
+        ]),
+    )
+}
+
+fn scope(name: &str, reference: usize) -> Json {
+    Json::object(vec![
+        ("name", Json::from(name)),
+        ("variablesReference", Json::from(reference)),
+        ("expensive", Json::from(false)),
+    ])
+}
+
+fn source(path: &Path) -> Json {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    Json::object(vec![
+        (
+            "name",
+            Json::from(
+                path.file_name()
+                    .map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+            ),
+        ),
+        ("path", Json::from(path.to_string_lossy().into_owned())),
+    ])
+}
+
+/// Finds which of the program's files `path` is, however either was written.
+fn file_index(debugger: &Debugger, path: &Path) -> Option<usize> {
+    let canonical = fs::canonicalize(path).ok();
+    debugger.assembly.files.iter().position(|file| {
+        file == path || (canonical.is_some() && fs::canonicalize(file).ok() == canonical)
+    })
+}
+
+fn address(debugger: &Debugger, value: u16) -> String {
+    match debugger.assembly.label_at(value) {
+        Some(_) => format!(
+            "0x{:03X} ({})",
+            value,
+            debugger.assembly.describe_address(value)
+        ),
+        None => format!("0x{:03X}", value),
+    }
+}
+
+fn describe(debugger: &Debugger, value: i64) -> String {
+    match u16::try_from(value) {
+        Ok(value) if debugger.assembly.label_at(value).is_some() => {
+            format!("{} ({})", address(debugger, value), value)
+        }
+        _ => format!("{} (0x{:X})", value, value),
+    }
+}
+
+/// Evaluates an expression, like `[I+2]` or `table+V0`.
+fn evaluate(debugger: &Debugger, expression: &str) -> Result<i64, String> {
+    let tokens = expression_tokens(expression)?;
+    let mut position = 0;
+    let value = sum(debugger, &tokens, &mut position)?;
+    match tokens.get(position) {
+        None => Ok(value),
+        Some(token) => Err(format!("unexpected `{}`", token)),
+    }
+}
+
+fn expression_tokens(expression: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-[]()".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else if c.is_alphanumeric() || c == '_' || c == '.' {
+            let mut word = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+            {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else {
+            return Err(format!("unexpected `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn sum(debugger: &Debugger, tokens: &[String], position: &mut usize) -> Result<i64, String> {
+    let mut value = term(debugger, tokens, position)?;
+    while let Some(operator) = tokens.get(*position).filter(|t| *t == "+" || *t == "-") {
+        *position += 1;
+        let rhs = term(debugger, tokens, position)?;
+        value = if operator == "+" {
+            value + rhs
+        } else {
+            value - rhs
+        };
+    }
+    Ok(value)
+}
+
+fn term(debugger: &Debugger, tokens: &[String], position: &mut usize) -> Result<i64, String> {
+    let token = tokens
+        .get(*position)
+        .ok_or_else(|| String::from("expression ends early"))?;
+    *position += 1;
+    let machine = &debugger.machine;
+    let close = |position: &mut usize, bracket: &str| {
+        if tokens.get(*position).map(String::as_str) == Some(bracket) {
+            *position += 1;
+            Ok(())
+        } else {
+            Err(format!("missing `{}`", bracket))
+        }
+    };
+    match token.as_str() {
+        "[" => {
+            let address = sum(debugger, tokens, position)?;
+            close(position, "]")?;
+            Ok(i64::from(
+                machine.memory[address.rem_euclid(MEMORY_SIZE as i64) as usize],
+            ))
+        }
+        "(" => {
+            let value = sum(debugger, tokens, position)?;
+            close(position, ")")?;
+            Ok(value)
+        }
+        "-" => Ok(-term(debugger, tokens, position)?),
+        word => {
+            let register = match word.to_ascii_uppercase().as_str() {
+                "I" => Some(i64::from(machine.i)),
+                "PC" => Some(i64::from(machine.pc)),
+                "SP" => Some(machine.stack.len() as i64),
+                "DT" => Some(i64::from(machine.delay_timer)),
+                "ST" => Some(i64::from(machine.sound_timer)),
+                upper => upper
+                    .strip_prefix('V')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .map(|x| i64::from(machine.v[usize::from(x)])),
+            };
+            register
+                .or_else(|| debugger.assembly.symbols.get(word).map(|&v| i64::from(v)))
+                .or_else(|| parse_number(word))
+                .ok_or_else(|| format!("undefined symbol `{}`", word))
+        }
+    }
+}
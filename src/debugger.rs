@@ -0,0 +1,503 @@
+//! Runs an assembled program under control: stepping, breakpoints and watchpoints, with
+//! addresses tied back to the source they were assembled from. Front ends like the `debug`
+//! command drive a [`Debugger`] and show its state.
+//!
+//! What each instruction changes is kept for a while, so the program can also be run
+//! backwards: a step at a time, to a breakpoint, to the last write to a register or byte of
+//! memory, or to the start of an earlier frame.
+
+use crate::cfg::{bit, read_sources, writes};
+use crate::instruction::{Instruction, Vx};
+use crate::machine::{Machine, MachineErr, DISPLAY_HEIGHT, MEMORY_SIZE};
+use crate::parser::parse_number;
+use crate::{Assembly, FileProvider, Target};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+/// How many instructions a run may take before stopping to let the user look around.
+pub const DEFAULT_RUN_LIMIT: u64 = 10_000_000;
+
+/// How many instructions can be undone by default.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+/// Why the program stopped running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// Finished a step, or reached the end of a step over or run to an address.
+    Stepped,
+    Breakpoint(u16),
+    /// A watched byte of memory changed.
+    Watchpoint {
+        address: u16,
+        old: u8,
+        new: u8,
+    },
+    /// Reached a jump to itself.
+    Halted,
+    WaitingForKey,
+    /// Ran for the run limit without stopping.
+    Limit,
+    /// Ran backwards as far as the history goes.
+    HistoryStart,
+    Error(MachineErr),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "stepped"),
+            Stop::Breakpoint(address) => write!(f, "breakpoint at {:03X}", address),
+            Stop::Watchpoint { address, old, new } => write!(
+                f,
+                "memory at {:03X} changed from {:02X} to {:02X}",
+                address, old, new
+            ),
+            Stop::Halted => write!(f, "halted"),
+            Stop::WaitingForKey => write!(f, "waiting for a key"),
+            Stop::Limit => write!(f, "still running, paused"),
+            Stop::HistoryStart => write!(f, "reached the start of the history"),
+            Stop::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Somewhere an instruction can write to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    Register(Vx),
+    I,
+    DelayTimer,
+    SoundTimer,
+    Memory(u16),
+}
+
+/// What running one instruction changed, so it can be undone.
+#[derive(Debug, Clone)]
+struct Delta {
+    instruction: Instruction,
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    cycles: u64,
+    rng: u32,
+    /// The stack, if the instruction changed it.
+    stack: Option<Vec<u16>>,
+    /// Each byte of memory the instruction wrote, with what it held before.
+    memory: Vec<(u16, u8)>,
+    /// The display, if the instruction drew to it.
+    display: Option<Box<[u64; DISPLAY_HEIGHT]>>,
+}
+
+impl Delta {
+    /// Whether the instruction wrote to `storage`, even if it wrote what was already there.
+    fn wrote(&self, storage: Storage, target: Target) -> bool {
+        use Instruction::*;
+
+        match storage {
+            Storage::Register(vx) => writes(self.instruction, target) & bit(vx) != 0,
+            Storage::I => match self.instruction {
+                LoadI(_) | AddI(_) | LoadFont(_) => true,
+                StoreRegisters(vx) | LoadRegisters(vx) => {
+                    target.load_store_increment(vx.index()) != 0
+                }
+                _ => false,
+            },
+            Storage::DelayTimer => matches!(self.instruction, SetDelay(_)),
+            Storage::SoundTimer => matches!(self.instruction, SetSound(_)),
+            Storage::Memory(address) => self.memory.iter().any(|&(a, _)| a == address),
+        }
+    }
+}
+
+/// A program loaded into a [`Machine`], with the assembly and source it came from.
+pub struct Debugger {
+    pub machine: Machine,
+    pub assembly: Assembly,
+    /// The text of each of [`Assembly::files`].
+    pub sources: Vec<String>,
+    /// How many instructions [`Debugger::resume`] runs before stopping with [`Stop::Limit`].
+    pub run_limit: u64,
+    breakpoints: BTreeSet<u16>,
+    /// Each watched address, with the value it had when last checked.
+    watchpoints: BTreeMap<u16, u8>,
+    /// How many instructions [`Debugger::step_back`] and the like can undo.
+    pub history_limit: usize,
+    history: VecDeque<Delta>,
+}
+
+impl Debugger {
+    /// Loads an assembled program, ready to run from its first byte, reading its source
+    /// through `files`.
+    pub fn new(
+        assembly: Assembly,
+        target: Target,
+        files: &dyn FileProvider,
+    ) -> Result<Self, MachineErr> {
+        let mut machine = Machine::new(target);
+        machine.load(assembly.base, &assembly.bytes)?;
+        machine.pc = assembly.base;
+        Ok(Debugger {
+            machine,
+            sources: read_sources(&assembly, files),
+            assembly,
+            run_limit: DEFAULT_RUN_LIMIT,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            history: VecDeque::new(),
+        })
+    }
+
+    /// Finds the address of a location, written as a label, a line of the main file, a
+    /// `file:line`, or an address like `0x208`.
+    pub fn resolve(&self, location: &str) -> Result<u16, String> {
+        if let Some(&value) = self.assembly.symbols.get(location) {
+            if usize::from(value) >= MEMORY_SIZE {
+                return Err(format!("`{}` is {:#X}, outside memory", location, value));
+            }
+            return Ok(value);
+        }
+        if location.starts_with("0x") || location.starts_with("0b") {
+            return parse_number(location)
+                .filter(|&address| (0..MEMORY_SIZE as i64).contains(&address))
+                .map(|address| address as u16)
+                .ok_or_else(|| format!("invalid address `{}`", location));
+        }
+        let (file, line) = match location.rfind(':') {
+            Some(colon) => (self.find_file(&location[..colon])?, &location[colon + 1..]),
+            None => (0, location),
+        };
+        let line = line
+            .parse()
+            .map_err(|_| format!("no label or line `{}`", location))?;
+        self.address_of(file, line)
+            .ok_or_else(|| format!("no code on or after line {}", line))
+    }
+
+    /// The first address assembled from `line` of a file, or from the nearest line after it
+    /// which assembled to something.
+    pub fn address_of(&self, file: usize, line: usize) -> Option<u16> {
+        self.assembly
+            .source_map
+            .iter()
+            .filter(|m| m.file == file && m.line >= line && m.len > 0)
+            .min_by_key(|m| (m.line, m.address))
+            .map(|m| m.address)
+    }
+
+    /// The file and line `address` was assembled from.
+    pub fn line_at(&self, address: u16) -> Option<(usize, usize)> {
+        self.assembly
+            .mapping_at(address)
+            .map(|mapping| (mapping.file, mapping.line))
+    }
+
+    fn find_file(&self, name: &str) -> Result<usize, String> {
+        self.assembly
+            .files
+            .iter()
+            .position(|path| path == Path::new(name) || path.ends_with(name))
+            .ok_or_else(|| format!("no file `{}` in the program", name))
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Sets a breakpoint at `address`, or clears it if there was one. Returns whether it's set.
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if self.breakpoints.remove(&address) {
+            false
+        } else {
+            self.breakpoints.insert(address)
+        }
+    }
+
+    pub fn set_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.watchpoints.keys().copied()
+    }
+
+    /// Watches the byte at `address`, or stops watching it. Returns whether it's watched.
+    pub fn toggle_watchpoint(&mut self, address: u16) -> bool {
+        if self.watchpoints.remove(&address).is_some() {
+            return false;
+        }
+        let value = self.machine.memory[usize::from(address) % MEMORY_SIZE];
+        self.watchpoints.insert(address, value);
+        true
+    }
+
+    /// Changes memory on behalf of the user, which doesn't trigger watchpoints.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.machine.memory[(usize::from(address) + offset) % MEMORY_SIZE] = byte;
+        }
+        self.refresh_watchpoints();
+    }
+
+    fn refresh_watchpoints(&mut self) {
+        for (&address, value) in self.watchpoints.iter_mut() {
+            *value = self.machine.memory[usize::from(address) % MEMORY_SIZE];
+        }
+    }
+
+    /// Runs one instruction.
+    pub fn step(&mut self) -> Stop {
+        self.execute().unwrap_or(Stop::Stepped)
+    }
+
+    /// Runs one instruction, or if it's a `CALL`, runs until the subroutine returns.
+    pub fn step_over(&mut self) -> Stop {
+        match self.machine.current_instruction() {
+            Ok(Instruction::Call(_)) => {
+                let depth = self.machine.stack.len();
+                let next = self.machine.pc.wrapping_add(2) & 0x0FFF;
+                match self.execute() {
+                    Some(stop) => stop,
+                    None => self.run_while(|m| !(m.pc == next && m.stack.len() <= depth)),
+                }
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self) -> Stop {
+        let depth = self.machine.stack.len();
+        match self.execute() {
+            Some(stop) => stop,
+            None => self.run_while(|m| m.stack.len() >= depth),
+        }
+    }
+
+    /// Runs until `address` is reached, or something else stops the program first.
+    pub fn run_to(&mut self, address: u16) -> Stop {
+        match self.execute() {
+            Some(stop) => stop,
+            None => self.run_while(|m| m.pc != address),
+        }
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, or the program can't go on.
+    pub fn resume(&mut self) -> Stop {
+        match self.execute() {
+            Some(stop) => stop,
+            None => self.run_while(|_| true),
+        }
+    }
+
+    /// Runs a frame's worth of instructions, stopping early like [`Debugger::resume`].
+    pub fn run_frame(&mut self) -> Stop {
+        let end = self.machine.cycles + self.machine.target.cycles_per_frame() as u64;
+        match self.execute() {
+            Some(stop) => stop,
+            None => self.run_while(|m| m.cycles < end),
+        }
+    }
+
+    /// The frame being run, counting from 0.
+    pub fn frame(&self) -> u64 {
+        self.machine.cycles / self.machine.target.cycles_per_frame() as u64
+    }
+
+    /// How many instructions can be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undoes the last instruction run.
+    pub fn step_back(&mut self) -> Stop {
+        match self.undo() {
+            Some(_) => Stop::Stepped,
+            None => Stop::HistoryStart,
+        }
+    }
+
+    /// Runs backwards until reaching a breakpoint, or undoing a write to a watched byte.
+    pub fn reverse_resume(&mut self) -> Stop {
+        loop {
+            // What the watched byte held after the write, which is what's in memory now.
+            let watched = self.history.back().and_then(|delta| {
+                delta
+                    .memory
+                    .iter()
+                    .find(|(address, _)| self.watchpoints.contains_key(address))
+                    .map(|&(address, old)| {
+                        let new = self.machine.memory[usize::from(address)];
+                        Stop::Watchpoint { address, old, new }
+                    })
+            });
+            if self.undo().is_none() {
+                return Stop::HistoryStart;
+            }
+            if let Some(stop) = watched {
+                return stop;
+            }
+            if self.breakpoints.contains(&self.machine.pc) {
+                return Stop::Breakpoint(self.machine.pc);
+            }
+        }
+    }
+
+    /// Runs backwards to just before the last instruction which wrote to `storage`.
+    pub fn reverse_to_write(&mut self, storage: Storage) -> Stop {
+        let target = self.machine.target;
+        loop {
+            match self.undo() {
+                Some(delta) if delta.wrote(storage, target) => return Stop::Stepped,
+                Some(_) => {}
+                None => return Stop::HistoryStart,
+            }
+        }
+    }
+
+    /// Runs backwards to the start of an earlier frame.
+    pub fn rewind_to_frame(&mut self, frame: u64) -> Stop {
+        let start = frame * self.machine.target.cycles_per_frame() as u64;
+        while self.machine.cycles > start {
+            if self.undo().is_none() {
+                return Stop::HistoryStart;
+            }
+        }
+        Stop::Stepped
+    }
+
+    /// Keeps running while `running` holds, stopping at breakpoints.
+    fn run_while<F>(&mut self, mut running: F) -> Stop
+    where
+        F: FnMut(&Machine) -> bool,
+    {
+        let mut steps = 0;
+        while running(&self.machine) {
+            if self.breakpoints.contains(&self.machine.pc) {
+                return Stop::Breakpoint(self.machine.pc);
+            }
+            if steps >= self.run_limit {
+                return Stop::Limit;
+            }
+            if let Some(stop) = self.execute() {
+                return stop;
+            }
+            steps += 1;
+        }
+        Stop::Stepped
+    }
+
+    /// Runs one instruction, ticking the timers at the end of each frame, and reports anything
+    /// which should stop the program.
+    fn execute(&mut self) -> Option<Stop> {
+        if self.machine.halted() {
+            return Some(Stop::Halted);
+        }
+        if self.machine.waiting_for_key() {
+            return Some(Stop::WaitingForKey);
+        }
+        let instruction = match self.machine.current_instruction() {
+            Ok(instruction) => instruction,
+            Err(err) => return Some(Stop::Error(err)),
+        };
+        let delta = self.record(instruction);
+        let result = self.machine.step();
+        let frame = self.machine.target.cycles_per_frame() as u64;
+        if result.is_ok() && self.machine.cycles.is_multiple_of(frame) {
+            self.machine.tick_timers();
+        }
+        // Even an instruction which fails may have changed something, so it's kept too.
+        if self.history_limit > 0 {
+            if self.history.len() >= self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(delta);
+        }
+        if let Err(err) = result {
+            return Some(Stop::Error(err));
+        }
+
+        // Every watched byte is brought up to date, but only the first change is reported.
+        let memory = &self.machine.memory;
+        let mut stop = None;
+        for (&address, old) in self.watchpoints.iter_mut() {
+            let new = memory[usize::from(address) % MEMORY_SIZE];
+            if new != *old && stop.is_none() {
+                stop = Some(Stop::Watchpoint {
+                    address,
+                    old: *old,
+                    new,
+                });
+            }
+            *old = new;
+        }
+        stop
+    }
+
+    /// Saves what `instruction` is about to change.
+    fn record(&self, instruction: Instruction) -> Delta {
+        use Instruction::*;
+
+        let machine = &self.machine;
+        let written = match instruction {
+            LoadBcd(_) => 3,
+            StoreRegisters(vx) => u16::from(vx.index()) + 1,
+            _ => 0,
+        };
+        let memory = (0..written)
+            .map(|offset| machine.i.wrapping_add(offset))
+            .filter(|&address| usize::from(address) < MEMORY_SIZE)
+            .map(|address| (address, machine.memory[usize::from(address)]))
+            .collect();
+        Delta {
+            instruction,
+            v: machine.v,
+            i: machine.i,
+            pc: machine.pc,
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+            cycles: machine.cycles,
+            rng: machine.rng,
+            stack: match instruction {
+                Call(_) | Ret => Some(machine.stack.clone()),
+                _ => None,
+            },
+            memory,
+            display: match instruction {
+                Cls | Draw(..) => Some(Box::new(machine.display)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Puts the machine back as it was before the last instruction in the history.
+    fn undo(&mut self) -> Option<Delta> {
+        let delta = self.history.pop_back()?;
+        let machine = &mut self.machine;
+        machine.v = delta.v;
+        machine.i = delta.i;
+        machine.pc = delta.pc;
+        machine.delay_timer = delta.delay_timer;
+        machine.sound_timer = delta.sound_timer;
+        machine.cycles = delta.cycles;
+        machine.rng = delta.rng;
+        if let Some(stack) = &delta.stack {
+            machine.stack = stack.clone();
+        }
+        for &(address, old) in &delta.memory {
+            machine.memory[usize::from(address)] = old;
+        }
+        if let Some(display) = &delta.display {
+            machine.display = **display;
+        }
+        self.refresh_watchpoints();
+        Some(delta)
+    }
+}
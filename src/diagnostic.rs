@@ -0,0 +1,137 @@
+use crate::ParseErr;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A file couldn't be read.
+    Io(io::ErrorKind),
+    Parse(ParseErr),
+    Warning(String),
+}
+
+/// An error or warning about a particular line of source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    /// The line the diagnostic is about, counting from 1, or 0 if it's about the whole file.
+    pub line: usize,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(file: PathBuf, line: usize, err: ParseErr) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            file,
+            line,
+            kind: DiagnosticKind::Parse(err),
+        }
+    }
+
+    pub(crate) fn warning(file: PathBuf, line: usize, msg: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            file,
+            line,
+            kind: DiagnosticKind::Warning(msg),
+        }
+    }
+
+    pub(crate) fn io(file: PathBuf, err: &io::Error) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            file,
+            line: 0,
+            kind: DiagnosticKind::Io(err.kind()),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// The message without the location.
+    pub fn message(&self) -> String {
+        match &self.kind {
+            DiagnosticKind::Io(kind) => io::Error::from(*kind).to_string(),
+            DiagnosticKind::Parse(err) => err.to_string(),
+            DiagnosticKind::Warning(msg) => msg.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.line == 0 {
+            write!(
+                f,
+                "{}: {}: {}",
+                self.file.display(),
+                severity,
+                self.message()
+            )
+        } else {
+            write!(
+                f,
+                "{}:{}: {}: {}",
+                self.file.display(),
+                self.line,
+                severity,
+                self.message()
+            )
+        }
+    }
+}
+
+/// Every error and warning from assembling a program, in the order they were found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter().filter(|d| d.is_error())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter().filter(|d| !d.is_error())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub(crate) fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, diagnostic) in self.0.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,70 @@
+use crate::instruction::{disassemble_instruction, Instruction};
+use crate::PROGRAM_START;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Disassembles a ROM into source the assembler accepts, producing the same bytes when
+/// reassembled. Jump, call, and `LD I` targets inside the ROM are given labels, and words which
+/// aren't instructions are emitted with `DW`.
+pub fn disassemble(bytes: &[u8]) -> String {
+    let end = PROGRAM_START as usize + bytes.len();
+    let words: Vec<(u16, Option<Instruction>)> = bytes
+        .chunks(2)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let addr = PROGRAM_START + idx as u16 * 2;
+            let instr = match chunk {
+                [high, low] => disassemble_instruction(u16::from(*high) << 8 | u16::from(*low)),
+                _ => None,
+            };
+            (addr, instr)
+        })
+        .collect();
+
+    let targets: BTreeSet<u16> = words
+        .iter()
+        .filter_map(|(_, instr)| match instr {
+            Some(Instruction::Jmp(addr))
+            | Some(Instruction::Call(addr))
+            | Some(Instruction::LoadI(addr))
+            | Some(Instruction::JmpV0(addr)) => Some(addr.0),
+            _ => None,
+        })
+        .filter(|&addr| addr >= PROGRAM_START && (addr as usize) < end && addr % 2 == 0)
+        .collect();
+    let label = |addr: u16| format!("L{:03X}", addr);
+
+    let mut out = String::new();
+    for (addr, instr) in words {
+        if targets.contains(&addr) {
+            writeln!(out, "{}:", label(addr)).unwrap();
+        }
+        let offset = (addr - PROGRAM_START) as usize;
+        let text = match instr {
+            Some(Instruction::Jmp(target)) if targets.contains(&target.0) => {
+                format!("JP {}", label(target.0))
+            }
+            Some(Instruction::Call(target)) if targets.contains(&target.0) => {
+                format!("CALL {}", label(target.0))
+            }
+            Some(Instruction::LoadI(target)) if targets.contains(&target.0) => {
+                format!("LD I, {}", label(target.0))
+            }
+            Some(Instruction::JmpV0(target)) if targets.contains(&target.0) => {
+                format!("JP V0, {}", label(target.0))
+            }
+            Some(instr) => instr.to_string(),
+            None if offset + 1 < bytes.len() => {
+                format!("DW 0x{:02X}{:02X}", bytes[offset], bytes[offset + 1])
+            }
+            None => format!("DB 0x{:02X}", bytes[offset]),
+        };
+        let raw: String = bytes[offset..(offset + 2).min(bytes.len())]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        writeln!(out, "    {:<20} ; {:03X}: {}", text, addr, raw).unwrap();
+    }
+
+    out
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Where the assembler reads source and included files from.
+pub trait FileProvider: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Returns a path which is the same for every way of naming the same file, used to detect
+    /// recursive includes.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        normalize(path)
+    }
+}
+
+impl fmt::Debug for dyn FileProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FileProvider")
+    }
+}
+
+/// Reads files from disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsFiles;
+
+impl FileProvider for FsFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| normalize(path))
+    }
+}
+
+/// Serves files from memory, for embedding the assembler where there's no filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFiles {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> Self {
+        MemoryFiles::default()
+    }
+
+    /// Adds a file, replacing any file already at `path`.
+    pub fn insert<P: AsRef<Path>, C: Into<Vec<u8>>>(&mut self, path: P, contents: C) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<Vec<u8>> {
+        self.files.remove(&normalize(path.as_ref()))
+    }
+}
+
+impl FileProvider for MemoryFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+}
+
+/// Removes `.` and resolves `..` components lexically, so `a/./b/../c` and `a/c` compare equal.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
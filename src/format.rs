@@ -0,0 +1,129 @@
+//! Reprints source in a canonical layout, keeping its comments and blank lines.
+
+use crate::parser::{parse_line, Operand, Statement};
+
+/// How mnemonics are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MnemonicCase {
+    Upper,
+    Lower,
+}
+
+/// How [`format_source`] lays out each line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatStyle {
+    pub case: MnemonicCase,
+    /// Spaces before each statement, and again for each structured block it's nested in.
+    pub indent: usize,
+    /// Written between operands, other than around the comparison in a condition.
+    pub separator: String,
+    /// The column comments after a statement start in, if the statement leaves room.
+    pub comment_column: usize,
+}
+
+impl Default for FormatStyle {
+    fn default() -> Self {
+        FormatStyle {
+            case: MnemonicCase::Upper,
+            indent: 4,
+            separator: String::from(", "),
+            comment_column: 32,
+        }
+    }
+}
+
+/// Reformats a source file. Labels start in the first column, statements are indented by how
+/// deeply they're nested in `.if`, `.loop` and `TEST` blocks, and comments after statements are
+/// lined up. Lines which don't parse are left as they are, so they can be fixed by hand.
+pub fn format_source(text: &str, style: &FormatStyle) -> String {
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    for source in text.lines() {
+        let line = match parse_line(source) {
+            Ok(line) => line,
+            Err(_) => {
+                out.push_str(source.trim_end());
+                out.push('\n');
+                continue;
+            }
+        };
+        let comment = source.find(';').map(|idx| source[idx..].trim_end());
+
+        let mnemonic = line.statement.as_ref().map(|s| s.mnemonic.as_str());
+        if matches!(
+            mnemonic,
+            Some(".ELSE" | ".ENDIF" | ".WHILE" | ".AGAIN" | "ENDTEST")
+        ) {
+            depth = depth.saturating_sub(1);
+        }
+        let column = style.indent * (depth + 1);
+
+        let mut code = String::new();
+        if let Some(label) = &line.label {
+            code.push_str(label);
+            code.push(':');
+        }
+        if let Some(statement) = &line.statement {
+            let pad = if code.len() < column {
+                column - code.len()
+            } else {
+                1
+            };
+            code.push_str(&" ".repeat(pad));
+            code.push_str(&statement_text(statement, style));
+        }
+
+        match comment {
+            Some(comment) if code.is_empty() => {
+                // Comments on their own line stay in the first column if they started there.
+                if !source.starts_with(';') {
+                    code.push_str(&" ".repeat(column));
+                }
+                code.push_str(comment);
+            }
+            Some(comment) => {
+                let pad = if code.len() < style.comment_column {
+                    style.comment_column - code.len()
+                } else {
+                    1
+                };
+                code.push_str(&" ".repeat(pad));
+                code.push_str(comment);
+            }
+            None => {}
+        }
+        out.push_str(&code);
+        out.push('\n');
+
+        if matches!(mnemonic, Some(".IF" | ".ELSE" | ".LOOP" | "TEST")) {
+            depth += 1;
+        }
+    }
+
+    // Finish with exactly one newline.
+    let end = out.trim_end().len();
+    out.truncate(end);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn statement_text(statement: &Statement, style: &FormatStyle) -> String {
+    let mut text = match style.case {
+        MnemonicCase::Upper => statement.mnemonic.clone(),
+        MnemonicCase::Lower => statement.mnemonic.to_ascii_lowercase(),
+    };
+    let mut previous: Option<&Operand> = None;
+    for operand in &statement.operands {
+        // Conditions are written `V0 == 5`, whatever separates other operands.
+        let separator = match (previous, operand) {
+            (None, _) | (Some(Operand::Compare(_)), _) | (_, Operand::Compare(_)) => " ",
+            _ => style.separator.as_str(),
+        };
+        text.push_str(separator);
+        text.push_str(&operand.to_string());
+        previous = Some(operand);
+    }
+    text
+}
@@ -0,0 +1,575 @@
+//! A stub speaking the GDB remote serial protocol, so debuggers which speak it can drive the
+//! built-in interpreter. Registers are described to the debugger with a target description,
+//! numbered V0 to VF, then I, PC, SP, DT and ST, and multi-byte registers are sent little
+//! endian. SP is the depth of the stack.
+//!
+//! Reverse stepping and continuing are supported, as far back as the debugger's history goes.
+//!
+//! Labels are given to the debugger through a [`symbol_file`], loaded with `symbol-file` or
+//! `add-symbol-file`.
+
+use crate::debugger::{Debugger, Stop};
+use crate::machine::{MachineErr, MEMORY_SIZE};
+use crate::Assembly;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+/// The largest packet the debugger may send.
+const PACKET_SIZE: usize = 0x4000;
+
+/// Register numbers past V0 to VF.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REGISTERS: usize = 21;
+
+// Signals reported when the program stops.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Serves one debugger session over `stream`, until the debugger detaches, kills the program
+/// or disconnects.
+pub fn serve<S: Read + Write>(debugger: &mut Debugger, stream: S) -> io::Result<()> {
+    let mut connection = Connection {
+        stream,
+        buffer: Vec::new(),
+        acks: true,
+    };
+    while let Some(packet) = connection.receive()? {
+        let reply = match handle(debugger, &mut connection, &packet) {
+            Some(reply) => reply,
+            None => {
+                connection.send("OK")?;
+                break;
+            }
+        };
+        connection.send(&reply)?;
+    }
+    Ok(())
+}
+
+/// Works out the reply to a packet, or `None` if the session is over.
+fn handle<S>(
+    debugger: &mut Debugger,
+    connection: &mut Connection<S>,
+    packet: &str,
+) -> Option<String> {
+    let (command, rest) = packet.split_at(packet.len().min(1));
+    let reply = match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => (0..REGISTERS)
+            .map(|n| hex(&read_register(debugger, n)))
+            .collect(),
+        "G" => {
+            let bytes = unhex(rest).unwrap_or_default();
+            let mut bytes = &bytes[..];
+            for n in 0..REGISTERS {
+                let size = register_size(n);
+                if bytes.len() < size {
+                    return Some(error());
+                }
+                write_register(debugger, n, &bytes[..size]);
+                bytes = &bytes[size..];
+            }
+            ok()
+        }
+        "p" => match usize::from_str_radix(rest, 16) {
+            Ok(n) if n < REGISTERS => hex(&read_register(debugger, n)),
+            _ => error(),
+        },
+        "P" => {
+            let parsed = rest.split_once('=').and_then(|(n, value)| {
+                let n = usize::from_str_radix(n, 16)
+                    .ok()
+                    .filter(|&n| n < REGISTERS)?;
+                let bytes = unhex(value).filter(|b| b.len() == register_size(n))?;
+                Some((n, bytes))
+            });
+            match parsed {
+                Some((n, bytes)) => {
+                    write_register(debugger, n, &bytes);
+                    ok()
+                }
+                None => error(),
+            }
+        }
+        "m" => match address_and_length(rest) {
+            Some((address, length)) => {
+                let bytes: Vec<u8> = (0..length)
+                    .map(|offset| debugger.machine.memory[(address + offset) % MEMORY_SIZE])
+                    .collect();
+                hex(&bytes)
+            }
+            None => error(),
+        },
+        "M" => {
+            let parsed = rest.split_once(':').and_then(|(range, data)| {
+                let (address, length) = address_and_length(range)?;
+                if address >= MEMORY_SIZE {
+                    return None;
+                }
+                unhex(data)
+                    .filter(|bytes| bytes.len() == length)
+                    .map(|bytes| (address, bytes))
+            });
+            match parsed {
+                Some((address, bytes)) => {
+                    debugger.write_memory(address as u16, &bytes);
+                    ok()
+                }
+                None => error(),
+            }
+        }
+        "Z" | "z" => breakpoint(debugger, command == "Z", rest),
+        "s" | "c" => {
+            if let Some(address) = resume_address(rest) {
+                debugger.machine.pc = address;
+            }
+            let stop = if command == "s" {
+                debugger.step()
+            } else {
+                debugger.resume()
+            };
+            stop_reply(&stop)
+        }
+        "b" => {
+            let stop = match rest {
+                "s" => debugger.step_back(),
+                "c" => debugger.reverse_resume(),
+                _ => return Some(String::new()),
+            };
+            stop_reply(&stop)
+        }
+        "H" | "T" => ok(),
+        "D" | "k" => return None,
+        "q" | "Q" | "v" => query(connection, packet),
+        _ => String::new(),
+    };
+    Some(reply)
+}
+
+/// Answers the general queries and settings the debugger needs, and the empty reply for
+/// those which aren't supported.
+fn query<S>(connection: &mut Connection<S>, packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return format!(
+            "PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+;ReverseStep+;ReverseContinue+",
+            PACKET_SIZE
+        );
+    }
+    if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return match address_and_length(request) {
+            Some((offset, length)) if offset <= TARGET_XML.len() => {
+                let end = (offset + length).min(TARGET_XML.len());
+                let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                format!("{}{}", more, &TARGET_XML[offset..end])
+            }
+            _ => error(),
+        };
+    }
+    match packet {
+        "QStartNoAckMode" => {
+            connection.acks = false;
+            ok()
+        }
+        "qAttached" => String::from("1"),
+        "qC" => String::from("QC1"),
+        "qfThreadInfo" => String::from("m1"),
+        "qsThreadInfo" => String::from("l"),
+        "qSymbol::" => ok(),
+        _ => String::new(),
+    }
+}
+
+/// Sets or clears a breakpoint or write watchpoint, from the body of a `Z` or `z` packet.
+fn breakpoint(debugger: &mut Debugger, set: bool, request: &str) -> String {
+    let mut fields = request.splitn(3, ',');
+    let kind = fields.next();
+    let address = fields
+        .next()
+        .and_then(|a| usize::from_str_radix(a, 16).ok());
+    let length = fields
+        .next()
+        .and_then(|l| usize::from_str_radix(l, 16).ok());
+    let (address, length) = match (address, length) {
+        (Some(address), Some(length)) if address < MEMORY_SIZE => (address as u16, length),
+        _ => return error(),
+    };
+    match kind {
+        // Software breakpoints are kept by the debugger, rather than written into memory.
+        Some("0") | Some("1") => {
+            if set {
+                debugger.set_breakpoint(address);
+            } else {
+                debugger.clear_breakpoint(address);
+            }
+            ok()
+        }
+        Some("2") => {
+            let watched: Vec<u16> = debugger.watchpoints().collect();
+            for offset in 0..length.max(1) as u16 {
+                let address = (address + offset) % MEMORY_SIZE as u16;
+                if watched.contains(&address) != set {
+                    debugger.toggle_watchpoint(address);
+                }
+            }
+            ok()
+        }
+        _ => String::new(),
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Stepped | Stop::Halted | Stop::WaitingForKey => format!("S{:02x}", SIGTRAP),
+        Stop::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+        Stop::Watchpoint { address, .. } => format!("T{:02x}watch:{:x};", SIGTRAP, address),
+        Stop::Limit => format!("S{:02x}", SIGINT),
+        Stop::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+        Stop::Error(MachineErr::InvalidOpcode { .. }) => format!("S{:02x}", SIGILL),
+        Stop::Error(_) => format!("S{:02x}", SIGSEGV),
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(debugger: &Debugger, n: usize) -> Vec<u8> {
+    let machine = &debugger.machine;
+    match n {
+        0..=15 => vec![machine.v[n]],
+        REG_I => machine.i.to_le_bytes().to_vec(),
+        REG_PC => machine.pc.to_le_bytes().to_vec(),
+        REG_SP => vec![machine.stack.len() as u8],
+        REG_DT => vec![machine.delay_timer],
+        REG_ST => vec![machine.sound_timer],
+        _ => unreachable!("register {} doesn't exist", n),
+    }
+}
+
+fn write_register(debugger: &mut Debugger, n: usize, bytes: &[u8]) {
+    let machine = &mut debugger.machine;
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]) & 0x0FFF;
+    match n {
+        0..=15 => machine.v[n] = bytes[0],
+        REG_I => machine.i = word(),
+        REG_PC => machine.pc = word(),
+        // Growing the stack pushes return addresses of zero.
+        REG_SP => {
+            let depth = usize::from(bytes[0]).min(machine.target.stack_size());
+            machine.stack.resize(depth, 0);
+        }
+        REG_DT => machine.delay_timer = bytes[0],
+        REG_ST => machine.sound_timer = bytes[0],
+        _ => unreachable!("register {} doesn't exist", n),
+    }
+}
+
+/// Reads the address a `c` or `s` packet asks to resume from, if it gives one.
+fn resume_address(request: &str) -> Option<u16> {
+    u16::from_str_radix(request, 16)
+        .ok()
+        .filter(|&address| usize::from(address) < MEMORY_SIZE)
+}
+
+/// Reads an `ADDR,LENGTH` pair.
+fn address_and_length(request: &str) -> Option<(usize, usize)> {
+    let (address, length) = request.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    Some((address, length.min(PACKET_SIZE / 2)))
+}
+
+fn ok() -> String {
+    String::from("OK")
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        write!(text, "{:02x}", byte).unwrap();
+        text
+    })
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+/// Reads and writes packets, framed as `$data#checksum`.
+struct Connection<S> {
+    stream: S,
+    buffer: Vec<u8>,
+    /// Whether packets are still acknowledged with `+`, as they are until the debugger asks
+    /// to stop.
+    acks: bool,
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Waits for the next packet, returning `None` once the debugger disconnects.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let start = match self.buffer.iter().position(|&b| b == b'$') {
+                Some(start) => start,
+                None => {
+                    // Acknowledgements, and interrupts, which can't arrive while the program
+                    // runs.
+                    self.buffer.clear();
+                    if !self.fill()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+            let end = match self.buffer[start..].iter().position(|&b| b == b'#') {
+                Some(end) if start + end + 2 < self.buffer.len() => start + end,
+                _ => {
+                    if !self.fill()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+
+            let body = self.buffer[start + 1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3])
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            self.buffer.drain(..end + 3);
+            if checksum != Some(sum(&body)) {
+                if self.acks {
+                    self.stream.write_all(b"-")?;
+                }
+                continue;
+            }
+            if self.acks {
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&unescape(&body)).into_owned()));
+        }
+    }
+
+    /// Reads more from the stream, returning `false` at the end of it.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 1024];
+        let read = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let body = escape(reply.as_bytes());
+        let mut packet = Vec::with_capacity(body.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&body);
+        packet.extend_from_slice(format!("#{:02x}", sum(&body)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Escapes the bytes which would end or confuse a packet, as `}` followed by the byte XORed
+/// with 0x20.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            escaped.extend_from_slice(&[b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+    escaped
+}
+
+fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|b| b ^ 0x20)),
+            byte => unescaped.push(byte),
+        }
+    }
+    unescaped
+}
+
+// ELF constants used by `symbol_file`.
+const ELF_HEADER_SIZE: usize = 52;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+const SHN_ABS: u16 = 0xFFF1;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+
+/// Writes a 32 bit ELF file holding the program as `.text` and every symbol in its symbol
+/// table, for the debugger to load. Labels inside the program are placed in `.text`, and
+/// everything else is an absolute value.
+pub fn symbol_file(assembly: &Assembly) -> Vec<u8> {
+    let end = usize::from(assembly.base) + assembly.bytes.len();
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYMBOL_SIZE];
+    for (name, &value) in &assembly.symbols {
+        let section = if value >= assembly.base && usize::from(value) < end {
+            1
+        } else {
+            SHN_ABS
+        };
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+        symtab.extend_from_slice(&u32::from(value).to_le_bytes());
+        symtab.extend_from_slice(&0u32.to_le_bytes());
+        symtab.push(STB_GLOBAL << 4 | STT_NOTYPE);
+        symtab.push(0);
+        symtab.extend_from_slice(&section.to_le_bytes());
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+    let text_offset = ELF_HEADER_SIZE;
+    let symtab_offset = text_offset + assembly.bytes.len();
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let headers_offset = (shstrtab_offset + shstrtab.len() + 3) & !3;
+
+    let mut elf = Vec::with_capacity(headers_offset + 5 * SECTION_HEADER_SIZE);
+    elf.extend_from_slice(b"\x7fELF");
+    // 32 bit, little endian, version 1.
+    elf.extend_from_slice(&[1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let half = |elf: &mut Vec<u8>, value: u16| elf.extend_from_slice(&value.to_le_bytes());
+    let word = |elf: &mut Vec<u8>, value: u32| elf.extend_from_slice(&value.to_le_bytes());
+    // An executable for no particular machine, entered at the start of the program.
+    half(&mut elf, 2);
+    half(&mut elf, 0);
+    word(&mut elf, 1);
+    word(&mut elf, u32::from(assembly.base));
+    word(&mut elf, 0);
+    word(&mut elf, headers_offset as u32);
+    word(&mut elf, 0);
+    half(&mut elf, ELF_HEADER_SIZE as u16);
+    half(&mut elf, 0);
+    half(&mut elf, 0);
+    half(&mut elf, SECTION_HEADER_SIZE as u16);
+    half(&mut elf, 5);
+    half(&mut elf, 4);
+
+    elf.extend_from_slice(&assembly.bytes);
+    elf.extend_from_slice(&symtab);
+    elf.extend_from_slice(&strtab);
+    elf.extend_from_slice(shstrtab);
+    elf.resize(headers_offset, 0);
+
+    // Name, type, flags, address, offset, size, link, info, alignment and entry size.
+    let sections: [[u32; 10]; 5] = [
+        [0; 10],
+        [
+            1,
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            u32::from(assembly.base),
+            text_offset as u32,
+            assembly.bytes.len() as u32,
+            0,
+            0,
+            2,
+            0,
+        ],
+        [
+            7,
+            SHT_SYMTAB,
+            0,
+            0,
+            symtab_offset as u32,
+            symtab.len() as u32,
+            3,
+            1,
+            4,
+            SYMBOL_SIZE as u32,
+        ],
+        [
+            15,
+            SHT_STRTAB,
+            0,
+            0,
+            strtab_offset as u32,
+            strtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ],
+        [
+            23,
+            SHT_STRTAB,
+            0,
+            0,
+            shstrtab_offset as u32,
+            shstrtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ],
+    ];
+    for section in &sections {
+        for &field in section {
+            word(&mut elf, field);
+        }
+    }
+    elf
+}
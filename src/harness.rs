@@ -0,0 +1,331 @@
+//! Runs assembled programs from Rust tests, referring to their labels by name:
+//!
+//! ```
+//! use chip8_assembler::harness::Harness;
+//! use chip8_assembler::V1;
+//!
+//! let mut harness = Harness::assemble(
+//!     "
+//!     start:
+//!         LD V0, 0
+//!         LD V1, 0
+//!         LD I, box
+//!         DRW V0, V1, 2
+//!     done:
+//!         JP done
+//!     box: DB 0x90, 0x60
+//!     ",
+//! );
+//! harness.run_to("done");
+//! harness.assert_register(V1, 0);
+//! harness.assert_memory("box", &[0x90, 0x60]);
+//! harness.assert_display(
+//!     "
+//!     #..#
+//!     .##.
+//!     ",
+//! );
+//! ```
+//!
+//! Problems are reported by panicking with a description, as `assert!` does, so a failing
+//! check fails the test it's in.
+
+use crate::instruction::Vx;
+use crate::machine::{Machine, DISPLAY_HEIGHT, DISPLAY_WIDTH, MEMORY_SIZE};
+use crate::{assemble_str, Assembly, Options, Target};
+use std::fmt::Write;
+use std::ops::{Deref, DerefMut};
+
+/// How many instructions [`Harness::run_until`] runs by default before giving up.
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+
+/// A [`Machine`] running an assembled program. Dereferences to the machine, so its registers,
+/// memory and display can be read and changed directly.
+#[derive(Clone)]
+pub struct Harness {
+    pub machine: Machine,
+    pub assembly: Assembly,
+    /// How many instructions [`Harness::run_until`] may run before the program is taken to be
+    /// stuck.
+    pub step_limit: u64,
+}
+
+impl Harness {
+    /// Loads an assembled program, ready to run from its first byte.
+    pub fn new(assembly: Assembly, target: Target) -> Self {
+        let mut machine = Machine::new(target);
+        if let Err(err) = machine.load(assembly.base, &assembly.bytes) {
+            panic!("couldn't load the program: {}", err);
+        }
+        machine.pc = assembly.base;
+        Harness {
+            machine,
+            assembly,
+            step_limit: DEFAULT_STEP_LIMIT,
+        }
+    }
+
+    /// Assembles `source` with the default options and loads it.
+    pub fn assemble(source: &str) -> Self {
+        Harness::assemble_with(source, &Options::default())
+    }
+
+    pub fn assemble_with(source: &str, opts: &Options) -> Self {
+        match assemble_str(source, opts) {
+            Ok(assembly) => Harness::new(assembly, opts.target),
+            Err(diagnostics) => panic!("couldn't assemble the program:\n{}", diagnostics),
+        }
+    }
+
+    /// The value of a label or `DEFINE`d symbol.
+    pub fn symbol(&self, name: &str) -> u16 {
+        match self.assembly.symbols.get(name) {
+            Some(&value) => value,
+            None => panic!("no symbol named `{}`", name),
+        }
+    }
+
+    /// Runs instructions until `done` holds, checking it before each one. Timers tick once per
+    /// frame's worth of instructions, as in [`Machine::run_frame`].
+    pub fn run_until<F>(&mut self, mut done: F)
+    where
+        F: FnMut(&Machine) -> bool,
+    {
+        let frame = self.machine.target.cycles_per_frame() as u64;
+        let mut steps = 0;
+        while !done(&self.machine) {
+            let at = self.describe_pc();
+            if steps >= self.step_limit {
+                panic!("still running at {} after {} steps", at, steps);
+            } else if self.machine.halted() {
+                panic!("halted at {}", at);
+            } else if self.machine.waiting_for_key() {
+                panic!("waiting for a key at {}", at);
+            }
+            if let Err(err) = self.machine.step() {
+                panic!("{} ({})", err, at);
+            }
+            steps += 1;
+            if self.machine.cycles.is_multiple_of(frame) {
+                self.machine.tick_timers();
+            }
+        }
+    }
+
+    /// Runs until the program reaches `label`.
+    pub fn run_to(&mut self, label: &str) {
+        let address = self.symbol(label);
+        self.run_until(|machine| machine.pc == address);
+    }
+
+    /// Runs `frames` frames, as [`Machine::run_frame`] does.
+    pub fn run_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            if let Err(err) = self.machine.run_frame() {
+                panic!("{} ({})", err, self.describe_pc());
+            }
+        }
+    }
+
+    /// Holds down a key, from 0 to F, until it's released.
+    pub fn press(&mut self, key: u8) {
+        self.machine.keys[check_key(key)] = true;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.machine.keys[check_key(key)] = false;
+    }
+
+    /// The `len` bytes of memory starting at a label.
+    pub fn memory_at(&self, label: &str, len: usize) -> &[u8] {
+        let start = usize::from(self.symbol(label));
+        assert!(
+            start + len <= MEMORY_SIZE,
+            "{} bytes at `{}` ({:#05X}) run past the end of memory",
+            len,
+            label,
+            start
+        );
+        &self.machine.memory[start..start + len]
+    }
+
+    pub fn assert_register(&self, register: Vx, expected: u8) {
+        let actual = self.machine.v[usize::from(register.index())];
+        assert!(
+            actual == expected,
+            "expected {} to be {} (0x{:02X}), but it's {} (0x{:02X}) at {}",
+            register,
+            expected,
+            expected,
+            actual,
+            actual,
+            self.describe_pc()
+        );
+    }
+
+    pub fn assert_i(&self, expected: u16) {
+        assert!(
+            self.machine.i == expected,
+            "expected I to be {:03X}, but it's {} at {}",
+            expected,
+            self.assembly.describe_address(self.machine.i),
+            self.describe_pc()
+        );
+    }
+
+    /// Checks the bytes of memory starting at a label.
+    pub fn assert_memory(&self, label: &str, expected: &[u8]) {
+        let actual = self.memory_at(label, expected.len());
+        if actual == expected {
+            return;
+        }
+        let mut message = format!(
+            "memory at `{}` doesn't match at {}:",
+            label,
+            self.describe_pc()
+        );
+        for (offset, (want, got)) in expected.iter().zip(actual.iter()).enumerate() {
+            if want != got {
+                write!(
+                    message,
+                    "\n  {}+{}: expected {:02X}, found {:02X}",
+                    label, offset, want, got
+                )
+                .unwrap();
+            }
+        }
+        panic!("{}", message);
+    }
+
+    /// Checks the display against a picture of it, with `#` for each pixel which is on and `.`
+    /// for each which is off, one row per line. Blank lines around the picture and indentation
+    /// are ignored, and pixels past the end of the rows given are expected to be off.
+    pub fn assert_display(&self, expected: &str) {
+        let expected = parse_picture(expected);
+        let actual = &self.machine.display;
+        let mut rows = Vec::new();
+        for (y, row) in actual.iter().enumerate() {
+            let want = expected.get(y).map_or(0, |&row| row);
+            if *row != want {
+                rows.push((y, want, *row));
+            }
+        }
+        if rows.is_empty() {
+            return;
+        }
+
+        let mut message = format!(
+            "display doesn't match at {} (- expected, + actual):",
+            self.describe_pc()
+        );
+        let width = picture_width(expected.iter().chain(actual.iter()));
+        for (y, want, got) in rows {
+            let markers: String = (0..width)
+                .map(|x| if bit(want ^ got, x) { '^' } else { ' ' })
+                .collect();
+            write!(
+                message,
+                "\n  row {:2} - {}\n         + {}\n           {}",
+                y,
+                render_row(want, width),
+                render_row(got, width),
+                markers.trim_end()
+            )
+            .unwrap();
+        }
+        write!(message, "\n\nactual display:\n{}", self.display_text()).unwrap();
+        panic!("{}", message);
+    }
+
+    /// Draws the display in the form [`Harness::assert_display`] takes, leaving off the
+    /// blank rows at the bottom and the blank columns on the right.
+    pub fn display_text(&self) -> String {
+        let rows = &self.machine.display;
+        let used = rows.iter().rposition(|&row| row != 0).map_or(0, |y| y + 1);
+        let width = picture_width(rows.iter());
+        let mut text = String::new();
+        for &row in &rows[..used] {
+            text.push_str(&render_row(row, width));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn describe_pc(&self) -> String {
+        self.assembly.describe_address(self.machine.pc)
+    }
+}
+
+impl Deref for Harness {
+    type Target = Machine;
+
+    fn deref(&self) -> &Machine {
+        &self.machine
+    }
+}
+
+impl DerefMut for Harness {
+    fn deref_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+}
+
+fn bit(row: u64, x: usize) -> bool {
+    row & (1 << (DISPLAY_WIDTH - 1 - x)) != 0
+}
+
+/// How many columns it takes to show every pixel which is on in `rows`.
+fn picture_width<'a, I: Iterator<Item = &'a u64>>(rows: I) -> usize {
+    let used = rows.fold(0, |all, row| all | row);
+    DISPLAY_WIDTH - used.trailing_zeros().min(DISPLAY_WIDTH as u32) as usize
+}
+
+fn render_row(row: u64, width: usize) -> String {
+    (0..width)
+        .map(|x| if bit(row, x) { '#' } else { '.' })
+        .collect()
+}
+
+/// Reads a picture of the display into rows, laid out as [`Machine::display`] is.
+fn parse_picture(picture: &str) -> Vec<u64> {
+    let lines: Vec<&str> = picture.lines().map(str::trim).collect();
+    let first = lines.iter().position(|l| !l.is_empty()).unwrap_or(0);
+    let last = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |l| l + 1);
+    let lines = &lines[first..last.max(first)];
+    assert!(
+        lines.len() <= DISPLAY_HEIGHT,
+        "expected display has {} rows, but the display only has {}",
+        lines.len(),
+        DISPLAY_HEIGHT
+    );
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(y, line)| {
+            assert!(
+                line.chars().count() <= DISPLAY_WIDTH,
+                "row {} of the expected display is wider than {} pixels",
+                y,
+                DISPLAY_WIDTH
+            );
+            line.chars().enumerate().fold(0, |row, (x, c)| match c {
+                '#' => row | 1 << (DISPLAY_WIDTH - 1 - x),
+                '.' => row,
+                other => panic!(
+                    "row {} of the expected display has `{}`, rather than `#` or `.`",
+                    y, other
+                ),
+            })
+        })
+        .collect()
+}
+
+/// The index of a key, panicking if there's no such key.
+fn check_key(key: u8) -> usize {
+    assert!(key <= 0xF, "there's no key {:X}; keys are 0 to F", key);
+    usize::from(key)
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+/// One of the sixteen general purpose registers, `V0` through `VF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Vx(pub(crate) u8);
+
+pub const V0: Vx = Vx(0x0);
+pub const V1: Vx = Vx(0x1);
+pub const V2: Vx = Vx(0x2);
+pub const V3: Vx = Vx(0x3);
+pub const V4: Vx = Vx(0x4);
+pub const V5: Vx = Vx(0x5);
+pub const V6: Vx = Vx(0x6);
+pub const V7: Vx = Vx(0x7);
+pub const V8: Vx = Vx(0x8);
+pub const V9: Vx = Vx(0x9);
+pub const VA: Vx = Vx(0xA);
+pub const VB: Vx = Vx(0xB);
+pub const VC: Vx = Vx(0xC);
+pub const VD: Vx = Vx(0xD);
+pub const VE: Vx = Vx(0xE);
+pub const VF: Vx = Vx(0xF);
+
+impl Vx {
+    /// Returns `None` if `index` isn't below 16.
+    pub fn new(index: u8) -> Option<Self> {
+        if index < 16 {
+            Some(Vx(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
+
+/// A 12-bit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Addr(pub(crate) u16);
+
+impl Addr {
+    /// Returns `None` if `addr` doesn't fit in 12 bits.
+    pub fn new(addr: u16) -> Option<Self> {
+        if addr <= 0x0FFF {
+            Some(Addr(addr))
+        } else {
+            None
+        }
+    }
+
+    pub fn value(self) -> u16 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Sys(Addr),
+    Jmp(Addr),
+    Call(Addr),
+    SkipEq(Vx, u8),
+    SkipNotEq(Vx, u8),
+    SkipEqVx(Vx, Vx),
+    Load(Vx, u8),
+    Add(Vx, u8),
+    LoadVx(Vx, Vx),
+    Or(Vx, Vx),
+    And(Vx, Vx),
+    XOr(Vx, Vx),
+    AddVx(Vx, Vx),
+    SubVx(Vx, Vx),
+    ShiftRight(Vx, Vx),
+    SubN(Vx, Vx),
+    ShiftLeft(Vx, Vx),
+    SkipNotEqVx(Vx, Vx),
+    LoadI(Addr),
+    JmpV0(Addr),
+    Rand(Vx, u8),
+    Draw(Vx, Vx, u8),
+    SkipKeyPressed(Vx),
+    SkipKeyNotPressed(Vx),
+    LoadDelay(Vx),
+    LoadKey(Vx),
+    SetDelay(Vx),
+    SetSound(Vx),
+    AddI(Vx),
+    LoadFont(Vx),
+    LoadBcd(Vx),
+    StoreRegisters(Vx),
+    LoadRegisters(Vx),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AssembledInstruction(pub(crate) u8, pub(crate) u8);
+
+pub(crate) fn assemble_instruction(instr: Instruction) -> AssembledInstruction {
+    fn construct_byte(high_nibble: u8, low_nibble: u8) -> u8 {
+        ((high_nibble & 0x0F) << 4) | (low_nibble & 0x0F)
+    }
+
+    match instr {
+        Instruction::Cls => AssembledInstruction(0x00, 0xe0),
+        Instruction::Ret => AssembledInstruction(0x00, 0xee),
+        Instruction::Sys(addr) => {
+            AssembledInstruction(construct_byte(0x00, (addr.0 >> 8) as u8), addr.0 as u8)
+        }
+        Instruction::Jmp(addr) => {
+            AssembledInstruction(construct_byte(0x01, (addr.0 >> 8) as u8), addr.0 as u8)
+        }
+        Instruction::Call(addr) => {
+            AssembledInstruction(construct_byte(0x02, (addr.0 >> 8) as u8), addr.0 as u8)
+        }
+        Instruction::SkipEq(vx, constant) => {
+            AssembledInstruction(construct_byte(0x03, vx.0), constant)
+        }
+        Instruction::SkipNotEq(vx, constant) => {
+            AssembledInstruction(construct_byte(0x04, vx.0), constant)
+        }
+        Instruction::SkipEqVx(vx, vy) => {
+            AssembledInstruction(construct_byte(0x05, vx.0), construct_byte(vy.0, 0x00))
+        }
+        Instruction::Load(vx, constant) => {
+            AssembledInstruction(construct_byte(0x06, vx.0), constant)
+        }
+        Instruction::Add(vx, constant) => {
+            AssembledInstruction(construct_byte(0x07, vx.0), constant)
+        }
+        Instruction::LoadVx(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x00))
+        }
+        Instruction::Or(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x01))
+        }
+        Instruction::And(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x02))
+        }
+        Instruction::XOr(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x03))
+        }
+        Instruction::AddVx(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x04))
+        }
+        Instruction::SubVx(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x05))
+        }
+        Instruction::ShiftRight(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x06))
+        }
+        Instruction::SubN(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x07))
+        }
+        Instruction::ShiftLeft(vx, vy) => {
+            AssembledInstruction(construct_byte(0x08, vx.0), construct_byte(vy.0, 0x0E))
+        }
+        Instruction::SkipNotEqVx(vx, vy) => {
+            AssembledInstruction(construct_byte(0x09, vx.0), construct_byte(vy.0, 0x00))
+        }
+        Instruction::LoadI(addr) => {
+            AssembledInstruction(construct_byte(0x0A, (addr.0 >> 8) as u8), addr.0 as u8)
+        }
+        Instruction::JmpV0(addr) => {
+            AssembledInstruction(construct_byte(0x0B, (addr.0 >> 8) as u8), addr.0 as u8)
+        }
+        Instruction::Rand(vx, constant) => {
+            AssembledInstruction(construct_byte(0x0C, vx.0), constant)
+        }
+        Instruction::Draw(vx, vy, constant) => {
+            AssembledInstruction(construct_byte(0x0D, vx.0), construct_byte(vy.0, constant))
+        }
+        Instruction::SkipKeyPressed(vx) => AssembledInstruction(construct_byte(0x0E, vx.0), 0x9E),
+        Instruction::SkipKeyNotPressed(vx) => {
+            AssembledInstruction(construct_byte(0x0E, vx.0), 0xA1)
+        }
+        Instruction::LoadDelay(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x07),
+        Instruction::LoadKey(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x0A),
+        Instruction::SetDelay(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x15),
+        Instruction::SetSound(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x18),
+        Instruction::AddI(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x1E),
+        Instruction::LoadFont(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x29),
+        Instruction::LoadBcd(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x33),
+        Instruction::StoreRegisters(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x55),
+        Instruction::LoadRegisters(vx) => AssembledInstruction(construct_byte(0x0F, vx.0), 0x65),
+    }
+}
+
+impl Instruction {
+    /// Encodes the instruction as it's stored in memory.
+    pub fn to_bytes(self) -> [u8; 2] {
+        let assembled = assemble_instruction(self);
+        [assembled.0, assembled.1]
+    }
+
+    pub fn opcode(self) -> u16 {
+        u16::from_be_bytes(self.to_bytes())
+    }
+
+    /// Decodes an opcode, returning `None` if it isn't a CHIP-8 instruction.
+    pub fn decode(opcode: u16) -> Option<Self> {
+        disassemble_instruction(opcode)
+    }
+}
+
+/// Decodes a big-endian opcode back into an [`Instruction`], returning `None` for words which
+/// don't correspond to any CHIP-8 instruction.
+pub(crate) fn disassemble_instruction(opcode: u16) -> Option<Instruction> {
+    let x = Vx(((opcode >> 8) & 0x0F) as u8);
+    let y = Vx(((opcode >> 4) & 0x0F) as u8);
+    let n = (opcode & 0x0F) as u8;
+    let kk = opcode as u8;
+    let addr = Addr(opcode & 0x0FFF);
+
+    let instr = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            _ => Instruction::Sys(addr),
+        },
+        0x1 => Instruction::Jmp(addr),
+        0x2 => Instruction::Call(addr),
+        0x3 => Instruction::SkipEq(x, kk),
+        0x4 => Instruction::SkipNotEq(x, kk),
+        0x5 if n == 0 => Instruction::SkipEqVx(x, y),
+        0x6 => Instruction::Load(x, kk),
+        0x7 => Instruction::Add(x, kk),
+        0x8 => match n {
+            0x0 => Instruction::LoadVx(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::XOr(x, y),
+            0x4 => Instruction::AddVx(x, y),
+            0x5 => Instruction::SubVx(x, y),
+            0x6 => Instruction::ShiftRight(x, y),
+            0x7 => Instruction::SubN(x, y),
+            0xE => Instruction::ShiftLeft(x, y),
+            _ => return None,
+        },
+        0x9 if n == 0 => Instruction::SkipNotEqVx(x, y),
+        0xA => Instruction::LoadI(addr),
+        0xB => Instruction::JmpV0(addr),
+        0xC => Instruction::Rand(x, kk),
+        0xD => Instruction::Draw(x, y, n),
+        0xE => match kk {
+            0x9E => Instruction::SkipKeyPressed(x),
+            0xA1 => Instruction::SkipKeyNotPressed(x),
+            _ => return None,
+        },
+        0xF => match kk {
+            0x07 => Instruction::LoadDelay(x),
+            0x0A => Instruction::LoadKey(x),
+            0x15 => Instruction::SetDelay(x),
+            0x18 => Instruction::SetSound(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LoadFont(x),
+            0x33 => Instruction::LoadBcd(x),
+            0x55 => Instruction::StoreRegisters(x),
+            0x65 => Instruction::LoadRegisters(x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(instr)
+}
+
+impl fmt::Display for Vx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "V{:X}", self.0)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:03X}", self.0)
+    }
+}
+
+/// Prints the instruction in the same syntax accepted by the assembler.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Sys(addr) => write!(f, "SYS {}", addr),
+            Instruction::Jmp(addr) => write!(f, "JP {}", addr),
+            Instruction::Call(addr) => write!(f, "CALL {}", addr),
+            Instruction::SkipEq(vx, constant) => write!(f, "SE {}, 0x{:02X}", vx, constant),
+            Instruction::SkipNotEq(vx, constant) => write!(f, "SNE {}, 0x{:02X}", vx, constant),
+            Instruction::SkipEqVx(vx, vy) => write!(f, "SE {}, {}", vx, vy),
+            Instruction::Load(vx, constant) => write!(f, "LD {}, 0x{:02X}", vx, constant),
+            Instruction::Add(vx, constant) => write!(f, "ADD {}, 0x{:02X}", vx, constant),
+            Instruction::LoadVx(vx, vy) => write!(f, "LD {}, {}", vx, vy),
+            Instruction::Or(vx, vy) => write!(f, "OR {}, {}", vx, vy),
+            Instruction::And(vx, vy) => write!(f, "AND {}, {}", vx, vy),
+            Instruction::XOr(vx, vy) => write!(f, "XOR {}, {}", vx, vy),
+            Instruction::AddVx(vx, vy) => write!(f, "ADD {}, {}", vx, vy),
+            Instruction::SubVx(vx, vy) => write!(f, "SUB {}, {}", vx, vy),
+            // Written back the way the one operand forms parse, with `Vy` as `V0`.
+            Instruction::ShiftRight(vx, V0) => write!(f, "SHR {}", vx),
+            Instruction::ShiftRight(vx, vy) => write!(f, "SHR {}, {}", vx, vy),
+            Instruction::SubN(vx, vy) => write!(f, "SUBN {}, {}", vx, vy),
+            Instruction::ShiftLeft(vx, V0) => write!(f, "SHL {}", vx),
+            Instruction::ShiftLeft(vx, vy) => write!(f, "SHL {}, {}", vx, vy),
+            Instruction::SkipNotEqVx(vx, vy) => write!(f, "SNE {}, {}", vx, vy),
+            Instruction::LoadI(addr) => write!(f, "LD I, {}", addr),
+            Instruction::JmpV0(addr) => write!(f, "JP V0, {}", addr),
+            Instruction::Rand(vx, constant) => write!(f, "RND {}, 0x{:02X}", vx, constant),
+            Instruction::Draw(vx, vy, constant) => {
+                write!(f, "DRW {}, {}, 0x{:X}", vx, vy, constant)
+            }
+            Instruction::SkipKeyPressed(vx) => write!(f, "SKP {}", vx),
+            Instruction::SkipKeyNotPressed(vx) => write!(f, "SKNP {}", vx),
+            Instruction::LoadDelay(vx) => write!(f, "LD {}, DT", vx),
+            Instruction::LoadKey(vx) => write!(f, "LD {}, K", vx),
+            Instruction::SetDelay(vx) => write!(f, "LD DT, {}", vx),
+            Instruction::SetSound(vx) => write!(f, "LD ST, {}", vx),
+            Instruction::AddI(vx) => write!(f, "ADD I, {}", vx),
+            Instruction::LoadFont(vx) => write!(f, "LD F, {}", vx),
+            Instruction::LoadBcd(vx) => write!(f, "LD B, {}", vx),
+            Instruction::StoreRegisters(vx) => write!(f, "LD [I], {}", vx),
+            Instruction::LoadRegisters(vx) => write!(f, "LD {}, [I]", vx),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::machine::MEMORY_SIZE;
+use crate::object::{is_uninitialized, ObjectFile, RelocKind, RelocTarget, SymbolValue};
+use crate::parser::{bounded, parse_number};
+use crate::{Assembly, ParseErr, SourceMapping, PROGRAM_START};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The name layout errors are reported against.
+const LAYOUT_NAME: &str = "<layout>";
+
+/// Where the linker places each section.
+///
+/// Written as a list of sections separated by commas or newlines, each either `name=ADDR` to
+/// place it at an address or just `name` to place it after the previous one. Addresses are
+/// written as in source, and `#` starts a comment:
+///
+/// ```text
+/// code
+/// data=0x600  # sprites
+/// vars
+/// ```
+///
+/// Sections which aren't listed are placed after those which are, in the order they first
+/// appear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Where the program is loaded, and so where the first section goes by default.
+    pub base: u16,
+    pub sections: Vec<Placement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub section: String,
+    /// The address to put the section at, or `None` to put it after the previous section.
+    pub start: Option<u16>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            base: PROGRAM_START,
+            sections: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = ParseErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut layout = Layout::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for entry in line.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (section, start) = match entry.find('=') {
+                    Some(idx) => {
+                        let start = entry[idx + 1..].trim();
+                        let start = parse_number(start)
+                            .ok_or_else(|| ParseErr::InvalidOperand(String::from(start)))
+                            .and_then(|start| bounded(start, 0x0FFF))?;
+                        (entry[..idx].trim(), Some(start))
+                    }
+                    None => (entry, None),
+                };
+                if layout.sections.iter().any(|p| p.section == section) {
+                    return Err(ParseErr::InvalidLayout(format!(
+                        "section `{}` is placed twice",
+                        section
+                    )));
+                }
+                layout.sections.push(Placement {
+                    section: String::from(section),
+                    start,
+                });
+            }
+        }
+        Ok(layout)
+    }
+}
+
+/// Where a section ended up in a linked program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedSection {
+    pub name: String,
+    pub start: u16,
+    pub len: u16,
+    /// Whether the section's contents are part of the program, rather than just reserved.
+    pub initialized: bool,
+}
+
+/// Links objects into a single program, placing sections as described by `layout`.
+///
+/// Within a section each object's part follows the previous object's, and every part starts at
+/// an even address. Exported symbols are shared between objects, and must be defined only once.
+pub fn link(objects: &[ObjectFile], layout: &Layout) -> Result<Assembly, Diagnostics> {
+    let base = layout.base;
+    let mut diagnostics = Diagnostics::default();
+    for object in objects {
+        for warning in &object.warnings {
+            diagnostics.push(warning.clone());
+        }
+    }
+    let mut layout_error = |msg: String| {
+        diagnostics.push(Diagnostic::error(
+            PathBuf::from(LAYOUT_NAME),
+            0,
+            ParseErr::InvalidLayout(msg),
+        ))
+    };
+
+    let mut placements: Vec<Placement> = layout
+        .sections
+        .iter()
+        .filter(|p| {
+            objects
+                .iter()
+                .any(|o| o.sections.iter().any(|s| s.name == p.section))
+        })
+        .cloned()
+        .collect();
+    for section in objects.iter().flat_map(|o| &o.sections) {
+        if !placements.iter().any(|p| p.section == section.name) {
+            placements.push(Placement {
+                section: section.name.clone(),
+                start: None,
+            });
+        }
+    }
+
+    // Addresses are kept wider than the program's so that overflow can be reported.
+    let mut placed: Vec<Vec<u32>> = objects.iter().map(|o| vec![0; o.sections.len()]).collect();
+    let mut sections = Vec::new();
+    let mut address = u32::from(base);
+    let mut end = u32::from(base);
+    for placement in &placements {
+        if let Some(start) = placement.start {
+            if start < base {
+                layout_error(format!(
+                    "section `{}` starts at {:#05X}, before the program at {:#05X}",
+                    placement.section, start, base
+                ));
+            }
+            address = u32::from(start.max(base));
+        }
+        address += address % 2;
+        let start = address;
+        let mut initialized = false;
+        for (object, placed) in objects.iter().zip(&mut placed) {
+            for (idx, section) in object.sections.iter().enumerate() {
+                if section.name == placement.section {
+                    address += address % 2;
+                    placed[idx] = address;
+                    // Reserved space isn't stored, so doesn't make the program longer.
+                    if !section.bytes.is_empty() {
+                        end = end.max(address + section.bytes.len() as u32);
+                    }
+                    address += u32::from(section.len());
+                    initialized |= !is_uninitialized(&section.name);
+                }
+            }
+        }
+        sections.push(PlacedSection {
+            name: placement.section.clone(),
+            start: start as u16,
+            len: (address - start) as u16,
+            initialized,
+        });
+        if address > MEMORY_SIZE as u32 {
+            layout_error(format!(
+                "section `{}` ends at {:#05X}, past the end of memory",
+                placement.section, address
+            ));
+        }
+    }
+
+    sections.sort_by_key(|s| s.start);
+    for pair in sections.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if a.len > 0 && b.len > 0 && u32::from(a.start) + u32::from(a.len) > u32::from(b.start) {
+            layout_error(format!(
+                "section `{}` at {:#05X} overlaps section `{}`",
+                b.name, b.start, a.name
+            ));
+        }
+    }
+
+    let mut bytes = vec![0; (end - u32::from(base)) as usize];
+    for (object, placed) in objects.iter().zip(&placed) {
+        for (section, &start) in object.sections.iter().zip(placed) {
+            if section.bytes.is_empty() {
+                continue;
+            }
+            let start = (start - u32::from(base)) as usize;
+            bytes[start..start + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+    }
+
+    let value_of = |object: usize, value: SymbolValue| match value {
+        SymbolValue::Absolute(value) => u32::from(value),
+        SymbolValue::Relative { section, offset } => placed[object][section] + u32::from(offset),
+    };
+
+    let mut globals: HashMap<&str, u32> = HashMap::new();
+    for (idx, object) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|s| s.exported) {
+            if globals
+                .insert(&symbol.name, value_of(idx, symbol.value))
+                .is_some()
+            {
+                diagnostics.push(Diagnostic::error(
+                    object.name.clone(),
+                    0,
+                    ParseErr::DuplicateSymbol(symbol.name.clone()),
+                ));
+            }
+        }
+    }
+
+    for (idx, object) in objects.iter().enumerate() {
+        for reloc in &object.relocations {
+            let max = match reloc.kind {
+                RelocKind::Addr12 => 0x0FFF,
+                RelocKind::Word => u16::MAX,
+            };
+            let value = match &reloc.target {
+                RelocTarget::Section { section, offset } => {
+                    Ok(placed[idx][*section] + u32::from(*offset))
+                }
+                RelocTarget::Symbol(name) => globals
+                    .get(name.as_str())
+                    .copied()
+                    .ok_or_else(|| ParseErr::UndefinedSymbol(name.clone())),
+            }
+            .and_then(|value| bounded(i64::from(value), max));
+
+            let at =
+                (placed[idx][reloc.section] - u32::from(base)) as usize + usize::from(reloc.offset);
+            match value {
+                Ok(value) => match reloc.kind {
+                    RelocKind::Addr12 => {
+                        bytes[at] = (bytes[at] & 0xF0) | (value >> 8) as u8;
+                        bytes[at + 1] = value as u8;
+                    }
+                    RelocKind::Word => {
+                        bytes[at] = (value >> 8) as u8;
+                        bytes[at + 1] = value as u8;
+                    }
+                },
+                Err(err) => {
+                    let (file, line) = locate(object, reloc.section, reloc.offset);
+                    diagnostics.push(Diagnostic::error(file, line, err));
+                }
+            }
+        }
+    }
+
+    if diagnostics.has_errors() {
+        return Err(diagnostics);
+    }
+
+    let mut symbols: BTreeMap<String, u16> = globals
+        .iter()
+        .map(|(&name, &value)| (String::from(name), value as u16))
+        .collect();
+    let mut labels = BTreeSet::new();
+    let mut files = Vec::new();
+    let mut source_map = Vec::new();
+    for (idx, object) in objects.iter().enumerate() {
+        // Where objects have local symbols of the same name, the first one wins.
+        for symbol in &object.symbols {
+            if symbol.exported || !symbols.contains_key(&symbol.name) {
+                symbols.insert(symbol.name.clone(), value_of(idx, symbol.value) as u16);
+                if matches!(symbol.value, SymbolValue::Relative { .. }) {
+                    labels.insert(symbol.name.clone());
+                }
+            }
+        }
+        source_map.extend(object.lines.iter().map(|m| SourceMapping {
+            address: (placed[idx][m.section] + u32::from(m.offset)) as u16,
+            len: m.len,
+            file: files.len() + m.file,
+            line: m.line,
+            kind: m.kind.clone(),
+        }));
+        files.extend(object.files.iter().cloned());
+    }
+    source_map.sort_by_key(|m| m.address);
+
+    Ok(Assembly {
+        base,
+        bytes,
+        symbols,
+        labels,
+        files,
+        source_map,
+        sections,
+        warnings: diagnostics.0,
+    })
+}
+
+/// Finds the line a relocation came from, falling back to the object itself.
+fn locate(object: &ObjectFile, section: usize, offset: u16) -> (PathBuf, usize) {
+    match object.line_at(section, offset) {
+        Some(mapping) => (object.files[mapping.file].clone(), mapping.line),
+        None => (object.name.clone(), 0),
+    }
+}
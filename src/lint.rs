@@ -0,0 +1,170 @@
+use crate::cfg::{bit, read_sources, reads, sets_flag, writes, Code, Decoded, Flow};
+use crate::clobbers;
+use crate::diagnostic::Diagnostic;
+use crate::files::FileProvider;
+use crate::instruction::{Instruction, VF};
+use crate::target::Target;
+use crate::Assembly;
+use std::collections::BTreeSet;
+
+/// Looks through an assembled program for common mistakes:
+///
+/// - a skip followed by data, nothing at all, a label, or a pseudo-instruction which expands to
+///   more than the one instruction it skips
+/// - code after `JP` or `RET` which nothing jumps to
+/// - a value kept in `VF` across an instruction which overwrites it with a flag
+/// - `DRW` with a height of 0, which draws nothing except on SUPER-CHIP
+/// - `LD I` pointing at code rather than data
+/// - `SYS`, which modern interpreters ignore
+/// - subroutines which break the `@clobbers`, `@preserves` or `@returns` annotations on their
+///   labels, and calls which rely on registers the subroutine clobbers
+///
+/// Source files are read for the annotations in their comments, so `files` should be the
+/// provider the program was assembled with.
+pub fn lint(assembly: &Assembly, files: &dyn FileProvider, target: Target) -> Vec<Diagnostic> {
+    let sources = read_sources(assembly, files);
+    let code = Code::new(assembly);
+    let targets = code.targets();
+    let labelled: BTreeSet<u16> = labels(assembly).map(|(_, value)| value).collect();
+    let mut diagnostics = Vec::new();
+    let mut warn = |decoded: &Decoded, msg: String| {
+        let mapping = &assembly.source_map[decoded.mapping];
+        diagnostics.push(Diagnostic::warning(
+            assembly.files[mapping.file].clone(),
+            mapping.line,
+            msg,
+        ));
+    };
+
+    // Where VF was last given a value other than a flag, and where that was overwritten.
+    let mut vf_set: Option<u16> = None;
+    let mut vf_clobbered: Option<(u16, Instruction)> = None;
+    for (&address, decoded) in &code.instructions {
+        let instr = decoded.instr;
+        let next = address + 2;
+        let reached = targets.contains(&address) || labelled.contains(&address);
+        let before = |offset| code.instructions.get(&address.wrapping_sub(offset));
+
+        // Unless it's skipped, nothing after a jump or return runs without being jumped to.
+        if let Some(prev) = before(2).filter(|d| Flow::of(d.instr).ends_block() && !reached) {
+            if !before(4).is_some_and(|d| Flow::of(d.instr) == Flow::Skip) {
+                warn(decoded, format!("unreachable code after `{}`", prev.instr));
+            }
+        }
+
+        if Flow::of(instr) == Flow::Skip {
+            match code.instructions.get(&next) {
+                None if code.data.contains(&next) => {
+                    warn(decoded, format!("`{}` skips over data", instr))
+                }
+                None => warn(
+                    decoded,
+                    format!("`{}` skips past the end of the code", instr),
+                ),
+                Some(skipped) => {
+                    let mapping = &assembly.source_map[skipped.mapping];
+                    if let Some(name) = label_at(assembly, next) {
+                        warn(
+                            decoded,
+                            format!(
+                                "`{}` skips `{}`, which is also reached by name",
+                                instr, name
+                            ),
+                        );
+                    } else if mapping.address == next && mapping.len > 2 {
+                        warn(
+                            decoded,
+                            format!(
+                                "`{}` only skips the first of the {} instructions on line {}",
+                                instr,
+                                mapping.len / 2,
+                                mapping.line
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        match instr {
+            Instruction::Draw(_, _, 0) if !target.draws_large_sprites() => {
+                warn(decoded, format!("`{}` draws nothing on {}", instr, target))
+            }
+            Instruction::LoadI(addr) if code.instructions.contains_key(&addr.value()) => warn(
+                decoded,
+                format!("`{}` points at code rather than data", instr),
+            ),
+            Instruction::Sys(_) => warn(
+                decoded,
+                format!("`{}` is ignored by modern interpreters", instr),
+            ),
+            _ => {}
+        }
+
+        // Only follow VF through straight line code.
+        if reached {
+            vf_set = None;
+            vf_clobbered = None;
+        }
+        if let (Some(set), Some((at, clobber))) = (vf_set, vf_clobbered) {
+            if reads(instr, target) & bit(VF) != 0 {
+                warn(
+                    decoded,
+                    format!(
+                        "reads VF set at {:03X}, but `{}` at {:03X} overwrote it with a flag",
+                        set, clobber, at
+                    ),
+                );
+                vf_set = None;
+            }
+        }
+        if writes(instr, target) & bit(VF) != 0 {
+            if !sets_flag(instr, target) {
+                vf_set = Some(address);
+                vf_clobbered = None;
+            } else if writes_vf_directly(instr) {
+                // The result is the flag, so it's being used as one.
+                vf_set = None;
+                vf_clobbered = None;
+            } else if vf_set.is_some() && vf_clobbered.is_none() {
+                vf_clobbered = Some((address, instr));
+            }
+        }
+        if !matches!(Flow::of(instr), Flow::Next | Flow::Skip) {
+            vf_set = None;
+            vf_clobbered = None;
+        }
+    }
+    diagnostics.extend(clobbers::check(assembly, &code, &sources, target));
+    diagnostics
+}
+
+/// Whether `VF` is the destination of an instruction, so its result is meant to be a flag.
+fn writes_vf_directly(instr: Instruction) -> bool {
+    use Instruction::*;
+
+    match instr {
+        AddVx(vx, _)
+        | SubVx(vx, _)
+        | SubN(vx, _)
+        | ShiftRight(vx, _)
+        | ShiftLeft(vx, _)
+        | Or(vx, _)
+        | And(vx, _)
+        | XOr(vx, _) => vx == VF,
+        _ => false,
+    }
+}
+
+fn label_at(assembly: &Assembly, address: u16) -> Option<&str> {
+    labels(assembly).find_map(|(name, value)| (value == address).then_some(name))
+}
+
+/// The labels in the program and their addresses, leaving out `DEFINE`d constants.
+fn labels(assembly: &Assembly) -> impl Iterator<Item = (&str, u16)> {
+    assembly
+        .symbols
+        .iter()
+        .filter(move |&(name, _)| assembly.labels.contains(name))
+        .map(|(name, &value)| (name.as_str(), value))
+}
@@ -0,0 +1,193 @@
+use crate::cfg::{read_sources, Code};
+use crate::files::FileProvider;
+use crate::instruction::Instruction;
+use crate::timing::{vip_cycles, Cycles, Timing};
+use crate::{Assembly, LineKind};
+use std::fmt::Write;
+
+/// Bytes shown on each row of data.
+const BYTES_PER_ROW: usize = 4;
+
+/// Renders each line of the program's source alongside the address and bytes it assembled to.
+///
+/// Pseudo-instructions are followed by the instructions they expanded to, marked with `=>`:
+///
+/// ```text
+/// 0204                 7      NOT V1
+/// 0204  6F FF                 => LD VF, 0xFF
+/// 0206  81 F3                 => XOR V1, VF
+/// ```
+///
+/// Files which can't be read are listed without their source.
+pub fn listing(assembly: &Assembly, files: &dyn FileProvider) -> String {
+    write_listing(assembly, files, false)
+}
+
+/// Renders a [`listing`] with how many COSMAC VIP machine cycles each line takes, followed by
+/// the time taken by each basic block and each subroutine. Times which vary are shown as
+/// `best-worst`, or `best+` where there's no limit, such as a loop.
+pub fn timing_listing(assembly: &Assembly, files: &dyn FileProvider) -> String {
+    write_listing(assembly, files, true)
+}
+
+fn write_listing(assembly: &Assembly, files: &dyn FileProvider, timing: bool) -> String {
+    let sources = read_sources(assembly, files);
+    let mut out = String::new();
+    // The cycles column, if there is one.
+    let cycles = |cycles: Option<Cycles>| match (timing, cycles) {
+        (false, _) => String::new(),
+        (true, Some(cycles)) => format!("{:>9}  ", cycles),
+        (true, None) => format!("{:9}  ", ""),
+    };
+
+    for (idx, path) in assembly.files.iter().enumerate() {
+        let lines: Vec<&str> = sources[idx].lines().collect();
+        let last_line = assembly
+            .source_map
+            .iter()
+            .filter(|m| m.file == idx)
+            .map(|m| m.line)
+            .max()
+            .unwrap_or(0)
+            .max(lines.len());
+
+        writeln!(out, "; {}", path.display()).unwrap();
+        for number in 1..=last_line {
+            let source = lines.get(number - 1).copied().unwrap_or_default();
+            let mapping = assembly
+                .source_map
+                .iter()
+                .find(|m| m.file == idx && m.line == number);
+            let mapping = match mapping {
+                Some(mapping) => mapping,
+                None => {
+                    writeln!(
+                        out,
+                        "{:4}  {:11}  {}{:5}  {}",
+                        "",
+                        "",
+                        cycles(None),
+                        number,
+                        source
+                    )
+                    .unwrap();
+                    continue;
+                }
+            };
+
+            let start = usize::from(mapping.address.wrapping_sub(assembly.base));
+            let end = (start + usize::from(mapping.len)).min(assembly.bytes.len());
+            let bytes = assembly.bytes.get(start..end).unwrap_or_default();
+
+            let is_data = mapping.kind == LineKind::Data;
+            let instructions: Vec<Instruction> = if is_data || !bytes.len().is_multiple_of(2) {
+                Vec::new()
+            } else {
+                bytes
+                    .chunks(2)
+                    .filter_map(|word| Instruction::decode(u16::from_be_bytes([word[0], word[1]])))
+                    .collect()
+            };
+            let total = instructions
+                .iter()
+                .map(|&instr| vip_cycles(instr))
+                .reduce(|a, b| a + b);
+            if mapping.kind.is_expanded() {
+                writeln!(
+                    out,
+                    "{:04X}  {:11}  {}{:5}  {}",
+                    mapping.address,
+                    "",
+                    cycles(total),
+                    number,
+                    source
+                )
+                .unwrap();
+                for (offset, instr) in instructions.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "{:04X}  {:11}  {}{:5}      => {}",
+                        usize::from(mapping.address) + offset * 2,
+                        hex(&instr.to_bytes()),
+                        cycles(Some(vip_cycles(*instr))),
+                        "",
+                        instr
+                    )
+                    .unwrap();
+                }
+            } else {
+                let mut rows = bytes.chunks(BYTES_PER_ROW);
+                writeln!(
+                    out,
+                    "{:04X}  {:11}  {}{:5}  {}",
+                    mapping.address,
+                    hex(rows.next().unwrap_or_default()),
+                    cycles(total),
+                    number,
+                    source
+                )
+                .unwrap();
+                for (row, chunk) in rows.enumerate() {
+                    let address = usize::from(mapping.address) + (row + 1) * BYTES_PER_ROW;
+                    writeln!(out, "{:04X}  {}", address, hex(chunk)).unwrap();
+                }
+            }
+        }
+    }
+
+    if timing {
+        write_timing(&mut out, assembly, &Code::new(assembly));
+    }
+    out
+}
+
+fn write_timing(out: &mut String, assembly: &Assembly, code: &Code) {
+    let timing = Timing::new(assembly, code);
+    let line_of = |address: u16| {
+        assembly
+            .mapping_at(address)
+            .map(|m| m.line.to_string())
+            .unwrap_or_default()
+    };
+
+    writeln!(out, "\n; Basic blocks, in COSMAC VIP machine cycles").unwrap();
+    writeln!(out, "; Start  End   Lines       Cycles").unwrap();
+    for (start, end, cycles) in &timing.blocks {
+        let (first, last) = (line_of(*start), line_of(*end));
+        let lines = if first == last {
+            first
+        } else {
+            format!("{}-{}", first, last)
+        };
+        writeln!(
+            out,
+            "; {:04X}   {:04X}  {:10}  {}",
+            start, end, lines, cycles
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "\n; Subroutines, from the first instruction to returning"
+    )
+    .unwrap();
+    writeln!(out, "; Address  Name              Cycles").unwrap();
+    for (address, cycles) in &timing.subroutines {
+        let name = assembly
+            .symbols
+            .iter()
+            .find(|&(_, value)| value == address)
+            .map(|(name, _)| name.as_str())
+            .unwrap_or_default();
+        writeln!(out, "; {:04X}     {:16}  {}", address, name, cycles).unwrap();
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,917 @@
+//! A language server for CHIP-8 assembly, speaking the Language Server Protocol over a pair of
+//! streams, normally stdin and stdout.
+//!
+//! Open documents are assembled whenever they change, with files they include read from disk
+//! unless they're open too. The server offers diagnostics from the assembler and [`lint`],
+//! go-to-definition, references, hover, completion, document symbols and rename. Symbols are
+//! found in every open document and every file they include.
+//!
+//! The target can be chosen with `{"target": "schip"}` as the initialization options.
+
+use crate::files::{normalize, FileProvider, FsFiles};
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::parser::parse_line;
+use crate::target::Target;
+use crate::{assemble_path, lint, Assembly, AssemblyCache, Diagnostic, Options, Severity};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Every form of every instruction and directive, as its syntax, encoding and what it does.
+/// Pseudo-instructions and directives have no encoding of their own.
+const FORMS: &[(&str, &str, &str)] = &[
+    ("CLS", "00E0", "Clear the display."),
+    ("RET", "00EE", "Return from a subroutine."),
+    (
+        "SYS addr",
+        "0nnn",
+        "Call a machine code routine. Ignored by modern interpreters.",
+    ),
+    ("JP addr", "1nnn", "Jump to `addr`."),
+    (
+        "JP V0, addr",
+        "Bnnn",
+        "Jump to `addr + V0`, or `addr + Vx` on SUPER-CHIP where `x` is the top nibble of `addr`.",
+    ),
+    ("CALL addr", "2nnn", "Call the subroutine at `addr`."),
+    ("SE Vx, byte", "3xkk", "Skip the next instruction if `Vx == byte`."),
+    ("SNE Vx, byte", "4xkk", "Skip the next instruction if `Vx != byte`."),
+    ("SE Vx, Vy", "5xy0", "Skip the next instruction if `Vx == Vy`."),
+    ("SNE Vx, Vy", "9xy0", "Skip the next instruction if `Vx != Vy`."),
+    ("LD Vx, byte", "6xkk", "`Vx = byte`."),
+    ("ADD Vx, byte", "7xkk", "`Vx = Vx + byte`, leaving `VF` alone."),
+    ("LD Vx, Vy", "8xy0", "`Vx = Vy`."),
+    ("OR Vx, Vy", "8xy1", "`Vx = Vx | Vy`. Resets `VF` on CHIP-8."),
+    ("AND Vx, Vy", "8xy2", "`Vx = Vx & Vy`. Resets `VF` on CHIP-8."),
+    ("XOR Vx, Vy", "8xy3", "`Vx = Vx ^ Vy`. Resets `VF` on CHIP-8."),
+    ("ADD Vx, Vy", "8xy4", "`Vx = Vx + Vy`, with `VF` set to the carry."),
+    (
+        "SUB Vx, Vy",
+        "8xy5",
+        "`Vx = Vx - Vy`, with `VF` set to 1 if there's no borrow.",
+    ),
+    (
+        "SUBN Vx, Vy",
+        "8xy7",
+        "`Vx = Vy - Vx`, with `VF` set to 1 if there's no borrow.",
+    ),
+    (
+        "SHR Vx, Vy",
+        "8xy6",
+        "`Vx = Vy >> 1`, with `VF` set to the bit shifted out. Shifts `Vx` in place on CHIP-48 and SUPER-CHIP. `SHR Vx` is `8x06`.",
+    ),
+    (
+        "SHL Vx, Vy",
+        "8xyE",
+        "`Vx = Vy << 1`, with `VF` set to the bit shifted out. Shifts `Vx` in place on CHIP-48 and SUPER-CHIP. `SHL Vx` is `8x0E`.",
+    ),
+    ("LD I, addr", "Annn", "`I = addr`."),
+    ("RND Vx, byte", "Cxkk", "`Vx = random & byte`."),
+    (
+        "DRW Vx, Vy, nibble",
+        "Dxyn",
+        "Draw the `nibble` byte sprite at `I` at (`Vx`, `Vy`), with `VF` set to 1 if any pixels were erased.",
+    ),
+    ("SKP Vx", "Ex9E", "Skip the next instruction if key `Vx` is down."),
+    ("SKNP Vx", "ExA1", "Skip the next instruction if key `Vx` is up."),
+    ("LD Vx, DT", "Fx07", "`Vx = DT`."),
+    ("LD Vx, K", "Fx0A", "Wait for a key to be pressed, and put it in `Vx`."),
+    ("LD DT, Vx", "Fx15", "`DT = Vx`."),
+    ("LD ST, Vx", "Fx18", "`ST = Vx`."),
+    ("ADD I, Vx", "Fx1E", "`I = I + Vx`."),
+    ("LD F, Vx", "Fx29", "`I` = the address of the font sprite for digit `Vx`."),
+    (
+        "LD B, Vx",
+        "Fx33",
+        "Store the hundreds, tens and ones digits of `Vx` at `I`, `I + 1` and `I + 2`.",
+    ),
+    (
+        "LD [I], Vx",
+        "Fx55",
+        "Store `V0` through `Vx` at `I`. Also advances `I` on CHIP-8.",
+    ),
+    (
+        "LD Vx, [I]",
+        "Fx65",
+        "Load `V0` through `Vx` from `I`. Also advances `I` on CHIP-8.",
+    ),
+    ("NOP", "", "Does nothing, as `LD V0, V0`."),
+    ("HALT", "", "Jumps to itself forever."),
+    ("SUB Vx, byte", "", "`Vx = Vx - byte`, as `ADD` of the two's complement."),
+    ("INC Vx", "", "`Vx = Vx + 1`."),
+    ("DEC Vx", "", "`Vx = Vx - 1`."),
+    ("CLR Vx", "", "`Vx = 0`."),
+    ("NOT Vx", "", "`Vx = ~Vx`, using `VF` as scratch."),
+    ("MOV a, b", "", "Any form of `LD`."),
+    ("JEQ Vx, b, label", "", "Jump to `label` if `Vx == b`."),
+    ("JNE Vx, b, label", "", "Jump to `label` if `Vx != b`."),
+    ("JLT Vx, b, label", "", "Jump to `label` if `Vx < b`, using `VF` as scratch."),
+    ("JLE Vx, b, label", "", "Jump to `label` if `Vx <= b`, using `VF` as scratch."),
+    ("JGT Vx, b, label", "", "Jump to `label` if `Vx > b`, using `VF` as scratch."),
+    ("JGE Vx, b, label", "", "Jump to `label` if `Vx >= b`, using `VF` as scratch."),
+    ("JKP Vx, label", "", "Jump to `label` if key `Vx` is down."),
+    ("JKNP Vx, label", "", "Jump to `label` if key `Vx` is up."),
+    ("DB byte, ...", "", "Bytes of data."),
+    ("DW word, ...", "", "Big-endian words of data."),
+    ("DS count", "", "Reserve `count` bytes."),
+    ("DEFINE name value", "", "Define a constant."),
+    ("INCLUDE \"file\"", "", "Assemble another file here."),
+    ("SECTION name", "", "Put what follows in a section."),
+    ("IMPORT name", "", "Use a symbol exported by another object."),
+    ("EXPORT name", "", "Let other objects use a symbol."),
+    ("PROC name", "", "Start a subroutine called `name`."),
+    ("ENDP", "", "End a subroutine."),
+    (".IF a == b", "", "Run what follows if the condition holds."),
+    (".ELSE", "", "Run what follows if the `.IF` condition didn't hold."),
+    (".ENDIF", "", "End an `.IF` block."),
+    (".LOOP", "", "Start a loop."),
+    (".WHILE a == b", "", "Go around the loop again if the condition holds."),
+    (".AGAIN", "", "Go around the loop again, until a `.BREAK` leaves it."),
+    (".BREAK", "", "Leave the innermost loop."),
+    (".CONTINUE", "", "Go around the innermost loop again."),
+    ("TEST \"name\"", "", "Start a test, run by the `test` command."),
+    (
+        "EXPECT a == b",
+        "",
+        "Fail the test unless the condition holds. `a` may be `Vx`, `I`, `DT`, `ST`, `[I]` or `[I+offset]`.",
+    ),
+    ("ENDTEST", "", "End a test, which passes if it gets here."),
+];
+
+/// Whether an operand is the name of a special register rather than a symbol, which depends on
+/// where it is, as in the parser: `LD F, V0` uses the font but `CALL f` calls a label.
+fn is_keyword(mnemonic: Option<&str>, operand: usize, name: &str) -> bool {
+    let names: &[&str] = match (mnemonic, operand) {
+        (Some("LD" | "MOV"), 1) => &["I", "DT", "ST", "F", "B"],
+        (Some("LD" | "MOV"), 2) => &["DT", "K"],
+        (Some("ADD"), 1) => &["I"],
+        (Some("EXPECT"), 1) => &["I", "DT", "ST"],
+        _ => &[],
+    };
+    names.contains(&name.to_ascii_uppercase().as_str())
+}
+
+const REGISTERS: &[&str] = &[
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "[I]", "DT", "ST", "K", "F", "B",
+];
+
+// Values from the protocol.
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_CONSTANT: usize = 14;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_CONSTANT: usize = 21;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Serves requests read from `input`, writing responses to `output`, until the client says to
+/// exit or closes `input`.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        for reply in server.handle(&message) {
+            let text = reply.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+        }
+        output.flush()?;
+        if server.exit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the body of the next message, or `None` at the end of the input.
+pub(crate) fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// What a name in the source is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Defines a label, including with `PROC`.
+    Label,
+    /// Defines a constant with `DEFINE`.
+    Constant,
+    Reference,
+    Mnemonic,
+}
+
+/// A name on a line of source, with its position in bytes.
+#[derive(Debug, Clone)]
+struct Token {
+    name: String,
+    role: Role,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+/// Finds every label, mnemonic, and operand which could be a symbol, in order.
+fn tokens(text: &str) -> Vec<Token> {
+    lazy_static! {
+        static ref TOKEN: Regex = Regex::new("\"[^\"]*\"|[a-zA-Z0-9_#.]+").unwrap();
+        static ref REGISTER: Regex = Regex::new("^[vV][0-9a-fA-F]$").unwrap();
+    }
+
+    let mut found = Vec::new();
+    for (line, source) in text.lines().enumerate() {
+        let code = &source[..source.find(';').unwrap_or(source.len())];
+        let mut mnemonic: Option<String> = None;
+        let mut operand = 0;
+        for m in TOKEN.find_iter(code) {
+            let name = m.as_str();
+            let role = if mnemonic.is_none() && code[m.end()..].starts_with(':') {
+                Role::Label
+            } else if mnemonic.is_none() {
+                mnemonic = Some(name.to_ascii_uppercase());
+                Role::Mnemonic
+            } else {
+                operand += 1;
+                let symbol = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && !REGISTER.is_match(name)
+                    && !is_keyword(mnemonic.as_deref(), operand, name);
+                match mnemonic.as_deref() {
+                    _ if !symbol => continue,
+                    Some("DEFINE") if operand == 1 => Role::Constant,
+                    Some("PROC") if operand == 1 => Role::Label,
+                    _ => Role::Reference,
+                }
+            };
+            found.push(Token {
+                name: String::from(name),
+                role,
+                line,
+                start: m.start(),
+                end: m.end(),
+            });
+        }
+    }
+    found
+}
+
+/// Reads open documents from memory, and everything else from disk.
+struct Overlay(HashMap<PathBuf, String>);
+
+impl FileProvider for Overlay {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.0.get(&normalize(path)) {
+            Some(text) => Ok(text.clone().into_bytes()),
+            None => FsFiles.read(path),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.0.contains_key(&normalize(path)) || FsFiles.is_file(path)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Server {
+    target: Target,
+    /// The text of each open document.
+    docs: HashMap<PathBuf, String>,
+    /// The last successful assembly of each open document.
+    assemblies: HashMap<PathBuf, Assembly>,
+    /// Documents are reassembled on every change, but most of their includes won't have changed.
+    cache: Arc<AssemblyCache>,
+    exit: bool,
+}
+
+/// The document and position a request is about, with the line counted from 0.
+struct Position {
+    path: PathBuf,
+    line: usize,
+    character: usize,
+}
+
+impl Server {
+    /// Handles a message, returning the response and any notifications to send.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = match message.get("method").as_str() {
+            Some(method) => method,
+            // A response to something the server never asks.
+            None => return Vec::new(),
+        };
+        let params = message.get("params");
+        let id = message.get("id");
+        let mut notifications = Vec::new();
+
+        let result = match method {
+            "initialize" => {
+                if let Some(target) = params.get("initializationOptions").get("target").as_str() {
+                    self.target = target.parse().unwrap_or_default();
+                }
+                Ok(capabilities())
+            }
+            "shutdown" => Ok(Json::Null),
+            "exit" => {
+                self.exit = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let doc = params.get("textDocument");
+                if let (Some(path), Some(text)) = (
+                    path_of(doc.get("uri").as_str().unwrap_or_default()),
+                    doc.get("text").as_str(),
+                ) {
+                    self.docs.insert(path.clone(), String::from(text));
+                    notifications = self.assemble(&path);
+                }
+                Ok(Json::Null)
+            }
+            "textDocument/didChange" => {
+                let uri = params.get("textDocument").get("uri").as_str();
+                // Only whole documents are synchronized, so the last change has everything.
+                let text = params.get("contentChanges").as_array().last();
+                if let (Some(path), Some(text)) = (
+                    uri.and_then(path_of),
+                    text.and_then(|c| c.get("text").as_str()),
+                ) {
+                    self.docs.insert(path.clone(), String::from(text));
+                    notifications = self.assemble(&path);
+                }
+                Ok(Json::Null)
+            }
+            "textDocument/didClose" => {
+                let uri = params.get("textDocument").get("uri").as_str();
+                if let Some(path) = uri.and_then(path_of) {
+                    self.docs.remove(&path);
+                    self.assemblies.remove(&path);
+                    notifications.push(publish(&path, Vec::new()));
+                }
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => position(params).map(|at| self.definition(&at)),
+            "textDocument/references" => position(params).map(|at| {
+                let declarations = params
+                    .get("context")
+                    .get("includeDeclaration")
+                    .as_bool()
+                    .unwrap_or(true);
+                self.references(&at, declarations)
+            }),
+            "textDocument/hover" => position(params).map(|at| self.hover(&at)),
+            "textDocument/completion" => Ok(self.completion()),
+            "textDocument/documentSymbol" => {
+                let uri = params.get("textDocument").get("uri").as_str();
+                match uri.and_then(path_of) {
+                    Some(path) => Ok(self.document_symbols(&path)),
+                    None => Err((INVALID_PARAMS, String::from("missing document"))),
+                }
+            }
+            "textDocument/rename" => position(params).and_then(|at| {
+                let new_name = params.get("newName").as_str().unwrap_or_default();
+                self.rename(&at, new_name)
+            }),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+
+        // Notifications don't get a response, even when they aren't understood.
+        if id.is_null() {
+            return notifications;
+        }
+        let response = match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            Err((code, message)) => Json::object(vec![
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::from(code)),
+                        ("message", Json::from(message)),
+                    ]),
+                ),
+            ]),
+        };
+        let mut replies = vec![response];
+        replies.extend(notifications);
+        replies
+    }
+
+    /// Assembles every open document which is `changed` or includes it, returning the
+    /// diagnostics for each of their files.
+    fn assemble(&mut self, changed: &Path) -> Vec<Json> {
+        let roots: Vec<PathBuf> = self
+            .docs
+            .keys()
+            .filter(|&path| {
+                path == changed
+                    || self
+                        .assemblies
+                        .get(path)
+                        .is_some_and(|a| a.files.iter().any(|f| normalize(f) == changed))
+            })
+            .cloned()
+            .collect();
+
+        let files = Arc::new(Overlay(self.docs.clone()));
+        let opts = Options {
+            target: self.target,
+            files: files.clone(),
+            cache: Some(self.cache.clone()),
+            ..Options::default()
+        };
+        let mut notifications = Vec::new();
+        for root in roots {
+            let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+            paths.insert(root.clone());
+            if let Some(previous) = self.assemblies.get(&root) {
+                paths.extend(previous.files.iter().map(|f| normalize(f)));
+            }
+            let diagnostics = match assemble_path(&root, &opts) {
+                Ok(assembly) => {
+                    paths.extend(assembly.files.iter().map(|f| normalize(f)));
+                    let mut diagnostics = assembly.warnings.clone();
+                    diagnostics.extend(lint(&assembly, files.as_ref(), self.target));
+                    self.assemblies.insert(root.clone(), assembly);
+                    diagnostics
+                }
+                Err(diagnostics) => diagnostics.0,
+            };
+
+            let mut by_file: BTreeMap<PathBuf, Vec<Json>> =
+                paths.into_iter().map(|p| (p, Vec::new())).collect();
+            for diagnostic in &diagnostics {
+                let path = normalize(&diagnostic.file);
+                let text = self.text(&path).unwrap_or_default();
+                by_file
+                    .entry(path)
+                    .or_default()
+                    .push(to_lsp(diagnostic, &text));
+            }
+            for (path, diagnostics) in by_file {
+                notifications.push(publish(&path, diagnostics));
+            }
+        }
+        notifications
+    }
+
+    /// The text of a file, from its open document if there is one.
+    fn text(&self, path: &Path) -> Option<String> {
+        match self.docs.get(path) {
+            Some(text) => Some(text.clone()),
+            None => fs::read_to_string(path).ok(),
+        }
+    }
+
+    /// Every open document, and every file they include.
+    fn workspace(&self) -> BTreeSet<PathBuf> {
+        let mut paths: BTreeSet<PathBuf> = self.docs.keys().cloned().collect();
+        for assembly in self.assemblies.values() {
+            paths.extend(assembly.files.iter().map(|f| normalize(f)));
+        }
+        paths
+    }
+
+    /// The tokens of every file in the workspace.
+    fn all_tokens(&self) -> Vec<(PathBuf, String, Vec<Token>)> {
+        self.workspace()
+            .into_iter()
+            .filter_map(|path| {
+                let text = self.text(&path)?;
+                let tokens = tokens(&text);
+                Some((path, text, tokens))
+            })
+            .collect()
+    }
+
+    /// The token at a position, along with the text of its file.
+    fn token_at(&self, at: &Position) -> Option<(Token, String)> {
+        let text = self.text(&at.path)?;
+        let line = text.lines().nth(at.line)?;
+        let offset = byte_offset(line, at.character);
+        let token = tokens(&text)
+            .into_iter()
+            .find(|t| t.line == at.line && t.start <= offset && offset <= t.end)?;
+        Some((token, text))
+    }
+
+    /// Every definition and use of the symbol at a position.
+    fn occurrences(&self, at: &Position) -> Vec<(PathBuf, String, Token)> {
+        let name = match self.token_at(at) {
+            Some((token, _)) if token.role != Role::Mnemonic => token.name,
+            _ => return Vec::new(),
+        };
+        let all = self.all_tokens();
+        let defined = all
+            .iter()
+            .flat_map(|(_, _, tokens)| tokens)
+            .any(|t| t.name == name && matches!(t.role, Role::Label | Role::Constant))
+            || self
+                .assemblies
+                .values()
+                .any(|a| a.symbols.contains_key(&name));
+        if !defined {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        for (path, text, tokens) in all {
+            for token in tokens {
+                if token.name == name && token.role != Role::Mnemonic {
+                    found.push((path.clone(), text.clone(), token));
+                }
+            }
+        }
+        found
+    }
+
+    fn definition(&self, at: &Position) -> Json {
+        let occurrences = self.occurrences(at);
+        let definitions = occurrences
+            .iter()
+            .filter(|(_, _, t)| matches!(t.role, Role::Label | Role::Constant));
+        // Prefer a definition in the same file, in case a name is reused.
+        let best = definitions
+            .clone()
+            .find(|(path, _, _)| *path == at.path)
+            .or_else(|| definitions.clone().next());
+        match best {
+            Some((path, text, token)) => location(path, text, token),
+            None => Json::Null,
+        }
+    }
+
+    fn references(&self, at: &Position, declarations: bool) -> Json {
+        let locations = self
+            .occurrences(at)
+            .iter()
+            .filter(|(_, _, t)| declarations || t.role == Role::Reference)
+            .map(|(path, text, token)| location(path, text, token))
+            .collect::<Vec<_>>();
+        Json::from(locations)
+    }
+
+    fn hover(&self, at: &Position) -> Json {
+        let (token, text) = match self.token_at(at) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let contents = match token.role {
+            Role::Mnemonic => {
+                let mut contents = String::new();
+                for (syntax, encoding, semantics) in FORMS {
+                    if syntax.split_whitespace().next() == Some(token.name.as_str()) {
+                        contents.push_str(&format!("`{}`", syntax));
+                        if !encoding.is_empty() {
+                            contents.push_str(&format!(" — `{}`", encoding));
+                        }
+                        contents.push_str(&format!("\n\n{}\n\n", semantics));
+                    }
+                }
+                contents.push_str(&self.assembled(&at.path, at.line, &text));
+                contents
+            }
+            _ => {
+                let value = self
+                    .assemblies
+                    .values()
+                    .find_map(|a| a.symbols.get(&token.name));
+                match value {
+                    Some(value) => format!("`{}` = `0x{:03X}` ({})", token.name, value, value),
+                    None => return Json::Null,
+                }
+            }
+        };
+        if contents.trim().is_empty() {
+            return Json::Null;
+        }
+        Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(contents.trim_end())),
+                ]),
+            ),
+            ("range", range(&text, token.line, token.start, token.end)),
+        ])
+    }
+
+    /// What a line assembled to, as markdown.
+    fn assembled(&self, path: &Path, line: usize, text: &str) -> String {
+        let found = self.assemblies.values().find_map(|a| {
+            let file = a.files.iter().position(|f| normalize(f) == path)?;
+            let mapping = a
+                .source_map
+                .iter()
+                .find(|m| m.file == file && m.line == line + 1 && m.len > 0)?;
+            let start = usize::from(mapping.address.wrapping_sub(a.base));
+            let end = (start + usize::from(mapping.len)).min(a.bytes.len());
+            Some((mapping.address, a.bytes.get(start..end)?))
+        });
+        let (address, bytes) = match found {
+            Some(found) => found,
+            None => return String::new(),
+        };
+        let is_data = text
+            .lines()
+            .nth(line)
+            .and_then(|source| parse_line(source).ok())
+            .and_then(|line| line.statement)
+            .is_some_and(|s| ["DB", "DW", "DS"].contains(&s.mnemonic.as_str()));
+
+        let mut out = String::from("Assembled:\n\n```\n");
+        if is_data || !bytes.len().is_multiple_of(2) {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            out.push_str(&format!("{:04X}  {}\n", address, hex.join(" ")));
+        } else {
+            for (idx, word) in bytes.chunks(2).enumerate() {
+                let opcode = u16::from_be_bytes([word[0], word[1]]);
+                let instr = Instruction::decode(opcode)
+                    .map(|instr| instr.to_string())
+                    .unwrap_or_default();
+                out.push_str(&format!(
+                    "{:04X}  {:04X}  {}\n",
+                    usize::from(address) + idx * 2,
+                    opcode,
+                    instr
+                ));
+            }
+        }
+        out.push_str("```");
+        out
+    }
+
+    fn completion(&self) -> Json {
+        let item = |label: &str, kind: usize, detail: String| {
+            Json::object(vec![
+                ("label", Json::from(label)),
+                ("kind", Json::from(kind)),
+                ("detail", Json::from(detail)),
+            ])
+        };
+
+        let mut items = Vec::new();
+        let mut mnemonics = BTreeMap::new();
+        for (syntax, _, semantics) in FORMS {
+            let mnemonic = syntax.split_whitespace().next().unwrap_or_default();
+            mnemonics.entry(mnemonic).or_insert(*semantics);
+        }
+        for (mnemonic, semantics) in mnemonics {
+            items.push(item(mnemonic, COMPLETION_KEYWORD, String::from(semantics)));
+        }
+        for register in REGISTERS {
+            items.push(item(register, COMPLETION_VARIABLE, String::new()));
+        }
+
+        let mut symbols = BTreeMap::new();
+        for (_, _, tokens) in self.all_tokens() {
+            for token in tokens {
+                if matches!(token.role, Role::Label | Role::Constant) {
+                    symbols.insert(token.name, token.role);
+                }
+            }
+        }
+        for (name, role) in symbols {
+            let value = self.assemblies.values().find_map(|a| a.symbols.get(&name));
+            let kind = if role == Role::Constant {
+                COMPLETION_CONSTANT
+            } else {
+                COMPLETION_FUNCTION
+            };
+            let detail = value.map(|v| format!("0x{:03X}", v)).unwrap_or_default();
+            items.push(item(&name, kind, detail));
+        }
+        Json::from(items)
+    }
+
+    fn document_symbols(&self, path: &Path) -> Json {
+        let text = self.text(path).unwrap_or_default();
+        let symbols = tokens(&text)
+            .into_iter()
+            .filter(|t| matches!(t.role, Role::Label | Role::Constant))
+            .map(|token| {
+                let kind = if token.role == Role::Constant {
+                    SYMBOL_CONSTANT
+                } else {
+                    SYMBOL_FUNCTION
+                };
+                let line_len = text.lines().nth(token.line).map_or(0, str::len);
+                Json::object(vec![
+                    ("name", Json::from(token.name.as_str())),
+                    ("kind", Json::from(kind)),
+                    ("range", range(&text, token.line, 0, line_len)),
+                    (
+                        "selectionRange",
+                        range(&text, token.line, token.start, token.end),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Json::from(symbols)
+    }
+
+    fn rename(&self, at: &Position, new_name: &str) -> Result<Json, (i64, String)> {
+        lazy_static! {
+            static ref NAME: Regex = Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+        }
+
+        if !NAME.is_match(new_name) {
+            return Err((
+                INVALID_PARAMS,
+                format!("`{}` isn't a valid symbol name", new_name),
+            ));
+        }
+        let occurrences = self.occurrences(at);
+        if occurrences.is_empty() {
+            return Err((INVALID_PARAMS, String::from("there's no symbol here")));
+        }
+        let mut changes: BTreeMap<String, Json> = BTreeMap::new();
+        for (path, text, token) in occurrences {
+            let edit = Json::object(vec![
+                ("range", range(&text, token.line, token.start, token.end)),
+                ("newText", Json::from(new_name)),
+            ]);
+            match changes
+                .entry(uri_of(&path))
+                .or_insert(Json::Array(Vec::new()))
+            {
+                Json::Array(edits) => edits.push(edit),
+                _ => unreachable!("edits are always an array"),
+            }
+        }
+        Ok(Json::object(vec![("changes", Json::Object(changes))]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                // Whole documents are sent on every change.
+                ("textDocumentSync", Json::from(1usize)),
+                ("definitionProvider", Json::from(true)),
+                ("referencesProvider", Json::from(true)),
+                ("hoverProvider", Json::from(true)),
+                (
+                    "completionProvider",
+                    Json::object(Vec::<(&str, Json)>::new()),
+                ),
+                ("documentSymbolProvider", Json::from(true)),
+                ("renameProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![
+                ("name", Json::from("chip8_lsp")),
+                ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+/// Reads the document and position most requests are about.
+fn position(params: &Json) -> Result<Position, (i64, String)> {
+    let path = params
+        .get("textDocument")
+        .get("uri")
+        .as_str()
+        .and_then(path_of);
+    let at = params.get("position");
+    match (path, at.get("line").as_u64(), at.get("character").as_u64()) {
+        (Some(path), Some(line), Some(character)) => Ok(Position {
+            path,
+            line: line as usize,
+            character: character as usize,
+        }),
+        _ => Err((INVALID_PARAMS, String::from("missing document or position"))),
+    }
+}
+
+fn publish(path: &Path, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", Json::from(uri_of(path))),
+                ("diagnostics", Json::from(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+/// Converts a diagnostic, covering the whole line it's about.
+fn to_lsp(diagnostic: &Diagnostic, text: &str) -> Json {
+    let line = diagnostic.line.saturating_sub(1);
+    let source = text.lines().nth(line).unwrap_or_default();
+    let start = source.len() - source.trim_start().len();
+    let severity = match diagnostic.severity {
+        Severity::Error => SEVERITY_ERROR,
+        Severity::Warning => SEVERITY_WARNING,
+    };
+    Json::object(vec![
+        ("range", range(text, line, start, source.trim_end().len())),
+        ("severity", Json::from(severity)),
+        ("source", Json::from("chip8_assembler")),
+        ("message", Json::from(diagnostic.message())),
+    ])
+}
+
+fn location(path: &Path, text: &str, token: &Token) -> Json {
+    Json::object(vec![
+        ("uri", Json::from(uri_of(path))),
+        ("range", range(text, token.line, token.start, token.end)),
+    ])
+}
+
+/// A range on one line, from byte offsets into it.
+fn range(text: &str, line: usize, start: usize, end: usize) -> Json {
+    let source = text.lines().nth(line).unwrap_or_default();
+    let point = |offset: usize| {
+        Json::object(vec![
+            ("line", Json::from(line)),
+            ("character", Json::from(character(source, offset))),
+        ])
+    };
+    Json::object(vec![("start", point(start)), ("end", point(end))])
+}
+
+/// Positions count UTF-16 code units.
+fn character(line: &str, offset: usize) -> usize {
+    line.get(..offset).map_or(0, |s| s.encode_utf16().count())
+}
+
+fn byte_offset(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= character {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn path_of(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = encoded
+            .get(idx + 1..idx + 3)
+            .filter(|_| bytes[idx] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    Some(normalize(Path::new(&*String::from_utf8_lossy(&decoded))))
+}
+
+fn uri_of(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
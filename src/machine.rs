@@ -0,0 +1,398 @@
+//! A CHIP-8 interpreter, used to run assembled programs without an external emulator.
+
+use crate::coverage::Coverage;
+use crate::instruction::{disassemble_instruction, Addr, Instruction, Vx};
+use crate::{Target, PROGRAM_START};
+use std::fmt;
+
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+
+/// Address the built-in hex font is loaded at.
+pub const FONT_START: u16 = 0x050;
+
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineErr {
+    ProgramTooLarge(usize),
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, addr: usize },
+}
+
+impl fmt::Display for MachineErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MachineErr::ProgramTooLarge(len) => {
+                write!(f, "program of {} bytes doesn't fit in memory", len)
+            }
+            MachineErr::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, pc)
+            }
+            MachineErr::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            MachineErr::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            MachineErr::MemoryOutOfBounds { pc, addr } => {
+                write!(f, "memory access at {:X} out of bounds at {:03X}", addr, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MachineErr {}
+
+/// The complete state of a CHIP-8 interpreter.
+#[derive(Clone)]
+pub struct Machine {
+    pub target: Target,
+    pub memory: [u8; MEMORY_SIZE],
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// One `u64` per row, with the leftmost pixel in the most significant bit.
+    pub display: [u64; DISPLAY_HEIGHT],
+    pub keys: [bool; 16],
+    /// Total number of instructions executed.
+    pub cycles: u64,
+    /// What has been run, if it's being recorded.
+    pub coverage: Option<Coverage>,
+    pub(crate) rng: u32,
+}
+
+impl Machine {
+    pub fn new(target: Target) -> Self {
+        let mut memory = [0; MEMORY_SIZE];
+        let font = FONT_START as usize;
+        memory[font..font + FONT.len()].copy_from_slice(&FONT);
+
+        Machine {
+            target,
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: PROGRAM_START,
+            stack: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            display: [0; DISPLAY_HEIGHT],
+            keys: [false; 16],
+            cycles: 0,
+            coverage: None,
+            rng: 0x2545_F491,
+        }
+    }
+
+    /// Creates a machine with `program` loaded at [`PROGRAM_START`].
+    pub fn with_program(target: Target, program: &[u8]) -> Result<Self, MachineErr> {
+        let mut machine = Machine::new(target);
+        machine.load(PROGRAM_START, program)?;
+        Ok(machine)
+    }
+
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> Result<(), MachineErr> {
+        let start = addr as usize;
+        if start + bytes.len() > MEMORY_SIZE {
+            return Err(MachineErr::ProgramTooLarge(bytes.len()));
+        }
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Seeds the generator used by `RND`, so runs can be reproduced.
+    pub fn seed(&mut self, seed: u32) {
+        self.rng = if seed == 0 { 1 } else { seed };
+    }
+
+    pub fn opcode_at(&self, addr: u16) -> u16 {
+        let addr = addr as usize % MEMORY_SIZE;
+        u16::from(self.memory[addr]) << 8 | u16::from(self.memory[(addr + 1) % MEMORY_SIZE])
+    }
+
+    /// Decodes the instruction the machine is about to execute.
+    pub fn current_instruction(&self) -> Result<Instruction, MachineErr> {
+        let opcode = self.opcode_at(self.pc);
+        disassemble_instruction(opcode).ok_or(MachineErr::InvalidOpcode {
+            pc: self.pc,
+            opcode,
+        })
+    }
+
+    /// Whether the next instruction jumps to itself, which is the usual way to end a program.
+    pub fn halted(&self) -> bool {
+        self.current_instruction() == Ok(Instruction::Jmp(Addr(self.pc)))
+    }
+
+    /// Whether the next instruction is a `LD Vx, K` with no key held.
+    pub fn waiting_for_key(&self) -> bool {
+        match self.current_instruction() {
+            Ok(Instruction::LoadKey(_)) => !self.keys.iter().any(|&k| k),
+            _ => false,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.display[y % DISPLAY_HEIGHT] & (1 << (DISPLAY_WIDTH - 1 - x % DISPLAY_WIDTH)) != 0
+    }
+
+    /// Counts down the delay and sound timers, as happens once every 60th of a second.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// Runs one frame's worth of instructions and then ticks the timers. Stops early if the
+    /// program halts or waits for a key.
+    pub fn run_frame(&mut self) -> Result<(), MachineErr> {
+        for _ in 0..self.target.cycles_per_frame() {
+            if self.halted() || self.waiting_for_key() {
+                break;
+            }
+            self.step()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<(), MachineErr> {
+        let instr = self.current_instruction()?;
+        let pc = self.pc;
+        self.pc = pc.wrapping_add(2) & 0x0FFF;
+        self.cycles += 1;
+
+        match instr {
+            Instruction::Cls => self.display = [0; DISPLAY_HEIGHT],
+            Instruction::Ret => {
+                self.pc = self.stack.pop().ok_or(MachineErr::StackUnderflow { pc })?;
+            }
+            // Machine code routines aren't supported, so these do nothing, as on most
+            // interpreters written after the VIP.
+            Instruction::Sys(_) => {}
+            Instruction::Jmp(addr) => self.pc = addr.0,
+            Instruction::Call(addr) => {
+                if self.stack.len() >= self.target.stack_size() {
+                    return Err(MachineErr::StackOverflow { pc });
+                }
+                self.stack.push(self.pc);
+                self.pc = addr.0;
+            }
+            Instruction::SkipEq(vx, constant) => self.skip_if(self.v[vx.0 as usize] == constant),
+            Instruction::SkipNotEq(vx, constant) => self.skip_if(self.v[vx.0 as usize] != constant),
+            Instruction::SkipEqVx(vx, vy) => self.skip_if(self.reg(vx) == self.reg(vy)),
+            Instruction::Load(vx, constant) => self.set(vx, constant),
+            Instruction::Add(vx, constant) => self.set(vx, self.reg(vx).wrapping_add(constant)),
+            Instruction::LoadVx(vx, vy) => self.set(vx, self.reg(vy)),
+            Instruction::Or(vx, vy) => self.logic(vx, self.reg(vx) | self.reg(vy)),
+            Instruction::And(vx, vy) => self.logic(vx, self.reg(vx) & self.reg(vy)),
+            Instruction::XOr(vx, vy) => self.logic(vx, self.reg(vx) ^ self.reg(vy)),
+            Instruction::AddVx(vx, vy) => {
+                let (result, carry) = self.reg(vx).overflowing_add(self.reg(vy));
+                self.set_with_flag(vx, result, carry);
+            }
+            Instruction::SubVx(vx, vy) => {
+                let (result, borrow) = self.reg(vx).overflowing_sub(self.reg(vy));
+                self.set_with_flag(vx, result, !borrow);
+            }
+            Instruction::SubN(vx, vy) => {
+                let (result, borrow) = self.reg(vy).overflowing_sub(self.reg(vx));
+                self.set_with_flag(vx, result, !borrow);
+            }
+            Instruction::ShiftRight(vx, vy) => {
+                let source = self.reg(if self.target.shifts_in_place() {
+                    vx
+                } else {
+                    vy
+                });
+                self.set_with_flag(vx, source >> 1, source & 0x01 != 0);
+            }
+            Instruction::ShiftLeft(vx, vy) => {
+                let source = self.reg(if self.target.shifts_in_place() {
+                    vx
+                } else {
+                    vy
+                });
+                self.set_with_flag(vx, source << 1, source & 0x80 != 0);
+            }
+            Instruction::SkipNotEqVx(vx, vy) => self.skip_if(self.reg(vx) != self.reg(vy)),
+            Instruction::LoadI(addr) => self.i = addr.0,
+            Instruction::JmpV0(addr) => {
+                let offset = if self.target.jump_uses_vx() {
+                    self.reg(Vx((addr.0 >> 8) as u8))
+                } else {
+                    self.v[0]
+                };
+                self.pc = (addr.0 + u16::from(offset)) & 0x0FFF;
+            }
+            Instruction::Rand(vx, constant) => {
+                let random = self.next_random();
+                self.set(vx, random & constant);
+            }
+            Instruction::Draw(vx, vy, height) => self.draw(pc, vx, vy, height)?,
+            Instruction::SkipKeyPressed(vx) => self.skip_if(self.key(vx)),
+            Instruction::SkipKeyNotPressed(vx) => self.skip_if(!self.key(vx)),
+            Instruction::LoadDelay(vx) => self.set(vx, self.delay_timer),
+            Instruction::LoadKey(vx) => match self.keys.iter().position(|&k| k) {
+                Some(key) => self.set(vx, key as u8),
+                None => self.pc = pc,
+            },
+            Instruction::SetDelay(vx) => self.delay_timer = self.reg(vx),
+            Instruction::SetSound(vx) => self.sound_timer = self.reg(vx),
+            Instruction::AddI(vx) => self.i = self.i.wrapping_add(u16::from(self.reg(vx))),
+            Instruction::LoadFont(vx) => {
+                self.i = FONT_START + u16::from(self.reg(vx) & 0x0F) * 5;
+            }
+            Instruction::LoadBcd(vx) => {
+                let value = self.reg(vx);
+                self.write(pc, 0, value / 100)?;
+                self.write(pc, 1, value / 10 % 10)?;
+                self.write(pc, 2, value % 10)?;
+            }
+            Instruction::StoreRegisters(vx) => {
+                for x in 0..=vx.0 {
+                    self.write(pc, u16::from(x), self.v[x as usize])?;
+                }
+                self.i = self.i.wrapping_add(self.target.load_store_increment(vx.0));
+            }
+            Instruction::LoadRegisters(vx) => {
+                for x in 0..=vx.0 {
+                    self.v[x as usize] = self.read(pc, u16::from(x))?;
+                }
+                self.i = self.i.wrapping_add(self.target.load_store_increment(vx.0));
+            }
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, instr, self.pc);
+        }
+        Ok(())
+    }
+
+    fn reg(&self, vx: Vx) -> u8 {
+        self.v[vx.0 as usize]
+    }
+
+    fn set(&mut self, vx: Vx, value: u8) {
+        self.v[vx.0 as usize] = value;
+    }
+
+    /// Sets `Vx` and then `VF`, so the flag wins when `Vx` is `VF`.
+    fn set_with_flag(&mut self, vx: Vx, value: u8, flag: bool) {
+        self.set(vx, value);
+        self.v[0xF] = flag as u8;
+    }
+
+    fn logic(&mut self, vx: Vx, value: u8) {
+        self.set(vx, value);
+        if self.target.logic_resets_vf() {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn key(&self, vx: Vx) -> bool {
+        self.keys[(self.reg(vx) & 0x0F) as usize]
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = (self.pc + 2) & 0x0FFF;
+        }
+    }
+
+    /// The address `offset` bytes past `I`, which is an error if it's outside of memory.
+    fn past_i(&self, pc: u16, offset: u16) -> Result<usize, MachineErr> {
+        let addr = usize::from(self.i) + usize::from(offset);
+        if addr < MEMORY_SIZE {
+            Ok(addr)
+        } else {
+            Err(MachineErr::MemoryOutOfBounds { pc, addr })
+        }
+    }
+
+    /// Reads the byte `offset` bytes past `I`.
+    fn read(&self, pc: u16, offset: u16) -> Result<u8, MachineErr> {
+        Ok(self.memory[self.past_i(pc, offset)?])
+    }
+
+    /// Writes the byte `offset` bytes past `I`.
+    fn write(&mut self, pc: u16, offset: u16, value: u8) -> Result<(), MachineErr> {
+        let addr = self.past_i(pc, offset)?;
+        self.memory[addr] = value;
+        Ok(())
+    }
+
+    fn draw(&mut self, pc: u16, vx: Vx, vy: Vx, height: u8) -> Result<(), MachineErr> {
+        let (rows, width) = match height {
+            0 if self.target.draws_large_sprites() => (16, 16),
+            _ => (height as usize, 8),
+        };
+        let x = self.reg(vx) as usize % DISPLAY_WIDTH;
+        let y = self.reg(vy) as usize % DISPLAY_HEIGHT;
+
+        let mut collision = false;
+        for row in 0..rows {
+            if y + row >= DISPLAY_HEIGHT {
+                break;
+            }
+            let sprite = if width == 16 {
+                u64::from(self.read(pc, row as u16 * 2)?) << 8
+                    | u64::from(self.read(pc, row as u16 * 2 + 1)?)
+            } else {
+                u64::from(self.read(pc, row as u16)?)
+            };
+            // Align the sprite to the left edge and then move it right, clipping at the edge.
+            let bits = (sprite << (DISPLAY_WIDTH - width)) >> x;
+            let line = &mut self.display[y + row];
+            collision |= *line & bits != 0;
+            *line ^= bits;
+        }
+        self.v[0xF] = collision as u8;
+
+        Ok(())
+    }
+
+    fn next_random(&mut self) -> u8 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        (x >> 24) as u8
+    }
+}
+
+impl fmt::Debug for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Machine")
+            .field("target", &self.target)
+            .field("v", &self.v)
+            .field("i", &self.i)
+            .field("pc", &self.pc)
+            .field("stack", &self.stack)
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("cycles", &self.cycles)
+            .finish()
+    }
+}
@@ -1,8 +1,972 @@
-use chip8_assembler::assemble_file;
+use chip8_assembler::debugger::Debugger;
+use chip8_assembler::machine::{Machine, MachineErr, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip8_assembler::{
+    assemble_path, compile_path, disassemble, format_source, gdb, link, lint, listing,
+    parse_number, read_object, run_tests, run_tests_with_coverage, timing_listing, tui, Assembly,
+    Coverage, CoverageFormat, CoverageReport, Diagnostic, DiagnosticKind, Diagnostics,
+    FileProvider, FormatStyle, Layout, MnemonicCase, ObjectFile, Options, Target, TraceFormat,
+    TraceOptions, Tracer, DEFAULT_CYCLE_LIMIT,
+};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Everything went fine.
+const EXIT_SUCCESS: i32 = 0;
+/// The program failed to assemble, or failed while running.
+const EXIT_FAILURE: i32 = 1;
+/// The command line couldn't be understood.
+const EXIT_USAGE: i32 = 2;
+/// A file couldn't be read or written.
+const EXIT_IO: i32 = 3;
+
+/// How often `--watch` checks whether files have changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Extension of object files, which `link` reads rather than assembling.
+const OBJECT_EXTENSION: &str = "c8o";
+
+const USAGE: &str = "\
+Usage: chip8_assembler <COMMAND> [OPTIONS] <INPUT>...
+
+Commands:
+  assemble  Assemble a source file into a ROM, or an object file with `--format obj`
+  link      Link object files or source files into a ROM
+  disasm    Disassemble a ROM into source
+  run       Run a source file or ROM headlessly and print the final state
+  fmt       Reformat a source file in place, keeping its comments and blank lines
+  check     Assemble a source file without writing any output, warning about likely bugs
+  test      Run the TEST blocks in a source file on the built-in interpreter
+  debug     Step through a source file or ROM on the built-in interpreter, showing its
+            source, registers and display
+
+Options:
+  -o, --output <FILE>    Where to write output, or `-` for stdout. Defaults to the input
+                         name with an extension matching the format, or stdout for disasm
+  -t, --target <TARGET>  Interpreter to build for: chip8, chip48, schip [default: chip8]
+  -f, --format <FORMAT>  Output format for assemble: bin, hex, obj [default: bin]
+      --base <ADDR>      Address the program, or a ROM given to run or debug, is loaded at
+                         [default: 0x200]
+      --layout <SPEC>    Where to place sections, as a comma separated list of `NAME=ADDR`
+                         to place a section at an address, or `NAME` to place it after the
+                         previous one
+      --layout-file <FILE>
+                         Read the layout from FILE, one section per line
+      --map <FILE>       Write a memory map of the program to FILE, or `-` for stdout
+      --symbols <FILE>   Write the symbol table to FILE as an ELF file, for a debugger to load
+      --listing <FILE>   Write a listing of the source with the address and bytes of each
+                         line to FILE, or `-` for stdout. Pseudo-instructions are shown with
+                         their expansions
+      --timing           Show how many COSMAC VIP machine cycles each line, basic block
+                         and subroutine takes in the listing
+  -D <NAME>[=<VALUE>]    Define a symbol, as if by DEFINE. VALUE defaults to 1
+  -I <DIR>               Search DIR for included files
+      --frames <N>       Number of frames to run for [default: 600]
+      --cycles <N>       Number of instructions each test may run [default: 100000]
+      --trace <FILE>     For run, write each instruction run, the label it's under and the
+                         registers it changed to FILE, or `-` for stdout
+      --trace-format <FORMAT>
+                         Format of the trace: text, bin [default: text]
+      --trace-filter <SPEC>
+                         Only trace the addresses in SPEC, a comma separated list of
+                         addresses, labels, and `START-END` ranges of them. A label on its
+                         own covers everything up to the next label
+      --trace-limit <BYTES>
+                         Stop tracing once the trace would grow past BYTES
+      --coverage <FILE>  For run and test, write which lines were run, and which ways each
+                         skip went, to FILE, or `-` for stdout. Test coverage leaves out the
+                         lines of the tests themselves
+      --coverage-format <FORMAT>
+                         Format of the coverage report: text, lcov, html [default: text]
+      --check            For fmt, don't write anything, but fail if the file isn't formatted
+      --case <CASE>      Case of mnemonics for fmt: upper, lower [default: upper]
+      --indent <N>       Spaces statements are indented by for fmt, and again for each
+                         block they're nested in [default: 4]
+      --separator <SEP>  What fmt writes between operands [default: \", \"]
+      --comment-column <N>
+                         Column fmt lines up comments after statements in [default: 32]
+      --gdb <PORT>       For debug, wait for a debugger speaking the GDB remote protocol on
+                         localhost PORT, rather than showing the terminal debugger
+  -w, --watch            Run the command again whenever the input, or any file it includes,
+                         changes. Only new diagnostics are printed each time
+  -q, --quiet            Only print errors
+  -v, --verbose          Print the symbol table and other details
+  -h, --help             Print this message
+  -V, --version          Print the version
+
+Addresses and values given to --base, --layout, --layout-file, -D and --trace-filter are
+written as in source: hex unless prefixed with `#` for decimal or `0b` for binary, so
+`--base 200` and `--base 0x200` are the same. Counts and ports are decimal.
+
+Exit codes:
+  0  success
+  1  assembly or runtime error
+  2  invalid command line
+  3  I/O error
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Assemble,
+    Link,
+    Disasm,
+    Run,
+    Fmt,
+    Check,
+    Test,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Bin,
+    Hex,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Debug, Clone)]
+struct Args {
+    command: Command,
+    input: PathBuf,
+    /// Further inputs, which only `link` accepts.
+    more_inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    format: Format,
+    map: Option<PathBuf>,
+    symbols: Option<PathBuf>,
+    listing: Option<PathBuf>,
+    timing: bool,
+    /// For `fmt`, only check whether the input is formatted.
+    check: bool,
+    style: FormatStyle,
+    watch: bool,
+    /// For `debug`, the port to serve the GDB remote protocol on.
+    gdb: Option<u16>,
+    frames: u64,
+    cycles: u64,
+    /// For `run`, where to write a trace of the instructions run.
+    trace: Option<PathBuf>,
+    trace_format: TraceFormat,
+    trace_filter: Option<String>,
+    trace_limit: Option<usize>,
+    /// For `run` and `test`, where to write a coverage report.
+    coverage: Option<PathBuf>,
+    coverage_format: CoverageFormat,
+    verbosity: Verbosity,
+    opts: Options,
+}
+
+#[derive(Debug)]
+enum CliErr {
+    Usage(String),
+    Io(PathBuf, io::Error),
+    Assemble(Diagnostics),
+    Machine(MachineErr),
+    /// `fmt --check` found a file which would be reformatted.
+    Unformatted(PathBuf),
+    /// Some of the tests run by `test` failed, out of how many.
+    TestsFailed(usize, usize),
+}
+
+impl CliErr {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliErr::Usage(_) => EXIT_USAGE,
+            CliErr::Io(..) => EXIT_IO,
+            CliErr::Assemble(diagnostics)
+                if diagnostics
+                    .iter()
+                    .any(|d| matches!(d.kind, DiagnosticKind::Io(_))) =>
+            {
+                EXIT_IO
+            }
+            CliErr::Assemble(_)
+            | CliErr::Machine(_)
+            | CliErr::Unformatted(_)
+            | CliErr::TestsFailed(..) => EXIT_FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for CliErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliErr::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            CliErr::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            CliErr::Assemble(err) => write!(f, "{}", err),
+            CliErr::Machine(err) => write!(f, "{}", err),
+            CliErr::Unformatted(path) => write!(f, "{} isn't formatted", path.display()),
+            CliErr::TestsFailed(failed, total) => write!(f, "{} of {} tests failed", failed, total),
+        }
+    }
+}
+
+impl From<Diagnostics> for CliErr {
+    fn from(err: Diagnostics) -> Self {
+        CliErr::Assemble(err)
+    }
+}
+
+impl From<MachineErr> for CliErr {
+    fn from(err: MachineErr) -> Self {
+        CliErr::Machine(err)
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let code = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) if args.watch => watch(args),
+        Ok(Some(args)) => match run(&args) {
+            Ok(()) => EXIT_SUCCESS,
+            // Diagnostics already say which are errors.
+            Err(CliErr::Assemble(diagnostics)) => {
+                eprintln!("{}", diagnostics);
+                CliErr::Assemble(diagnostics).exit_code()
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                err.exit_code()
+            }
+        },
+        Ok(None) => EXIT_SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            err.exit_code()
+        }
+    };
+    process::exit(code);
+}
+
+/// Remembers every file read through it, and when it was last modified before it was read, so
+/// `--watch` knows which files to watch and can tell if they change even while being read.
+#[derive(Debug)]
+struct Recording {
+    files: Arc<dyn FileProvider>,
+    read: Mutex<BTreeMap<PathBuf, Option<SystemTime>>>,
+}
+
+impl FileProvider for Recording {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| modified(path));
+        self.files.read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.is_file(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        self.files.canonicalize(path)
+    }
+}
+
+/// Runs the command, then runs it again whenever a file it read changes, until interrupted.
+/// Diagnostics already printed by the previous run aren't printed again.
+fn watch(mut args: Args) -> ! {
+    let recording = Arc::new(Recording {
+        files: args.opts.files.clone(),
+        read: Mutex::default(),
+    });
+    args.opts.files = recording.clone();
+    args.opts.cache.get_or_insert_with(Default::default);
+
+    let mut previous: Vec<Diagnostic> = Vec::new();
+    loop {
+        recording.read.lock().unwrap().clear();
+        let mut files: BTreeMap<PathBuf, Option<SystemTime>> = iter::once(&args.input)
+            .chain(&args.more_inputs)
+            .map(|path| (path.clone(), modified(path)))
+            .collect();
+        let diagnostics = match run(&args) {
+            Ok(()) => Vec::new(),
+            Err(CliErr::Assemble(diagnostics)) => diagnostics.0,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                Vec::new()
+            }
+        };
+        let mut unchanged = 0;
+        for diagnostic in &diagnostics {
+            if previous.contains(diagnostic) {
+                unchanged += 1;
+            } else {
+                eprintln!("{}", diagnostic);
+            }
+        }
+        let fixed = previous.iter().filter(|d| !diagnostics.contains(d)).count();
+        if args.verbosity >= Verbosity::Normal && (fixed > 0 || unchanged > 0) {
+            eprintln!("{} fixed, {} unchanged", fixed, unchanged);
+        }
+
+        files.extend(recording.read.lock().unwrap().clone());
+        for diagnostic in &diagnostics {
+            files
+                .entry(diagnostic.file.clone())
+                .or_insert_with(|| modified(&diagnostic.file));
+        }
+        previous = diagnostics;
+
+        if args.verbosity >= Verbosity::Normal {
+            eprintln!("watching {} files for changes", files.len());
+        }
+        let changed = wait_for_change(&files);
+        if args.verbosity >= Verbosity::Normal {
+            eprintln!("\n{} changed", changed.display());
+        }
+    }
+}
+
+/// When a file was last modified, or `None` if it can't be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Polls files until one is modified, created or deleted since the time given for it,
+/// returning which.
+fn wait_for_change(files: &BTreeMap<PathBuf, Option<SystemTime>>) -> PathBuf {
+    loop {
+        for (path, before) in files {
+            if modified(path) != *before {
+                return path.clone();
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Parses the command line, returning `None` if it only asked for help or the version.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, CliErr> {
+    let mut command = None;
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut format = Format::Bin;
+    let mut map = None;
+    let mut symbols = None;
+    let mut listing = None;
+    let mut timing = false;
+    let mut check = false;
+    let mut style = FormatStyle::default();
+    let mut watch = false;
+    let mut gdb = None;
+    let mut frames = 600;
+    let mut cycles = DEFAULT_CYCLE_LIMIT;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_filter = None;
+    let mut trace_limit = None;
+    let mut coverage = None;
+    let mut coverage_format = CoverageFormat::default();
+    let mut verbosity = Verbosity::Normal;
+    let mut opts = Options::default();
+
+    while let Some(arg) = args.next() {
+        // Support both `--flag value` and `--flag=value`, as well as `-Dvalue`.
+        let (flag, inline) = match arg.find('=') {
+            Some(idx) if arg.starts_with("--") => {
+                (&arg[..idx], Some(String::from(&arg[idx + 1..])))
+            }
+            _ if arg.len() > 2 && (arg.starts_with("-D") || arg.starts_with("-I")) => {
+                (&arg[..2], Some(String::from(&arg[2..])))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliErr::Usage(format!("`{}` requires a value", name)))
+        };
+
+        match flag {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(None);
+            }
+            "-V" | "--version" => {
+                println!("chip8_assembler {}", env!("CARGO_PKG_VERSION"));
+                return Ok(None);
+            }
+            "-o" | "--output" => output = Some(PathBuf::from(value(flag)?)),
+            "-t" | "--target" => {
+                opts.target = value(flag)?.parse::<Target>().map_err(CliErr::Usage)?;
+            }
+            "-f" | "--format" => {
+                format = match value(flag)?.as_str() {
+                    "bin" => Format::Bin,
+                    "hex" => Format::Hex,
+                    "obj" => Format::Object,
+                    other => return Err(CliErr::Usage(format!("unknown format `{}`", other))),
+                }
+            }
+            "-D" => {
+                let define = value(flag)?;
+                let (name, number) = match define.find('=') {
+                    Some(idx) => (&define[..idx], parse_address(flag, &define[idx + 1..])?),
+                    None => (define.as_str(), 1),
+                };
+                opts.defines.insert(String::from(name), number);
+            }
+            "--base" => opts.layout.base = parse_address(flag, &value(flag)?)?,
+            "--layout" => add_layout(&mut opts.layout, &value(flag)?, flag)?,
+            "--layout-file" => {
+                let path = PathBuf::from(value(flag)?);
+                let spec = fs::read_to_string(&path).map_err(|err| CliErr::Io(path, err))?;
+                add_layout(&mut opts.layout, &spec, flag)?;
+            }
+            "--map" => map = Some(PathBuf::from(value(flag)?)),
+            "--symbols" => symbols = Some(PathBuf::from(value(flag)?)),
+            "--gdb" => gdb = Some(parse_count(flag, &value(flag)?)?),
+            "--listing" => listing = Some(PathBuf::from(value(flag)?)),
+            "--timing" => timing = true,
+            "--check" => check = true,
+            "-w" | "--watch" => watch = true,
+            "--case" => {
+                style.case = match value(flag)?.as_str() {
+                    "upper" => MnemonicCase::Upper,
+                    "lower" => MnemonicCase::Lower,
+                    other => return Err(CliErr::Usage(format!("unknown case `{}`", other))),
+                }
+            }
+            "--indent" => style.indent = usize::from(parse_count(flag, &value(flag)?)?),
+            "--separator" => style.separator = value(flag)?,
+            "--comment-column" => {
+                style.comment_column = usize::from(parse_count(flag, &value(flag)?)?)
+            }
+            "-I" => opts.include_paths.push(PathBuf::from(value(flag)?)),
+            "--frames" => {
+                let frames_arg = value(flag)?;
+                frames = frames_arg
+                    .parse()
+                    .map_err(|_| CliErr::Usage(format!("invalid frame count `{}`", frames_arg)))?;
+            }
+            "--cycles" => {
+                let cycles_arg = value(flag)?;
+                cycles = cycles_arg
+                    .parse()
+                    .map_err(|_| CliErr::Usage(format!("invalid cycle count `{}`", cycles_arg)))?;
+            }
+            "--trace" => trace = Some(PathBuf::from(value(flag)?)),
+            "--trace-format" => {
+                trace_format = value(flag)?.parse().map_err(CliErr::Usage)?;
+            }
+            "--trace-filter" => trace_filter = Some(value(flag)?),
+            "--trace-limit" => {
+                let limit_arg = value(flag)?;
+                trace_limit =
+                    Some(limit_arg.parse().map_err(|_| {
+                        CliErr::Usage(format!("invalid trace limit `{}`", limit_arg))
+                    })?);
+            }
+            "--coverage" => coverage = Some(PathBuf::from(value(flag)?)),
+            "--coverage-format" => {
+                coverage_format = value(flag)?.parse().map_err(CliErr::Usage)?;
+            }
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            _ if flag.starts_with('-') && flag != "-" => {
+                return Err(CliErr::Usage(format!("unknown option `{}`", flag)))
+            }
+            _ if command.is_none() => {
+                command = Some(match flag {
+                    "assemble" => Command::Assemble,
+                    "link" => Command::Link,
+                    "disasm" => Command::Disasm,
+                    "run" => Command::Run,
+                    "fmt" => Command::Fmt,
+                    "check" => Command::Check,
+                    "test" => Command::Test,
+                    "debug" => Command::Debug,
+                    other => return Err(CliErr::Usage(format!("unknown command `{}`", other))),
+                })
+            }
+            _ => inputs.push(PathBuf::from(flag)),
+        }
+    }
+
+    let command = command.ok_or_else(|| CliErr::Usage(String::from("no command given")))?;
+    if inputs.is_empty() {
+        return Err(CliErr::Usage(String::from("no input file given")));
+    }
+    let input = inputs.remove(0);
+    if let Some(extra) = inputs.first().filter(|_| command != Command::Link) {
+        return Err(CliErr::Usage(format!(
+            "unexpected argument `{}`",
+            extra.display()
+        )));
+    }
+    if format == Format::Object && command == Command::Link {
+        return Err(CliErr::Usage(String::from(
+            "link can't produce an object file",
+        )));
+    }
+    if timing && listing.is_none() {
+        return Err(CliErr::Usage(String::from("--timing needs --listing")));
+    }
+    if trace.is_none() && (trace_filter.is_some() || trace_limit.is_some()) {
+        return Err(CliErr::Usage(String::from(
+            "--trace-filter and --trace-limit need --trace",
+        )));
+    }
+    Ok(Some(Args {
+        command,
+        input,
+        more_inputs: inputs,
+        output,
+        format,
+        map,
+        symbols,
+        listing,
+        timing,
+        check,
+        style,
+        watch,
+        gdb,
+        frames,
+        cycles,
+        trace,
+        trace_format,
+        trace_filter,
+        trace_limit,
+        coverage,
+        coverage_format,
+        verbosity,
+        opts,
+    }))
+}
+
+fn add_layout(layout: &mut Layout, spec: &str, flag: &str) -> Result<(), CliErr> {
+    let parsed = spec
+        .parse::<Layout>()
+        .map_err(|err| CliErr::Usage(format!("{} for {}", err, flag)))?;
+    layout.sections.extend(parsed.sections);
+    Ok(())
+}
+
+/// Parses an address or value given on the command line, written as it would be in source.
+fn parse_address(flag: &str, value: &str) -> Result<u16, CliErr> {
+    parse_number(value)
+        .and_then(|number| u16::try_from(number).ok())
+        .ok_or_else(|| CliErr::Usage(format!("invalid value `{}` for {}", value, flag)))
+}
+
+/// Parses a count or port given on the command line, which is decimal unless prefixed.
+fn parse_count(flag: &str, value: &str) -> Result<u16, CliErr> {
+    let parsed = if let Some(hex) = value.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = value.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| CliErr::Usage(format!("invalid value `{}` for {}", value, flag)))
+}
+
+fn run(args: &Args) -> Result<(), CliErr> {
+    match args.command {
+        Command::Assemble if args.format == Format::Object => {
+            let object = compile(args, &args.input)?;
+            let output = args
+                .output
+                .clone()
+                .unwrap_or_else(|| args.input.with_extension(OBJECT_EXTENSION));
+            write_output(&output, object.to_string().as_bytes())?;
+
+            if args.verbosity >= Verbosity::Verbose {
+                for section in &object.sections {
+                    eprintln!("section {}: {} bytes", section.name, section.bytes.len());
+                }
+                eprintln!("wrote {}", output.display());
+            }
+            Ok(())
+        }
+        Command::Assemble => {
+            let assembly = assemble(args)?;
+            write_program(args, &assembly)
+        }
+        Command::Link => {
+            let mut objects = Vec::new();
+            for input in std::iter::once(&args.input).chain(&args.more_inputs) {
+                objects.push(compile(args, input)?);
+            }
+            let assembly = link(&objects, &args.opts.layout)?;
+            report(args, &assembly);
+            write_program(args, &assembly)
+        }
+        Command::Check => {
+            let assembly = assemble(args)?;
+            if args.verbosity >= Verbosity::Normal {
+                for warning in lint(&assembly, args.opts.files.as_ref(), args.opts.target) {
+                    eprintln!("{}", warning);
+                }
+            }
+            if args.verbosity >= Verbosity::Verbose {
+                eprintln!("{}: ok", args.input.display());
+            }
+            Ok(())
+        }
+        Command::Disasm => {
+            let bytes = read_input(&args.input)?;
+            let output = args.output.clone().unwrap_or_else(|| PathBuf::from("-"));
+            write_output(&output, disassemble(&bytes).as_bytes())
+        }
+        Command::Run => {
+            let assembly = read_program(args)?;
+
+            if args.coverage.is_some() && assembly.source_map.is_empty() {
+                return Err(CliErr::Usage(String::from(
+                    "--coverage needs a source file rather than a ROM",
+                )));
+            }
+            let mut machine = Machine::new(args.opts.target);
+            machine.load(assembly.base, &assembly.bytes)?;
+            machine.pc = assembly.base;
+            if args.coverage.is_some() {
+                machine.coverage = Some(Coverage::new());
+            }
+            let result = match &args.trace {
+                Some(path) => {
+                    let ranges = match &args.trace_filter {
+                        Some(spec) => {
+                            TraceOptions::parse_ranges(spec, &assembly).map_err(CliErr::Usage)?
+                        }
+                        None => Vec::new(),
+                    };
+                    let options = TraceOptions {
+                        format: args.trace_format,
+                        ranges,
+                        limit: args.trace_limit,
+                    };
+                    let mut tracer = Tracer::new(&assembly, options);
+                    // Write what was traced even if the program fails, as that's when it's
+                    // most wanted.
+                    let result = run_machine(args, &mut machine, |m| tracer.run_frame(m));
+                    write_output(path, &tracer.into_bytes())?;
+                    result
+                }
+                None => run_machine(args, &mut machine, Machine::run_frame),
+            };
+            if machine.halted() {
+                let pc = machine.pc;
+                if let Some(coverage) = &mut machine.coverage {
+                    coverage.hit(pc);
+                }
+            }
+            if let (Some(path), Some(coverage)) = (&args.coverage, &machine.coverage) {
+                let report = CoverageReport::new(&assembly, coverage, &*args.opts.files);
+                write_output(path, report.render(args.coverage_format).as_bytes())?;
+            }
+            result?;
+
+            if args.verbosity >= Verbosity::Normal {
+                print_machine(&machine);
+            }
+            Ok(())
+        }
+        Command::Debug => {
+            let assembly = read_program(args)?;
+            if let Some(path) = &args.symbols {
+                write_output(path, &gdb::symbol_file(&assembly))?;
+            }
+            let mut debugger = Debugger::new(assembly, args.opts.target, &*args.opts.files)?;
+            match args.gdb {
+                Some(port) => serve_gdb(args, &mut debugger, port),
+                None => {
+                    let stdin = io::stdin();
+                    tui::debug(&mut debugger, stdin.lock(), io::stdout())
+                        .map_err(|err| CliErr::Io(PathBuf::from("-"), err))
+                }
+            }
+        }
+        Command::Test => {
+            let results = match &args.coverage {
+                Some(path) => {
+                    let (results, report) =
+                        run_tests_with_coverage(&args.input, &args.opts, args.cycles)?;
+                    write_output(path, report.render(args.coverage_format).as_bytes())?;
+                    results
+                }
+                None => run_tests(&args.input, &args.opts, args.cycles)?,
+            };
+            let failed = results.iter().filter(|r| !r.passed()).count();
+            for result in &results {
+                let status = if result.passed() { "ok" } else { "FAIL" };
+                if args.verbosity >= Verbosity::Normal || !result.passed() {
+                    println!(
+                        "{:4}  {} ({}:{})",
+                        status,
+                        result.name,
+                        result.file.display(),
+                        result.line
+                    );
+                }
+                for failure in &result.failures {
+                    println!(
+                        "        {}:{}: {}",
+                        failure.file.display(),
+                        failure.line,
+                        failure.message
+                    );
+                }
+            }
+            if args.verbosity >= Verbosity::Normal {
+                println!(
+                    "{} tests, {} passed, {} failed",
+                    results.len(),
+                    results.len() - failed,
+                    failed
+                );
+            }
+            if failed > 0 {
+                return Err(CliErr::TestsFailed(failed, results.len()));
+            }
+            Ok(())
+        }
+        Command::Fmt => {
+            let bytes = read_input(&args.input)?;
+            let text = String::from_utf8_lossy(&bytes);
+            let formatted = format_source(&text, &args.style);
+            if args.check {
+                return if formatted == text {
+                    Ok(())
+                } else {
+                    Err(CliErr::Unformatted(args.input.clone()))
+                };
+            }
+            let output = args.output.clone().unwrap_or_else(|| args.input.clone());
+            // Leave files which are already formatted untouched.
+            if output == args.input && formatted == text {
+                return Ok(());
+            }
+            write_output(&output, formatted.as_bytes())
+        }
+    }
+}
+
+/// Writes an assembled program in the requested format.
+fn write_program(args: &Args, assembly: &Assembly) -> Result<(), CliErr> {
+    let (bytes, extension) = match args.format {
+        Format::Bin => (assembly.bytes.clone(), "ch8"),
+        Format::Hex => (hex_dump(&assembly.bytes).into_bytes(), "hex"),
+        Format::Object => unreachable!("objects aren't linked programs"),
+    };
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.input.with_extension(extension));
+    write_output(&output, &bytes)?;
+    if let Some(map) = &args.map {
+        write_output(map, memory_map(assembly).as_bytes())?;
+    }
+    if let Some(path) = &args.symbols {
+        write_output(path, &gdb::symbol_file(assembly))?;
+    }
+    if let Some(path) = &args.listing {
+        let text = if args.timing {
+            timing_listing(assembly, &*args.opts.files)
+        } else {
+            listing(assembly, &*args.opts.files)
+        };
+        write_output(path, text.as_bytes())?;
+    }
+
+    if args.verbosity >= Verbosity::Verbose {
+        eprintln!(
+            "assembled {} bytes into {}",
+            assembly.bytes.len(),
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// Runs `machine` for the frames asked for, or until it stops or waits for a key.
+fn run_machine(
+    args: &Args,
+    machine: &mut Machine,
+    mut run_frame: impl FnMut(&mut Machine) -> Result<(), MachineErr>,
+) -> Result<(), CliErr> {
+    for _ in 0..args.frames {
+        if machine.halted() || machine.waiting_for_key() {
+            break;
+        }
+        run_frame(machine)?;
+    }
+    Ok(())
+}
+
+/// Waits for a debugger to connect on `port`, and serves it until it detaches.
+fn serve_gdb(args: &Args, debugger: &mut Debugger, port: u16) -> Result<(), CliErr> {
+    let address = format!("127.0.0.1:{}", port);
+    let io_err = |err| CliErr::Io(PathBuf::from(&address), err);
+    let listener = TcpListener::bind(&address).map_err(io_err)?;
+    if args.verbosity >= Verbosity::Normal {
+        eprintln!("waiting for a debugger on {}", address);
+    }
+    let (stream, peer) = listener.accept().map_err(io_err)?;
+    if args.verbosity >= Verbosity::Verbose {
+        eprintln!("debugger connected from {}", peer);
+    }
+    gdb::serve(debugger, stream).map_err(io_err)
+}
+
+/// Lists where each section and symbol ended up.
+fn memory_map(assembly: &Assembly) -> String {
+    let mut map = String::from("Section           Start  End     Size\n");
+    for section in &assembly.sections {
+        let end = u32::from(section.start) + u32::from(section.len);
+        map.push_str(&format!(
+            "{:<16}  0x{:03X}  0x{:03X}  {:>4}{}\n",
+            section.name,
+            section.start,
+            end.saturating_sub(1),
+            section.len,
+            if section.initialized {
+                ""
+            } else {
+                "  reserved"
+            }
+        ));
+    }
+
+    let mut symbols: Vec<_> = assembly.symbols.iter().collect();
+    symbols.sort_by_key(|&(name, value)| (*value, name));
+    map.push_str("\nSymbol            Value\n");
+    for (name, value) in symbols {
+        map.push_str(&format!("{:<16}  0x{:03X}\n", name, value));
+    }
+    map
+}
+
+fn is_rom(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ["ch8", "c8", "rom", "bin"].contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Reads a ROM, loaded at `--base`, or assembles a source file.
+fn read_program(args: &Args) -> Result<Assembly, CliErr> {
+    if is_rom(&args.input) {
+        Ok(Assembly {
+            base: args.opts.layout.base,
+            bytes: read_input(&args.input)?,
+            ..Assembly::default()
+        })
+    } else {
+        assemble(args)
+    }
+}
+
+fn read_input(path: &Path) -> Result<Vec<u8>, CliErr> {
+    fs::read(path).map_err(|err| CliErr::Io(path.to_path_buf(), err))
+}
+
+fn write_output(path: &Path, bytes: &[u8]) -> Result<(), CliErr> {
+    if path == Path::new("-") {
+        io::stdout()
+            .write_all(bytes)
+            .map_err(|err| CliErr::Io(path.to_path_buf(), err))
+    } else {
+        fs::write(path, bytes).map_err(|err| CliErr::Io(path.to_path_buf(), err))
+    }
+}
+
+/// Reads an object file, or assembles a source file into one.
+fn compile(args: &Args, path: &Path) -> Result<ObjectFile, CliErr> {
+    let is_object = path.extension().and_then(|ext| ext.to_str()) == Some(OBJECT_EXTENSION);
+    let object = if is_object {
+        read_object(path, &args.opts)?
+    } else {
+        compile_path(path, &args.opts)?
+    };
+    if args.verbosity >= Verbosity::Normal {
+        for warning in &object.warnings {
+            eprintln!("{}", warning);
+        }
+    }
+    Ok(object)
+}
+
+/// Assembles the input, printing warnings and, if verbose, the symbol table.
+fn assemble(args: &Args) -> Result<Assembly, CliErr> {
+    let assembly = assemble_path(&args.input, &args.opts)?;
+    if args.verbosity >= Verbosity::Normal {
+        for warning in &assembly.warnings {
+            eprintln!("{}", warning);
+        }
+    }
+    report(args, &assembly);
+    Ok(assembly)
+}
+
+/// Prints the symbol table if verbose.
+fn report(args: &Args, assembly: &Assembly) {
+    if args.verbosity >= Verbosity::Verbose {
+        for (name, value) in &assembly.symbols {
+            eprintln!("{} = 0x{:03X}", name, value);
+        }
+    }
+}
+
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .map(|row| {
+            let mut line = row
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            line.push('\n');
+            line
+        })
+        .collect()
+}
 
-    assemble_file(&args[1], &args[2]).unwrap();
+fn print_machine(machine: &Machine) {
+    for (idx, value) in machine.v.iter().enumerate() {
+        print!(
+            "V{:X}={:02X}{}",
+            idx,
+            value,
+            if idx == 7 { '\n' } else { ' ' }
+        );
+    }
+    println!();
+    println!(
+        "I={:03X} PC={:03X} SP={} DT={:02X} ST={:02X} cycles={}",
+        machine.i,
+        machine.pc,
+        machine.stack.len(),
+        machine.delay_timer,
+        machine.sound_timer,
+        machine.cycles
+    );
+    for y in 0..DISPLAY_HEIGHT {
+        let row: String = (0..DISPLAY_WIDTH)
+            .map(|x| if machine.pixel(x, y) { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
 }
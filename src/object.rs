@@ -0,0 +1,447 @@
+//! Relocatable object files, produced by assembling a file on its own so that several can be
+//! linked into one program later.
+//!
+//! Objects are stored as text, one record per line:
+//!
+//! ```text
+//! CHIP8OBJ 1
+//! FILE 0 src/main.asm
+//! SECTION 0 code
+//! BYTES 0 6000A000
+//! SECTION 1 vars
+//! RESERVE 1 16
+//! SYMBOL start export 0 0
+//! CONST SPEED local 3
+//! IMPORT draw_sprite
+//! RELOC 0 2 addr12 section 0 6
+//! RELOC 0 4 addr12 symbol draw_sprite
+//! LINE 0 0 2 0 3
+//! LINE 0 2 4 0 4 expanded
+//! LINE 0 6 2 0 5 data
+//! ```
+
+use crate::Diagnostic;
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const MAGIC: &str = "CHIP8OBJ";
+const VERSION: u32 = 1;
+
+/// The section code goes in when no `SECTION` directive has been seen.
+pub const DEFAULT_SECTION: &str = "code";
+
+/// The section for variables, which only reserves space and isn't stored in the program.
+pub const VARS_SECTION: &str = "vars";
+
+/// Whether a section only reserves space, rather than holding code or data.
+pub fn is_uninitialized(section: &str) -> bool {
+    section == VARS_SECTION
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// Space reserved after `bytes`, which isn't stored in the program.
+    pub reserved: u16,
+}
+
+impl Section {
+    /// The number of bytes of memory the section takes up.
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16 + self.reserved
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Where a symbol points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolValue {
+    /// A constant, which doesn't move when linked.
+    Absolute(u16),
+    /// An offset into one of the object's sections.
+    Relative { section: usize, offset: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: SymbolValue,
+    /// Whether other objects may refer to the symbol.
+    pub exported: bool,
+}
+
+/// What a relocation patches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// The low 12 bits of an instruction, as in `JP addr`.
+    Addr12,
+    /// A big-endian word, as in `DW addr`.
+    Word,
+}
+
+/// What a relocation refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocTarget {
+    /// An offset into one of the object's own sections.
+    Section { section: usize, offset: u16 },
+    /// A symbol imported from another object.
+    Symbol(String),
+}
+
+/// A value which can't be filled in until the object is linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: usize,
+    /// Offset of the instruction or data being patched within the section.
+    pub offset: u16,
+    pub kind: RelocKind,
+    pub target: RelocTarget,
+}
+
+/// What a line assembled to, so tools working from the assembled program needn't read its
+/// source again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LineKind {
+    /// Instructions, as they were written.
+    #[default]
+    Code,
+    /// Instructions other than the ones written, such as a pseudo-instruction or `.if`.
+    Expanded,
+    /// `DB`, `DW` or `DS`.
+    Data,
+    /// The `TEST` starting a test, with its name.
+    Test(String),
+    /// An `EXPECT`, with its condition, such as `V0 == 6`.
+    Expect(String),
+    EndTest,
+}
+
+impl LineKind {
+    /// Whether the line assembled to something other than what was written.
+    pub fn is_expanded(&self) -> bool {
+        !matches!(self, LineKind::Code | LineKind::Data)
+    }
+}
+
+/// Ties a range of a section to the line it was assembled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMapping {
+    pub section: usize,
+    pub offset: u16,
+    pub len: u16,
+    /// Index into [`ObjectFile::files`].
+    pub file: usize,
+    pub line: usize,
+    pub kind: LineKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectFile {
+    /// Where the object came from, for diagnostics. Not stored in the object itself.
+    pub name: PathBuf,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    /// Every source file read while assembling the object.
+    pub files: Vec<PathBuf>,
+    pub lines: Vec<LineMapping>,
+    /// Warnings from assembling the object. Not stored in the object itself.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl ObjectFile {
+    /// Finds the source line a byte of a section came from.
+    pub fn line_at(&self, section: usize, offset: u16) -> Option<&LineMapping> {
+        self.lines.iter().find(|m| {
+            m.section == section && m.offset <= offset && offset < m.offset + m.len.max(1)
+        })
+    }
+
+    /// Parses an object previously written with its `Display` implementation.
+    pub fn parse(text: &str) -> Result<ObjectFile, String> {
+        let mut object = ObjectFile::default();
+        let mut lines = text.lines().enumerate();
+
+        match lines
+            .next()
+            .map(|(_, l)| l.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(ref header) if header.len() == 2 && header[0] == MAGIC => {
+                if header[1] != VERSION.to_string() {
+                    return Err(format!("unsupported object version {}", header[1]));
+                }
+            }
+            _ => return Err(String::from("not a CHIP-8 object file")),
+        }
+
+        for (idx, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            object
+                .parse_record(line, &fields)
+                .map_err(|err| format!("line {}: {}", idx + 1, err))?;
+        }
+
+        object.check()?;
+        Ok(object)
+    }
+
+    fn parse_record(&mut self, line: &str, fields: &[&str]) -> Result<(), String> {
+        match fields {
+            [] => {}
+            ["FILE", index, ..] => {
+                expect_index(index, self.files.len())?;
+                // Paths may contain spaces, so take the rest of the line.
+                let path = line.trim_start()[4..].trim_start()[index.len()..].trim_start();
+                self.files.push(PathBuf::from(path));
+            }
+            ["SECTION", index, name] => {
+                expect_index(index, self.sections.len())?;
+                self.sections.push(Section {
+                    name: String::from(*name),
+                    bytes: Vec::new(),
+                    reserved: 0,
+                });
+            }
+            ["RESERVE", section, size] => {
+                let section = number::<usize>(section)?;
+                self.sections
+                    .get_mut(section)
+                    .ok_or_else(|| format!("no section {}", section))?
+                    .reserved = number(size)?;
+            }
+            ["BYTES", section, hex] => {
+                let section = number::<usize>(section)?;
+                let bytes = decode_hex(hex)?;
+                self.sections
+                    .get_mut(section)
+                    .ok_or_else(|| format!("no section {}", section))?
+                    .bytes
+                    .extend(bytes);
+            }
+            ["SYMBOL", name, visibility, section, offset] => self.symbols.push(Symbol {
+                name: String::from(*name),
+                value: SymbolValue::Relative {
+                    section: number(section)?,
+                    offset: number(offset)?,
+                },
+                exported: exported(visibility)?,
+            }),
+            ["CONST", name, visibility, value] => self.symbols.push(Symbol {
+                name: String::from(*name),
+                value: SymbolValue::Absolute(number(value)?),
+                exported: exported(visibility)?,
+            }),
+            ["IMPORT", name] => self.imports.push(String::from(*name)),
+            ["RELOC", section, offset, kind, "section", target, target_offset] => {
+                self.relocations.push(Relocation {
+                    section: number(section)?,
+                    offset: number(offset)?,
+                    kind: kind.parse()?,
+                    target: RelocTarget::Section {
+                        section: number(target)?,
+                        offset: number(target_offset)?,
+                    },
+                })
+            }
+            ["RELOC", section, offset, kind, "symbol", name] => self.relocations.push(Relocation {
+                section: number(section)?,
+                offset: number(offset)?,
+                kind: kind.parse()?,
+                target: RelocTarget::Symbol(String::from(*name)),
+            }),
+            ["LINE", section, offset, len, file, line_number, kind @ ..] => {
+                self.lines.push(LineMapping {
+                    section: number(section)?,
+                    offset: number(offset)?,
+                    len: number(len)?,
+                    file: number(file)?,
+                    line: number(line_number)?,
+                    kind: line_kind(line, kind)?,
+                })
+            }
+            [record, ..] => return Err(format!("invalid `{}` record", record)),
+        }
+        Ok(())
+    }
+
+    /// Makes sure everything which refers to a section or file refers to one which exists.
+    fn check(&self) -> Result<(), String> {
+        let sections = self.sections.len();
+        let symbol_sections = self.symbols.iter().filter_map(|s| match s.value {
+            SymbolValue::Relative { section, .. } => Some(section),
+            SymbolValue::Absolute(_) => None,
+        });
+        let reloc_sections = self.relocations.iter().flat_map(|r| match r.target {
+            RelocTarget::Section { section, .. } => vec![r.section, section],
+            RelocTarget::Symbol(_) => vec![r.section],
+        });
+        let line_sections = self.lines.iter().map(|l| l.section);
+        if let Some(section) = symbol_sections
+            .chain(reloc_sections)
+            .chain(line_sections)
+            .find(|&s| s >= sections)
+        {
+            return Err(format!("no section {}", section));
+        }
+        if let Some(reloc) = self
+            .relocations
+            .iter()
+            .find(|r| usize::from(r.offset) + 2 > self.sections[r.section].bytes.len())
+        {
+            return Err(format!(
+                "relocation at offset {} is outside section {}",
+                reloc.offset, reloc.section
+            ));
+        }
+        if let Some(line) = self.lines.iter().find(|l| l.file >= self.files.len()) {
+            return Err(format!("no file {}", line.file));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ObjectFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        for (idx, file) in self.files.iter().enumerate() {
+            writeln!(f, "FILE {} {}", idx, file.display())?;
+        }
+        for (idx, section) in self.sections.iter().enumerate() {
+            writeln!(f, "SECTION {} {}", idx, section.name)?;
+            for chunk in section.bytes.chunks(32) {
+                let mut hex = String::new();
+                for byte in chunk {
+                    write!(hex, "{:02X}", byte)?;
+                }
+                writeln!(f, "BYTES {} {}", idx, hex)?;
+            }
+            if section.reserved > 0 {
+                writeln!(f, "RESERVE {} {}", idx, section.reserved)?;
+            }
+        }
+        for symbol in &self.symbols {
+            let visibility = if symbol.exported { "export" } else { "local" };
+            match symbol.value {
+                SymbolValue::Relative { section, offset } => writeln!(
+                    f,
+                    "SYMBOL {} {} {} {}",
+                    symbol.name, visibility, section, offset
+                )?,
+                SymbolValue::Absolute(value) => {
+                    writeln!(f, "CONST {} {} {}", symbol.name, visibility, value)?
+                }
+            }
+        }
+        for import in &self.imports {
+            writeln!(f, "IMPORT {}", import)?;
+        }
+        for reloc in &self.relocations {
+            write!(
+                f,
+                "RELOC {} {} {} ",
+                reloc.section, reloc.offset, reloc.kind
+            )?;
+            match &reloc.target {
+                RelocTarget::Section { section, offset } => {
+                    writeln!(f, "section {} {}", section, offset)?
+                }
+                RelocTarget::Symbol(name) => writeln!(f, "symbol {}", name)?,
+            }
+        }
+        for line in &self.lines {
+            write!(
+                f,
+                "LINE {} {} {} {} {}",
+                line.section, line.offset, line.len, line.file, line.line
+            )?;
+            match &line.kind {
+                LineKind::Code => writeln!(f)?,
+                LineKind::Expanded => writeln!(f, " expanded")?,
+                LineKind::Data => writeln!(f, " data")?,
+                LineKind::Test(name) => writeln!(f, " test {}", name)?,
+                LineKind::Expect(condition) => writeln!(f, " expect {}", condition)?,
+                LineKind::EndTest => writeln!(f, " endtest")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RelocKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RelocKind::Addr12 => "addr12",
+            RelocKind::Word => "word",
+        })
+    }
+}
+
+impl FromStr for RelocKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "addr12" => Ok(RelocKind::Addr12),
+            "word" => Ok(RelocKind::Word),
+            _ => Err(format!("unknown relocation kind `{}`", s)),
+        }
+    }
+}
+
+fn number<T: FromStr>(field: &str) -> Result<T, String> {
+    field
+        .parse()
+        .map_err(|_| format!("invalid number `{}`", field))
+}
+
+/// Records which introduce numbered things must number them in order.
+fn expect_index(field: &str, expected: usize) -> Result<(), String> {
+    if number::<usize>(field)? != expected {
+        return Err(format!("expected index {} but found {}", expected, field));
+    }
+    Ok(())
+}
+
+/// Reads the kind at the end of a `LINE` record, from its `fields` after the line number.
+fn line_kind(line: &str, fields: &[&str]) -> Result<LineKind, String> {
+    // Test names and conditions may contain spaces, so take the rest of the line.
+    let rest = || {
+        let kind = line.find(&format!(" {} ", fields[0])).unwrap_or(0);
+        String::from(line[kind + fields[0].len() + 2..].trim())
+    };
+    match fields {
+        [] => Ok(LineKind::Code),
+        ["expanded"] => Ok(LineKind::Expanded),
+        ["data"] => Ok(LineKind::Data),
+        ["endtest"] => Ok(LineKind::EndTest),
+        ["test", _, ..] => Ok(LineKind::Test(rest())),
+        ["expect", _, ..] => Ok(LineKind::Expect(rest())),
+        [kind, ..] => Err(format!("unknown line kind `{}`", kind)),
+    }
+}
+
+fn exported(field: &str) -> Result<bool, String> {
+    match field {
+        "export" => Ok(true),
+        "local" => Ok(false),
+        _ => Err(format!("invalid visibility `{}`", field)),
+    }
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("invalid hex `{}`", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&hex[idx..idx + 2], 16).map_err(|_| format!("invalid hex `{}`", hex))
+        })
+        .collect()
+}
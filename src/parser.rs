@@ -0,0 +1,598 @@
+use crate::instruction::{Addr, Instruction, Vx, V0};
+use crate::object::{RelocKind, RelocTarget};
+use crate::pseudo;
+use crate::target::Target;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A single operand of a statement, classified by its syntax alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Operand {
+    Register(Vx),
+    I,
+    IndirectI,
+    /// Memory at an offset from `I`, as in `[I+2]`, which only `EXPECT` accepts.
+    IndexedI(String),
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Str(String),
+    Value(String),
+    Compare(Comparison),
+}
+
+/// A comparison in the condition of a structured block, such as `.if V0 == 5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// The comparison which holds exactly when this one doesn't.
+    pub(crate) fn negate(self) -> Self {
+        match self {
+            Comparison::Eq => Comparison::Ne,
+            Comparison::Ne => Comparison::Eq,
+            Comparison::Lt => Comparison::Ge,
+            Comparison::Le => Comparison::Gt,
+            Comparison::Gt => Comparison::Le,
+            Comparison::Ge => Comparison::Lt,
+        }
+    }
+
+    pub(crate) fn holds(self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+
+    /// The pseudo-instruction which jumps when the comparison holds.
+    pub(crate) fn branch(self) -> &'static str {
+        match self {
+            Comparison::Eq => "JEQ",
+            Comparison::Ne => "JNE",
+            Comparison::Lt => "JLT",
+            Comparison::Le => "JLE",
+            Comparison::Gt => "JGT",
+            Comparison::Ge => "JGE",
+        }
+    }
+}
+
+/// A mnemonic and its operands, with any label and comment on the line stripped off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Statement {
+    pub(crate) mnemonic: String,
+    pub(crate) operands: Vec<Operand>,
+}
+
+/// The meaningful contents of one line of source.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Line {
+    pub(crate) label: Option<String>,
+    pub(crate) statement: Option<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErr {
+    InvalidInstruction(String),
+    IncorrectArgumentCount {
+        required: u8,
+        found: u8,
+        msg: String,
+    },
+    UnimplementedInstruction(String),
+    InvalidOperand(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    ValueOutOfRange {
+        value: i64,
+        max: u16,
+    },
+    IncludeNotFound(String),
+    RecursiveInclude(String),
+    /// A structured block is opened without being closed, or the reverse.
+    UnbalancedBlock(String),
+    /// Subroutines which call themselves, directly or not.
+    Recursion(String),
+    /// Calls which nest deeper than the stack, along the deepest chain of subroutines.
+    StackDepth {
+        depth: usize,
+        max: usize,
+        chain: String,
+    },
+    /// An address was used where a value is needed before the program is linked.
+    NotRelocatable(String),
+    InvalidObject(String),
+    /// Something other than `DS` was used in an uninitialized section.
+    Uninitialized(String, String),
+    InvalidLayout(String),
+}
+
+impl fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErr::InvalidInstruction(line) => write!(f, "invalid instruction `{}`", line),
+            ParseErr::IncorrectArgumentCount {
+                required,
+                found,
+                msg,
+            } => write!(
+                f,
+                "`{}` takes {} operand(s) but {} were given",
+                msg, required, found
+            ),
+            ParseErr::UnimplementedInstruction(line) => {
+                write!(f, "unrecognised operands in `{}`", line)
+            }
+            ParseErr::InvalidOperand(operand) => write!(f, "invalid operand `{}`", operand),
+            ParseErr::UndefinedSymbol(name) => write!(f, "undefined symbol `{}`", name),
+            ParseErr::DuplicateSymbol(name) => write!(f, "symbol `{}` is already defined", name),
+            ParseErr::ValueOutOfRange { value, max } => {
+                write!(f, "value {:#X} is out of range (max {:#X})", value, max)
+            }
+            ParseErr::IncludeNotFound(name) => write!(f, "can't find included file `{}`", name),
+            ParseErr::RecursiveInclude(name) => write!(f, "`{}` includes itself", name),
+            ParseErr::UnbalancedBlock(msg) => write!(f, "{}", msg),
+            ParseErr::Recursion(chain) => write!(f, "recursive call: {}", chain),
+            ParseErr::StackDepth { depth, max, chain } => write!(
+                f,
+                "calls nest {} deep but the stack only holds {}: {}",
+                depth, max, chain
+            ),
+            ParseErr::NotRelocatable(name) => write!(
+                f,
+                "`{}` is an address, which isn't known until the program is linked",
+                name
+            ),
+            ParseErr::InvalidObject(msg) => write!(f, "invalid object file: {}", msg),
+            ParseErr::Uninitialized(mnemonic, section) => write!(
+                f,
+                "`{}` can't be used in uninitialized section `{}`, only `DS`",
+                mnemonic, section
+            ),
+            ParseErr::InvalidLayout(msg) => write!(f, "invalid layout: {}", msg),
+        }
+    }
+}
+
+/// Splits a line of source into its label and statement.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseErr> {
+    lazy_static! {
+        static ref LINE: Regex = Regex::new(
+            "^\\s*((?P<label>[a-zA-Z0-9_]+):)?\\s*((?P<mnemonic>\\.?[a-zA-Z]+)(\\s+(?P<operands>[^;]*?))?)?\\s*(;.*)?$"
+        )
+        .unwrap();
+        static ref OPERAND: Regex =
+            Regex::new("\"[^\"]*\"|\\[[^\\]]*\\]|[=!<>]=?|[^,\\s=!<>]+").unwrap();
+    }
+
+    let captures = LINE
+        .captures(line)
+        .ok_or_else(|| ParseErr::InvalidInstruction(String::from(line.trim())))?;
+    let label = captures.name("label").map(|l| String::from(l.as_str()));
+    let statement = match captures.name("mnemonic") {
+        Some(mnemonic) => {
+            let operands = match captures.name("operands") {
+                Some(operands) => OPERAND
+                    .find_iter(operands.as_str())
+                    .map(|op| parse_operand(op.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => Vec::new(),
+            };
+            let mnemonic = mnemonic.as_str().to_ascii_uppercase();
+            Some(Statement {
+                operands: keywords(&mnemonic, operands),
+                mnemonic,
+            })
+        }
+        None => None,
+    };
+
+    Ok(Line { label, statement })
+}
+
+fn parse_operand(operand: &str) -> Result<Operand, ParseErr> {
+    lazy_static! {
+        static ref REGISTER: Regex = Regex::new("^[vV](?P<Vx>[0-9a-fA-F])$").unwrap();
+        static ref SYMBOL: Regex = Regex::new("^[a-zA-Z0-9_#]+$").unwrap();
+        static ref INDEXED: Regex =
+            Regex::new("^\\[\\s*[iI]\\s*\\+\\s*(?P<offset>[a-zA-Z0-9_#]+)\\s*\\]$").unwrap();
+    }
+
+    if let Some(captures) = REGISTER.captures(operand) {
+        let vx = u8::from_str_radix(captures.name("Vx").unwrap().as_str(), 16).unwrap();
+        return Ok(Operand::Register(Vx(vx)));
+    }
+
+    Ok(match operand.to_ascii_uppercase().as_str() {
+        "[I]" => Operand::IndirectI,
+        "==" => Operand::Compare(Comparison::Eq),
+        "!=" => Operand::Compare(Comparison::Ne),
+        "<" => Operand::Compare(Comparison::Lt),
+        "<=" => Operand::Compare(Comparison::Le),
+        ">" => Operand::Compare(Comparison::Gt),
+        ">=" => Operand::Compare(Comparison::Ge),
+        _ if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') => {
+            Operand::Str(String::from(&operand[1..operand.len() - 1]))
+        }
+        _ if SYMBOL.is_match(operand) => Operand::Value(String::from(operand)),
+        _ if INDEXED.is_match(operand) => {
+            let offset = &INDEXED.captures(operand).unwrap()["offset"];
+            Operand::IndexedI(String::from(offset))
+        }
+        _ => return Err(ParseErr::InvalidOperand(String::from(operand))),
+    })
+}
+
+/// Reads the names of special registers as keywords in the operand positions which take them,
+/// such as `LD F, Vx` or `LD Vx, K`. Anywhere else they're values, so `LD V0, F` loads 0x0F and
+/// `CALL f` calls a label named `f`.
+fn keywords(mnemonic: &str, mut operands: Vec<Operand>) -> Vec<Operand> {
+    let promote = |operand: &mut Operand, names: &[&str]| {
+        let name = match operand {
+            Operand::Value(name) => name.to_ascii_uppercase(),
+            _ => return,
+        };
+        if !names.contains(&name.as_str()) {
+            return;
+        }
+        *operand = match name.as_str() {
+            "I" => Operand::I,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            _ => Operand::Bcd,
+        };
+    };
+    match (mnemonic, &mut operands[..]) {
+        ("LD" | "MOV", [Operand::Register(_), source]) => promote(source, &["DT", "K"]),
+        ("LD" | "MOV", [dest, _]) => promote(dest, &["I", "DT", "ST", "F", "B"]),
+        ("ADD", [dest, _]) => promote(dest, &["I"]),
+        ("EXPECT", [subject, ..]) => promote(subject, &["I", "DT", "ST"]),
+        _ => {}
+    }
+    operands
+}
+
+/// Parses a numeric literal. Literals may be prefixed with `0x` for hex, `0b` for binary, or `#`
+/// for decimal. Unprefixed literals are hex.
+pub fn parse_number(value: &str) -> Option<i64> {
+    let (digits, radix) = if value.starts_with("0x") || value.starts_with("0X") {
+        (&value[2..], 16)
+    } else if value.starts_with("0b") || value.starts_with("0B") {
+        (&value[2..], 2)
+    } else if let Some(digits) = value.strip_prefix('#') {
+        (digits, 10)
+    } else {
+        (value, 16)
+    };
+
+    if digits.is_empty() {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// What an operand resolves to. Addresses in the program aren't known until it's linked, so
+/// they're left as relocations for the linker to fill in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    Number(i64),
+    Reloc(RelocTarget),
+}
+
+/// A relocation needed by a single statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fixup {
+    /// Offset of the patched bytes from the start of the statement.
+    pub(crate) offset: u16,
+    pub(crate) kind: RelocKind,
+    pub(crate) target: RelocTarget,
+}
+
+/// Resolves operands against the symbols of the file being assembled, collecting the
+/// relocations needed for any which refer to addresses.
+pub(crate) struct Resolver<'a> {
+    symbols: &'a HashMap<String, Value>,
+    /// Where the statement being resolved starts.
+    here: RelocTarget,
+    /// Offset of the instruction being resolved from the start of the statement, for statements
+    /// which expand to several instructions.
+    pub(crate) offset: u16,
+    pub(crate) fixups: Vec<Fixup>,
+}
+
+impl<'a> Resolver<'a> {
+    pub(crate) fn new(symbols: &'a HashMap<String, Value>, here: RelocTarget) -> Self {
+        Resolver {
+            symbols,
+            here,
+            offset: 0,
+            fixups: Vec::new(),
+        }
+    }
+
+    /// Resolves a value operand, preferring symbols over bare hex literals.
+    pub(crate) fn value(&self, value: &str) -> Result<Value, ParseErr> {
+        if let Some(symbol) = self.symbols.get(value) {
+            return Ok(symbol.clone());
+        }
+        parse_number(value)
+            .map(Value::Number)
+            .ok_or_else(|| ParseErr::UndefinedSymbol(String::from(value)))
+    }
+
+    /// Resolves an operand which must be known now, such as an immediate byte.
+    fn number(&self, value: &str, max: u16) -> Result<u16, ParseErr> {
+        match self.value(value)? {
+            Value::Number(number) => bounded(number, max),
+            Value::Reloc(_) => Err(ParseErr::NotRelocatable(String::from(value))),
+        }
+    }
+
+    /// Resolves an operand which may be an address, recording a relocation and leaving zero in
+    /// its place if it is.
+    fn relocatable(
+        &mut self,
+        value: &str,
+        max: u16,
+        offset: u16,
+        kind: RelocKind,
+    ) -> Result<u16, ParseErr> {
+        match self.value(value)? {
+            Value::Number(number) => bounded(number, max),
+            Value::Reloc(target) => {
+                self.fixups.push(Fixup {
+                    offset,
+                    kind,
+                    target,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    /// Resolves the number of bytes reserved by `DS`.
+    pub(crate) fn count(&self, value: &str) -> Result<u16, ParseErr> {
+        self.number(value, 0x0FFF)
+    }
+
+    pub(crate) fn byte(&self, value: &str) -> Result<u8, ParseErr> {
+        self.number(value, 0xFF).map(|b| b as u8)
+    }
+
+    fn nibble(&self, value: &str) -> Result<u8, ParseErr> {
+        self.number(value, 0x0F).map(|n| n as u8)
+    }
+
+    pub(crate) fn addr(&mut self, value: &str) -> Result<Addr, ParseErr> {
+        self.relocatable(value, 0x0FFF, self.offset, RelocKind::Addr12)
+            .map(Addr)
+    }
+
+    /// The address of the instruction being resolved, offset by `delta` bytes.
+    pub(crate) fn here(&mut self, delta: u16) -> Addr {
+        let target = match &self.here {
+            RelocTarget::Section { section, offset } => RelocTarget::Section {
+                section: *section,
+                offset: offset + self.offset + delta,
+            },
+            RelocTarget::Symbol(_) => self.here.clone(),
+        };
+        self.fixups.push(Fixup {
+            offset: self.offset,
+            kind: RelocKind::Addr12,
+            target,
+        });
+        Addr(0)
+    }
+}
+
+pub(crate) fn bounded(value: i64, max: u16) -> Result<u16, ParseErr> {
+    if value < 0 || value > i64::from(max) {
+        return Err(ParseErr::ValueOutOfRange { value, max });
+    }
+    Ok(value as u16)
+}
+
+/// Encodes the operands of a `DB` or `DW` directive.
+pub(crate) fn parse_data(
+    statement: &Statement,
+    resolver: &mut Resolver,
+) -> Result<Vec<u8>, ParseErr> {
+    let mut bytes = Vec::new();
+    for operand in &statement.operands {
+        let value = match operand {
+            Operand::Value(value) => value,
+            op => return Err(ParseErr::InvalidOperand(op.to_string())),
+        };
+        if statement.mnemonic == "DW" {
+            let offset = bytes.len() as u16;
+            let word = resolver.relocatable(value, u16::MAX, offset, RelocKind::Word)?;
+            bytes.push((word >> 8) as u8);
+            bytes.push(word as u8);
+        } else {
+            bytes.push(resolver.byte(value)?);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Number of bytes a statement assembles to.
+pub(crate) fn statement_size(statement: &Statement, target: Target) -> u16 {
+    if let Some(len) = pseudo::expansion_len(statement, target) {
+        return len * 2;
+    }
+    match statement.mnemonic.as_str() {
+        "DEFINE" | "INCLUDE" | "SECTION" | "IMPORT" | "EXPORT" | "PROC" | "ENDP" => 0,
+        // The size reserved by `DS` depends on its operand, so it's worked out by the assembler.
+        "DS" => 0,
+        "DB" => statement.operands.len() as u16,
+        "DW" => statement.operands.len() as u16 * 2,
+        _ => 2,
+    }
+}
+
+/// The number of operands each mnemonic accepts, used to give a better error than
+/// [`ParseErr::UnimplementedInstruction`] when the count is wrong.
+fn operand_count(mnemonic: &str) -> Option<(u8, u8)> {
+    Some(match mnemonic {
+        "CLS" | "RET" | "NOP" | "HALT" => (0, 0),
+        "SYS" | "CALL" | "SKP" | "SKNP" | "INC" | "DEC" | "NOT" | "CLR" => (1, 1),
+        "JKP" | "JKNP" => (2, 2),
+        "JEQ" | "JNE" | "JLT" | "JLE" | "JGT" | "JGE" => (3, 3),
+        "JP" => (1, 2),
+        "SHR" | "SHL" => (1, 2),
+        "SE" | "SNE" | "LD" | "MOV" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" | "RND" => {
+            (2, 2)
+        }
+        "DRW" => (3, 3),
+        _ => return None,
+    })
+}
+
+/// Parses an instruction or pseudo-instruction into the real instructions it assembles to.
+pub(crate) fn parse_statement(
+    statement: &Statement,
+    target: Target,
+    resolver: &mut Resolver,
+) -> Result<Vec<Instruction>, ParseErr> {
+    match pseudo::expand(statement, target, resolver)? {
+        Some(instructions) => Ok(instructions),
+        None => parse_instruction(statement, resolver).map(|instr| vec![instr]),
+    }
+}
+
+pub(crate) fn parse_instruction(
+    statement: &Statement,
+    resolver: &mut Resolver,
+) -> Result<Instruction, ParseErr> {
+    use Operand::*;
+
+    let mnemonic = statement.mnemonic.as_str();
+    let instr = match (mnemonic, &statement.operands[..]) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SYS", [Value(a)]) => Instruction::Sys(resolver.addr(a)?),
+        ("JP", [Value(a)]) => Instruction::Jmp(resolver.addr(a)?),
+        ("JP", [Register(Vx(0)), Value(a)]) => Instruction::JmpV0(resolver.addr(a)?),
+        ("CALL", [Value(a)]) => Instruction::Call(resolver.addr(a)?),
+        ("SE", [Register(vx), Register(vy)]) => Instruction::SkipEqVx(*vx, *vy),
+        ("SE", [Register(vx), Value(c)]) => Instruction::SkipEq(*vx, resolver.byte(c)?),
+        ("SNE", [Register(vx), Register(vy)]) => Instruction::SkipNotEqVx(*vx, *vy),
+        ("SNE", [Register(vx), Value(c)]) => Instruction::SkipNotEq(*vx, resolver.byte(c)?),
+        ("LD", [Register(vx), Register(vy)]) => Instruction::LoadVx(*vx, *vy),
+        ("LD", [Register(vx), Value(c)]) => Instruction::Load(*vx, resolver.byte(c)?),
+        ("LD", [Register(vx), DelayTimer]) => Instruction::LoadDelay(*vx),
+        ("LD", [Register(vx), Key]) => Instruction::LoadKey(*vx),
+        ("LD", [Register(vx), IndirectI]) => Instruction::LoadRegisters(*vx),
+        ("LD", [I, Value(a)]) => Instruction::LoadI(resolver.addr(a)?),
+        ("LD", [DelayTimer, Register(vx)]) => Instruction::SetDelay(*vx),
+        ("LD", [SoundTimer, Register(vx)]) => Instruction::SetSound(*vx),
+        ("LD", [Font, Register(vx)]) => Instruction::LoadFont(*vx),
+        ("LD", [Bcd, Register(vx)]) => Instruction::LoadBcd(*vx),
+        ("LD", [IndirectI, Register(vx)]) => Instruction::StoreRegisters(*vx),
+        ("ADD", [Register(vx), Register(vy)]) => Instruction::AddVx(*vx, *vy),
+        ("ADD", [Register(vx), Value(c)]) => Instruction::Add(*vx, resolver.byte(c)?),
+        ("ADD", [I, Register(vx)]) => Instruction::AddI(*vx),
+        ("OR", [Register(vx), Register(vy)]) => Instruction::Or(*vx, *vy),
+        ("AND", [Register(vx), Register(vy)]) => Instruction::And(*vx, *vy),
+        ("XOR", [Register(vx), Register(vy)]) => Instruction::XOr(*vx, *vy),
+        ("SUB", [Register(vx), Register(vy)]) => Instruction::SubVx(*vx, *vy),
+        ("SUBN", [Register(vx), Register(vy)]) => Instruction::SubN(*vx, *vy),
+        // The one operand forms leave `Vy` as `V0`, as they always have.
+        ("SHR", [Register(vx)]) => Instruction::ShiftRight(*vx, V0),
+        ("SHR", [Register(vx), Register(vy)]) => Instruction::ShiftRight(*vx, *vy),
+        ("SHL", [Register(vx)]) => Instruction::ShiftLeft(*vx, V0),
+        ("SHL", [Register(vx), Register(vy)]) => Instruction::ShiftLeft(*vx, *vy),
+        ("RND", [Register(vx), Value(c)]) => Instruction::Rand(*vx, resolver.byte(c)?),
+        ("DRW", [Register(vx), Register(vy), Value(n)]) => {
+            Instruction::Draw(*vx, *vy, resolver.nibble(n)?)
+        }
+        ("SKP", [Register(vx)]) => Instruction::SkipKeyPressed(*vx),
+        ("SKNP", [Register(vx)]) => Instruction::SkipKeyNotPressed(*vx),
+        _ => {
+            return Err(match operand_count(mnemonic) {
+                None => ParseErr::InvalidInstruction(statement.to_string()),
+                Some((min, max)) => {
+                    let found = statement.operands.len() as u8;
+                    if found < min || found > max {
+                        ParseErr::IncorrectArgumentCount {
+                            required: if found < min { min } else { max },
+                            found,
+                            msg: String::from(mnemonic),
+                        }
+                    } else {
+                        ParseErr::UnimplementedInstruction(statement.to_string())
+                    }
+                }
+            })
+        }
+    };
+
+    Ok(instr)
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(vx) => write!(f, "{}", vx),
+            Operand::I => write!(f, "I"),
+            Operand::IndirectI => write!(f, "[I]"),
+            Operand::IndexedI(offset) => write!(f, "[I+{}]", offset),
+            Operand::DelayTimer => write!(f, "DT"),
+            Operand::SoundTimer => write!(f, "ST"),
+            Operand::Key => write!(f, "K"),
+            Operand::Font => write!(f, "F"),
+            Operand::Bcd => write!(f, "B"),
+            Operand::Str(s) => write!(f, "\"{}\"", s),
+            Operand::Value(v) => write!(f, "{}", v),
+            Operand::Compare(comparison) => write!(f, "{}", comparison),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        })
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        let mut previous: Option<&Operand> = None;
+        for operand in &self.operands {
+            // Conditions are written `V0 == 5`, without commas.
+            let separator = match (previous, operand) {
+                (None, _) | (Some(Operand::Compare(_)), _) | (_, Operand::Compare(_)) => " ",
+                _ => ", ",
+            };
+            write!(f, "{}{}", separator, operand)?;
+            previous = Some(operand);
+        }
+        Ok(())
+    }
+}
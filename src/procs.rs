@@ -0,0 +1,320 @@
+//! Subroutines marked with `PROC name ... ENDP`, and checks that calls between them fit on the
+//! interpreter's stack.
+//!
+//! Calls are followed when they're to a label inside a subroutine in the same object. Calls to
+//! imported symbols or fixed addresses can't be followed, so aren't checked.
+
+use crate::assembler::{spliced, SourceLine};
+use crate::blocks;
+use crate::diagnostic::Diagnostic;
+use crate::object::RelocTarget;
+use crate::parser::{statement_size, Operand, ParseErr, Statement, Value};
+use crate::target::Target;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// A subroutine, covering `start..end` of its section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Proc {
+    name: String,
+    section: usize,
+    start: u16,
+    end: u16,
+    /// The indices of its `PROC` and `ENDP` lines.
+    first: usize,
+    last: usize,
+}
+
+/// The subroutines in an object, collected as it's laid out.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Procs {
+    procs: Vec<Proc>,
+    open: Option<Proc>,
+}
+
+impl Procs {
+    /// Starts a subroutine at `line`.
+    pub(crate) fn open(
+        &mut self,
+        name: &str,
+        section: usize,
+        start: u16,
+        line: usize,
+    ) -> Result<(), ParseErr> {
+        if let Some(open) = &self.open {
+            return Err(ParseErr::UnbalancedBlock(format!(
+                "`PROC {}` is inside `PROC {}`, which hasn't ended",
+                name, open.name
+            )));
+        }
+        self.open = Some(Proc {
+            name: String::from(name),
+            section,
+            start,
+            end: start,
+            first: line,
+            last: line,
+        });
+        Ok(())
+    }
+
+    /// Ends the open subroutine at `line`.
+    pub(crate) fn close(&mut self, section: usize, end: u16, line: usize) -> Result<(), ParseErr> {
+        let mut proc = self
+            .open
+            .take()
+            .ok_or_else(|| ParseErr::UnbalancedBlock(String::from("`ENDP` without `PROC`")))?;
+        if proc.section != section {
+            return Err(ParseErr::UnbalancedBlock(format!(
+                "`PROC {}` ends in a different section than it starts",
+                proc.name
+            )));
+        }
+        proc.end = end;
+        proc.last = line;
+        self.procs.push(proc);
+        Ok(())
+    }
+
+    /// Whether the same subroutine is open in both.
+    pub(crate) fn same_open(&self, other: &Procs) -> bool {
+        self.open == other.open
+    }
+
+    /// What `later` collected after `earlier`, following what `self` collected instead.
+    pub(crate) fn spliced(&self, earlier: &Procs, later: &Procs) -> Procs {
+        Procs {
+            procs: spliced(&self.procs, &earlier.procs, &later.procs),
+            open: later.open.clone(),
+        }
+    }
+
+    /// The line and name of a subroutine which was never ended.
+    pub(crate) fn unclosed(&self) -> Option<(usize, &str)> {
+        self.open.as_ref().map(|p| (p.first, p.name.as_str()))
+    }
+
+    /// Warns about subroutines which can run off their end, and reports recursion or calls
+    /// nested deeper than `target`'s stack.
+    pub(crate) fn check(
+        &self,
+        lines: &[SourceLine],
+        files: &[PathBuf],
+        symbols: &HashMap<String, Value>,
+        target: Target,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let error = |line: usize, err: ParseErr| {
+            let line = &lines[line];
+            Diagnostic::error(files[line.file].clone(), line.number, err)
+        };
+
+        for proc in &self.procs {
+            if falls_through(&lines[proc.first + 1..proc.last], target) {
+                let line = &lines[proc.last];
+                diagnostics.push(Diagnostic::warning(
+                    files[line.file].clone(),
+                    line.number,
+                    format!("`PROC {}` can run past `ENDP` without returning", proc.name),
+                ));
+            }
+        }
+
+        // The calls out of each subroutine, and out of code which isn't in one.
+        let mut calls: Vec<Vec<Call>> = vec![Vec::new(); self.procs.len()];
+        let mut top_level = Vec::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let callee = match &line.line.statement {
+                Some(Statement { mnemonic, operands }) if mnemonic == "CALL" => match &operands[..]
+                {
+                    [Operand::Value(name)] => self.containing(symbols.get(name)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let call = match callee {
+                Some(callee) => Call { callee, line: idx },
+                None => continue,
+            };
+            match self
+                .procs
+                .iter()
+                .position(|p| p.first < idx && idx < p.last)
+            {
+                Some(caller) => calls[caller].push(call),
+                None => top_level.push(call),
+            }
+        }
+
+        let mut search = Search {
+            procs: &self.procs,
+            calls: &calls,
+            state: vec![State::Unvisited; self.procs.len()],
+            depth: vec![0; self.procs.len()],
+            deepest: vec![None; self.procs.len()],
+            recursion: Vec::new(),
+        };
+        for proc in 0..self.procs.len() {
+            search.visit(proc, &mut vec![proc]);
+        }
+        for (line, chain) in &search.recursion {
+            diagnostics.push(error(*line, ParseErr::Recursion(chain.clone())));
+        }
+        if !search.recursion.is_empty() {
+            return diagnostics;
+        }
+
+        // Code outside any subroutine is where the program starts, so is always an entry point,
+        // as is any subroutine nothing else calls.
+        let max = target.stack_size();
+        if let Some(call) = top_level.iter().max_by_key(|c| search.depth[c.callee]) {
+            let depth = search.depth[call.callee] + 1;
+            if depth > max {
+                let chain = search.chain(call.callee);
+                diagnostics.push(error(call.line, ParseErr::StackDepth { depth, max, chain }));
+            }
+        }
+        for (idx, proc) in self.procs.iter().enumerate() {
+            let called = calls.iter().flatten().any(|c| c.callee == idx)
+                || top_level.iter().any(|c| c.callee == idx);
+            let depth = search.depth[idx];
+            if !called && depth > max {
+                let chain = search.chain(idx);
+                diagnostics.push(error(
+                    proc.first,
+                    ParseErr::StackDepth { depth, max, chain },
+                ));
+            }
+        }
+        diagnostics
+    }
+
+    /// The subroutine an address is in, if it's in one.
+    fn containing(&self, value: Option<&Value>) -> Option<usize> {
+        let (section, offset) = match value {
+            Some(Value::Reloc(RelocTarget::Section { section, offset })) => (*section, *offset),
+            _ => return None,
+        };
+        self.procs.iter().position(|p| {
+            p.section == section && (p.start == offset || (p.start..p.end).contains(&offset))
+        })
+    }
+}
+
+/// Whether the lines of a subroutine, between `PROC` and `ENDP`, can run on past the end.
+fn falls_through(lines: &[SourceLine], target: Target) -> bool {
+    let mut last: Option<&Statement> = None;
+    let mut skipped = false;
+    let mut labelled = false;
+    // The labels generated for blocks which something reachable jumps to. The others, like the
+    // end of an `.if` whose branches both return, can't be reached.
+    let mut targets = HashSet::new();
+    for line in lines {
+        if let Some(label) = &line.line.label {
+            if !label.starts_with(blocks::HIDDEN_PREFIX) || targets.contains(label) {
+                labelled = true;
+            }
+        }
+        if let Some(statement) = &line.line.statement {
+            if statement_size(statement, target) > 0 {
+                if labelled || skipped || !last.is_some_and(ends) {
+                    targets.extend(
+                        statement
+                            .operands
+                            .iter()
+                            .filter_map(|operand| match operand {
+                                Operand::Value(name) if name.starts_with(blocks::HIDDEN_PREFIX) => {
+                                    Some(name)
+                                }
+                                _ => None,
+                            }),
+                    );
+                }
+                skipped =
+                    last.is_some_and(|s| ["SE", "SNE", "SKP", "SKNP"].contains(&&*s.mnemonic));
+                last = Some(statement);
+                labelled = false;
+            }
+        }
+    }
+    // A label after the last instruction means something can jump to the end.
+    !last.is_some_and(ends) || skipped || labelled
+}
+
+/// Whether execution never goes on past `statement` to the next.
+fn ends(statement: &Statement) -> bool {
+    ["RET", "JP", "HALT"].contains(&&*statement.mnemonic)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    callee: usize,
+    /// The index of the line the call is on.
+    line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+/// A depth first search of the call graph, finding how deep calls from each subroutine nest.
+struct Search<'a> {
+    procs: &'a [Proc],
+    calls: &'a [Vec<Call>],
+    state: Vec<State>,
+    /// The most return addresses pushed by calls from each subroutine.
+    depth: Vec<usize>,
+    /// The call from each subroutine which nests deepest.
+    deepest: Vec<Option<usize>>,
+    /// Each call which closes a cycle, with the cycle.
+    recursion: Vec<(usize, String)>,
+}
+
+impl<'a> Search<'a> {
+    fn visit(&mut self, proc: usize, path: &mut Vec<usize>) {
+        if self.state[proc] != State::Unvisited {
+            return;
+        }
+        self.state[proc] = State::Visiting;
+        let calls = self.calls;
+        for call in &calls[proc] {
+            match self.state[call.callee] {
+                State::Visiting => {
+                    let from = path.iter().position(|&p| p == call.callee).unwrap_or(0);
+                    let mut cycle: Vec<&str> = path[from..]
+                        .iter()
+                        .map(|&p| self.procs[p].name.as_str())
+                        .collect();
+                    cycle.push(&self.procs[call.callee].name);
+                    self.recursion.push((call.line, cycle.join(" -> ")));
+                    continue;
+                }
+                State::Unvisited => {
+                    path.push(call.callee);
+                    self.visit(call.callee, path);
+                    path.pop();
+                }
+                State::Done => {}
+            }
+            if self.depth[call.callee] + 1 > self.depth[proc] {
+                self.depth[proc] = self.depth[call.callee] + 1;
+                self.deepest[proc] = Some(call.callee);
+            }
+        }
+        self.state[proc] = State::Done;
+    }
+
+    /// The subroutines along the deepest chain of calls starting from `proc`.
+    fn chain(&self, proc: usize) -> String {
+        let mut names = vec![self.procs[proc].name.as_str()];
+        let mut next = self.deepest[proc];
+        while let Some(proc) = next {
+            names.push(&self.procs[proc].name);
+            next = self.deepest[proc];
+        }
+        names.join(" -> ")
+    }
+}
@@ -0,0 +1,203 @@
+//! Pseudo-instructions, which the assembler expands into one or more real instructions.
+//!
+//! | Pseudo-instruction | Expansion                                                       |
+//! |--------------------|-----------------------------------------------------------------|
+//! | `NOP`              | `LD V0, V0`                                                     |
+//! | `SUB Vx, byte`     | `ADD Vx, -byte`, using the two's complement                     |
+//! | `INC Vx`           | `ADD Vx, 1`                                                     |
+//! | `DEC Vx`           | `ADD Vx, 0xFF`                                                  |
+//! | `NOT Vx`           | `LD VF, 0xFF` then `XOR Vx, VF`                                 |
+//! | `MOV a, b`         | `LD a, b`, in any of its forms                                  |
+//! | `SHR Vx, Vy`       | `LD Vx, Vy` then `SHR Vx, Vx` on targets shifting `Vx` in place |
+//! | `SHL Vx, Vy`       | `LD Vx, Vy` then `SHL Vx, Vx` on targets shifting `Vx` in place |
+//! | `HALT`             | `JP` to itself                                                  |
+//! | `CLR Vx`           | `LD Vx, 0`                                                      |
+//!
+//! There are also branches, which expand into a skip over a jump:
+//!
+//! | Pseudo-instruction  | Jumps when       | Expansion                                          |
+//! |---------------------|------------------|----------------------------------------------------|
+//! | `JEQ Vx, b, label`  | `Vx == b`        | `SNE Vx, b`, `JP label`                            |
+//! | `JNE Vx, b, label`  | `Vx != b`        | `SE Vx, b`, `JP label`                             |
+//! | `JKP Vx, label`     | key `Vx` is down | `SKNP Vx`, `JP label`                              |
+//! | `JKNP Vx, label`    | key `Vx` is up   | `SKP Vx`, `JP label`                               |
+//! | `JLT Vx, Vy, label` | `Vx < Vy`        | `LD VF, Vx`, `SUB VF, Vy`, `SNE VF, 0`, `JP label` |
+//! | `JGE Vx, Vy, label` | `Vx >= Vy`       | `LD VF, Vx`, `SUB VF, Vy`, `SE VF, 0`, `JP label`  |
+//! | `JGT Vx, b, label`  | `Vx > b`         | `LD VF, b`, `SUB VF, Vx`, `SNE VF, 0`, `JP label`  |
+//! | `JLE Vx, b, label`  | `Vx <= b`        | `LD VF, b`, `SUB VF, Vx`, `SE VF, 0`, `JP label`   |
+//!
+//! `b` may be a register or a byte. Comparing against a byte, `JLT` and `JGE` start with
+//! `LD VF, byte` then `SUBN VF, Vx` instead, which leaves the same borrow flag in `VF`.
+//!
+//! The shift expansions name `Vx` as `Vy` too, so the copy is what's shifted whichever register
+//! the interpreter reads. `SHR Vx` and `SHL Vx` on their own aren't expanded: they're the real
+//! instructions with `Vy` left as `V0`, so on `chip8` they shift `V0` into `Vx`. Write
+//! `SHR Vx, Vx` to shift `Vx` itself on every target.
+//!
+//! Unlike `SUB Vx, Vy`, none of these set `VF` as a flag, though `NOT` and the comparisons use
+//! it as scratch so can't take it as an operand.
+//! Listings show each pseudo-instruction followed by its expansion.
+
+use crate::instruction::{Instruction, V0, VF};
+use crate::parser::{parse_instruction, Operand, ParseErr, Resolver, Statement};
+use crate::target::Target;
+
+/// The number of instructions a statement expands to, or `None` if it isn't a
+/// pseudo-instruction. This must agree with [`expand`].
+pub(crate) fn expansion_len(statement: &Statement, target: Target) -> Option<u16> {
+    use Operand::*;
+
+    let len = match (statement.mnemonic.as_str(), &statement.operands[..]) {
+        ("NOP", []) | ("HALT", []) => 1,
+        ("SUB", [Register(_), Value(_)]) => 1,
+        ("INC", [Register(_)]) | ("DEC", [Register(_)]) | ("CLR", [Register(_)]) => 1,
+        ("NOT", [Register(_)]) => 2,
+        ("MOV", _) => 1,
+        ("SHR", [Register(vx), Register(vy)]) | ("SHL", [Register(vx), Register(vy)])
+            if vx != vy && target.shifts_in_place() =>
+        {
+            2
+        }
+        ("JEQ", [Register(_), Register(_) | Value(_), Value(_)])
+        | ("JNE", [Register(_), Register(_) | Value(_), Value(_)]) => 2,
+        ("JKP", [Register(_), Value(_)]) | ("JKNP", [Register(_), Value(_)]) => 2,
+        ("JLT", [Register(_), Register(_) | Value(_), Value(_)])
+        | ("JGE", [Register(_), Register(_) | Value(_), Value(_)])
+        | ("JGT", [Register(_), Register(_) | Value(_), Value(_)])
+        | ("JLE", [Register(_), Register(_) | Value(_), Value(_)]) => 4,
+        _ => return None,
+    };
+    Some(len)
+}
+
+/// Expands a pseudo-instruction, or returns `None` if the statement is a real instruction.
+pub(crate) fn expand(
+    statement: &Statement,
+    target: Target,
+    resolver: &mut Resolver,
+) -> Result<Option<Vec<Instruction>>, ParseErr> {
+    use Operand::*;
+
+    let instructions = match (statement.mnemonic.as_str(), &statement.operands[..]) {
+        ("NOP", []) => vec![Instruction::LoadVx(V0, V0)],
+        ("HALT", []) => vec![Instruction::Jmp(resolver.here(0))],
+        ("SUB", [Register(vx), Value(c)]) => {
+            vec![Instruction::Add(*vx, resolver.byte(c)?.wrapping_neg())]
+        }
+        ("INC", [Register(vx)]) => vec![Instruction::Add(*vx, 1)],
+        ("DEC", [Register(vx)]) => vec![Instruction::Add(*vx, 0xFF)],
+        ("CLR", [Register(vx)]) => vec![Instruction::Load(*vx, 0)],
+        ("NOT", [Register(vx)]) if *vx == VF => {
+            return Err(ParseErr::InvalidOperand(vx.to_string()))
+        }
+        ("NOT", [Register(vx)]) => vec![Instruction::Load(VF, 0xFF), Instruction::XOr(*vx, VF)],
+        ("MOV", operands) => {
+            let load = Statement {
+                mnemonic: String::from("LD"),
+                operands: operands.to_vec(),
+            };
+            // Errors are about the `MOV` that was written, not the `LD` it became.
+            let instruction = parse_instruction(&load, resolver).map_err(|err| match err {
+                ParseErr::IncorrectArgumentCount {
+                    required, found, ..
+                } => ParseErr::IncorrectArgumentCount {
+                    required,
+                    found,
+                    msg: statement.mnemonic.clone(),
+                },
+                ParseErr::UnimplementedInstruction(_) => {
+                    ParseErr::UnimplementedInstruction(statement.to_string())
+                }
+                err => err,
+            })?;
+            vec![instruction]
+        }
+        ("SHR", [Register(vx), Register(vy)]) if vx != vy && target.shifts_in_place() => vec![
+            Instruction::LoadVx(*vx, *vy),
+            Instruction::ShiftRight(*vx, *vx),
+        ],
+        ("SHL", [Register(vx), Register(vy)]) if vx != vy && target.shifts_in_place() => vec![
+            Instruction::LoadVx(*vx, *vy),
+            Instruction::ShiftLeft(*vx, *vx),
+        ],
+        ("JEQ", [Register(vx), Register(vy), Value(label)]) => {
+            skip_over_jump(vec![Instruction::SkipNotEqVx(*vx, *vy)], label, resolver)?
+        }
+        ("JEQ", [Register(vx), Value(c), Value(label)]) => {
+            let skip = Instruction::SkipNotEq(*vx, resolver.byte(c)?);
+            skip_over_jump(vec![skip], label, resolver)?
+        }
+        ("JNE", [Register(vx), Register(vy), Value(label)]) => {
+            skip_over_jump(vec![Instruction::SkipEqVx(*vx, *vy)], label, resolver)?
+        }
+        ("JNE", [Register(vx), Value(c), Value(label)]) => {
+            let skip = Instruction::SkipEq(*vx, resolver.byte(c)?);
+            skip_over_jump(vec![skip], label, resolver)?
+        }
+        ("JKP", [Register(vx), Value(label)]) => {
+            skip_over_jump(vec![Instruction::SkipKeyNotPressed(*vx)], label, resolver)?
+        }
+        ("JKNP", [Register(vx), Value(label)]) => {
+            skip_over_jump(vec![Instruction::SkipKeyPressed(*vx)], label, resolver)?
+        }
+        (mnemonic @ "JLT", [Register(vx), rhs, Value(label)])
+        | (mnemonic @ "JGE", [Register(vx), rhs, Value(label)]) => {
+            // Either way VF ends up as 1 if Vx >= rhs, or 0 if the subtraction borrowed.
+            let mut instructions = match rhs {
+                Register(vy) if *vx != VF && *vy != VF => {
+                    vec![Instruction::LoadVx(VF, *vx), Instruction::SubVx(VF, *vy)]
+                }
+                Value(c) if *vx != VF => {
+                    vec![
+                        Instruction::Load(VF, resolver.byte(c)?),
+                        Instruction::SubN(VF, *vx),
+                    ]
+                }
+                Register(_) | Value(_) => return Err(ParseErr::InvalidOperand(VF.to_string())),
+                _ => return Ok(None),
+            };
+            instructions.push(if mnemonic == "JLT" {
+                Instruction::SkipNotEq(VF, 0)
+            } else {
+                Instruction::SkipEq(VF, 0)
+            });
+            skip_over_jump(instructions, label, resolver)?
+        }
+        (mnemonic @ "JGT", [Register(vx), rhs, Value(label)])
+        | (mnemonic @ "JLE", [Register(vx), rhs, Value(label)]) => {
+            // VF ends up as 1 if rhs >= Vx, or 0 if the subtraction borrowed.
+            let load = match rhs {
+                Register(vy) if *vx != VF && *vy != VF => Instruction::LoadVx(VF, *vy),
+                Value(c) if *vx != VF => Instruction::Load(VF, resolver.byte(c)?),
+                Register(_) | Value(_) => return Err(ParseErr::InvalidOperand(VF.to_string())),
+                _ => return Ok(None),
+            };
+            let skip = if mnemonic == "JGT" {
+                Instruction::SkipNotEq(VF, 0)
+            } else {
+                Instruction::SkipEq(VF, 0)
+            };
+            skip_over_jump(
+                vec![load, Instruction::SubVx(VF, *vx), skip],
+                label,
+                resolver,
+            )?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(instructions))
+}
+
+/// Appends a jump to `label` to instructions ending in a skip, so the jump is taken unless the
+/// skip is.
+fn skip_over_jump(
+    mut instructions: Vec<Instruction>,
+    label: &str,
+    resolver: &mut Resolver,
+) -> Result<Vec<Instruction>, ParseErr> {
+    resolver.offset = instructions.len() as u16 * 2;
+    let jump = resolver.addr(label);
+    resolver.offset = 0;
+    instructions.push(Instruction::Jmp(jump?));
+    Ok(instructions)
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The family of interpreter a program is being built for. CHIP-8 interpreters disagree on the
+/// behaviour of a handful of instructions, so anything which depends on those quirks is keyed off
+/// of this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Target {
+    /// The original COSMAC VIP interpreter.
+    #[default]
+    Chip8,
+    /// The HP-48 CHIP-48 interpreter.
+    Chip48,
+    /// SUPER-CHIP 1.1.
+    SChip,
+}
+
+impl Target {
+    pub const ALL: [Target; 3] = [Target::Chip8, Target::Chip48, Target::SChip];
+
+    /// Number of return addresses the interpreter can hold.
+    pub fn stack_size(self) -> usize {
+        match self {
+            Target::Chip8 => 12,
+            Target::Chip48 | Target::SChip => 16,
+        }
+    }
+
+    /// Whether `SHR`/`SHL` shift `Vx` in place instead of storing the shifted `Vy` into `Vx`.
+    pub fn shifts_in_place(self) -> bool {
+        self != Target::Chip8
+    }
+
+    /// How far `LD [I], Vx` and `LD Vx, [I]` move `I`, given `x`.
+    pub fn load_store_increment(self, x: u8) -> u16 {
+        match self {
+            Target::Chip8 => u16::from(x) + 1,
+            Target::Chip48 => u16::from(x),
+            Target::SChip => 0,
+        }
+    }
+
+    /// Whether `JP V0, addr` actually adds `Vx`, where `x` is the high nibble of `addr`.
+    pub fn jump_uses_vx(self) -> bool {
+        self == Target::SChip
+    }
+
+    /// Whether `OR`, `AND` and `XOR` reset `VF`.
+    pub fn logic_resets_vf(self) -> bool {
+        self == Target::Chip8
+    }
+
+    /// Whether `DRW Vx, Vy, 0` draws a 16x16 sprite rather than nothing.
+    pub fn draws_large_sprites(self) -> bool {
+        self == Target::SChip
+    }
+
+    /// Roughly how many instructions the interpreter gets through in one 60Hz frame.
+    pub fn cycles_per_frame(self) -> usize {
+        match self {
+            Target::Chip8 => 15,
+            Target::Chip48 | Target::SChip => 30,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Target::Chip8 => "chip8",
+            Target::Chip48 => "chip48",
+            Target::SChip => "schip",
+        })
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" => Ok(Target::Chip8),
+            "chip48" | "chip-48" => Ok(Target::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(Target::SChip),
+            _ => Err(format!("unknown target `{}`", s)),
+        }
+    }
+}
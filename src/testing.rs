@@ -0,0 +1,422 @@
+//! Unit tests written in assembly, run on the built-in interpreter:
+//!
+//! ```text
+//! TEST "double"
+//!     LD V0, 3
+//!     CALL double
+//!     EXPECT V0 == 6
+//!     LD I, table
+//!     EXPECT [I+2] == 0x10
+//! ENDTEST
+//! ```
+//!
+//! Each test starts running at its `TEST` line, with the rest of the program loaded as usual,
+//! and passes if it reaches `ENDTEST` with every `EXPECT` it passed holding. `EXPECT` compares
+//! `Vx`, `I`, `DT`, `ST`, `[I]` or `[I+offset]` with a register or value, using `==`, `!=`, `<`,
+//! `<=`, `>` or `>=`.
+//!
+//! Tests are left out of the program unless [`Options::tests`] is set, in which case `TEST` and
+//! `EXPECT` assemble to `NOP` and `ENDTEST` to `HALT`, so each has an address.
+
+use crate::assembler::SourceLine;
+use crate::coverage::{Coverage, CoverageReport};
+use crate::instruction::Vx;
+use crate::machine::{Machine, MEMORY_SIZE};
+use crate::object::LineKind;
+use crate::parser::{parse_line, parse_number, Comparison, Line, Operand, ParseErr, Statement};
+use crate::{assemble_path, Assembly, Diagnostics, Options, Target};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How many instructions a test may run by default before it's taken to be stuck.
+pub const DEFAULT_CYCLE_LIMIT: u64 = 100_000;
+
+/// A condition checked by `EXPECT`.
+#[derive(Debug, Clone)]
+struct Expect {
+    subject: Operand,
+    comparison: Comparison,
+    value: Operand,
+}
+
+/// A test found in the source, with the addresses its directives assembled to.
+#[derive(Debug)]
+struct Test {
+    name: String,
+    file: usize,
+    line: usize,
+    /// The line of the `ENDTEST`.
+    end_line: usize,
+    start: u16,
+    /// Each `EXPECT`, by address, with its line.
+    expects: BTreeMap<u16, (usize, Expect)>,
+    end: u16,
+}
+
+/// The outcome of one test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    pub file: PathBuf,
+    /// The line of the `TEST`.
+    pub line: usize,
+    pub failures: Vec<TestFailure>,
+    /// Instructions run.
+    pub cycles: u64,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// An `EXPECT` which didn't hold, or the reason a test stopped before its `ENDTEST`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestFailure {
+    pub file: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+/// Assembles the file at `path` with its tests, and runs each of them for at most
+/// `cycle_limit` instructions.
+pub fn run_tests(
+    path: &Path,
+    opts: &Options,
+    cycle_limit: u64,
+) -> Result<Vec<TestResult>, Diagnostics> {
+    run_all(path, opts, cycle_limit, false).map(|(results, _)| results)
+}
+
+/// Runs tests as [`run_tests`] does, also reporting which lines outside of the tests they ran.
+pub fn run_tests_with_coverage(
+    path: &Path,
+    opts: &Options,
+    cycle_limit: u64,
+) -> Result<(Vec<TestResult>, CoverageReport), Diagnostics> {
+    let (results, report) = run_all(path, opts, cycle_limit, true)?;
+    Ok((results, report.unwrap()))
+}
+
+fn run_all(
+    path: &Path,
+    opts: &Options,
+    cycle_limit: u64,
+    with_coverage: bool,
+) -> Result<(Vec<TestResult>, Option<CoverageReport>), Diagnostics> {
+    let opts = Options {
+        tests: true,
+        ..opts.clone()
+    };
+    let assembly = assemble_path(path, &opts)?;
+    let tests = find_tests(&assembly);
+    let mut coverage = with_coverage.then(Coverage::new);
+    let results = tests
+        .iter()
+        .map(|test| run_test(&assembly, opts.target, test, cycle_limit, &mut coverage))
+        .collect();
+    let report = coverage.map(|coverage| {
+        let mut report = CoverageReport::new(&assembly, &coverage, opts.files.as_ref());
+        report.retain(|line| {
+            !tests
+                .iter()
+                .any(|t| t.file == line.file && (t.line..=t.end_line).contains(&line.line))
+        });
+        report
+    });
+    Ok((results, report))
+}
+
+/// Finds the tests from the markers their directives left in the source map.
+fn find_tests(assembly: &Assembly) -> Vec<Test> {
+    let mut tests = Vec::new();
+    let mut open: Option<Test> = None;
+    for mapping in &assembly.source_map {
+        match (&mapping.kind, &mut open) {
+            (LineKind::Test(name), _) => {
+                open = Some(Test {
+                    name: name.clone(),
+                    file: mapping.file,
+                    line: mapping.line,
+                    end_line: 0,
+                    start: mapping.address,
+                    expects: BTreeMap::new(),
+                    end: 0,
+                })
+            }
+            (LineKind::Expect(condition), Some(test)) => {
+                let expect = parse_line(&format!("EXPECT {}", condition))
+                    .ok()
+                    .and_then(|line| line.statement)
+                    .and_then(|statement| expectation(&statement).ok());
+                if let Some(expect) = expect {
+                    test.expects.insert(mapping.address, (mapping.line, expect));
+                }
+            }
+            (LineKind::EndTest, Some(_)) => {
+                let mut test = open.take().unwrap();
+                test.end = mapping.address;
+                test.end_line = mapping.line;
+                tests.push(test);
+            }
+            _ => {}
+        }
+    }
+    tests
+}
+
+/// Runs a test, adding what it ran to `coverage` if that's being recorded.
+fn run_test(
+    assembly: &Assembly,
+    target: Target,
+    test: &Test,
+    cycle_limit: u64,
+    coverage: &mut Option<Coverage>,
+) -> TestResult {
+    let file = assembly.files[test.file].clone();
+    let mut result = TestResult {
+        name: test.name.clone(),
+        file: file.clone(),
+        line: test.line,
+        failures: Vec::new(),
+        cycles: 0,
+    };
+    // Problems while running are reported at the line being run, if it's known.
+    let fail_at = |address: u16, message: String| match assembly.mapping_at(address) {
+        Some(mapping) => TestFailure {
+            file: assembly.files[mapping.file].clone(),
+            line: mapping.line,
+            message,
+        },
+        None => TestFailure {
+            file: file.clone(),
+            line: test.line,
+            message,
+        },
+    };
+
+    let mut machine = Machine::new(target);
+    if let Err(err) = machine.load(assembly.base, &assembly.bytes) {
+        result.failures.push(fail_at(test.start, err.to_string()));
+        return result;
+    }
+    machine.pc = test.start;
+    machine.coverage = coverage.take();
+    let frame = target.cycles_per_frame() as u64;
+    while machine.pc != test.end {
+        let pc = machine.pc;
+        if let Some((line, expect)) = test.expects.get(&pc) {
+            if let Err(message) = check(assembly, &machine, expect) {
+                result.failures.push(TestFailure {
+                    file: file.clone(),
+                    line: *line,
+                    message,
+                });
+            }
+        }
+
+        let stopped = if machine.cycles >= cycle_limit {
+            Some(format!(
+                "didn't reach `ENDTEST` within {} cycles",
+                cycle_limit
+            ))
+        } else if machine.halted() {
+            Some(format!("halted at {:03X} before `ENDTEST`", pc))
+        } else if machine.waiting_for_key() {
+            Some(format!("waiting for a key at {:03X}", pc))
+        } else {
+            machine.step().err().map(|err| err.to_string())
+        };
+        if let Some(message) = stopped {
+            result.failures.push(fail_at(pc, message));
+            break;
+        }
+        if machine.cycles.is_multiple_of(frame) {
+            machine.tick_timers();
+        }
+    }
+    result.cycles = machine.cycles;
+    *coverage = machine.coverage.take();
+    result
+}
+
+/// Checks an `EXPECT` against the machine, describing what was found if it doesn't hold.
+fn check(assembly: &Assembly, machine: &Machine, expect: &Expect) -> Result<(), String> {
+    let actual = value(assembly, machine, &expect.subject)?;
+    let wanted = value(assembly, machine, &expect.value)?;
+    if expect.comparison.holds(actual, wanted) {
+        return Ok(());
+    }
+    Err(format!(
+        "expected {} {} {}, but {} is {} (0x{:02X})",
+        expect.subject, expect.comparison, expect.value, expect.subject, actual, actual
+    ))
+}
+
+fn value(assembly: &Assembly, machine: &Machine, operand: &Operand) -> Result<i64, String> {
+    let number = |value: &str| {
+        assembly
+            .symbols
+            .get(value)
+            .map(|&v| i64::from(v))
+            .or_else(|| parse_number(value))
+            .ok_or_else(|| format!("undefined symbol `{}`", value))
+    };
+    let memory = |offset: i64| {
+        let address = (i64::from(machine.i) + offset).rem_euclid(MEMORY_SIZE as i64);
+        i64::from(machine.memory[address as usize])
+    };
+    Ok(match operand {
+        Operand::Register(Vx(x)) => i64::from(machine.v[usize::from(*x)]),
+        Operand::I => i64::from(machine.i),
+        Operand::DelayTimer => i64::from(machine.delay_timer),
+        Operand::SoundTimer => i64::from(machine.sound_timer),
+        Operand::IndirectI => memory(0),
+        Operand::IndexedI(offset) => memory(number(offset)?),
+        Operand::Value(value) => number(value)?,
+        other => return Err(format!("can't compare `{}`", other)),
+    })
+}
+
+/// Reads the condition of an `EXPECT`.
+fn expectation(statement: &Statement) -> Result<Expect, ParseErr> {
+    match &statement.operands[..] {
+        [subject, Operand::Compare(comparison), value] => {
+            match subject {
+                Operand::Register(_)
+                | Operand::I
+                | Operand::DelayTimer
+                | Operand::SoundTimer
+                | Operand::IndirectI
+                | Operand::IndexedI(_) => {}
+                other => return Err(ParseErr::InvalidOperand(other.to_string())),
+            }
+            match value {
+                Operand::Register(_) | Operand::Value(_) => {}
+                other => return Err(ParseErr::InvalidOperand(other.to_string())),
+            }
+            Ok(Expect {
+                subject: subject.clone(),
+                comparison: *comparison,
+                value: value.clone(),
+            })
+        }
+        operands => Err(ParseErr::IncorrectArgumentCount {
+            required: 3,
+            found: operands.len() as u8,
+            msg: String::from("EXPECT"),
+        }),
+    }
+}
+
+/// Checks the test blocks in `lines`, replacing their directives with instructions if `keep`
+/// is set, or leaving the blocks out if not.
+pub(crate) fn lower<F>(lines: Vec<SourceLine>, keep: bool, mut error: F) -> Vec<SourceLine>
+where
+    F: FnMut(usize, usize, ParseErr),
+{
+    let mut lowered = Vec::with_capacity(lines.len());
+    // The file and line of the `TEST` being read.
+    let mut open: Option<(usize, usize)> = None;
+    for line in lines {
+        let statement = match &line.line.statement {
+            Some(statement) => statement,
+            None if open.is_some() && !keep => continue,
+            None => {
+                lowered.push(line);
+                continue;
+            }
+        };
+        let (replacement, kind) = match statement.mnemonic.as_str() {
+            "TEST" => {
+                match (&statement.operands[..], open) {
+                    (_, Some((_, number))) => error(
+                        line.file,
+                        line.number,
+                        ParseErr::UnbalancedBlock(format!(
+                            "`TEST` inside the `TEST` on line {}",
+                            number
+                        )),
+                    ),
+                    ([Operand::Str(_)], None) => {}
+                    ([op], None) => error(
+                        line.file,
+                        line.number,
+                        ParseErr::InvalidOperand(op.to_string()),
+                    ),
+                    (ops, None) => error(
+                        line.file,
+                        line.number,
+                        ParseErr::IncorrectArgumentCount {
+                            required: 1,
+                            found: ops.len() as u8,
+                            msg: String::from("TEST"),
+                        },
+                    ),
+                }
+                open = Some((line.file, line.number));
+                let name = match &statement.operands[..] {
+                    [Operand::Str(name)] => name.clone(),
+                    _ => String::new(),
+                };
+                ("NOP", LineKind::Test(name))
+            }
+            "EXPECT" => {
+                if open.is_none() {
+                    error(
+                        line.file,
+                        line.number,
+                        ParseErr::UnbalancedBlock(String::from("`EXPECT` outside of a `TEST`")),
+                    );
+                } else if let Err(err) = expectation(statement) {
+                    error(line.file, line.number, err);
+                }
+                // The condition, without the mnemonic.
+                let statement = statement.to_string();
+                let condition = statement["EXPECT".len()..].trim_start();
+                ("NOP", LineKind::Expect(String::from(condition)))
+            }
+            "ENDTEST" => {
+                if open.take().is_none() {
+                    error(
+                        line.file,
+                        line.number,
+                        ParseErr::UnbalancedBlock(String::from("`ENDTEST` without `TEST`")),
+                    );
+                    continue;
+                }
+                if !keep {
+                    continue;
+                }
+                ("HALT", LineKind::EndTest)
+            }
+            _ if open.is_some() && !keep => continue,
+            _ => {
+                lowered.push(line);
+                continue;
+            }
+        };
+        if keep {
+            lowered.push(SourceLine {
+                line: Line {
+                    label: line.line.label.clone(),
+                    statement: Some(Statement {
+                        mnemonic: String::from(replacement),
+                        operands: Vec::new(),
+                    }),
+                },
+                kind,
+                ..line
+            });
+        }
+    }
+    if let Some((file, number)) = open {
+        error(
+            file,
+            number,
+            ParseErr::UnbalancedBlock(String::from("`TEST` without `ENDTEST`")),
+        );
+    }
+    lowered
+}
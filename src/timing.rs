@@ -0,0 +1,259 @@
+//! How long instructions take on the COSMAC VIP, in machine cycles of its 1.76MHz CDP1802, each
+//! of which is eight clock cycles. A 60Hz frame is about [`FRAME_CYCLES`] machine cycles.
+//!
+//! The figures are approximate, from measurements of the original interpreter, and include the
+//! [`FETCH_CYCLES`] it spends fetching and decoding every instruction.
+
+use crate::cfg::{Code, Flow};
+use crate::instruction::Instruction;
+use crate::Assembly;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// Machine cycles in one 60Hz frame.
+pub const FRAME_CYCLES: u32 = 3668;
+
+/// Machine cycles spent fetching and decoding each instruction.
+pub const FETCH_CYCLES: u32 = 40;
+
+/// The range of machine cycles something can take. `worst` is `None` if there's no limit, such
+/// as when waiting for a key or going around a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycles {
+    pub best: u32,
+    pub worst: Option<u32>,
+}
+
+/// For paths which never finish, such as those which loop back on themselves.
+const NEVER: Cycles = Cycles {
+    best: u32::MAX,
+    worst: None,
+};
+
+impl Cycles {
+    pub fn exactly(cycles: u32) -> Self {
+        Cycles {
+            best: cycles,
+            worst: Some(cycles),
+        }
+    }
+
+    fn between(best: u32, worst: u32) -> Self {
+        Cycles {
+            best,
+            worst: Some(worst),
+        }
+    }
+}
+
+impl std::ops::Add for Cycles {
+    type Output = Cycles;
+
+    fn add(self, other: Cycles) -> Cycles {
+        Cycles {
+            best: self.best.saturating_add(other.best),
+            worst: self.worst.and_then(|a| other.worst.map(|b| a + b)),
+        }
+    }
+}
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self.worst {
+            _ if *self == NEVER => String::from("-"),
+            Some(worst) if worst == self.best => self.best.to_string(),
+            Some(worst) => format!("{}-{}", self.best, worst),
+            None => format!("{}+", self.best),
+        };
+        // Pad as a whole so the column lines up.
+        f.pad(&text)
+    }
+}
+
+/// How long an instruction takes. Skips take longer when they skip, so `best` is the time when
+/// they don't. How long `DRW` takes depends on where the sprite is drawn, and it waits for the
+/// start of the next frame, so the range covers every position and wait.
+pub fn vip_cycles(instr: Instruction) -> Cycles {
+    use Instruction::*;
+
+    let execute = match instr {
+        Cls => 24,
+        Ret => 10,
+        // Machine code routines take however long they take.
+        Sys(_) => 0,
+        Jmp(_) => 12,
+        Call(_) => 26,
+        SkipEq(..) | SkipNotEq(..) => return Cycles::between(FETCH_CYCLES + 10, FETCH_CYCLES + 14),
+        SkipEqVx(..) | SkipNotEqVx(..) | SkipKeyPressed(_) | SkipKeyNotPressed(_) => {
+            return Cycles::between(FETCH_CYCLES + 14, FETCH_CYCLES + 18)
+        }
+        Load(..) => 6,
+        Add(..) => 10,
+        LoadVx(..) | Or(..) | And(..) | XOr(..) | AddVx(..) | SubVx(..) | ShiftRight(..)
+        | SubN(..) | ShiftLeft(..) => 44,
+        LoadI(_) => 12,
+        JmpV0(_) => 22,
+        Rand(..) => 36,
+        Draw(_, _, height) => {
+            return Cycles::between(
+                FETCH_CYCLES + vip_draw_cycles(height, 0),
+                FETCH_CYCLES + vip_draw_cycles(height, 7) + FRAME_CYCLES,
+            )
+        }
+        LoadDelay(_) | SetDelay(_) | SetSound(_) => 10,
+        LoadKey(_) => {
+            return Cycles {
+                best: FETCH_CYCLES + 10,
+                worst: None,
+            }
+        }
+        AddI(_) => 19,
+        LoadFont(_) => 20,
+        // Each digit is found by repeated subtraction, so larger values take longer.
+        LoadBcd(_) => return Cycles::between(FETCH_CYCLES + 80, FETCH_CYCLES + 204),
+        StoreRegisters(vx) | LoadRegisters(vx) => 22 + 14 * (u32::from(vx.index()) + 1),
+    };
+    Cycles::exactly(FETCH_CYCLES + execute)
+}
+
+/// How long `DRW` takes to draw `height` rows at column `x`, not counting the wait for the next
+/// frame. Sprites not on a byte boundary are shifted into place a bit at a time.
+pub fn vip_draw_cycles(height: u8, x: u8) -> u32 {
+    let shift = u32::from(x % 8);
+    let row = if shift == 0 { 20 } else { 28 + 4 * shift };
+    40 + u32::from(height) * row
+}
+
+/// The basic blocks of a program, and how long each subroutine takes from its first instruction
+/// to returning.
+#[derive(Debug, Default)]
+pub(crate) struct Timing {
+    /// The first and last instruction of each block, and how long it takes.
+    pub(crate) blocks: Vec<(u16, u16, Cycles)>,
+    /// Each subroutine which is called, by address.
+    pub(crate) subroutines: BTreeMap<u16, Cycles>,
+}
+
+impl Timing {
+    pub(crate) fn new(assembly: &Assembly, code: &Code) -> Self {
+        let mut leaders: BTreeSet<u16> = code.targets();
+        leaders.insert(assembly.base);
+        for (&address, decoded) in &code.instructions {
+            if Flow::of(decoded.instr) != Flow::Next {
+                leaders.insert(address + 2);
+            }
+        }
+
+        let mut timing = Timing::default();
+        let mut block: Option<(u16, u16, Cycles)> = None;
+        for (&address, decoded) in &code.instructions {
+            let cycles = vip_cycles(decoded.instr);
+            block = match block {
+                Some((start, end, total)) if end + 2 == address && !leaders.contains(&address) => {
+                    Some((start, address, total + cycles))
+                }
+                previous => {
+                    timing.blocks.extend(previous);
+                    Some((address, address, cycles))
+                }
+            };
+        }
+        timing.blocks.extend(block);
+
+        let mut search = Search {
+            code,
+            memo: HashMap::new(),
+            open: HashMap::new(),
+            looped: usize::MAX,
+            subroutines: HashMap::new(),
+        };
+        for decoded in code.instructions.values() {
+            if let Flow::Call(entry) = Flow::of(decoded.instr) {
+                let cycles = search.subroutine(entry);
+                timing.subroutines.insert(entry, cycles);
+            }
+        }
+        timing
+    }
+}
+
+/// Finds the quickest and slowest paths from instructions to a return.
+struct Search<'a> {
+    code: &'a Code,
+    /// The cycles from each instruction in the subroutine being searched.
+    memo: HashMap<u16, Cycles>,
+    /// The instructions being searched from, which means there's a loop if they're reached
+    /// again, with how many were being searched when each was reached.
+    open: HashMap<u16, usize>,
+    /// The fewest that were being searched when any open instruction was reached again. Until
+    /// that loop is closed, what's found depends on not going around it, so isn't kept.
+    looped: usize,
+    subroutines: HashMap<u16, Option<Cycles>>,
+}
+
+impl<'a> Search<'a> {
+    fn subroutine(&mut self, entry: u16) -> Cycles {
+        match self.subroutines.get(&entry) {
+            Some(Some(cycles)) => return *cycles,
+            Some(None) => return NEVER,
+            None => {}
+        }
+        self.subroutines.insert(entry, None);
+        let memo = std::mem::take(&mut self.memo);
+        let open = std::mem::take(&mut self.open);
+        let looped = std::mem::replace(&mut self.looped, usize::MAX);
+        let cycles = self.from(entry);
+        self.memo = memo;
+        self.open = open;
+        self.looped = looped;
+        self.subroutines.insert(entry, Some(cycles));
+        cycles
+    }
+
+    fn from(&mut self, address: u16) -> Cycles {
+        if let Some(&cycles) = self.memo.get(&address) {
+            return cycles;
+        }
+        // Going around a loop can be left out of the quickest path, and makes the slowest path
+        // endless.
+        if let Some(&depth) = self.open.get(&address) {
+            self.looped = self.looped.min(depth);
+            return NEVER;
+        }
+        let instr = match self.code.instructions.get(&address) {
+            Some(decoded) => decoded.instr,
+            // Running off the end of the code, or into data, never returns.
+            None => return NEVER,
+        };
+        let depth = self.open.len();
+        self.open.insert(address, depth);
+        let outer = std::mem::replace(&mut self.looped, usize::MAX);
+        let own = vip_cycles(instr);
+        let cycles = match Flow::of(instr) {
+            Flow::Next => own + self.from(address + 2),
+            Flow::Skip => {
+                let stay = Cycles::exactly(own.best) + self.from(address + 2);
+                let skip = Cycles {
+                    best: own.worst.unwrap_or(own.best),
+                    worst: own.worst,
+                } + self.from(address + 4);
+                Cycles {
+                    best: stay.best.min(skip.best),
+                    worst: stay.worst.and_then(|a| skip.worst.map(|b| a.max(b))),
+                }
+            }
+            Flow::Jump(target) => own + self.from(target),
+            Flow::Call(target) => own + self.subroutine(target) + self.from(address + 2),
+            Flow::Return => own,
+            Flow::Indirect => NEVER,
+        };
+        self.open.remove(&address);
+        if self.looped >= depth {
+            self.memo.insert(address, cycles);
+            self.looped = outer;
+        } else {
+            self.looped = self.looped.min(outer);
+        }
+        cycles
+    }
+}
@@ -0,0 +1,281 @@
+//! Traces of the instructions a program runs, for comparing two builds or runs.
+//!
+//! The text form has a line per instruction, giving how many instructions had run, the
+//! address, the closest label, the instruction, and the registers it changed:
+//!
+//! ```text
+//!        3  202  start+2             CALL 0x20C          SP=1
+//!        4  20C  draw                LD I, 0x212         I=212
+//! ```
+//!
+//! The binary form starts with `C8TR` and a version byte of 1, followed by a record per
+//! instruction: the address and opcode as big-endian words, a count of changed registers, and
+//! for each a byte naming it (0 to 15 for `V0` to `VF`, then `I`, `DT`, `ST` and `SP`) and its
+//! new value, which is a big-endian word for `I` and a byte otherwise.
+
+use crate::instruction::Instruction;
+use crate::machine::{Machine, MachineErr};
+use crate::parser::parse_number;
+use crate::Assembly;
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+const BINARY_MAGIC: &[u8] = b"C8TR";
+const BINARY_VERSION: u8 = 1;
+
+// Register numbers past V0 to VF in binary traces.
+const REG_I: u8 = 16;
+const REG_DT: u8 = 17;
+const REG_ST: u8 = 18;
+const REG_SP: u8 = 19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "bin" | "binary" => Ok(TraceFormat::Binary),
+            other => Err(format!("unknown trace format `{}`", other)),
+        }
+    }
+}
+
+/// What to trace, and how.
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    pub format: TraceFormat,
+    /// Only instructions at these addresses are traced, or every instruction if empty.
+    pub ranges: Vec<RangeInclusive<u16>>,
+    /// The most bytes the trace may take, after which it stops.
+    pub limit: Option<usize>,
+}
+
+impl TraceOptions {
+    /// Reads a comma separated list of addresses to trace, each an address or label, or a
+    /// range of them written `START-END`. A label on its own stands for everything from it up
+    /// to the next label.
+    pub fn parse_ranges(
+        spec: &str,
+        assembly: &Assembly,
+    ) -> Result<Vec<RangeInclusive<u16>>, String> {
+        let address = |name: &str| {
+            assembly
+                .symbols
+                .get(name)
+                .copied()
+                .or_else(|| parse_number(name).and_then(|n| u16::try_from(n).ok()))
+                .ok_or_else(|| format!("unknown label or address `{}` in trace filter", name))
+        };
+        spec.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| match item.split_once('-') {
+                Some((start, end)) => Ok(address(start.trim())?..=address(end.trim())?),
+                None if assembly.symbols.contains_key(item) => {
+                    let start = address(item)?;
+                    Ok(start..=routine_end(assembly, start))
+                }
+                None => address(item).map(|at| at..=at),
+            })
+            .collect()
+    }
+}
+
+/// The last address before the next label after `start`, or the end of the program.
+fn routine_end(assembly: &Assembly, start: u16) -> u16 {
+    let end = (usize::from(assembly.base) + assembly.bytes.len()).max(usize::from(start) + 1);
+    let next = assembly
+        .symbols
+        .values()
+        .copied()
+        .filter(|&value| value > start && usize::from(value) < end)
+        .min()
+        .map_or(end, usize::from);
+    (next - 1) as u16
+}
+
+/// Runs a machine while recording what it does.
+pub struct Tracer<'a> {
+    assembly: &'a Assembly,
+    options: TraceOptions,
+    trace: Vec<u8>,
+    /// Set once the trace reaches its limit.
+    truncated: bool,
+    traced: u64,
+}
+
+impl<'a> Tracer<'a> {
+    /// Starts a trace of `assembly`, whose symbols give the labels in it.
+    pub fn new(assembly: &'a Assembly, options: TraceOptions) -> Self {
+        let mut trace = Vec::new();
+        if options.format == TraceFormat::Binary {
+            trace.extend_from_slice(BINARY_MAGIC);
+            trace.push(BINARY_VERSION);
+        }
+        Tracer {
+            assembly,
+            options,
+            trace,
+            truncated: false,
+            traced: 0,
+        }
+    }
+
+    /// Runs one frame, as [`Machine::run_frame`] does, tracing each instruction.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<(), MachineErr> {
+        for _ in 0..machine.target.cycles_per_frame() {
+            if machine.halted() || machine.waiting_for_key() {
+                break;
+            }
+            self.step(machine)?;
+        }
+        machine.tick_timers();
+        Ok(())
+    }
+
+    /// Runs one instruction, tracing it if it's in the ranges being traced.
+    pub fn step(&mut self, machine: &mut Machine) -> Result<(), MachineErr> {
+        let pc = machine.pc;
+        let opcode = machine.opcode_at(pc);
+        let before = Registers::of(machine);
+        machine.step()?;
+        if self.wanted(pc) {
+            let changes = before.changes(&Registers::of(machine));
+            self.record(machine.cycles, pc, opcode, &changes);
+        }
+        Ok(())
+    }
+
+    /// How many instructions have been traced.
+    pub fn traced(&self) -> u64 {
+        self.traced
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Finishes the trace, noting in a text trace if it was cut short.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if self.truncated && self.options.format == TraceFormat::Text {
+            let note = format!("trace limit reached after {} instructions\n", self.traced);
+            self.trace.extend_from_slice(note.as_bytes());
+        }
+        self.trace
+    }
+
+    fn wanted(&self, pc: u16) -> bool {
+        !self.truncated
+            && (self.options.ranges.is_empty()
+                || self.options.ranges.iter().any(|range| range.contains(&pc)))
+    }
+
+    fn record(&mut self, cycle: u64, pc: u16, opcode: u16, changes: &[(u8, u16)]) {
+        let record = match self.options.format {
+            TraceFormat::Text => self.text_record(cycle, pc, opcode, changes).into_bytes(),
+            TraceFormat::Binary => binary_record(pc, opcode, changes),
+        };
+        if let Some(limit) = self.options.limit {
+            if self.trace.len() + record.len() > limit {
+                self.truncated = true;
+                return;
+            }
+        }
+        self.trace.extend_from_slice(&record);
+        self.traced += 1;
+    }
+
+    fn text_record(&self, cycle: u64, pc: u16, opcode: u16, changes: &[(u8, u16)]) -> String {
+        let label = match self.assembly.label_at(pc) {
+            Some(_) => self.assembly.describe_address(pc),
+            None => String::new(),
+        };
+        let instruction = Instruction::decode(opcode)
+            .map_or_else(|| format!("DW 0x{:04X}", opcode), |i| i.to_string());
+        let mut line = format!(
+            "{:8}  {:03X}  {:<18}  {:<18}",
+            cycle, pc, label, instruction
+        );
+        for &(register, value) in changes {
+            match register {
+                0..=15 => write!(line, "  V{:X}={:02X}", register, value),
+                REG_I => write!(line, "  I={:03X}", value),
+                REG_DT => write!(line, "  DT={:02X}", value),
+                REG_ST => write!(line, "  ST={:02X}", value),
+                _ => write!(line, "  SP={}", value),
+            }
+            .unwrap();
+        }
+        let mut line = String::from(line.trim_end());
+        line.push('\n');
+        line
+    }
+}
+
+fn binary_record(pc: u16, opcode: u16, changes: &[(u8, u16)]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(5 + changes.len() * 3);
+    record.extend_from_slice(&pc.to_be_bytes());
+    record.extend_from_slice(&opcode.to_be_bytes());
+    record.push(changes.len() as u8);
+    for &(register, value) in changes {
+        record.push(register);
+        if register == REG_I {
+            record.extend_from_slice(&value.to_be_bytes());
+        } else {
+            record.push(value as u8);
+        }
+    }
+    record
+}
+
+/// The registers a trace reports changes to.
+struct Registers {
+    v: [u8; 16],
+    i: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    sp: usize,
+}
+
+impl Registers {
+    fn of(machine: &Machine) -> Self {
+        Registers {
+            v: machine.v,
+            i: machine.i,
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+            sp: machine.stack.len(),
+        }
+    }
+
+    /// Each register which differs in `after`, by number, with its new value.
+    fn changes(&self, after: &Registers) -> Vec<(u8, u16)> {
+        let mut changes: Vec<(u8, u16)> = (0..16)
+            .filter(|&x| self.v[x] != after.v[x])
+            .map(|x| (x as u8, u16::from(after.v[x])))
+            .collect();
+        if self.i != after.i {
+            changes.push((REG_I, after.i));
+        }
+        if self.delay_timer != after.delay_timer {
+            changes.push((REG_DT, u16::from(after.delay_timer)));
+        }
+        if self.sound_timer != after.sound_timer {
+            changes.push((REG_ST, u16::from(after.sound_timer)));
+        }
+        if self.sp != after.sp {
+            changes.push((REG_SP, after.sp as u16));
+        }
+        changes
+    }
+}
@@ -0,0 +1,430 @@
+//! The terminal front end of the `debug` command. The screen is redrawn after each command,
+//! which is read a line at a time so it works in any terminal, and from a script.
+
+use crate::debugger::{Debugger, Stop, Storage};
+use crate::instruction::{Instruction, Vx};
+use crate::machine::{DISPLAY_HEIGHT, DISPLAY_WIDTH, MEMORY_SIZE};
+use std::io::{self, BufRead, Write};
+
+/// Lines of source shown around the cursor.
+const SOURCE_LINES: usize = 15;
+/// Instructions shown in the disassembly.
+const DISASSEMBLY_LINES: usize = 11;
+/// Width of the source and disassembly panels.
+const LEFT_WIDTH: usize = 64;
+
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+const HELP: &str = "\
+s, step [N]        run N instructions [1]
+n, next            step, running CALLs until they return
+c, continue        run until a breakpoint, watchpoint or halt
+u, until [LOC]     run until LOC, or the line under the cursor
+f, frame [N]       run N frames
+back [N]           undo N instructions [1]
+rc                 run backwards to a breakpoint or watchpoint
+rw PLACE           run backwards to the last write to a register, I, DT, ST or an address
+rewind N           run backwards to the start of frame N
+j, k [N]           move the cursor down or up N lines
+g, goto LOC        move the cursor to LOC
+b, break [LOC]     set or clear a breakpoint at LOC, or the cursor
+w, watch ADDR      start or stop watching a byte of memory
+key K              hold or release key K (0-F)
+q, quit            leave the debugger
+An empty line repeats the last command. LOC is a label, a line, FILE:LINE, or an address
+like 0x208.";
+
+/// The state of the screen between commands.
+struct View {
+    /// The file and line the cursor is on.
+    cursor: (usize, usize),
+    /// What happened after the last command.
+    status: String,
+    last_command: String,
+}
+
+/// Runs the debugger, reading commands from `input` and drawing to `output` until the input
+/// ends or the user quits.
+pub fn debug<R: BufRead, W: Write>(
+    debugger: &mut Debugger,
+    input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let mut view = View {
+        cursor: debugger.line_at(debugger.machine.pc).unwrap_or((0, 1)),
+        status: String::from("type `help` for a list of commands"),
+        last_command: String::new(),
+    };
+    draw(debugger, &view, &mut output)?;
+    for line in input.lines() {
+        let line = line?;
+        let command = if line.trim().is_empty() {
+            view.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        if matches!(command.as_str(), "q" | "quit") {
+            break;
+        }
+        view.status = match execute(debugger, &mut view, &command) {
+            Ok(status) => status,
+            Err(message) => format!("error: {}", message),
+        };
+        view.last_command = command;
+        draw(debugger, &view, &mut output)?;
+    }
+    Ok(())
+}
+
+/// Runs a command, returning what to show in the status line.
+fn execute(debugger: &mut Debugger, view: &mut View, command: &str) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let argument = words.next();
+    let count = || match argument {
+        Some(n) => n
+            .parse::<usize>()
+            .map_err(|_| format!("invalid count `{}`", n)),
+        None => Ok(1),
+    };
+
+    let stop = match name {
+        "" => return Ok(String::new()),
+        "h" | "help" => return Ok(String::from(HELP)),
+        "s" | "step" => {
+            let mut stop = Stop::Stepped;
+            for _ in 0..count()? {
+                stop = debugger.step();
+                if stop != Stop::Stepped {
+                    break;
+                }
+            }
+            stop
+        }
+        "n" | "next" => debugger.step_over(),
+        "c" | "continue" => debugger.resume(),
+        "u" | "until" => {
+            let address = match argument {
+                Some(location) => debugger.resolve(location)?,
+                None => debugger
+                    .address_of(view.cursor.0, view.cursor.1)
+                    .ok_or("no code on or after the cursor")?,
+            };
+            debugger.run_to(address)
+        }
+        "f" | "frame" => {
+            let mut stop = Stop::Stepped;
+            for _ in 0..count()? {
+                stop = debugger.run_frame();
+                if stop != Stop::Stepped {
+                    break;
+                }
+            }
+            stop
+        }
+        "back" => {
+            let mut stop = Stop::Stepped;
+            for _ in 0..count()? {
+                stop = debugger.step_back();
+                if stop != Stop::Stepped {
+                    break;
+                }
+            }
+            stop
+        }
+        "rc" => debugger.reverse_resume(),
+        "rw" => {
+            let place = argument.ok_or("rw needs a register or address")?;
+            let storage = storage(debugger, place)?;
+            debugger.reverse_to_write(storage)
+        }
+        "rewind" => {
+            let frame = argument
+                .and_then(|n| n.parse().ok())
+                .ok_or("rewind needs a frame number")?;
+            debugger.rewind_to_frame(frame)
+        }
+        "j" | "k" => {
+            let lines = count()?;
+            let (file, line) = view.cursor;
+            let last = debugger.sources.get(file).map_or(1, |s| s.lines().count());
+            view.cursor.1 = if name == "j" {
+                (line + lines).min(last.max(1))
+            } else {
+                line.saturating_sub(lines).max(1)
+            };
+            return Ok(String::new());
+        }
+        "g" | "goto" => {
+            let location = argument.ok_or("goto needs a location")?;
+            let address = debugger.resolve(location)?;
+            view.cursor = debugger
+                .line_at(address)
+                .ok_or_else(|| format!("no source for {:03X}", address))?;
+            return Ok(String::new());
+        }
+        "b" | "break" => {
+            let address = match argument {
+                Some(location) => debugger.resolve(location)?,
+                None => debugger
+                    .address_of(view.cursor.0, view.cursor.1)
+                    .ok_or("no code on or after the cursor")?,
+            };
+            let set = debugger.toggle_breakpoint(address);
+            return Ok(format!(
+                "breakpoint at {} {}",
+                debugger.assembly.describe_address(address),
+                if set { "set" } else { "cleared" }
+            ));
+        }
+        "w" | "watch" => {
+            let location = argument.ok_or("watch needs an address")?;
+            let address = debugger.resolve(location)?;
+            let watched = debugger.toggle_watchpoint(address);
+            return Ok(format!(
+                "{} {:03X}",
+                if watched {
+                    "watching"
+                } else {
+                    "stopped watching"
+                },
+                address
+            ));
+        }
+        "key" => {
+            let key = argument
+                .and_then(|k| u8::from_str_radix(k, 16).ok())
+                .filter(|&k| k < 16)
+                .ok_or("key needs a key from 0 to F")?;
+            let held = &mut debugger.machine.keys[usize::from(key)];
+            *held = !*held;
+            return Ok(format!(
+                "key {:X} {}",
+                key,
+                if *held { "held" } else { "released" }
+            ));
+        }
+        other => return Err(format!("unknown command `{}`", other)),
+    };
+
+    // The cursor follows the program.
+    if let Some(line) = debugger.line_at(debugger.machine.pc) {
+        view.cursor = line;
+    }
+    Ok(match stop {
+        Stop::Breakpoint(address) => format!(
+            "breakpoint at {}",
+            debugger.assembly.describe_address(address)
+        ),
+        stop => stop.to_string(),
+    })
+}
+
+/// Reads a register, or the address of a byte of memory.
+fn storage(debugger: &Debugger, place: &str) -> Result<Storage, String> {
+    let register = place
+        .strip_prefix(|c| c == 'V' || c == 'v')
+        .filter(|x| x.len() == 1)
+        .and_then(|x| u8::from_str_radix(x, 16).ok());
+    Ok(match (register, place.to_ascii_uppercase().as_str()) {
+        (Some(x), _) => Storage::Register(Vx(x)),
+        (None, "I") => Storage::I,
+        (None, "DT") => Storage::DelayTimer,
+        (None, "ST") => Storage::SoundTimer,
+        (None, _) => Storage::Memory(debugger.resolve(place)?),
+    })
+}
+
+fn draw<W: Write>(debugger: &Debugger, view: &View, output: &mut W) -> io::Result<()> {
+    let mut left = source_panel(debugger, view);
+    left.push(String::new());
+    left.extend(disassembly_panel(debugger));
+    let mut right = registers_panel(debugger);
+    right.push(String::new());
+    right.extend(display_panel(debugger));
+
+    write!(output, "{}", CLEAR_SCREEN)?;
+    for idx in 0..left.len().max(right.len()) {
+        let left = left.get(idx).map_or("", String::as_str);
+        let right = right.get(idx).map_or("", String::as_str);
+        let padding = LEFT_WIDTH.saturating_sub(left.chars().count());
+        writeln!(
+            output,
+            "{}{:padding$}  {}",
+            left,
+            "",
+            right,
+            padding = padding
+        )?;
+    }
+    writeln!(output)?;
+    for line in view.status.lines() {
+        writeln!(output, "{}", line)?;
+    }
+    write!(output, "(debug) ")?;
+    output.flush()
+}
+
+fn heading(title: &str, width: usize) -> String {
+    let rule = width.saturating_sub(title.chars().count() + 4);
+    format!("── {} {}", title, "─".repeat(rule))
+}
+
+/// The source around the cursor, marking the line being run with `>`, the cursor with `@`
+/// and breakpoints with `*`.
+fn source_panel(debugger: &Debugger, view: &View) -> Vec<String> {
+    let (file, cursor) = view.cursor;
+    let name = debugger
+        .assembly
+        .files
+        .get(file)
+        .map_or_else(|| String::from("no source"), |f| f.display().to_string());
+    let mut lines = vec![heading(&name, LEFT_WIDTH)];
+    let text = match debugger.sources.get(file) {
+        Some(text) => text,
+        None => return lines,
+    };
+
+    let current = debugger.line_at(debugger.machine.pc);
+    let first = cursor.saturating_sub(SOURCE_LINES / 2).max(1);
+    for (idx, source) in text.lines().enumerate().skip(first - 1).take(SOURCE_LINES) {
+        let number = idx + 1;
+        let breakpoint = debugger
+            .breakpoints()
+            .iter()
+            .any(|&address| debugger.line_at(address) == Some((file, number)));
+        let line = format!(
+            "{}{}{} {:4}  {}",
+            if breakpoint { '*' } else { ' ' },
+            if current == Some((file, number)) {
+                '>'
+            } else {
+                ' '
+            },
+            if cursor == number { '@' } else { ' ' },
+            number,
+            source.replace('\t', "    ")
+        );
+        lines.push(line.chars().take(LEFT_WIDTH).collect());
+    }
+    lines
+}
+
+/// The instructions around the program counter.
+fn disassembly_panel(debugger: &Debugger) -> Vec<String> {
+    let machine = &debugger.machine;
+    let mut lines = vec![heading("Disassembly", LEFT_WIDTH)];
+    let start = machine
+        .pc
+        .saturating_sub(2 * (DISASSEMBLY_LINES as u16 / 2));
+    for address in (start..).step_by(2).take(DISASSEMBLY_LINES) {
+        if usize::from(address) + 1 >= MEMORY_SIZE {
+            break;
+        }
+        let opcode = machine.opcode_at(address);
+        let text = Instruction::decode(opcode)
+            .map_or_else(|| format!("DW 0x{:04X}", opcode), |i| i.to_string());
+        let line = format!(
+            "{}{} {:03X}  {:04X}  {:<20} {}",
+            if debugger.breakpoints().contains(&address) {
+                '*'
+            } else {
+                ' '
+            },
+            if address == machine.pc { '>' } else { ' ' },
+            address,
+            opcode,
+            text,
+            debugger
+                .assembly
+                .label_at(address)
+                .map_or_else(String::new, |_| debugger.assembly.describe_address(address))
+        );
+        lines.push(line.trim_end().chars().take(LEFT_WIDTH).collect());
+    }
+    lines
+}
+
+fn registers_panel(debugger: &Debugger) -> Vec<String> {
+    let machine = &debugger.machine;
+    let assembly = &debugger.assembly;
+    let mut lines = vec![heading("Registers", DISPLAY_WIDTH + 2)];
+    for row in machine.v.chunks(4).enumerate() {
+        let (y, values) = row;
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(x, value)| format!("V{:X} {:02X}", y * 4 + x, value))
+            .collect();
+        lines.push(cells.join("   "));
+    }
+    lines.push(format!(
+        "PC {:03X} {:<18} I  {:03X} {}",
+        machine.pc,
+        assembly.describe_address(machine.pc),
+        machine.i,
+        assembly.describe_address(machine.i)
+    ));
+    lines.push(format!(
+        "SP {:<22} DT {:02X}   ST {:02X}   cycles {}",
+        machine.stack.len(),
+        machine.delay_timer,
+        machine.sound_timer,
+        machine.cycles
+    ));
+    let stack: Vec<String> = machine
+        .stack
+        .iter()
+        .rev()
+        .map(|&address| assembly.describe_address(address))
+        .collect();
+    lines.push(format!("Stack  {}", stack.join("  ")));
+    lines.push(format!(
+        "Frame  {}   history {} instructions",
+        debugger.frame(),
+        debugger.history_len()
+    ));
+
+    let breakpoints: Vec<String> = debugger
+        .breakpoints()
+        .iter()
+        .map(|&address| assembly.describe_address(address))
+        .collect();
+    lines.push(format!("Breakpoints  {}", breakpoints.join("  ")));
+    let watchpoints: Vec<String> = debugger
+        .watchpoints()
+        .map(|address| {
+            format!(
+                "{:03X}={:02X}",
+                address,
+                machine.memory[usize::from(address)]
+            )
+        })
+        .collect();
+    lines.push(format!("Watching  {}", watchpoints.join("  ")));
+    let keys: Vec<String> = (0..16)
+        .filter(|&k| machine.keys[k])
+        .map(|k| format!("{:X}", k))
+        .collect();
+    lines.push(format!("Keys held  {}", keys.join(" ")));
+    lines
+}
+
+/// The display, two rows of pixels to each line of text.
+fn display_panel(debugger: &Debugger) -> Vec<String> {
+    let machine = &debugger.machine;
+    let mut lines = vec![format!("┌{}┐", "─".repeat(DISPLAY_WIDTH))];
+    for y in (0..DISPLAY_HEIGHT).step_by(2) {
+        let row: String = (0..DISPLAY_WIDTH)
+            .map(|x| match (machine.pixel(x, y), machine.pixel(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect();
+        lines.push(format!("│{}│", row));
+    }
+    lines.push(format!("└{}┘", "─".repeat(DISPLAY_WIDTH)));
+    lines
+}
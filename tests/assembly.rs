@@ -0,0 +1,121 @@
+//! Assembling from memory, with includes served by a `MemoryFiles`.
+
+use chip8_assembler::{
+    assemble_path, assemble_str, DiagnosticKind, MemoryFiles, Options, Severity,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn options(files: &[(&str, &str)]) -> Options {
+    let mut memory = MemoryFiles::new();
+    for (path, text) in files {
+        memory.insert(path, *text);
+    }
+    Options {
+        files: Arc::new(memory),
+        ..Options::default()
+    }
+}
+
+#[test]
+fn returns_symbols_and_source_map() {
+    let assembly = assemble_str("start:\n    CLS\n\nsprite: DB 0x80, 0x40", &options(&[])).unwrap();
+    assert_eq!(assembly.base, 0x200);
+    assert_eq!(assembly.bytes, [0x00, 0xE0, 0x80, 0x40]);
+    assert_eq!(assembly.symbols["start"], 0x200);
+    assert_eq!(assembly.symbols["sprite"], 0x202);
+    assert_eq!(assembly.files, [PathBuf::from("<input>")]);
+    let mapped: Vec<(u16, u16, usize)> = assembly
+        .source_map
+        .iter()
+        .filter(|m| m.len > 0)
+        .map(|m| (m.address, m.len, m.line))
+        .collect();
+    assert_eq!(mapped, [(0x200, 2, 2), (0x202, 2, 4)]);
+    assert_eq!(
+        assembly.address_of_line(Path::new("<input>"), 4),
+        Some(0x202)
+    );
+    assert_eq!(assembly.describe_address(0x203), "sprite+1");
+    assert!(assembly.warnings.is_empty());
+}
+
+#[test]
+fn includes_are_read_from_the_file_provider() {
+    let opts = options(&[
+        (
+            "game/main.asm",
+            "INCLUDE \"sprites.asm\"\nINCLUDE \"lib.asm\"\n    CALL clear",
+        ),
+        ("game/sprites.asm", "ball: DB 0xC0"),
+        ("lib/lib.asm", "clear: CLS\n    RET"),
+    ]);
+    let opts = Options {
+        include_paths: vec![PathBuf::from("lib")],
+        ..opts
+    };
+    let assembly = assemble_path(Path::new("game/main.asm"), &opts).unwrap();
+    assert_eq!(assembly.bytes, [0xC0, 0x00, 0xE0, 0x00, 0xEE, 0x22, 0x01]);
+    assert_eq!(
+        assembly.files,
+        [
+            PathBuf::from("game/main.asm"),
+            PathBuf::from("game/sprites.asm"),
+            PathBuf::from("lib/lib.asm")
+        ]
+    );
+    let mapping = assembly.mapping_at(0x203).unwrap();
+    assert_eq!((mapping.file, mapping.line), (2, 2));
+}
+
+#[test]
+fn include_errors() {
+    let opts = options(&[
+        ("a.asm", "INCLUDE \"b.asm\""),
+        ("b.asm", "INCLUDE \"a.asm\""),
+    ]);
+    let err = assemble_path(Path::new("a.asm"), &opts).unwrap_err();
+    assert_eq!(err.to_string(), "b.asm:1: error: `a.asm` includes itself");
+
+    let err = assemble_str("INCLUDE \"missing.asm\"", &options(&[])).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "<input>:1: error: can't find included file `missing.asm`"
+    );
+
+    let err = assemble_path(Path::new("missing.asm"), &options(&[])).unwrap_err();
+    let diagnostic = err.iter().next().unwrap();
+    assert_eq!(diagnostic.line, 0);
+    assert!(matches!(diagnostic.kind, DiagnosticKind::Io(_)));
+}
+
+#[test]
+fn reports_every_error_and_warning() {
+    let err = assemble_str("JP nowhere\nLD V0, 0x100\nDB 1\n    CLS", &options(&[])).unwrap_err();
+    assert_eq!(
+        err.iter().map(|d| (d.severity, d.line)).collect::<Vec<_>>(),
+        [
+            (Severity::Error, 1),
+            (Severity::Error, 2),
+            (Severity::Warning, 4)
+        ]
+    );
+    assert_eq!(err.errors().count(), 2);
+    assert_eq!(
+        err.warnings().next().unwrap().message(),
+        "instruction at odd offset 005 in section `code`"
+    );
+
+    let assembly = assemble_str("DB 1\n    CLS", &options(&[])).unwrap();
+    assert_eq!(assembly.warnings.len(), 1);
+}
+
+#[test]
+fn sections_too_large_for_memory_are_errors() {
+    let source = "    DS 0xFFF\n".repeat(17);
+    let err = assemble_str(&source, &options(&[])).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "<input>:17: error: value 0x10FEF is out of range (max 0xFFFF)"
+    );
+}
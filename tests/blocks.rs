@@ -0,0 +1,161 @@
+//! Structured control flow, checked through the listing of what it lowers to and by running it.
+
+use chip8_assembler::harness::Harness;
+use chip8_assembler::{assemble_path, assemble_str, listing, MemoryFiles, Options, V0, V1};
+use std::path::Path;
+use std::sync::Arc;
+
+fn list(source: &str) -> String {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", source);
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    match assemble_path(Path::new("main.asm"), &opts) {
+        Ok(assembly) => listing(&assembly, &*opts.files),
+        Err(diagnostics) => panic!("{}", diagnostics),
+    }
+}
+
+fn errors(source: &str) -> String {
+    assemble_str(source, &Options::default())
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn if_else_lowers_to_a_branch_around_each_arm() {
+    assert_eq!(
+        list(".if V0 == 5\n    CLS\n.else\n    RET\n.endif\n"),
+        "\
+; main.asm
+0200                   1  .if V0 == 5
+0200  30 05                   => SE V0, 0x05
+0202  12 08                   => JP 0x208
+0204  00 E0            2      CLS
+0206                   3  .else
+0206  12 0A                   => JP 0x20A
+0208  00 EE            4      RET
+                       5  .endif
+"
+    );
+}
+
+#[test]
+fn while_goes_around_again_if_its_condition_holds() {
+    assert_eq!(
+        list(
+            "\
+.loop
+    ADD V1, 0xFF
+    .if V1 == 2
+        .continue
+    .endif
+.while V1 != 0
+"
+        ),
+        "\
+; main.asm
+                       1  .loop
+0200  71 FF            2      ADD V1, 0xFF
+0202                   3      .if V1 == 2
+0202  31 02                   => SE V1, 0x02
+0204  12 08                   => JP 0x208
+0206                   4          .continue
+0206  12 08                   => JP 0x208
+                       5      .endif
+0208                   6  .while V1 != 0
+0208  31 00                   => SE V1, 0x00
+020A  12 00                   => JP 0x200
+"
+    );
+}
+
+#[test]
+fn again_always_goes_around_again() {
+    assert_eq!(
+        list(
+            "\
+.loop
+    .if V2 == 5
+        .break
+    .endif
+    INC V2
+.again
+"
+        ),
+        "\
+; main.asm
+                       1  .loop
+0200                   2      .if V2 == 5
+0200  32 05                   => SE V2, 0x05
+0202  12 06                   => JP 0x206
+0204                   3          .break
+0204  12 0A                   => JP 0x20A
+                       4      .endif
+0206                   5      INC V2
+0206  72 01                   => ADD V2, 0x01
+0208                   6  .again
+0208  12 00                   => JP 0x200
+"
+    );
+}
+
+#[test]
+fn nested_blocks_run() {
+    // Counts V1 down from 10, adding the odd numbers into V0 and stopping early at 3.
+    let mut harness = Harness::assemble(
+        "
+            LD V1, #10
+        .loop
+            LD V2, V1
+            LD V3, 1
+            AND V2, V3
+            .if V2 == 1
+                ADD V0, V1
+            .endif
+            .if V1 == 3
+                .break
+            .endif
+            ADD V1, 0xFF
+        .while V1 != 0
+        done:
+            JP done
+        ",
+    );
+    harness.run_to("done");
+    harness.assert_register(V0, 9 + 7 + 5 + 3);
+    harness.assert_register(V1, 3);
+}
+
+#[test]
+fn unbalanced_blocks() {
+    assert_eq!(errors(".endif"), "<input>:1: error: `.endif` without `.if`");
+    assert_eq!(
+        errors(".again"),
+        "<input>:1: error: `.again` without `.loop`"
+    );
+    assert_eq!(
+        errors(".break"),
+        "<input>:1: error: `.break` outside of a `.loop`"
+    );
+    assert_eq!(
+        errors(".loop\n    CLS"),
+        "<input>:1: error: `.loop` is never closed with `.while` or `.again`"
+    );
+    assert_eq!(
+        errors(".if V0 == 1\n.again"),
+        "<input>:2: error: `.again` doesn't match the `.if` on line 1\n\
+         <input>:1: error: `.if` is never closed with `.endif`"
+    );
+    assert_eq!(
+        errors(".if V0 == 1\n.else\n.else\n.endif"),
+        "<input>:3: error: the `.if` on line 1 already has an `.else`"
+    );
+    assert_eq!(
+        errors(".if V0 1\n.endif"),
+        "<input>:1: error: `.if` takes 3 operand(s) but 2 were given\n\
+         <input>:2: error: `.endif` without `.if`"
+    );
+}
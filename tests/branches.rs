@@ -0,0 +1,126 @@
+//! Branch pseudo-instructions, checked against their expansions and then run both ways.
+
+use chip8_assembler::harness::Harness;
+use chip8_assembler::{assemble_str, Options};
+
+fn assemble(source: &str) -> Vec<u8> {
+    match assemble_str(source, &Options::default()) {
+        Ok(assembly) => assembly.bytes,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    }
+}
+
+fn error(source: &str) -> String {
+    assemble_str(source, &Options::default())
+        .unwrap_err()
+        .to_string()
+}
+
+/// Runs `branch` with `V1` and `V2` set, returning whether it jumped.
+fn jumps(branch: &str, a: u8, b: u8) -> bool {
+    let mut harness = Harness::assemble(&format!(
+        "
+            LD V1, {:#04X}
+            LD V2, {:#04X}
+            {}
+            LD V0, 0
+            JP done
+        yes:
+            LD V0, 1
+        done:
+            JP done
+        ",
+        a, b, branch
+    ));
+    harness.run_to("done");
+    harness.v[0] == 1
+}
+
+#[test]
+fn expansions() {
+    // Each jumps to the `JP` at 0x200.
+    let expanded = |branch: &str| assemble(&format!("target: CLS\n{}", branch))[2..].to_vec();
+    assert_eq!(expanded("JEQ V1, V2, target"), [0x91, 0x20, 0x12, 0x00]);
+    assert_eq!(expanded("JEQ V1, 5, target"), [0x41, 0x05, 0x12, 0x00]);
+    assert_eq!(expanded("JNE V1, V2, target"), [0x51, 0x20, 0x12, 0x00]);
+    assert_eq!(expanded("JNE V1, 5, target"), [0x31, 0x05, 0x12, 0x00]);
+    assert_eq!(expanded("JKP V1, target"), [0xE1, 0xA1, 0x12, 0x00]);
+    assert_eq!(expanded("JKNP V1, target"), [0xE1, 0x9E, 0x12, 0x00]);
+    assert_eq!(
+        expanded("JLT V1, V2, target"),
+        [0x8F, 0x10, 0x8F, 0x25, 0x4F, 0x00, 0x12, 0x00]
+    );
+    assert_eq!(
+        expanded("JGE V1, 5, target"),
+        [0x6F, 0x05, 0x8F, 0x17, 0x3F, 0x00, 0x12, 0x00]
+    );
+    assert_eq!(
+        expanded("JGT V1, V2, target"),
+        [0x8F, 0x20, 0x8F, 0x15, 0x4F, 0x00, 0x12, 0x00]
+    );
+    assert_eq!(
+        expanded("JLE V1, 5, target"),
+        [0x6F, 0x05, 0x8F, 0x15, 0x3F, 0x00, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn forward_labels_account_for_the_expansions() {
+    assert_eq!(
+        assemble("JLT V1, V2, end\nJEQ V1, 0, end\nend: CLS"),
+        [0x8F, 0x10, 0x8F, 0x25, 0x4F, 0x00, 0x12, 0x0C, 0x41, 0x00, 0x12, 0x0C, 0x00, 0xE0]
+    );
+}
+
+#[test]
+fn comparisons_jump_when_they_hold() {
+    let values = [0, 1, 5, 0x7F, 0x80, 0xFF];
+    for &a in &values {
+        for &b in &values {
+            let c = format!("{:#04X}", b);
+            let cases = [
+                ("JEQ", a == b),
+                ("JNE", a != b),
+                ("JLT", a < b),
+                ("JGE", a >= b),
+                ("JGT", a > b),
+                ("JLE", a <= b),
+            ];
+            for (mnemonic, expected) in cases {
+                for rhs in ["V2", c.as_str()] {
+                    let branch = format!("{} V1, {}, yes", mnemonic, rhs);
+                    assert_eq!(jumps(&branch, a, b), expected, "{} with V1={}", branch, a);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn key_branches_jump_on_the_key_in_vx() {
+    for (branch, pressed) in [("JKP V1, yes", true), ("JKNP V1, yes", false)] {
+        for press in [false, true] {
+            let mut harness = Harness::assemble(&format!(
+                "LD V1, 7\n{}\nLD V0, 0\ndone: JP done\nyes: LD V0, 1\nJP done",
+                branch
+            ));
+            if press {
+                harness.press(7);
+            }
+            harness.run_to("done");
+            assert_eq!(harness.v[0] == 1, press == pressed, "{}", branch);
+        }
+    }
+}
+
+#[test]
+fn comparisons_need_vf_for_scratch() {
+    assert_eq!(
+        error("JLT VF, V1, 0x200"),
+        "<input>:1: error: invalid operand `VF`"
+    );
+    assert_eq!(
+        error("JGT V1, VF, 0x200"),
+        "<input>:1: error: invalid operand `VF`"
+    );
+}
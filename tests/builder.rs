@@ -0,0 +1,157 @@
+//! Programs built from Rust, checked against the same program assembled from source.
+
+use chip8_assembler::builder::{AtI, ProgramBuilder, B, DT, F, I, K, ST};
+use chip8_assembler::{assemble_str, Options, ParseErr, V0, V1, V2, V3, V4, V5, V6, V7, V8, V9};
+
+#[test]
+fn builds_the_same_bytes_as_the_text_assembler() {
+    let source = "\
+start:
+    CLS
+    LD V0, 5
+    LD V1, V0
+    LD V2, DT
+    LD V3, K
+    LD DT, V4
+    LD ST, V5
+    LD F, V6
+    LD B, V7
+    LD [I], V8
+    LD V9, [I]
+    LD I, sprite
+    ADD V0, 1
+    ADD V0, V1
+    ADD I, V2
+    OR V0, V1
+    AND V0, V1
+    XOR V0, V1
+    SUB V0, V1
+    SUBN V0, V1
+    SHR V3
+    SHL V3
+    RND V4, 0x0F
+    SE V0, 3
+    SNE V0, V1
+    SKP V5
+    SKNP V6
+    DRW V0, V1, 1
+    CALL sub
+    JP V0, table
+    JP start
+sub:
+    SYS 0x123
+    RET
+table:
+    DW sprite, 0x1234
+sprite:
+    DB 0x80, 0x40
+";
+    let mut b = ProgramBuilder::new();
+    b.label("start")
+        .cls()
+        .ld(V0, 5)
+        .ld(V1, V0)
+        .ld(V2, DT)
+        .ld(V3, K)
+        .ld(DT, V4)
+        .ld(ST, V5)
+        .ld(F, V6)
+        .ld(B, V7)
+        .ld(AtI, V8)
+        .ld(V9, AtI)
+        .ld(I, "sprite")
+        .add(V0, 1)
+        .add(V0, V1)
+        .add(I, V2)
+        .or(V0, V1)
+        .and(V0, V1)
+        .xor(V0, V1)
+        .sub(V0, V1)
+        .subn(V0, V1)
+        .shr(V3)
+        .shl(V3)
+        .rnd(V4, 0x0F)
+        .se(V0, 3)
+        .sne(V0, V1)
+        .skp(V5)
+        .sknp(V6)
+        .drw(V0, V1, 1)
+        .call("sub")
+        .jp_v0("table")
+        .jp("start");
+    b.label("sub").sys(0x123).ret();
+    b.label("table").dw_label("sprite").dw(&[0x1234]);
+    b.label("sprite").db(&[0x80, 0x40]);
+
+    let built = b.build().unwrap();
+    let assembled = assemble_str(source, &Options::default()).unwrap();
+    assert_eq!(built.bytes, assembled.bytes);
+    assert_eq!(built.symbols, assembled.symbols);
+    assert_eq!(b.address(), 0x200 + assembled.bytes.len() as u16);
+}
+
+#[test]
+fn errors() {
+    let mut b = ProgramBuilder::new();
+    b.jp("nowhere");
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::UndefinedSymbol(String::from("nowhere"))
+    );
+
+    let mut b = ProgramBuilder::new();
+    b.label("twice").cls().label("twice");
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::DuplicateSymbol(String::from("twice"))
+    );
+
+    let mut b = ProgramBuilder::new();
+    b.drw(V0, V1, 16);
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::ValueOutOfRange {
+            value: 16,
+            max: 0x0F
+        }
+    );
+
+    let mut b = ProgramBuilder::new();
+    b.call(0x1000);
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::ValueOutOfRange {
+            value: 0x1000,
+            max: 0x0FFF
+        }
+    );
+}
+
+#[test]
+fn programs_too_large_for_memory_are_errors() {
+    let mut b = ProgramBuilder::new();
+    b.db(&[0; 0xDFF]).db(&[0]);
+    assert_eq!(b.address(), 0x1000);
+    b.build().unwrap();
+
+    b.db(&[0]).cls();
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::ValueOutOfRange {
+            value: 0x1001,
+            max: 0x1000
+        }
+    );
+    assert_eq!(b.address(), 0x1000);
+
+    let mut b = ProgramBuilder::new();
+    b.db(&[0; 0xFDFF]);
+    assert_eq!(
+        b.build().unwrap_err(),
+        ParseErr::ValueOutOfRange {
+            value: 0xFFFF,
+            max: 0x1000
+        }
+    );
+    assert_eq!(b.address(), 0x200);
+}
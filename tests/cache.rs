@@ -0,0 +1,202 @@
+//! Assembling with a [`AssemblyCache`] must give exactly what a clean build gives.
+
+use chip8_assembler::{assemble_path, Assembly, AssemblyCache, Diagnostics, MemoryFiles, Options};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAIN: &str = "\
+DEFINE speed 3
+INCLUDE \"sprites.asm\"
+start:
+    LD V0, speed
+    CALL move
+    .if V0 == 9
+        CLS
+    .endif
+    LD I, ship
+    DRW V0, V1, 5
+    JP start
+INCLUDE \"move.asm\"
+";
+
+const MOVE: &str = "\
+move:
+    ADD V0, speed
+    RET
+";
+
+const SPRITES: &str = "\
+ship: DB 0x20, 0x70, 0xF8, 0x70, 0x20
+";
+
+fn options(files: &[(&str, &str)], cache: Option<&Arc<AssemblyCache>>) -> Options {
+    let mut memory = MemoryFiles::new();
+    for (path, text) in files {
+        memory.insert(path, *text);
+    }
+    Options {
+        files: Arc::new(memory),
+        cache: cache.cloned(),
+        ..Options::default()
+    }
+}
+
+/// Assembles with the cache, and checks the result against a clean build.
+fn assemble_both(
+    files: &[(&str, &str)],
+    cache: &Arc<AssemblyCache>,
+) -> Result<Assembly, Diagnostics> {
+    let clean = assemble_path(Path::new("main.asm"), &options(files, None));
+    let cached = assemble_path(Path::new("main.asm"), &options(files, Some(cache)));
+    match (&clean, &cached) {
+        (Ok(clean), Ok(cached)) => {
+            assert_eq!(clean.base, cached.base);
+            assert_eq!(clean.bytes, cached.bytes);
+            assert_eq!(clean.symbols, cached.symbols);
+            assert_eq!(clean.files, cached.files);
+            assert_eq!(clean.source_map, cached.source_map);
+            assert_eq!(clean.sections, cached.sections);
+            assert_eq!(clean.warnings, cached.warnings);
+        }
+        (Err(clean), Err(cached)) => assert_eq!(clean, cached),
+        _ => panic!("clean build gave {:?}, cached gave {:?}", clean, cached),
+    }
+    cached
+}
+
+#[test]
+fn matches_a_clean_build_as_files_change() {
+    let cache = Arc::new(AssemblyCache::new());
+    let project = [
+        ("main.asm", MAIN),
+        ("move.asm", MOVE),
+        ("sprites.asm", SPRITES),
+    ];
+    let first = assemble_both(&project, &cache).unwrap();
+    assert_eq!(cache.reused(), 0);
+
+    // Nothing changed, so nothing is parsed again.
+    let again = assemble_both(&project, &cache).unwrap();
+    assert_eq!(cache.reused(), 3);
+    assert_eq!(first.bytes, again.bytes);
+
+    // Growing an include moves everything after it.
+    let sprites = "ship: DB 0x20, 0x70, 0xF8, 0x70, 0x20\nbig: DS 6\n";
+    let moved = assemble_both(
+        &[
+            ("main.asm", MAIN),
+            ("move.asm", MOVE),
+            ("sprites.asm", sprites),
+        ],
+        &cache,
+    )
+    .unwrap();
+    assert_eq!(cache.reused(), 5);
+    assert_ne!(first.symbols["move"], moved.symbols["move"]);
+
+    // Errors are the same too, and the cache recovers when they're fixed.
+    let broken = "move:\n    ADD V0, nowhere\n    RET\n";
+    assemble_both(
+        &[
+            ("main.asm", MAIN),
+            ("move.asm", broken),
+            ("sprites.asm", SPRITES),
+        ],
+        &cache,
+    )
+    .unwrap_err();
+    let fixed = assemble_both(&project, &cache).unwrap();
+    assert_eq!(first.bytes, fixed.bytes);
+    assert_eq!(first.symbols, fixed.symbols);
+}
+
+#[test]
+fn main_file_changes_are_picked_up() {
+    let cache = Arc::new(AssemblyCache::new());
+    let project = [
+        ("main.asm", MAIN),
+        ("move.asm", MOVE),
+        ("sprites.asm", SPRITES),
+    ];
+    assemble_both(&project, &cache).unwrap();
+
+    let main = MAIN.replace("DEFINE speed 3", "DEFINE speed 4");
+    let changed = assemble_both(
+        &[
+            ("main.asm", &main),
+            ("move.asm", MOVE),
+            ("sprites.asm", SPRITES),
+        ],
+        &cache,
+    )
+    .unwrap();
+    assert_eq!(cache.reused(), 2);
+    assert_eq!(changed.symbols["speed"], 4);
+}
+
+#[test]
+fn the_same_text_is_parsed_once_whatever_its_path() {
+    let cache = Arc::new(AssemblyCache::new());
+    let main = "INCLUDE \"a.asm\"\nINCLUDE \"b.asm\"\nstart: JP start\n";
+    let shared = "    CLS\n";
+    assemble_both(
+        &[("main.asm", main), ("a.asm", shared), ("b.asm", shared)],
+        &cache,
+    )
+    .unwrap();
+    assert_eq!(cache.reused(), 1);
+}
+
+#[test]
+fn only_files_after_a_change_in_layout_are_laid_out_again() {
+    let cache = Arc::new(AssemblyCache::new());
+    let mut laid_out = 0;
+    let mut assemble = |sprites: &str, moves: &str| {
+        let files = [
+            ("main.asm", MAIN),
+            ("move.asm", moves),
+            ("sprites.asm", sprites),
+        ];
+        let assembly = assemble_both(&files, &cache);
+        let lines = cache.lines_laid_out() - laid_out;
+        laid_out = cache.lines_laid_out();
+        (assembly, lines)
+    };
+    let (_, all) = assemble(SPRITES, MOVE);
+    assert_eq!(all, 14);
+    assert_eq!(assemble(SPRITES, MOVE).1, 0);
+
+    // Changing what a sprite holds moves nothing, so only its own line is laid out again.
+    let changed = "ship: DB 0x20, 0x70, 0xF8, 0x70, 0x21\n";
+    assert_eq!(assemble(changed, MOVE).1, 1);
+
+    // Growing it moves every line after it, from every file.
+    let grown = "ship: DB 0x20, 0x70, 0xF8, 0x70, 0x20, 0\n";
+    let (assembly, lines) = assemble(grown, MOVE);
+    assert_eq!(lines, all - 1);
+    assert_eq!(assembly.unwrap().symbols["move"], 0x216);
+
+    // Errors are laid out again too, and kept with the lines which had them.
+    let broken = "move:\n    ADD V0, speed\nmove:\n    RET\n";
+    let (assembly, lines) = assemble(grown, broken);
+    assert_eq!(lines, 4);
+    assert_eq!(
+        assembly.unwrap_err().to_string(),
+        "move.asm:3: error: symbol `move` is already defined"
+    );
+    let (assembly, lines) = assemble(changed, MOVE);
+    assert_eq!(lines, all - 1);
+    assert_eq!(assembly.unwrap().symbols["move"], 0x215);
+
+    // An error which moves nothing is kept along with those found after it last time.
+    let sectioned = format!("{}SECTION code\n", grown);
+    assert_eq!(assemble(&sectioned, broken).1, all + 1);
+    let unbalanced = format!("{}ENDP\n", grown);
+    let (assembly, lines) = assemble(&unbalanced, broken);
+    assert_eq!(lines, 2);
+    assert_eq!(
+        assembly.unwrap_err().to_string(),
+        "sprites.asm:2: error: `ENDP` without `PROC`\n\
+         move.asm:3: error: symbol `move` is already defined"
+    );
+}
@@ -0,0 +1,343 @@
+//! The command line, run as a separate process on files in a scratch directory.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const PROGRAM: &str = "\
+start:
+    LD V0, SPEED
+    JP start
+";
+
+/// Makes an empty directory for a test to work in.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("chip8_cli_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("prog.asm"), PROGRAM).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8_assembler"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn assemble_writes_next_to_the_input_by_default() {
+    let dir = scratch("default_output");
+    let output = run(&dir, &["assemble", "-D", "SPEED=0x12", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        fs::read(dir.join("prog.ch8")).unwrap(),
+        [0x60, 0x12, 0x12, 0x00]
+    );
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn assemble_writes_hex_to_stdout() {
+    let dir = scratch("hex_output");
+    let output = run(
+        &dir,
+        &[
+            "assemble", "-f", "hex", "-o", "-", "-D", "SPEED", "prog.asm",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "60 01 12 00\n");
+}
+
+#[test]
+fn verbose_prints_the_symbol_table() {
+    let dir = scratch("verbose");
+    let output = run(&dir, &["assemble", "-v", "-D", "SPEED", "prog.asm"]);
+    assert_eq!(
+        stderr(&output),
+        "SPEED = 0x001\nstart = 0x200\nassembled 4 bytes into prog.ch8\n"
+    );
+}
+
+#[test]
+fn disasm_reads_a_rom() {
+    let dir = scratch("disasm");
+    fs::write(dir.join("prog.ch8"), [0x60, 0x12, 0x12, 0x00]).unwrap();
+    let output = run(&dir, &["disasm", "prog.ch8"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "L200:\n    LD V0, 0x12          ; 200: 6012\n    JP L200              ; 202: 1200\n"
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let dir = scratch("exit_codes");
+
+    let output = run(&dir, &["check", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "prog.asm:2: error: undefined symbol `SPEED`\n"
+    );
+    assert!(!dir.join("prog.ch8").exists());
+
+    let output = run(&dir, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("error: no command given\n\nUsage:"));
+
+    let output = run(&dir, &["assemble", "--bogus", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("error: unknown option `--bogus`\n"));
+
+    let output = run(&dir, &["assemble", "missing.asm"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).starts_with("missing.asm: error: "));
+}
+
+#[test]
+fn help_and_version() {
+    let dir = scratch("help");
+    let output = run(&dir, &["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Usage: chip8_assembler <COMMAND>"));
+
+    let output = run(&dir, &["--version"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        format!("chip8_assembler {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn run_loads_at_the_base() {
+    let dir = scratch("run_base");
+    // LD V0, 5; JP 0x302
+    fs::write(dir.join("prog.ch8"), [0x60, 0x05, 0x13, 0x02]).unwrap();
+    let output = run(
+        &dir,
+        &["run", "--base", "0x300", "--frames", "1", "prog.ch8"],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let state = stdout(&output);
+    assert!(state.starts_with("V0=05 "), "{}", state);
+    assert!(state.contains("\nI=000 PC=302 "), "{}", state);
+
+    let output = run(
+        &dir,
+        &[
+            "run", "--base", "0x300", "--frames", "1", "-D", "SPEED=5", "prog.asm",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let state = stdout(&output);
+    assert!(state.starts_with("V0=05 "), "{}", state);
+    assert!(state.contains("\nI=000 PC=30"), "{}", state);
+}
+
+#[test]
+fn link_reads_objects_and_sources() {
+    let dir = scratch("link");
+    fs::write(
+        dir.join("main.asm"),
+        "IMPORT draw\nstart: CALL draw\n    JP start\n",
+    )
+    .unwrap();
+    fs::write(dir.join("draw.asm"), "EXPORT draw\ndraw: RET\n").unwrap();
+
+    let output = run(&dir, &["assemble", "-f", "obj", "main.asm"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(dir.join("main.c8o").exists());
+
+    let output = run(&dir, &["link", "main.c8o", "draw.asm", "-o", "game.ch8"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        fs::read(dir.join("game.ch8")).unwrap(),
+        [0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]
+    );
+}
+
+#[test]
+fn map_shows_where_the_layout_put_sections() {
+    let dir = scratch("map");
+    fs::write(
+        dir.join("vars.asm"),
+        "start: JP start\nSECTION vars\nscore: DS 2\n",
+    )
+    .unwrap();
+    let output = run(
+        &dir,
+        &[
+            "assemble",
+            "--layout",
+            "code, vars=0x300",
+            "--map",
+            "-",
+            "vars.asm",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "\
+Section           Start  End     Size
+code              0x200  0x201     2
+vars              0x300  0x301     2  reserved
+
+Symbol            Value
+start             0x200
+score             0x300
+"
+    );
+    assert_eq!(fs::read(dir.join("vars.ch8")).unwrap(), [0x12, 0x00]);
+}
+
+#[test]
+fn fmt_check_fails_until_the_file_is_formatted() {
+    let dir = scratch("fmt_check");
+    fs::write(dir.join("prog.asm"), "start: ld v0,1\n  jp start\n").unwrap();
+    let output = run(&dir, &["fmt", "--check", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "error: prog.asm isn't formatted\n");
+
+    let output = run(&dir, &["fmt", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(dir.join("prog.asm")).unwrap(),
+        "start: LD V0, 1\n    JP start\n"
+    );
+    let output = run(&dir, &["fmt", "--check", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+/// A child process which is killed when dropped, so a failing test doesn't leave it running.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn watch_reassembles_when_an_included_file_changes() {
+    let dir = scratch("watch");
+    fs::write(
+        dir.join("prog.asm"),
+        "INCLUDE \"inc.asm\"\nstart: JP start\n",
+    )
+    .unwrap();
+    fs::write(dir.join("inc.asm"), "CLS\n").unwrap();
+    let mut child = Running(
+        Command::new(env!("CARGO_BIN_EXE_chip8_assembler"))
+            .args(["assemble", "--watch", "prog.asm"])
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let (lines, received) = mpsc::channel();
+    let stderr = child.0.stderr.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            if lines.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    // Reads lines up to where it starts watching again.
+    let until_watching = || {
+        let mut seen = Vec::new();
+        loop {
+            let line = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            let watching = line.starts_with("watching");
+            seen.push(line);
+            if watching {
+                return seen;
+            }
+        }
+    };
+
+    assert_eq!(until_watching(), ["watching 2 files for changes"]);
+    assert_eq!(
+        fs::read(dir.join("prog.ch8")).unwrap(),
+        [0x00, 0xE0, 0x12, 0x02]
+    );
+
+    fs::write(dir.join("inc.asm"), "JP nowhere\n").unwrap();
+    assert_eq!(
+        until_watching(),
+        [
+            "",
+            "inc.asm changed",
+            "inc.asm:1: error: undefined symbol `nowhere`",
+            "watching 2 files for changes",
+        ]
+    );
+
+    fs::write(dir.join("inc.asm"), "CLS\n").unwrap();
+    assert_eq!(
+        until_watching(),
+        [
+            "",
+            "inc.asm changed",
+            "1 fixed, 0 unchanged",
+            "watching 2 files for changes",
+        ]
+    );
+}
+
+#[test]
+fn numbers_are_written_as_in_source() {
+    let dir = scratch("numbers");
+    for (args, expected) in [
+        (
+            ["--base", "300", "-D", "SPEED=10"],
+            [0x60, 0x10, 0x13, 0x00],
+        ),
+        (
+            ["--base", "0x300", "-D", "SPEED=#10"],
+            [0x60, 0x0A, 0x13, 0x00],
+        ),
+        (
+            ["--base", "#768", "-D", "SPEED=0b11"],
+            [0x60, 0x03, 0x13, 0x00],
+        ),
+    ] {
+        let mut command = vec!["assemble", "-o", "prog.ch8"];
+        command.extend(&args);
+        command.push("prog.asm");
+        let output = run(&dir, &command);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(
+            fs::read(dir.join("prog.ch8")).unwrap(),
+            expected,
+            "{:?}",
+            args
+        );
+    }
+
+    let output = run(&dir, &["assemble", "--base", "10000", "prog.asm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("error: invalid value `10000` for --base\n"));
+}
@@ -0,0 +1,109 @@
+//! `@clobbers`, `@preserves` and `@returns` annotations on subroutines, checked by linting.
+
+use chip8_assembler::{assemble_path, lint, MemoryFiles, Options, Target};
+use std::path::Path;
+use std::sync::Arc;
+
+fn lints(source: &str) -> Vec<String> {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", source);
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = match assemble_path(Path::new("main.asm"), &opts) {
+        Ok(assembly) => assembly,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    };
+    lint(&assembly, &*opts.files, Target::Chip8)
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+/// A program calling `sub`, which starts on line 3.
+fn calling(sub: &str) -> String {
+    format!("    CALL sub\nhalt: JP halt\n{}", sub)
+}
+
+#[test]
+fn writing_a_preserved_register() {
+    assert_eq!(
+        lints(&calling("; @preserves V1\nsub:\n    LD V1, 2\n    RET\n")),
+        ["main.asm:5: warning: `sub` preserves V1, but `LD V1, 0x02` writes it"]
+    );
+    assert_eq!(
+        lints(&calling("; @clobbers V0\nsub:\n    LD V1, 2\n    RET\n")),
+        ["main.asm:5: warning: `sub` preserves V1, but `LD V1, 0x02` writes it"]
+    );
+    assert!(lints(&calling("; @returns V1\nsub:\n    LD V1, 2\n    RET\n")).is_empty());
+}
+
+#[test]
+fn reading_a_clobbered_register() {
+    assert_eq!(
+        lints("    LD V2, 1\n    CALL sub\n    LD V3, V2\nhalt: JP halt\nsub: ; @clobbers V2\n    RET\n"),
+        ["main.asm:2: warning: V2 is read after calling `sub`, which clobbers it"]
+    );
+    // Setting it again after the call is fine.
+    assert!(lints(
+        "    CALL sub\n    LD V2, 1\n    LD V3, V2\nhalt: JP halt\nsub: ; @clobbers V2\n    RET\n"
+    )
+    .is_empty());
+}
+
+#[test]
+fn saving_and_restoring_preserves() {
+    let sub = "\
+; @preserves V0-V3
+sub:
+    LD I, 0x300
+    LD [I], V3
+    LD V2, 9
+    LD I, 0x300
+    LD V3, [I]
+    RET
+";
+    assert!(lints(&calling(sub)).is_empty());
+
+    // Restoring fewer registers than were saved leaves the rest changed.
+    let sub = sub.replace("LD V3, [I]", "LD V1, [I]");
+    assert_eq!(
+        lints(&calling(&sub)),
+        ["main.asm:7: warning: `sub` preserves V2, but `LD V2, 0x09` writes it"]
+    );
+}
+
+#[test]
+fn register_lists() {
+    let sub = |annotation: &str| {
+        calling(&format!(
+            "; {}\nsub:\n    LD V3, 1\n    LD V5, 1\n    RET\n",
+            annotation
+        ))
+    };
+    assert!(lints(&sub("@clobbers V3 - V5")).is_empty());
+    assert!(lints(&sub("@clobbers v3, V4,V5")).is_empty());
+    // The list ends at the first word which isn't a register.
+    assert!(lints(&sub("@clobbers V3-V5 while drawing, V6 unused")).is_empty());
+    assert_eq!(
+        lints(&sub("@clobbers V3 but not V5")),
+        ["main.asm:6: warning: `sub` preserves V5, but `LD V5, 0x01` writes it"]
+    );
+    assert_eq!(
+        lints(&sub("@clobbers everything")),
+        [
+            "main.asm:4: warning: `everything` isn't a register",
+            "main.asm:5: warning: `sub` preserves V3, but `LD V3, 0x01` writes it",
+            "main.asm:6: warning: `sub` preserves V5, but `LD V5, 0x01` writes it",
+        ]
+    );
+    assert_eq!(
+        lints(&sub("@clobbers V3-VG")),
+        [
+            "main.asm:4: warning: `VG` isn't a register",
+            "main.asm:5: warning: `sub` preserves V3, but `LD V3, 0x01` writes it",
+            "main.asm:6: warning: `sub` preserves V5, but `LD V5, 0x01` writes it",
+        ]
+    );
+}
@@ -0,0 +1,121 @@
+//! Coverage recorded by the interpreter, mapped back to lines and written out as reports.
+
+use chip8_assembler::machine::Machine;
+use chip8_assembler::{
+    assemble_path, run_tests_with_coverage, Branch, Coverage, CoverageReport, MemoryFiles, Options,
+    DEFAULT_CYCLE_LIMIT,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAIN: &str = "\
+start:
+    LD V0, 3
+    CALL count
+done:
+    JP done
+
+count:
+    ADD V0, 0xFF
+    SE V0, 0
+    JP count
+    SE V2, 1
+    LD V1, 1
+    RET
+
+unused:
+    LD V5, 5
+    RET
+
+TEST \"count\"
+    LD V0, 2
+    CALL count
+    EXPECT V0 == 0
+ENDTEST
+
+sprite: DB 0x80
+";
+
+fn options() -> Options {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", MAIN);
+    Options {
+        files: Arc::new(files),
+        ..Options::default()
+    }
+}
+
+fn run() -> CoverageReport {
+    let opts = options();
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    let mut machine = Machine::with_program(opts.target, &assembly.bytes).unwrap();
+    machine.coverage = Some(Coverage::new());
+    while !machine.halted() {
+        machine.step().unwrap();
+    }
+    let coverage = machine.coverage.unwrap();
+    assert_eq!(coverage.hits(0x206), 3);
+    assert_eq!(
+        coverage.branch(0x208),
+        Some(Branch {
+            taken: 1,
+            not_taken: 2
+        })
+    );
+    CoverageReport::new(&assembly, &coverage, &*opts.files)
+}
+
+#[test]
+fn maps_runs_and_skips_to_lines() {
+    let report = run();
+    let hits: Vec<(usize, u64)> = report.lines.iter().map(|l| (l.line, l.hits)).collect();
+    assert_eq!(
+        hits,
+        [
+            (2, 1),
+            (3, 1),
+            (5, 0),
+            (8, 3),
+            (9, 3),
+            (10, 2),
+            (11, 1),
+            (12, 1),
+            (13, 1),
+            (16, 0),
+            (17, 0)
+        ]
+    );
+    let skip = report.lines.iter().find(|l| l.line == 11).unwrap();
+    assert!(!skip.covered());
+    assert_eq!(
+        report.text(),
+        "main.asm: 8/11 lines (72.7%), 3/4 branches (75.0%)\n  \
+         not run: 5, 16-17\n  11: never skipped\n"
+    );
+}
+
+#[test]
+fn writes_lcov_branches_for_each_way_a_skip_goes() {
+    let lcov = run().lcov();
+    assert!(lcov.starts_with("TN:\nSF:main.asm\nDA:2,1\n"));
+    assert!(lcov.contains("BRDA:9,0,0,2\nBRDA:9,0,1,1\nBRDA:11,0,0,1\nBRDA:11,0,1,0\n"));
+    assert!(lcov.ends_with("BRF:4\nBRH:3\nLF:11\nLH:8\nend_of_record\n"));
+}
+
+#[test]
+fn test_coverage_leaves_out_the_tests() {
+    let (results, report) =
+        run_tests_with_coverage(Path::new("main.asm"), &options(), DEFAULT_CYCLE_LIMIT).unwrap();
+    assert!(results.iter().all(|r| r.passed()));
+    let run: Vec<usize> = report
+        .lines
+        .iter()
+        .filter(|l| l.hits > 0)
+        .map(|l| l.line)
+        .collect();
+    assert_eq!(run, [8, 9, 10, 11, 12, 13]);
+    assert!(report.lines.iter().all(|l| l.line < 19));
+    assert!(report
+        .html()
+        .contains("<tr class=\"partial\"><td class=\"n\">11</td>"));
+}
@@ -0,0 +1,156 @@
+//! Drives the debug adapter through a scripted session.
+
+use chip8_assembler::dap::serve;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+const SOURCE: &str = "\
+start:
+    LD V0, 1
+    CALL draw
+    LD V1, 2
+done:
+    JP done
+
+draw:
+    LD I, box
+    ADD V0, 4
+    RET
+
+box: DB 0x90, 0x60
+";
+
+/// Writes the program somewhere the adapter can read it.
+fn program(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("chip8_dap_{}_{}.asm", name, std::process::id()));
+    fs::write(&path, SOURCE).unwrap();
+    path
+}
+
+/// Frames each message the way a client would.
+fn session(messages: &[String]) -> Vec<String> {
+    let mut input = String::new();
+    for message in messages {
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        ));
+    }
+    let mut output = Vec::new();
+    serve(Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut replies = Vec::new();
+    let mut rest = output.as_str();
+    while let Some(idx) = rest.find("\r\n\r\n") {
+        let length: usize = rest["Content-Length: ".len()..idx].parse().unwrap();
+        replies.push(String::from(&rest[idx + 4..idx + 4 + length]));
+        rest = &rest[idx + 4 + length..];
+    }
+    replies
+}
+
+fn request(seq: usize, command: &str, arguments: &str) -> String {
+    format!(
+        r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+        seq, command, arguments
+    )
+}
+
+fn response(replies: &[String], seq: usize) -> &str {
+    let prefix = format!(r#""request_seq":{},"#, seq);
+    replies
+        .iter()
+        .find(|r| r.contains(&prefix))
+        .unwrap_or_else(|| panic!("no response to {} in {:?}", seq, replies))
+}
+
+fn events<'a>(replies: &'a [String], event: &str) -> Vec<&'a str> {
+    let name = format!(r#""event":"{}""#, event);
+    replies
+        .iter()
+        .filter(|r| r.contains(&name))
+        .map(String::as_str)
+        .collect()
+}
+
+#[test]
+fn stops_at_breakpoints_and_shows_state() {
+    let path = program("breakpoints");
+    let path_json = path.display().to_string().replace('\\', "\\\\");
+    let replies = session(&[
+        request(1, "initialize", r#"{"adapterID":"chip8"}"#),
+        request(2, "launch", &format!(r#"{{"program":"{}"}}"#, path_json)),
+        request(
+            3,
+            "setBreakpoints",
+            &format!(
+                r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":9}},{{"line":7}},{{"line":40}}]}}"#,
+                path_json
+            ),
+        ),
+        request(4, "configurationDone", "{}"),
+        request(5, "stackTrace", r#"{"threadId":1}"#),
+        request(6, "variables", r#"{"variablesReference":1}"#),
+        request(7, "evaluate", r#"{"expression":"[box+1]"}"#),
+        request(8, "evaluate", r#"{"expression":"V0 + 3"}"#),
+        request(9, "evaluate", r#"{"expression":"nowhere"}"#),
+        request(10, "stepOut", r#"{"threadId":1}"#),
+        request(11, "stackTrace", r#"{"threadId":1}"#),
+        request(12, "continue", r#"{"threadId":1}"#),
+        request(13, "disconnect", "{}"),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(response(&replies, 1).contains(r#""supportsConfigurationDoneRequest":true"#));
+    assert_eq!(events(&replies, "initialized").len(), 1);
+    // The breakpoint on the blank line moves to the next line with code, past the label, and
+    // the one past the end can't be set.
+    let breakpoints = response(&replies, 3);
+    assert_eq!(
+        breakpoints.matches(r#""line":9,"verified":true"#).count(),
+        2
+    );
+    assert!(breakpoints.contains(r#""verified":false"#));
+
+    let stopped = events(&replies, "stopped");
+    assert!(stopped[0].contains(r#""reason":"breakpoint""#));
+    let trace = response(&replies, 5);
+    assert!(trace.contains(r#""line":9,"name":"draw""#));
+    assert!(trace.contains(r#""line":3,"name":"start""#));
+    assert!(trace.contains(r#""totalFrames":2"#));
+
+    let registers = response(&replies, 6);
+    assert!(registers.contains(r#"{"name":"V0","value":"0x01 (1)","variablesReference":0}"#));
+    assert!(registers.contains(r#"{"name":"PC","value":"0x208 (draw)","variablesReference":0}"#));
+    assert!(response(&replies, 7).contains(r#""result":"96 (0x60)""#));
+    assert!(response(&replies, 8).contains(r#""result":"4 (0x4)""#));
+    assert!(response(&replies, 9).contains(r#""message":"undefined symbol `nowhere`""#));
+
+    assert!(stopped[1].contains(r#""reason":"step""#));
+    assert!(response(&replies, 11).contains(r#""line":4,"name":"start""#));
+    assert_eq!(events(&replies, "terminated").len(), 1);
+    assert!(response(&replies, 13).contains(r#""success":true"#));
+}
+
+#[test]
+fn reports_programs_which_fail_to_assemble() {
+    let path = env::temp_dir().join(format!("chip8_dap_broken_{}.asm", std::process::id()));
+    fs::write(&path, "    JP nowhere\n").unwrap();
+    let path_json = path.display().to_string().replace('\\', "\\\\");
+    let replies = session(&[
+        request(1, "initialize", "{}"),
+        request(2, "launch", &format!(r#"{{"program":"{}"}}"#, path_json)),
+        request(3, "threads", "{}"),
+        request(4, "stackTrace", r#"{"threadId":1}"#),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(response(&replies, 2).contains(r#""success":false"#));
+    assert!(events(&replies, "output")[0].contains("nowhere"));
+    assert!(response(&replies, 3).contains(r#""name":"CHIP-8""#));
+    assert!(response(&replies, 4).contains(r#""message":"no program has been launched""#));
+}
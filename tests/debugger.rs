@@ -0,0 +1,181 @@
+//! Stepping, breakpoints and watchpoints, checked against a program held in memory.
+
+use chip8_assembler::debugger::{Debugger, Stop, Storage};
+use chip8_assembler::{assemble_path, MemoryFiles, Options, VF};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAIN: &str = "\
+start:
+    LD V0, 1
+    CALL draw
+    LD I, counter
+    LD V3, 7
+    LD [I], V3
+done:
+    JP done
+
+draw:
+    LD I, box
+    DRW V0, V0, 2
+    RET
+
+box: DB 0x90, 0x60
+counter: DB 0
+DEFINE BIG FFFF
+";
+
+fn debugger() -> Debugger {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", MAIN);
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    Debugger::new(assembly, opts.target, &*opts.files).unwrap()
+}
+
+#[test]
+fn resolves_labels_lines_and_addresses() {
+    let debugger = debugger();
+    let draw = debugger.resolve("draw").unwrap();
+    assert_eq!(debugger.resolve("11").unwrap(), draw);
+    assert_eq!(debugger.resolve("main.asm:10").unwrap(), draw);
+    assert_eq!(debugger.resolve("0x20C").unwrap(), draw);
+    assert_eq!(debugger.line_at(draw), Some((0, 11)));
+    assert!(debugger.resolve("nowhere").is_err());
+    assert!(debugger.resolve("other.asm:3").is_err());
+}
+
+#[test]
+fn steps_over_calls_and_stops_at_breakpoints() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.step(), Stop::Stepped);
+    assert_eq!(debugger.step_over(), Stop::Stepped);
+    assert_eq!(debugger.machine.pc, debugger.resolve("4").unwrap());
+    assert_eq!(debugger.machine.stack, []);
+
+    let mut debugger = self::debugger();
+    let draw = debugger.resolve("draw").unwrap();
+    debugger.set_breakpoint(draw);
+    assert_eq!(debugger.resume(), Stop::Breakpoint(draw));
+    assert_eq!(
+        debugger.resolve("13").map(|rts| debugger.run_to(rts)),
+        Ok(Stop::Stepped)
+    );
+    assert_eq!(debugger.resume(), Stop::Halted);
+    assert_eq!(debugger.machine.pc, debugger.resolve("done").unwrap());
+}
+
+#[test]
+fn watchpoints_stop_when_memory_changes() {
+    let mut debugger = debugger();
+    let counter = debugger.resolve("counter").unwrap();
+    assert!(debugger.toggle_watchpoint(counter));
+    assert_eq!(
+        debugger.resume(),
+        Stop::Watchpoint {
+            address: counter,
+            old: 0,
+            new: 1
+        }
+    );
+    assert_eq!(debugger.line_at(debugger.machine.pc), Some((0, 8)));
+}
+
+#[test]
+fn watchpoints_outside_memory_are_harmless() {
+    let mut debugger = debugger();
+    assert_eq!(
+        debugger.resolve("BIG"),
+        Err(String::from("`BIG` is 0xFFFF, outside memory"))
+    );
+
+    assert!(debugger.toggle_watchpoint(0xFFFF));
+    debugger.write_memory(0x0FFF, &[1]);
+    assert_eq!(debugger.step(), Stop::Stepped);
+    assert_eq!(debugger.step_back(), Stop::Stepped);
+    assert_eq!(debugger.machine.pc, 0x200);
+}
+
+#[test]
+fn steps_back_to_earlier_states() {
+    let mut debugger = debugger();
+    let start = debugger.machine.clone();
+    while debugger.step() == Stop::Stepped {}
+    assert_eq!(debugger.history_len(), 8);
+    assert_ne!(debugger.machine.display, start.display);
+
+    while debugger.step_back() == Stop::Stepped {}
+    assert_eq!(debugger.machine.pc, start.pc);
+    assert_eq!(debugger.machine.v, start.v);
+    assert_eq!(debugger.machine.i, start.i);
+    assert_eq!(debugger.machine.cycles, 0);
+    assert_eq!(debugger.machine.display, start.display);
+    assert_eq!(&debugger.machine.memory[..], &start.memory[..]);
+    assert_eq!(debugger.step_back(), Stop::HistoryStart);
+}
+
+#[test]
+fn runs_back_to_writes_and_breakpoints() {
+    let mut debugger = debugger();
+    let counter = debugger.resolve("counter").unwrap();
+    debugger.resume();
+
+    // `I` was last set by `LD [I], V3`, which moves it on past what it stored.
+    assert_eq!(debugger.reverse_to_write(Storage::I), Stop::Stepped);
+    assert_eq!(debugger.line_at(debugger.machine.pc), Some((0, 6)));
+    assert_eq!(debugger.machine.memory[usize::from(counter)], 0);
+    assert_eq!(
+        debugger.reverse_to_write(Storage::Register(VF)),
+        Stop::Stepped
+    );
+    assert_eq!(debugger.line_at(debugger.machine.pc), Some((0, 12)));
+    assert_eq!(debugger.machine.stack.len(), 1);
+
+    debugger.resume();
+    debugger.toggle_watchpoint(counter);
+    assert_eq!(
+        debugger.reverse_resume(),
+        Stop::Watchpoint {
+            address: counter,
+            old: 0,
+            new: 1
+        }
+    );
+    debugger.set_breakpoint(debugger.resolve("draw").unwrap());
+    assert_eq!(
+        debugger.reverse_resume(),
+        Stop::Breakpoint(debugger.resolve("draw").unwrap())
+    );
+    assert_eq!(
+        debugger.reverse_to_write(Storage::Memory(counter)),
+        Stop::HistoryStart
+    );
+}
+
+#[test]
+fn rewinds_to_frames_within_the_history() {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", "loop:\n    ADD V0, 1\n    JP loop\n");
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    let mut debugger = Debugger::new(assembly, opts.target, &*opts.files).unwrap();
+    debugger.history_limit = 100;
+    for _ in 0..10 {
+        debugger.run_frame();
+    }
+    assert_eq!(debugger.frame(), 10);
+
+    assert_eq!(debugger.rewind_to_frame(6), Stop::Stepped);
+    assert_eq!(debugger.frame(), 6);
+    assert_eq!(debugger.machine.cycles, 90);
+    assert_eq!(debugger.machine.v[0], 45);
+    // Only the last 100 instructions were kept.
+    assert_eq!(debugger.rewind_to_frame(2), Stop::HistoryStart);
+    assert_eq!(debugger.machine.cycles, 50);
+}
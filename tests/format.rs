@@ -0,0 +1,90 @@
+//! The formatter, checked by its output and by formatting what it wrote again.
+
+use chip8_assembler::{format_source, FormatStyle, MnemonicCase};
+
+const MESSY: &str = "\
+; Draws a dot.
+  start:   ld   v0,5 ; x
+LD V1 , 6
+    .if v0==5
+CLS
+.else
+ .loop
+      add v0,1
+   .again
+      .endif
+TEST \"dot drawn\"
+expect V0 == 5
+ENDTEST
+a_very_long_label_indeed: jp start ; back to the start
+    ; on its own
+JP ??
+DB \"unterminated
+
+
+";
+
+const TIDY: &str = "\
+; Draws a dot.
+start:  LD V0, 5                ; x
+        LD V1, 6
+        .IF V0 == 5
+                CLS
+        .ELSE
+                .LOOP
+                        ADD V0, 1
+                .AGAIN
+        .ENDIF
+        TEST \"dot drawn\"
+                EXPECT V0 == 5
+        ENDTEST
+a_very_long_label_indeed: JP start ; back to the start
+        ; on its own
+JP ??
+DB \"unterminated
+";
+
+/// Formats `text`, checking that formatting the result again changes nothing.
+fn format(text: &str, style: &FormatStyle) -> String {
+    let formatted = format_source(text, style);
+    assert_eq!(format_source(&formatted, style), formatted);
+    formatted
+}
+
+#[test]
+fn formats_to_the_default_style() {
+    let style = FormatStyle {
+        indent: 8,
+        ..FormatStyle::default()
+    };
+    assert_eq!(format(MESSY, &style), TIDY);
+}
+
+#[test]
+fn formatting_is_idempotent_in_every_style() {
+    let styles = [
+        FormatStyle::default(),
+        FormatStyle {
+            case: MnemonicCase::Lower,
+            indent: 2,
+            separator: String::from(","),
+            comment_column: 0,
+        },
+        FormatStyle {
+            indent: 0,
+            separator: String::from(" , "),
+            comment_column: 80,
+            ..FormatStyle::default()
+        },
+    ];
+    for style in &styles {
+        format(MESSY, style);
+        format(TIDY, style);
+    }
+}
+
+#[test]
+fn empty_files_stay_empty() {
+    assert_eq!(format("", &FormatStyle::default()), "");
+    assert_eq!(format("\n\n  \n", &FormatStyle::default()), "");
+}
@@ -0,0 +1,133 @@
+//! A scripted GDB remote protocol session against a program held in memory.
+
+use chip8_assembler::debugger::Debugger;
+use chip8_assembler::gdb::{serve, symbol_file};
+use chip8_assembler::{assemble_str, Options};
+use std::io::{self, Cursor, Read, Write};
+
+const PROGRAM: &str = "
+start:
+    LD V0, 1
+    CALL draw
+done:
+    JP done
+draw:
+    LD I, box
+    RET
+box: DB 0x90, 0x60
+";
+
+/// Reads the packets it was given, and keeps whatever's written back.
+struct Session {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Session {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn packet(body: &str) -> String {
+    let sum = body.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", body, sum)
+}
+
+/// Sends each packet in turn, returning the body of each reply.
+fn session(packets: &[&str]) -> Vec<String> {
+    let assembly = assemble_str(PROGRAM, &Options::default()).unwrap();
+    let opts = Options::default();
+    let mut debugger = Debugger::new(assembly, opts.target, &*opts.files).unwrap();
+    let mut session = Session {
+        input: Cursor::new(
+            packets
+                .iter()
+                .map(|p| packet(p))
+                .collect::<String>()
+                .into_bytes(),
+        ),
+        output: Vec::new(),
+    };
+    serve(&mut debugger, &mut session).unwrap();
+
+    let output = String::from_utf8(session.output).unwrap();
+    output
+        .split('$')
+        .skip(1)
+        .map(|reply| {
+            // Each reply is followed by the acknowledgement of the next packet.
+            let reply = reply.trim_end_matches('+');
+            let (body, sum) = reply.split_at(reply.rfind('#').unwrap());
+            assert_eq!(&packet(body)[body.len() + 1..], sum, "bad checksum");
+            String::from(body)
+        })
+        .collect()
+}
+
+#[test]
+fn stops_at_breakpoints_and_reads_state() {
+    let replies = session(&[
+        "?", "Z0,206,2", "c", "g", "p11", "m20a,2", "P0=2a", "p0", "s", "p12", "D",
+    ]);
+    assert_eq!(
+        replies,
+        [
+            "S05",
+            "OK",
+            "T05swbreak:;",
+            // V0 to VF, then I, PC, SP, DT and ST.
+            "0100000000000000000000000000000000000602010000",
+            "0602",
+            "9060",
+            "OK",
+            "2a",
+            "S05",
+            "01",
+            "OK",
+        ]
+    );
+}
+
+#[test]
+fn describes_the_target_and_watches_memory() {
+    let replies = session(&[
+        "qSupported:swbreak+",
+        "qXfer:features:read:target.xml:0,2000",
+        // Writes from the debugger don't trigger watchpoints.
+        "Z2,210,1",
+        "M210,1:00",
+        "c",
+        "vMustReplyEmpty",
+        "k",
+    ]);
+    assert!(replies[0].contains("qXfer:features:read+"));
+    assert!(replies[1].starts_with('l'));
+    assert!(replies[1].contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert_eq!(replies[2..], ["OK", "OK", "S05", "", "OK"]);
+}
+
+#[test]
+fn symbol_file_lists_labels() {
+    let assembly = assemble_str(PROGRAM, &Options::default()).unwrap();
+    let elf = symbol_file(&assembly);
+    assert_eq!(&elf[..6], b"\x7fELF\x01\x01");
+    // Each name is in the string table, and the program is in `.text`.
+    for name in assembly.symbols.keys() {
+        let name = format!("\0{}\0", name);
+        assert!(elf.windows(name.len()).any(|w| w == name.as_bytes()));
+    }
+    assert!(elf
+        .windows(assembly.bytes.len())
+        .any(|w| w == &assembly.bytes[..]));
+}
@@ -0,0 +1,86 @@
+//! Drives a small program through the harness, as a game's own tests would.
+
+use chip8_assembler::harness::Harness;
+use chip8_assembler::V0;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+const PROGRAM: &str = "
+start:
+    LD V0, 0
+    LD V1, 0
+    LD I, box
+    DRW V0, V1, 2
+    LD V2, K
+    ADD V0, V2
+    LD DT, V2
+done:
+    JP done
+box: DB 0x90, 0x60
+";
+
+#[test]
+fn runs_to_labels_and_checks_the_display() {
+    let mut harness = Harness::assemble(PROGRAM);
+    harness.press(5);
+    harness.run_to("done");
+    harness.assert_register(V0, 5);
+    harness.assert_i(harness.symbol("box"));
+    harness.assert_memory("box", &[0x90, 0x60]);
+    harness.assert_display(
+        "
+        #..#
+        .##.
+        ",
+    );
+    assert_eq!(harness.display_text(), "#..#\n.##.\n");
+
+    assert_eq!(harness.delay_timer, 5);
+    harness.run_frames(2);
+    assert_eq!(harness.delay_timer, 3);
+}
+
+#[test]
+fn mismatches_are_described() {
+    let mut harness = Harness::assemble(PROGRAM);
+    harness.press(1);
+    harness.run_until(|machine| machine.v[0] == 1);
+
+    let message = |check: &dyn Fn(&Harness)| {
+        let err = catch_unwind(AssertUnwindSafe(|| check(&harness))).unwrap_err();
+        err.downcast_ref::<String>().unwrap().clone()
+    };
+    assert_eq!(
+        message(&|h| h.assert_register(V0, 2)),
+        "expected V0 to be 2 (0x02), but it's 1 (0x01) at start+12"
+    );
+    assert_eq!(
+        message(&|h| h.assert_memory("box", &[0x90, 0x61])),
+        "memory at `box` doesn't match at start+12:\n  box+1: expected 61, found 60"
+    );
+    assert_eq!(
+        message(&|h| h.assert_display("#..#\n.#..")),
+        "display doesn't match at start+12 (- expected, + actual):\n  \
+         row  1 - .#..\n         + .##.\n             ^\n\nactual display:\n#..#\n.##.\n"
+    );
+    assert_eq!(
+        message(&|h| {
+            h.memory_at("box", 0x1000);
+        }),
+        "4096 bytes at `box` (0x210) run past the end of memory"
+    );
+}
+
+#[test]
+#[should_panic(expected = "there's no key 10; keys are 0 to F")]
+fn keys_go_up_to_f() {
+    let mut harness = Harness::assemble(PROGRAM);
+    harness.press(0x10);
+}
+
+#[test]
+#[should_panic(expected = "halted at done")]
+fn stops_when_the_program_halts() {
+    let mut harness = Harness::assemble(PROGRAM);
+    harness.press(0);
+    harness.run_until(|machine| machine.v[0] == 9);
+}
@@ -0,0 +1,142 @@
+//! Objects assembled on their own, written out, read back and linked together.
+
+use chip8_assembler::{
+    compile_source, link, read_object, Layout, MemoryFiles, ObjectFile, Options,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAIN: &str = "\
+IMPORT draw
+EXPORT start
+DEFINE SPEED 3
+start:
+    LD V0, SPEED
+    CALL draw
+    JP start
+SECTION vars
+score: DS 2
+";
+
+const DRAW: &str = "\
+EXPORT draw
+draw:
+    LD I, dot
+    DRW V0, V0, 1
+    RET
+dot: DB 0x80
+";
+
+fn compile(path: &str, source: &str) -> ObjectFile {
+    compile_source(Path::new(path), source, &Options::default()).unwrap()
+}
+
+#[test]
+fn writes_relocations_for_imports_and_local_addresses() {
+    assert_eq!(
+        compile("main.asm", MAIN).to_string(),
+        "\
+CHIP8OBJ 1
+FILE 0 main.asm
+SECTION 0 code
+BYTES 0 600320001000
+SECTION 1 vars
+RESERVE 1 2
+CONST SPEED local 3
+SYMBOL score local 1 0
+SYMBOL start export 0 0
+IMPORT draw
+RELOC 0 2 addr12 symbol draw
+RELOC 0 4 addr12 section 0 0
+LINE 0 0 2 0 5
+LINE 0 2 2 0 6
+LINE 0 4 2 0 7
+LINE 1 0 2 0 9 data
+"
+    );
+}
+
+#[test]
+fn objects_read_back_the_same() {
+    let object = compile("main.asm", MAIN);
+    let mut files = MemoryFiles::new();
+    files.insert("main.c8o", object.to_string());
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let read = read_object(Path::new("main.c8o"), &opts).unwrap();
+    assert_eq!(read.to_string(), object.to_string());
+
+    let mut files = MemoryFiles::new();
+    files.insert("bad.c8o", "CHIP8OBJ 2\n");
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let err = read_object(Path::new("bad.c8o"), &opts).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("bad.c8o: error: invalid object file: "));
+}
+
+#[test]
+fn links_objects_in_order() {
+    let objects = [compile("main.asm", MAIN), compile("draw.asm", DRAW)];
+    let assembly = link(&objects, &Layout::default()).unwrap();
+    assert_eq!(
+        assembly.bytes,
+        [0x60, 0x03, 0x22, 0x06, 0x12, 0x00, 0xA2, 0x0C, 0xD0, 0x01, 0x00, 0xEE, 0x80]
+    );
+    assert_eq!(assembly.symbols["draw"], 0x206);
+    assert_eq!(assembly.symbols["dot"], 0x20C);
+    assert_eq!(assembly.symbols["score"], 0x20E);
+    let mapping = assembly.mapping_at(0x208).unwrap();
+    assert_eq!(assembly.files[mapping.file], Path::new("draw.asm"));
+    assert_eq!(mapping.line, 4);
+}
+
+#[test]
+fn link_errors() {
+    let objects = [
+        compile("bad.asm", "IMPORT nothing\n    CALL nothing"),
+        compile("draw.asm", DRAW),
+    ];
+    let err = link(&objects, &Layout::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "bad.asm:2: error: undefined symbol `nothing`"
+    );
+
+    let objects = [
+        compile("dup.asm", "EXPORT draw\ndraw: RET"),
+        compile("draw.asm", DRAW),
+    ];
+    let err = link(&objects, &Layout::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "draw.asm: error: symbol `draw` is already defined"
+    );
+}
+
+#[test]
+fn line_kinds_read_back_the_same() {
+    let opts = Options {
+        tests: true,
+        ..Options::default()
+    };
+    let source = "TEST \"adds  two\"\n    ADD V0, 2\n    EXPECT V0 == 2\nENDTEST\nNOT V1\nDB 1";
+    let object = compile_source(Path::new("t.asm"), source, &opts).unwrap();
+    let text = object.to_string();
+    assert!(text.ends_with(
+        "\
+LINE 0 0 2 0 1 test adds  two
+LINE 0 2 2 0 2
+LINE 0 4 2 0 3 expect V0 == 2
+LINE 0 6 2 0 4 endtest
+LINE 0 8 4 0 5 expanded
+LINE 0 12 1 0 6 data
+"
+    ));
+    assert_eq!(ObjectFile::parse(&text).unwrap().lines, object.lines);
+}
@@ -0,0 +1,91 @@
+//! Lints, checked by the exact warnings they give.
+
+use chip8_assembler::{assemble_str, lint, FsFiles, Options, Target};
+
+fn lint_for(target: Target, source: &str) -> Vec<String> {
+    let opts = Options {
+        target,
+        ..Options::default()
+    };
+    let assembly = match assemble_str(source, &opts) {
+        Ok(assembly) => assembly,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    };
+    lint(&assembly, &FsFiles, target)
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+fn lints(source: &str) -> Vec<String> {
+    lint_for(Target::Chip8, source)
+}
+
+#[test]
+fn skips() {
+    assert_eq!(
+        lints("SE V0, 1\nDB 1, 2\n"),
+        ["<input>:1: warning: `SE V0, 0x01` skips over data"]
+    );
+    assert_eq!(
+        lints("CLS\nSNE V0, 1"),
+        ["<input>:2: warning: `SNE V0, 0x01` skips past the end of the code"]
+    );
+    assert_eq!(
+        lints("SE V0, 1\nNOT V1\nhalt: JP halt"),
+        ["<input>:1: warning: `SE V0, 0x01` only skips the first of the 2 instructions on line 2"]
+    );
+    assert_eq!(
+        lints("SE V0, 1\nagain: JP again\nJP again"),
+        ["<input>:1: warning: `SE V0, 0x01` skips `again`, which is also reached by name"]
+    );
+    // A constant isn't a label, even with the same value as an address.
+    assert!(lints("DEFINE LIMIT 202\nSE V0, 1\nCLS\nhalt: JP halt").is_empty());
+}
+
+#[test]
+fn unreachable_code() {
+    assert_eq!(
+        lints("start: JP start\nCLS\nRET"),
+        ["<input>:2: warning: unreachable code after `JP 0x200`"]
+    );
+    assert_eq!(
+        lints("DEFINE LIMIT 202\nstart: JP start\nCLS\nRET"),
+        ["<input>:3: warning: unreachable code after `JP 0x200`"]
+    );
+    // Code after a skipped jump, or which is jumped to, is reachable.
+    assert!(lints("SE V0, 1\nJP end\nCLS\nend: JP end").is_empty());
+}
+
+#[test]
+fn flags_overwriting_vf() {
+    assert_eq!(
+        lints("LD VF, 1\nADD V0, V1\nLD V2, VF\nhalt: JP halt"),
+        ["<input>:3: warning: reads VF set at 200, but `ADD V0, V1` at 202 overwrote it with a flag"]
+    );
+    // Reading the flag itself is what it's for.
+    assert!(lints("ADD V0, V1\nLD V2, VF\nhalt: JP halt").is_empty());
+}
+
+#[test]
+fn instructions() {
+    assert_eq!(
+        lints("DRW V0, V1, 0\nhalt: JP halt"),
+        ["<input>:1: warning: `DRW V0, V1, 0x0` draws nothing on chip8"]
+    );
+    assert!(lint_for(Target::SChip, "DRW V0, V1, 0\nhalt: JP halt").is_empty());
+    assert_eq!(
+        lints("start: LD I, start\nhalt: JP halt"),
+        ["<input>:1: warning: `LD I, 0x200` points at code rather than data"]
+    );
+    assert_eq!(
+        lints("SYS 0x300\nhalt: JP halt"),
+        ["<input>:1: warning: `SYS 0x300` is ignored by modern interpreters"]
+    );
+}
+
+#[test]
+fn data_is_told_from_code_without_the_source() {
+    // Read as code, the sprite would be a jump nothing reaches, and `LD I` would point at it.
+    assert!(lints("LD I, sprite\nhalt: JP halt\nsprite: DB 0x12, 0x00").is_empty());
+}
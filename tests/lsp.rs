@@ -0,0 +1,151 @@
+//! Drives the language server through a scripted session.
+
+use chip8_assembler::lsp::serve;
+use std::io::Cursor;
+
+const URI: &str = "file:///project/main.asm";
+
+const SOURCE: &str = "\
+DEFINE speed 3
+start:
+    LD V0, speed
+    CALL draw
+    JP start
+draw:
+    RET
+";
+
+/// Frames each message the way a client would.
+fn session(messages: &[String]) -> Vec<String> {
+    let mut input = String::new();
+    for message in messages {
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        ));
+    }
+    let mut output = Vec::new();
+    serve(Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut replies = Vec::new();
+    let mut rest = output.as_str();
+    while let Some(idx) = rest.find("\r\n\r\n") {
+        let length: usize = rest["Content-Length: ".len()..idx].parse().unwrap();
+        let body = &rest[idx + 4..idx + 4 + length];
+        replies.push(String::from(body));
+        rest = &rest[idx + 4 + length..];
+    }
+    replies
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    )
+}
+
+fn at(line: usize, character: usize) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    )
+}
+
+fn open(text: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"chip8","version":1,"text":"{}"}}}}}}"#,
+        URI,
+        text.replace('\n', "\\n")
+    )
+}
+
+fn reply(replies: &[String], id: usize) -> &str {
+    let prefix = format!(r#""id":{},"#, id);
+    replies
+        .iter()
+        .find(|r| r.contains(&prefix))
+        .unwrap_or_else(|| panic!("no reply to {} in {:?}", id, replies))
+}
+
+#[test]
+fn answers_requests_about_symbols() {
+    let replies = session(&[
+        request(1, "initialize", "{}"),
+        open(SOURCE),
+        request(2, "textDocument/definition", &at(3, 10)),
+        request(3, "textDocument/references", &at(5, 1)),
+        request(4, "textDocument/hover", &at(2, 5)),
+        request(5, "textDocument/completion", &at(2, 0)),
+        request(
+            6,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+        request(
+            7,
+            "textDocument/rename",
+            &format!(
+                r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":2,"character":12}},"newName":"pace"}}"#,
+                URI
+            ),
+        ),
+        request(8, "shutdown", "null"),
+        String::from(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+    ]);
+
+    assert!(reply(&replies, 1).contains(r#""hoverProvider":true"#));
+    assert!(replies
+        .iter()
+        .any(|r| r.contains("publishDiagnostics") && r.contains(r#""diagnostics":[]"#)));
+
+    // `draw` on line 4 is defined on line 6.
+    let definition = reply(&replies, 2);
+    assert!(definition.contains(r#""start":{"character":0,"line":5}"#));
+
+    let references = reply(&replies, 3);
+    assert_eq!(references.matches(r#""uri""#).count(), 2);
+
+    let hover = reply(&replies, 4);
+    assert!(hover.contains("6xkk"));
+    assert!(hover.contains("0200  6003  LD V0, 0x03"));
+
+    let completion = reply(&replies, 5);
+    assert!(completion.contains(r#""label":"DRW""#));
+    assert!(completion.contains(r#""label":"speed""#));
+
+    let symbols = reply(&replies, 6);
+    assert!(symbols.contains(r#""name":"start""#));
+    assert!(symbols.contains(r#""name":"draw""#));
+
+    let rename = reply(&replies, 7);
+    assert_eq!(rename.matches(r#""newText":"pace""#).count(), 2);
+}
+
+#[test]
+fn publishes_errors() {
+    let replies = session(&[request(1, "initialize", "{}"), open("    LD V0, nowhere\n")]);
+
+    let published = replies
+        .iter()
+        .find(|r| r.contains("publishDiagnostics"))
+        .unwrap();
+    assert!(published.contains(r#""severity":1"#));
+    assert!(published.contains("nowhere"));
+    assert!(published.contains(r#""start":{"character":4,"line":0}"#));
+}
+
+#[test]
+fn hovers_over_directives() {
+    let replies = session(&[
+        request(1, "initialize", "{}"),
+        open("sprite:\n    DB 0x80, 0x40\n"),
+        request(2, "textDocument/hover", &at(1, 5)),
+    ]);
+    let hover = reply(&replies, 2);
+    assert!(hover.contains("DB byte, ..."), "{}", hover);
+    assert!(hover.contains("Bytes of data."), "{}", hover);
+    assert!(!hover.contains("string"), "{}", hover);
+}
@@ -0,0 +1,44 @@
+//! The interpreter, run an instruction at a time on hand-assembled programs.
+
+use chip8_assembler::machine::{Machine, MachineErr};
+use chip8_assembler::Target;
+
+fn machine(program: &[u8]) -> Machine {
+    Machine::with_program(Target::default(), program).unwrap()
+}
+
+#[test]
+fn memory_past_i_is_out_of_bounds_even_when_i_wraps() {
+    // LD [I], V3; LD V3, [I]; LD B, V0; DRW V0, V0, 4
+    for opcode in [[0xF3, 0x55], [0xF3, 0x65], [0xF0, 0x33], [0xD0, 0x04]] {
+        let mut machine = machine(&opcode);
+        machine.i = 0xFFFE;
+        assert_eq!(
+            machine.step(),
+            Err(MachineErr::MemoryOutOfBounds {
+                pc: 0x200,
+                addr: 0xFFFE
+            })
+        );
+    }
+
+    let mut machine = machine(&[0xF3, 0x55]);
+    machine.i = 0x0FFE;
+    assert_eq!(
+        machine.step(),
+        Err(MachineErr::MemoryOutOfBounds {
+            pc: 0x200,
+            addr: 0x1000
+        })
+    );
+}
+
+#[test]
+fn add_i_wraps() {
+    // ADD I, V0
+    let mut machine = machine(&[0xF0, 0x1E]);
+    machine.i = 0xFFFF;
+    machine.v[0] = 2;
+    machine.step().unwrap();
+    assert_eq!(machine.i, 1);
+}
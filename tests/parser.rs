@@ -0,0 +1,183 @@
+//! Instructions and operands, checked against the bytes they assemble to.
+
+use chip8_assembler::{assemble_str, disassemble, Options};
+
+fn assemble(source: &str) -> Vec<u8> {
+    match assemble_str(source, &Options::default()) {
+        Ok(assembly) => assembly.bytes,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    }
+}
+
+fn error(source: &str) -> String {
+    assemble_str(source, &Options::default())
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn register_names_are_hex_digits_outside_their_operands() {
+    assert_eq!(assemble("LD V0, F"), [0x60, 0x0F]);
+    assert_eq!(assemble("DRW V0, V1, F"), [0xD0, 0x1F]);
+    assert_eq!(assemble("SE V2, B"), [0x32, 0x0B]);
+    assert_eq!(assemble("ADD V3, B"), [0x73, 0x0B]);
+    assert_eq!(assemble("RND V4, F"), [0xC4, 0x0F]);
+}
+
+#[test]
+fn register_names_can_be_labels() {
+    assert_eq!(assemble("JP f\nf:\n    CALL f"), [0x12, 0x02, 0x22, 0x02]);
+    assert_eq!(assemble("CLS\nb: CALL b"), [0x00, 0xE0, 0x22, 0x02]);
+    assert_eq!(
+        assemble("i: JP i\nk: JP k\ndt: JP dt\nst: JP st"),
+        [0x12, 0x00, 0x12, 0x02, 0x12, 0x04, 0x12, 0x06]
+    );
+    assert_eq!(assemble("LD I, b\nb: DB 0x80"), [0xA2, 0x02, 0x80]);
+}
+
+#[test]
+fn register_names_are_keywords_where_they_are_taken() {
+    assert_eq!(assemble("LD F, V1"), [0xF1, 0x29]);
+    assert_eq!(assemble("LD B, V2"), [0xF2, 0x33]);
+    assert_eq!(assemble("LD V3, DT"), [0xF3, 0x07]);
+    assert_eq!(assemble("LD V4, K"), [0xF4, 0x0A]);
+    assert_eq!(assemble("LD DT, V5"), [0xF5, 0x15]);
+    assert_eq!(assemble("LD ST, V6"), [0xF6, 0x18]);
+    assert_eq!(assemble("LD I, 0x123"), [0xA1, 0x23]);
+    assert_eq!(assemble("ADD I, V7"), [0xF7, 0x1E]);
+    assert_eq!(assemble("ld f, v1\nld v2, k"), [0xF1, 0x29, 0xF2, 0x0A]);
+}
+
+#[test]
+fn keywords_out_of_place_are_undefined_symbols() {
+    assert_eq!(error("JP K"), "<input>:1: error: undefined symbol `K`");
+}
+
+#[test]
+fn one_operand_shifts_leave_vy_as_v0() {
+    assert_eq!(assemble("SHR V3"), [0x83, 0x06]);
+    assert_eq!(assemble("SHL V3"), [0x83, 0x0E]);
+    assert_eq!(assemble("SHR V3, V4"), [0x83, 0x46]);
+    assert_eq!(assemble("SHL V3, V4"), [0x83, 0x4E]);
+}
+
+#[test]
+fn every_instruction() {
+    let source = "\
+    CLS
+    RET
+    SYS 0x123
+    JP 0x234
+    CALL 0x345
+    SE V1, 0x12
+    SNE V2, 0x23
+    SE V3, V4
+    LD V5, 0x34
+    ADD V6, 0x45
+    LD V7, V8
+    OR V9, VA
+    AND VB, VC
+    XOR VD, VE
+    ADD VF, V0
+    SUB V1, V2
+    SHR V3, V4
+    SUBN V5, V6
+    SHL V7, V8
+    SNE V9, VA
+    LD I, 0x456
+    JP V0, 0x567
+    RND VB, 0x56
+    DRW VC, VD, 0xE
+    SKP VE
+    SKNP VF
+    LD V0, DT
+    LD V1, K
+    LD DT, V2
+    LD ST, V3
+    ADD I, V4
+    LD F, V5
+    LD B, V6
+    LD [I], V7
+    LD V8, [I]
+";
+    assert_eq!(
+        assemble(source),
+        [
+            0x00, 0xE0, 0x00, 0xEE, 0x01, 0x23, 0x12, 0x34, 0x23, 0x45, 0x31, 0x12, 0x42, 0x23,
+            0x53, 0x40, 0x65, 0x34, 0x76, 0x45, 0x87, 0x80, 0x89, 0xA1, 0x8B, 0xC2, 0x8D, 0xE3,
+            0x8F, 0x04, 0x81, 0x25, 0x83, 0x46, 0x85, 0x67, 0x87, 0x8E, 0x99, 0xA0, 0xA4, 0x56,
+            0xB5, 0x67, 0xCB, 0x56, 0xDC, 0xDE, 0xEE, 0x9E, 0xEF, 0xA1, 0xF0, 0x07, 0xF1, 0x0A,
+            0xF2, 0x15, 0xF3, 0x18, 0xF4, 0x1E, 0xF5, 0x29, 0xF6, 0x33, 0xF7, 0x55, 0xF8, 0x65,
+        ]
+    );
+}
+
+#[test]
+fn numbers_are_hex_unless_prefixed() {
+    assert_eq!(
+        assemble("LD V0, 10\nLD V1, #10\nLD V2, 0x10\nLD V3, 0b10"),
+        [0x60, 0x10, 0x61, 0x0A, 0x62, 0x10, 0x63, 0x02]
+    );
+}
+
+#[test]
+fn labels_and_defines() {
+    assert_eq!(
+        assemble("DEFINE speed 3\nstart: LD V0, speed\nloop:\n    JP loop\n    JP start"),
+        [0x60, 0x03, 0x12, 0x02, 0x12, 0x00]
+    );
+    assert_eq!(assemble("; a comment\n\n    CLS ; another\n"), [0x00, 0xE0]);
+}
+
+#[test]
+fn defines_from_options() {
+    let mut opts = Options::default();
+    opts.defines.insert(String::from("SPEED"), 0x12);
+    let assembly = assemble_str("LD V0, SPEED", &opts).unwrap();
+    assert_eq!(assembly.bytes, [0x60, 0x12]);
+    assert_eq!(assembly.symbols["SPEED"], 0x12);
+}
+
+#[test]
+fn diagnostics() {
+    assert_eq!(
+        error("CLS V0"),
+        "<input>:1: error: `CLS` takes 0 operand(s) but 1 were given"
+    );
+    assert_eq!(
+        error("CLS\nJP"),
+        "<input>:2: error: `JP` takes 1 operand(s) but 0 were given"
+    );
+    assert_eq!(
+        error("LD V0, 0x100"),
+        "<input>:1: error: value 0x100 is out of range (max 0xFF)"
+    );
+    assert_eq!(
+        error("DRW V0, V1, 10"),
+        "<input>:1: error: value 0x10 is out of range (max 0xF)"
+    );
+    assert_eq!(
+        error("FOO V1"),
+        "<input>:1: error: invalid instruction `FOO V1`"
+    );
+    assert_eq!(
+        error("SE I, 1"),
+        "<input>:1: error: unrecognised operands in `SE I, 1`"
+    );
+}
+
+#[test]
+fn shifts_disassemble_to_what_they_were_assembled_from() {
+    let bytes = assemble("SHR V3\nSHL V3\nSHR V3, V3\nSHL V3, V4");
+    let text = disassemble(&bytes);
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    assert_eq!(
+        lines,
+        [
+            "SHR V3               ; 200: 8306",
+            "SHL V3               ; 202: 830E",
+            "SHR V3, V3           ; 204: 8336",
+            "SHL V3, V4           ; 206: 834E",
+        ]
+    );
+}
@@ -0,0 +1,126 @@
+//! `PROC` subroutines: how deep their calls nest, and whether they return.
+
+use chip8_assembler::{assemble_str, Diagnostics, Options, Target};
+
+/// Subroutines `p0` to `p{n-1}`, each calling the next.
+fn chain(n: usize) -> String {
+    let mut source = String::new();
+    for i in 0..n {
+        source.push_str(&format!("PROC p{}\n", i));
+        if i + 1 < n {
+            source.push_str(&format!("    CALL p{}\n", i + 1));
+        }
+        source.push_str("    RET\nENDP\n");
+    }
+    source
+}
+
+fn assemble(target: Target, source: &str) -> Result<Vec<String>, Diagnostics> {
+    let opts = Options {
+        target,
+        ..Options::default()
+    };
+    assemble_str(source, &opts)
+        .map(|assembly| assembly.warnings.iter().map(|w| w.to_string()).collect())
+}
+
+fn errors(source: &str) -> String {
+    assemble(Target::Chip8, source).unwrap_err().to_string()
+}
+
+#[test]
+fn calls_may_nest_as_deep_as_the_stack() {
+    let source = format!("    CALL p0\nhalt: JP halt\n{}", chain(12));
+    assert_eq!(
+        assemble(Target::Chip8, &source).unwrap(),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn calls_nesting_deeper_than_the_stack_are_errors() {
+    let source = format!("    CALL p0\nhalt: JP halt\n{}", chain(13));
+    assert_eq!(
+        errors(&source),
+        "<input>:1: error: calls nest 13 deep but the stack only holds 12: \
+         p0 -> p1 -> p2 -> p3 -> p4 -> p5 -> p6 -> p7 -> p8 -> p9 -> p10 -> p11 -> p12"
+    );
+    // SUPER-CHIP's stack holds 16.
+    assert!(assemble(Target::SChip, &source).is_ok());
+
+    // A subroutine nothing calls is an entry point of its own, so doesn't take up the stack.
+    assert!(assemble(Target::Chip8, &chain(13)).is_ok());
+    assert_eq!(
+        errors(&chain(14)),
+        "<input>:1: error: calls nest 13 deep but the stack only holds 12: \
+         p0 -> p1 -> p2 -> p3 -> p4 -> p5 -> p6 -> p7 -> p8 -> p9 -> p10 -> p11 -> p12 -> p13"
+    );
+}
+
+#[test]
+fn recursion_is_an_error() {
+    assert_eq!(
+        errors("PROC a\n    CALL b\n    RET\nENDP\nPROC b\n    CALL a\n    RET\nENDP"),
+        "<input>:6: error: recursive call: a -> b -> a"
+    );
+}
+
+#[test]
+fn warns_about_subroutines_which_run_past_their_end() {
+    let source = "\
+PROC skips
+    SE V0, 1
+    RET
+ENDP
+PROC loads
+    LD V0, 1
+ENDP
+PROC jumps
+    JP jumps
+ENDP
+PROC halts
+    HALT
+ENDP
+PROC labelled
+    RET
+after:
+ENDP
+PROC branches
+    .if V0 == 1
+        RET
+    .else
+        RET
+    .endif
+ENDP
+PROC breaks
+    .loop
+        .if V0 == 1
+            .break
+        .endif
+    .again
+ENDP
+";
+    assert_eq!(
+        assemble(Target::Chip8, source).unwrap(),
+        [
+            "<input>:4: warning: `PROC skips` can run past `ENDP` without returning",
+            "<input>:7: warning: `PROC loads` can run past `ENDP` without returning",
+            "<input>:17: warning: `PROC labelled` can run past `ENDP` without returning",
+            "<input>:31: warning: `PROC breaks` can run past `ENDP` without returning",
+        ]
+    );
+}
+
+#[test]
+fn unbalanced_procs() {
+    assert_eq!(
+        errors("PROC a\nPROC b\n    RET\nENDP\nENDP\nPROC c\n    RET"),
+        "<input>:2: error: `PROC b` is inside `PROC a`, which hasn't ended\n\
+         <input>:5: error: `ENDP` without `PROC`\n\
+         <input>:6: error: `PROC c` is never ended with `ENDP`"
+    );
+    assert_eq!(
+        errors("PROC a\nSECTION data\n    RET\nENDP"),
+        "<input>:4: error: `PROC a` ends in a different section than it starts"
+    );
+}
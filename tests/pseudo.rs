@@ -0,0 +1,105 @@
+//! Pseudo-instructions, checked against the instructions they expand to.
+
+use chip8_assembler::{assemble_path, assemble_str, listing, MemoryFiles, Options, Target};
+use std::path::Path;
+use std::sync::Arc;
+
+fn assemble_for(target: Target, source: &str) -> Vec<u8> {
+    let opts = Options {
+        target,
+        ..Options::default()
+    };
+    match assemble_str(source, &opts) {
+        Ok(assembly) => assembly.bytes,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    }
+}
+
+fn assemble(source: &str) -> Vec<u8> {
+    assemble_for(Target::default(), source)
+}
+
+fn error(source: &str) -> String {
+    assemble_str(source, &Options::default())
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn single_instructions() {
+    assert_eq!(assemble("NOP"), [0x80, 0x00]);
+    assert_eq!(assemble("SUB V1, 3"), [0x71, 0xFD]);
+    assert_eq!(assemble("SUB V1, 0"), [0x71, 0x00]);
+    assert_eq!(assemble("INC V2"), [0x72, 0x01]);
+    assert_eq!(assemble("DEC V3"), [0x73, 0xFF]);
+    assert_eq!(assemble("CLR V9"), [0x69, 0x00]);
+    assert_eq!(assemble("CLS\nHALT"), [0x00, 0xE0, 0x12, 0x02]);
+}
+
+#[test]
+fn not_uses_vf_as_scratch() {
+    assert_eq!(assemble("NOT V4"), [0x6F, 0xFF, 0x84, 0xF3]);
+    assert_eq!(error("NOT VF"), "<input>:1: error: invalid operand `VF`");
+}
+
+#[test]
+fn mov_is_ld() {
+    let movs = "MOV V5, V6\nMOV V5, 7\nMOV I, 0x300\nMOV V1, DT\nMOV DT, V1\nMOV F, V2";
+    let lds = "LD V5, V6\nLD V5, 7\nLD I, 0x300\nLD V1, DT\nLD DT, V1\nLD F, V2";
+    assert_eq!(assemble(movs), assemble(lds));
+    assert_eq!(
+        error("MOV V1, V2, V3"),
+        "<input>:1: error: `MOV` takes 2 operand(s) but 3 were given"
+    );
+    assert_eq!(
+        error("MOV I, V1"),
+        "<input>:1: error: unrecognised operands in `MOV I, V1`"
+    );
+}
+
+#[test]
+fn two_register_shifts_copy_vy_first_where_vx_shifts_in_place() {
+    let source = "SHR V7, V8\nSHL V7, V8";
+    assert_eq!(
+        assemble_for(Target::Chip8, source),
+        [0x87, 0x86, 0x87, 0x8E]
+    );
+    for target in [Target::Chip48, Target::SChip] {
+        assert_eq!(
+            assemble_for(target, source),
+            [0x87, 0x80, 0x87, 0x76, 0x87, 0x80, 0x87, 0x7E]
+        );
+    }
+    // Shifting a register into itself is the same everywhere.
+    assert_eq!(assemble_for(Target::SChip, "SHR V7, V7"), [0x87, 0x76]);
+    assert_eq!(assemble_for(Target::SChip, "SHR V7"), [0x87, 0x06]);
+}
+
+#[test]
+fn labels_after_expansions_account_for_their_size() {
+    let bytes = assemble_for(Target::SChip, "NOT V1\nSHR V2, V3\nend: JP end");
+    assert_eq!(&bytes[8..], [0x12, 0x08]);
+}
+
+#[test]
+fn listings_show_expansions() {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", "start:\n    NOT V4\n    HALT\n");
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    assert_eq!(
+        listing(&assembly, &*opts.files),
+        "\
+; main.asm
+                       1  start:
+0200                   2      NOT V4
+0200  6F FF                   => LD VF, 0xFF
+0202  84 F3                   => XOR V4, VF
+0204                   3      HALT
+0204  12 04                   => JP 0x204
+"
+    );
+}
@@ -0,0 +1,123 @@
+//! Sections, and where layouts and `--base` place them.
+
+use chip8_assembler::{assemble_str, Assembly, Layout, Options, PlacedSection};
+
+const PROGRAM: &str = "\
+start:
+    LD I, sprite
+    JP start
+SECTION data
+sprite: DB 0x80
+SECTION vars
+score: DS 2
+SECTION code
+    RET
+";
+
+fn assemble(layout: &str) -> Assembly {
+    let opts = Options {
+        layout: layout.parse().unwrap(),
+        ..Options::default()
+    };
+    match assemble_str(PROGRAM, &opts) {
+        Ok(assembly) => assembly,
+        Err(diagnostics) => panic!("{}", diagnostics),
+    }
+}
+
+fn error(layout: Layout, source: &str) -> String {
+    let opts = Options {
+        layout,
+        ..Options::default()
+    };
+    assemble_str(source, &opts).unwrap_err().to_string()
+}
+
+fn section(name: &str, start: u16, len: u16, initialized: bool) -> PlacedSection {
+    PlacedSection {
+        name: String::from(name),
+        start,
+        len,
+        initialized,
+    }
+}
+
+#[test]
+fn sections_follow_each_other_in_the_order_they_appear() {
+    let assembly = assemble("");
+    assert_eq!(assembly.bytes, [0xA2, 0x06, 0x12, 0x00, 0x00, 0xEE, 0x80]);
+    assert_eq!(
+        assembly.sections,
+        [
+            section("code", 0x200, 6, true),
+            section("data", 0x206, 1, true),
+            section("vars", 0x208, 2, false),
+        ]
+    );
+    assert_eq!(assembly.symbols["score"], 0x208);
+}
+
+#[test]
+fn layouts_place_sections_at_addresses() {
+    let assembly = assemble("data=0x300, vars\ncode # after vars");
+    assert_eq!(
+        assembly.sections,
+        [
+            section("data", 0x300, 1, true),
+            section("vars", 0x302, 2, false),
+            section("code", 0x304, 6, true),
+        ]
+    );
+    assert_eq!(assembly.base, 0x200);
+    assert_eq!(assembly.bytes.len(), 0x10A);
+    assert_eq!(assembly.bytes[0x100], 0x80);
+    assert_eq!(
+        &assembly.bytes[0x104..],
+        [0xA3, 0x00, 0x13, 0x04, 0x00, 0xEE]
+    );
+}
+
+#[test]
+fn the_base_moves_the_whole_program() {
+    let opts = Options {
+        layout: Layout {
+            base: 0x600,
+            ..Layout::default()
+        },
+        ..Options::default()
+    };
+    let assembly = assemble_str(PROGRAM, &opts).unwrap();
+    assert_eq!(assembly.base, 0x600);
+    assert_eq!(assembly.bytes, [0xA6, 0x06, 0x16, 0x00, 0x00, 0xEE, 0x80]);
+    assert_eq!(assembly.symbols["start"], 0x600);
+}
+
+#[test]
+fn layout_errors() {
+    assert_eq!(
+        "code, code".parse::<Layout>().unwrap_err().to_string(),
+        "invalid layout: section `code` is placed twice"
+    );
+    assert_eq!(
+        error("code=0x100".parse().unwrap(), "CLS"),
+        "<layout>: error: invalid layout: section `code` starts at 0x100, before the program at 0x200"
+    );
+    assert_eq!(
+        error(
+            "code, data=0x202".parse().unwrap(),
+            "CLS\nCLS\nSECTION data\nDB 1"
+        ),
+        "<layout>: error: invalid layout: section `data` at 0x202 overlaps section `code`"
+    );
+    assert_eq!(
+        error(
+            "vars=0xFFE, code=0x200".parse().unwrap(),
+            "SECTION vars\nDS 4"
+        ),
+        "<layout>: error: invalid layout: section `vars` ends at 0x1002, past the end of memory"
+    );
+    assert_eq!(
+        error(Layout::default(), "SECTION vars\nscore: DB 0"),
+        "<input>:2: error: `DB` can't be used in uninitialized section `vars`, only `DS`"
+    );
+}
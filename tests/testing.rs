@@ -0,0 +1,157 @@
+//! `TEST` blocks, assembled into the program and run on the interpreter.
+
+use chip8_assembler::{
+    assemble_path, run_tests, run_tests_with_coverage, MemoryFiles, Options, TestResult,
+    DEFAULT_CYCLE_LIMIT,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+const SOURCE: &str = "\
+start:
+    JP start
+double:
+    ADD V0, V0
+    RET
+table:
+    DB 0x01, 0x02, 0x10
+
+TEST \"double\"
+    LD V0, 3
+    CALL double
+    EXPECT V0 == 6
+    LD I, table
+    EXPECT [I+2] == 0x10
+    EXPECT [I] < V0
+ENDTEST
+
+TEST \"fails\"
+    LD V0, 3
+    EXPECT V0 != 3
+    EXPECT DT == 0
+    EXPECT V0 >= 4
+ENDTEST
+
+TEST \"halts\"
+    JP start
+ENDTEST
+
+TEST \"never ends\"
+loop:
+    ADD V1, 1
+    JP loop
+ENDTEST
+";
+
+fn options(source: &str) -> Options {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", source);
+    Options {
+        files: Arc::new(files),
+        ..Options::default()
+    }
+}
+
+/// Each test's name, and the line and message of each failure.
+fn summary(results: &[TestResult]) -> Vec<(String, Vec<(usize, String)>)> {
+    results
+        .iter()
+        .map(|r| {
+            let failures = r
+                .failures
+                .iter()
+                .map(|f| (f.line, f.message.clone()))
+                .collect();
+            (r.name.clone(), failures)
+        })
+        .collect()
+}
+
+#[test]
+fn runs_each_test() {
+    let results = run_tests(Path::new("main.asm"), &options(SOURCE), 1000).unwrap();
+    assert_eq!(
+        summary(&results),
+        [
+            (String::from("double"), vec![]),
+            (
+                String::from("fails"),
+                vec![
+                    (20, String::from("expected V0 != 3, but V0 is 3 (0x03)")),
+                    (22, String::from("expected V0 >= 4, but V0 is 3 (0x03)")),
+                ]
+            ),
+            (
+                String::from("halts"),
+                vec![(2, String::from("halted at 200 before `ENDTEST`"))]
+            ),
+            (
+                String::from("never ends"),
+                vec![(
+                    32,
+                    String::from("didn't reach `ENDTEST` within 1000 cycles")
+                )]
+            ),
+        ]
+    );
+    assert!(results[0].passed());
+    assert_eq!(results[0].line, 9);
+    assert_eq!(results[0].file, Path::new("main.asm"));
+}
+
+#[test]
+fn tests_are_left_out_unless_asked_for() {
+    let opts = options(SOURCE);
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    assert_eq!(assembly.bytes.len(), 9);
+    let with_tests = Options {
+        tests: true,
+        ..opts
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &with_tests).unwrap();
+    assert!(assembly.bytes.len() > 9);
+}
+
+#[test]
+fn coverage_leaves_out_the_tests_themselves() {
+    let (results, report) =
+        run_tests_with_coverage(Path::new("main.asm"), &options(SOURCE), DEFAULT_CYCLE_LIMIT)
+            .unwrap();
+    assert_eq!(results.len(), 4);
+    let lines: Vec<(usize, u64)> = report.lines.iter().map(|l| (l.line, l.hits)).collect();
+    // The test which jumps to `start` stops there as it's halted, so it never runs.
+    assert_eq!(lines, [(2, 0), (4, 1), (5, 1)]);
+}
+
+#[test]
+fn unbalanced_tests_are_errors() {
+    let error = |source: &str| {
+        run_tests(Path::new("main.asm"), &options(source), 1000)
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        error("TEST \"a\"\nTEST \"b\"\nENDTEST"),
+        "main.asm:2: error: `TEST` inside the `TEST` on line 1"
+    );
+    assert_eq!(
+        error("EXPECT V0 == 1"),
+        "main.asm:1: error: `EXPECT` outside of a `TEST`"
+    );
+    assert_eq!(
+        error("ENDTEST"),
+        "main.asm:1: error: `ENDTEST` without `TEST`"
+    );
+    assert_eq!(
+        error("TEST \"a\"\n    CLS"),
+        "main.asm:1: error: `TEST` without `ENDTEST`"
+    );
+    assert_eq!(
+        error("TEST \"a\"\n    EXPECT V0\nENDTEST"),
+        "main.asm:2: error: `EXPECT` takes 3 operand(s) but 1 were given"
+    );
+    assert_eq!(
+        error("TEST \"a\"\n    EXPECT 5 == V0\nENDTEST"),
+        "main.asm:2: error: invalid operand `5`"
+    );
+}
@@ -0,0 +1,150 @@
+//! COSMAC VIP timings, for single instructions and for whole programs in timing listings.
+
+use chip8_assembler::{
+    assemble_path, timing_listing, vip_cycles, vip_draw_cycles, Cycles, Instruction, MemoryFiles,
+    Options, FETCH_CYCLES, FRAME_CYCLES,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+fn cycles(opcode: u16) -> String {
+    vip_cycles(Instruction::decode(opcode).unwrap()).to_string()
+}
+
+#[test]
+fn instructions() {
+    assert_eq!(cycles(0x00E0), "64");
+    assert_eq!(cycles(0x00EE), "50");
+    assert_eq!(cycles(0x1200), "52");
+    assert_eq!(cycles(0x6005), "46");
+    assert_eq!(cycles(0x8014), "84");
+    // Skips take longer when they skip.
+    assert_eq!(cycles(0x3005), "50-54");
+    assert_eq!(cycles(0x5010), "54-58");
+    // Waiting for a key can take forever.
+    assert_eq!(cycles(0xF00A), "50+");
+    // Saving more registers takes longer.
+    assert_eq!(cycles(0xF055), "76");
+    assert_eq!(cycles(0xFF55), "286");
+}
+
+#[test]
+fn drawing_depends_on_the_position_and_waits_for_a_frame() {
+    assert_eq!(vip_draw_cycles(5, 0), 140);
+    assert_eq!(vip_draw_cycles(5, 8), 140);
+    assert_eq!(vip_draw_cycles(5, 3), 240);
+    let draw = vip_cycles(Instruction::decode(0xD015).unwrap());
+    assert_eq!(draw.best, FETCH_CYCLES + 140);
+    assert_eq!(
+        draw.worst,
+        Some(FETCH_CYCLES + vip_draw_cycles(5, 7) + FRAME_CYCLES)
+    );
+}
+
+#[test]
+fn cycles_add_and_display() {
+    let sum = Cycles::exactly(10) + vip_cycles(Instruction::decode(0x3005).unwrap());
+    assert_eq!(sum.to_string(), "60-64");
+    let unbounded = sum + vip_cycles(Instruction::decode(0xF00A).unwrap());
+    assert_eq!(unbounded.to_string(), "110+");
+    assert_eq!(format!("[{:>6}]", Cycles::exactly(46)), "[    46]");
+}
+
+#[test]
+fn listings_time_blocks_and_subroutines() {
+    let mut files = MemoryFiles::new();
+    files.insert(
+        "main.asm",
+        "\
+start:
+    CALL clear
+    SE V0, 1
+    LD V1, 2
+loop:
+    JP loop
+clear:
+    CLS
+    RET
+",
+    );
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    assert_eq!(
+        timing_listing(&assembly, &*opts.files),
+        "\
+; main.asm
+                                  1  start:
+0200  22 08               66      2      CALL clear
+0202  30 01            50-54      3      SE V0, 1
+0204  61 02               46      4      LD V1, 2
+                                  5  loop:
+0206  12 06               52      6      JP loop
+                                  7  clear:
+0208  00 E0               64      8      CLS
+020A  00 EE               50      9      RET
+
+; Basic blocks, in COSMAC VIP machine cycles
+; Start  End   Lines       Cycles
+; 0200   0200  2           66
+; 0202   0202  3           50-54
+; 0204   0204  4           46
+; 0206   0206  6           52
+; 0208   020A  8-9         114
+
+; Subroutines, from the first instruction to returning
+; Address  Name              Cycles
+; 0208     clear             114
+"
+    );
+}
+
+#[test]
+fn loops_entered_part_way_round_are_timed_from_where_they_are_entered() {
+    let mut files = MemoryFiles::new();
+    files.insert(
+        "main.asm",
+        "\
+start:
+    CALL work
+    CALL helper
+halt:
+    JP halt
+work:
+    SE V0, 0
+    JP slow
+again:
+    CALL helper
+check:
+    SNE V0, 5
+    RET
+    JP again
+slow:
+    LD V1, 1
+    JP check
+helper:
+    RET
+",
+    );
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    let assembly = assemble_path(Path::new("main.asm"), &opts).unwrap();
+    let listing = timing_listing(&assembly, &*opts.files);
+    // Skipping straight into the loop is quicker than going by way of `slow`.
+    assert!(
+        listing.ends_with(
+            "\
+; Subroutines, from the first instruction to returning
+; Address  Name              Cycles
+; 0206     work              270+
+; 0216     helper            50
+"
+        ),
+        "{}",
+        listing
+    );
+}
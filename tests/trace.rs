@@ -0,0 +1,101 @@
+//! Traces of a small program, in both formats and with filters and limits.
+
+use chip8_assembler::machine::Machine;
+use chip8_assembler::{
+    assemble_path, Assembly, MemoryFiles, Options, TraceFormat, TraceOptions, Tracer,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAIN: &str = "\
+start:
+    LD V0, 2
+    CALL draw
+loop:
+    ADD V0, 0xFF
+    SE V0, 0
+    JP loop
+done:
+    JP done
+
+draw:
+    LD I, sprite
+    RET
+
+sprite: DB 0x80
+";
+
+fn assembly() -> Assembly {
+    let mut files = MemoryFiles::new();
+    files.insert("main.asm", MAIN);
+    let opts = Options {
+        files: Arc::new(files),
+        ..Options::default()
+    };
+    assemble_path(Path::new("main.asm"), &opts).unwrap()
+}
+
+fn trace(assembly: &Assembly, options: TraceOptions) -> Vec<u8> {
+    let mut machine = Machine::with_program(Default::default(), &assembly.bytes).unwrap();
+    let mut tracer = Tracer::new(assembly, options);
+    while !machine.halted() {
+        tracer.step(&mut machine).unwrap();
+    }
+    tracer.into_bytes()
+}
+
+#[test]
+fn text_traces_name_labels_and_changed_registers() {
+    let assembly = assembly();
+    let text = String::from_utf8(trace(&assembly, TraceOptions::default())).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 9);
+    assert_eq!(
+        lines[0],
+        "       1  200  start               LD V0, 0x02         V0=02"
+    );
+    assert_eq!(
+        lines[2],
+        "       3  20C  draw                LD I, 0x210         I=210"
+    );
+    assert_eq!(
+        lines[3],
+        "       4  20E  draw+2              RET                 SP=0"
+    );
+    assert_eq!(lines[6], "       7  208  loop+4              JP 0x204");
+}
+
+#[test]
+fn filters_keep_only_the_ranges_asked_for() {
+    let assembly = assembly();
+    let ranges = TraceOptions::parse_ranges("draw, 0x200-0x201", &assembly).unwrap();
+    assert_eq!(ranges, vec![0x20C..=0x20F, 0x200..=0x201]);
+    let options = TraceOptions {
+        ranges,
+        ..TraceOptions::default()
+    };
+    let text = String::from_utf8(trace(&assembly, options)).unwrap();
+    let addresses: Vec<&str> = text.lines().map(|line| &line[10..13]).collect();
+    assert_eq!(addresses, ["200", "20C", "20E"]);
+
+    assert!(TraceOptions::parse_ranges("missing", &assembly).is_err());
+}
+
+#[test]
+fn binary_traces_stop_at_their_limit() {
+    let assembly = assembly();
+    let options = TraceOptions {
+        format: TraceFormat::Binary,
+        limit: Some(5 + 8 + 7),
+        ..TraceOptions::default()
+    };
+    let bytes = trace(&assembly, options);
+    assert_eq!(
+        bytes,
+        [
+            b'C', b'8', b'T', b'R', 1, // header
+            0x02, 0x00, 0x60, 0x02, 1, 0, 0x02, // LD V0, 2
+            0x02, 0x02, 0x22, 0x0C, 1, 19, 1, // CALL draw
+        ]
+    );
+}